@@ -0,0 +1,7 @@
+fn main() {
+    // Generate the same parser as the server, from its grammar
+    lalrpop::Configuration::new()
+        .set_in_dir("../src")
+        .process()
+        .unwrap();
+}
@@ -0,0 +1,46 @@
+// Feeds arbitrary text to the parser and the position mapping, neither of which may panic
+#![no_main]
+
+use lalrpop_util::lalrpop_mod;
+use libfuzzer_sys::fuzz_target;
+use tower_lsp::lsp_types::Position;
+
+#[allow(dead_code)]
+#[path = "../../src/ast.rs"]
+mod ast;
+#[allow(dead_code)]
+#[path = "../../src/position.rs"]
+mod position;
+
+lalrpop_mod!(xdr);
+
+fuzz_target!(|data: &[u8]| {
+    // Documents arrive as JSON strings, so the server only ever sees UTF-8
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let spec = xdr::SpecificationParser::new().parse(text).ok();
+    let lines = position::line_starts(text);
+    for encoding in [position::Encoding::Utf8, position::Encoding::Utf16] {
+        for defn in spec.iter().flat_map(|spec| &spec.defns) {
+            if let Some(id) = defn.id() {
+                let pos = position::position_at(text, id.start, encoding);
+                assert_eq!(position::offset_at(text, pos, encoding), id.start);
+            }
+        }
+        for offset in 0..=text.len() + 1 {
+            let pos = position::position_at(text, offset, encoding);
+            assert!(position::offset_at(text, pos, encoding) <= text.len());
+            assert!((pos.line as usize) < lines.len());
+        }
+        // Positions past the end of a line or the document, as a client might send
+        for line in 0..=lines.len() as u32 {
+            for character in [0, 1, 3, u32::MAX] {
+                position::offset_at(text, Position { line, character }, encoding);
+            }
+        }
+    }
+});
+        }
+    }
+});
@@ -0,0 +1,692 @@
+// Code actions
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::codegen::{Schema, SourceFile};
+use crate::includes::{self, RefKind};
+use crate::lint::{
+    self, SYNTAX_ERROR, UNBOUNDED_LENGTH, UNDEFINED_NAME, UNHANDLED_CASE, UNUSED_DEFINITION,
+};
+use crate::position::Encoding;
+use crate::rename::non_code_spans;
+use crate::scaffold;
+
+pub const ADD_DOC_COMMENT: CodeActionKind = CodeActionKind::new("source.addDocComment");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentStyle {
+    // /* ... */
+    Block,
+    // // ...
+    Line,
+}
+
+// How often each comment style is used for documentation in the workspace
+#[derive(Debug, Default)]
+pub struct CommentStyles {
+    block: usize,
+    line: usize,
+}
+
+impl CommentStyles {
+    // Counts the comments directly above lines that start a definition
+    pub fn count(&mut self, text: &str) {
+        let mut prev = "";
+        for line in text.lines().map(str::trim) {
+            let is_defn = ["const ", "typedef ", "enum ", "struct ", "union "]
+                .iter()
+                .any(|keyword| line.starts_with(keyword));
+            if is_defn {
+                if prev.ends_with("*/") {
+                    self.block += 1;
+                } else if prev.starts_with("//") {
+                    self.line += 1;
+                }
+            }
+            prev = line;
+        }
+    }
+
+    pub fn style(&self) -> CommentStyle {
+        if self.line > self.block {
+            CommentStyle::Line
+        } else {
+            CommentStyle::Block
+        }
+    }
+}
+
+// The top-level definition spanning the given offset (from its keyword up to the next
+// definition)
+pub fn definition_at(file: &SourceFile, offset: usize) -> Option<&Definition> {
+    let starts: Vec<(usize, &Definition)> = file
+        .spec
+        .defns
+        .iter()
+        .filter_map(|defn| Some((file.definition_start(defn)?, defn)))
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .find(|(i, (start, _))| {
+            let end = starts.get(i + 1).map_or(file.text.len(), |(next, _)| *next);
+            *start <= offset && offset < end.max(start + 1)
+        })
+        .map(|(_, (_, defn))| *defn)
+}
+
+// Lines describing the parts of a definition, for the doc comment template
+fn member_lines(defn: &Definition) -> Vec<String> {
+    let fields = |decls: &mut dyn Iterator<Item = &Declaration>| -> Vec<String> {
+        decls
+            .filter_map(|decl| decl.id())
+            .map(|id| format!("{}: TODO", id.id))
+            .collect()
+    };
+    match defn {
+        Definition::Enum { body, .. } => body
+            .body
+            .iter()
+            .map(|assign| format!("{}: TODO", assign.id.id))
+            .collect(),
+        Definition::Struct { body, .. } => fields(&mut body.body.iter()),
+        Definition::Union { body, .. } => fields(
+            &mut std::iter::once(&*body.discriminant)
+                .chain(body.cases.iter().map(|case| &case.decl))
+                .chain(body.default.as_deref()),
+        ),
+        _ => vec![],
+    }
+}
+
+// A source action inserting a doc comment template above the undocumented definition at
+// `offset`
+pub fn add_doc_comment(
+    file: &SourceFile,
+    uri: &Url,
+    offset: usize,
+    style: CommentStyle,
+    encoding: Encoding,
+) -> Option<CodeAction> {
+    let defn = definition_at(file, offset)?;
+    if file.doc_comment(defn).is_some() {
+        return None;
+    }
+    let id = defn.id()?;
+    let start = file.definition_start(defn)?;
+    let line_start = file.text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let indent = &file.text[line_start..start];
+    if !indent.trim().is_empty() {
+        // Something else precedes the definition on its line
+        return None;
+    }
+    let mut body = vec![format!("TODO: describe {}.", id.id)];
+    let members = member_lines(defn);
+    if !members.is_empty() {
+        body.push(String::new());
+        body.extend(members);
+    }
+    let mut comment = String::new();
+    let mut push = |line: &str| {
+        comment.push_str(indent);
+        comment.push_str(line.trim_end());
+        comment.push('\n');
+    };
+    match style {
+        CommentStyle::Block => {
+            push("/*");
+            for line in &body {
+                push(&format!(" * {line}"));
+            }
+            push(" */");
+        }
+        CommentStyle::Line => {
+            for line in &body {
+                push(&format!("// {line}"));
+            }
+        }
+    }
+    let pos = file.position_at(line_start, encoding);
+    Some(CodeAction {
+        title: format!("Add doc comment for `{}`", id.id),
+        kind: Some(ADD_DOC_COMMENT),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![TextEdit {
+                    range: Range {
+                        start: pos,
+                        end: pos,
+                    },
+                    new_text: comment,
+                }],
+            )])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+// `dataLen` -> `DATA_LEN_MAX`
+fn bound_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            out.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        out.push(c.to_ascii_uppercase());
+    }
+    out + "_MAX"
+}
+
+// Quick fixes for `unbounded-length` diagnostics giving the declaration (`int xs<>`, `opaque
+// data<>`, `string name<>`) a named bound, defining the constant (as `default_bound`) unless
+// `is_defined` says it already exists
+pub fn add_bound(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    default_bound: u64,
+    is_defined: &dyn Fn(&str) -> bool,
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    let mut result = vec![];
+    for (defn, id, open, close) in lint::unbounded_declarations(file) {
+        let start = file.position_at(id.start, encoding);
+        let Some(diagnostic) = diagnostics
+            .iter()
+            .find(|d| has_code(d, UNBOUNDED_LENGTH) && d.range.start == start)
+        else {
+            continue;
+        };
+        // Typedefs are named after the type, everything else after the field
+        let name = bound_name(&id.id);
+        let mut edits = vec![TextEdit {
+            range: Range {
+                start: file.position_at(open, encoding),
+                end: file.position_at(close, encoding),
+            },
+            new_text: format!("<{name}>"),
+        }];
+        if !is_defined(&name) {
+            let Some(defn_start) = file.definition_start(defn) else {
+                continue;
+            };
+            let defn_line = file.text[..defn_start].rfind('\n').map_or(0, |i| i + 1);
+            let indent = &file.text[defn_line..defn_start];
+            let pos = file.position_at(defn_line, encoding);
+            edits.insert(
+                0,
+                TextEdit {
+                    range: Range {
+                        start: pos,
+                        end: pos,
+                    },
+                    new_text: format!("{indent}const {name} = {default_bound};\n"),
+                },
+            );
+        }
+        result.push(CodeAction {
+            title: format!("Bound `{}` by `{}`", id.id, name),
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), edits)])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    result
+}
+
+// The number of single-character insertions, deletions, and substitutions turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = (diag + usize::from(ca != cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+// Where stubs for undefined names go: the line of the file's first definition, above any comment
+// directly over it
+fn stub_offset(file: &SourceFile) -> Option<usize> {
+    let start = file
+        .spec
+        .defns
+        .iter()
+        .find_map(|defn| file.definition_start(defn))?;
+    let line_start = file.text[..start].rfind('\n').map_or(0, |i| i + 1);
+    Some(comment_start(file, line_start))
+}
+
+// The start of the comment lines directly above the line starting at `line_start`, or
+// `line_start` if there are none
+fn comment_start(file: &SourceFile, mut line_start: usize) -> usize {
+    while line_start > 0 {
+        let prev_start = file.text[..line_start - 1].rfind('\n').map_or(0, |i| i + 1);
+        let prev = file.text[prev_start..line_start].trim();
+        let comment = ["//", "/*", "*"].iter().any(|p| prev.starts_with(p)) || prev.ends_with("*/");
+        if !comment {
+            break;
+        }
+        line_start = prev_start;
+    }
+    line_start
+}
+
+// Quick fixes for the names `undefined-name` diagnostics flag: defining a stub at the top of the
+// file (a constant set to `default_bound` for names used as values, an `int` typedef for names
+// used as types), or changing the name to one of the closest among `names`
+pub fn define_undefined(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    default_bound: u64,
+    names: &[&str],
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    let mut refs = vec![];
+    includes::visit_references(&file.spec, &mut refs);
+    let mut stubbed = vec![];
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        if diagnostic.code != Some(NumberOrString::String(UNDEFINED_NAME.to_string())) {
+            continue;
+        }
+        let start = file.offset_at(diagnostic.range.start, encoding);
+        let Some((id, kind)) = refs.iter().find(|(id, _)| id.start == start) else {
+            continue;
+        };
+        let fix = |title: String, edit: TextEdit| CodeAction {
+            title,
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        if !stubbed.contains(&id.id)
+            && let Some(offset) = stub_offset(file)
+        {
+            stubbed.push(id.id.clone());
+            let (title, stub) = match kind {
+                RefKind::Value => (
+                    format!("Create `const {} = {default_bound};`", id.id),
+                    format!("const {} = {default_bound};", id.id),
+                ),
+                RefKind::Type => (
+                    format!("Create typedef for `{}`", id.id),
+                    format!("typedef int {};", id.id),
+                ),
+            };
+            let pos = file.position_at(offset, encoding);
+            result.push(fix(
+                title,
+                TextEdit {
+                    range: Range {
+                        start: pos,
+                        end: pos,
+                    },
+                    new_text: format!("{stub}\n\n"),
+                },
+            ));
+        }
+        // Allow about one typo in every three characters
+        let most = (id.id.chars().count() / 3).max(1);
+        let mut similar: Vec<(usize, &str)> = names
+            .iter()
+            .map(|name| (edit_distance(&id.id, name), *name))
+            .filter(|&(distance, _)| distance <= most)
+            .collect();
+        similar.sort();
+        for (_, name) in similar.into_iter().take(3) {
+            result.push(fix(
+                format!("Change to `{name}`"),
+                TextEdit {
+                    range: Range {
+                        start: file.position_at(id.start, encoding),
+                        end: file.position_at(id.end, encoding),
+                    },
+                    new_text: name.to_string(),
+                },
+            ));
+        }
+    }
+    result
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn has_code(diagnostic: &Diagnostic, code: &str) -> bool {
+    matches!(&diagnostic.code, Some(NumberOrString::String(found)) if found == code)
+}
+
+// A quick fix for `diagnostic` marked preferred: safe to apply without review, so `fix_all`
+// takes it
+fn safe_fix(title: String, uri: &Url, diagnostic: &Diagnostic, edits: Vec<TextEdit>) -> CodeAction {
+    CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        is_preferred: Some(true),
+        ..Default::default()
+    }
+}
+
+// The union of `file` whose body (from `switch`) starts at `start`
+fn union_at(file: &SourceFile, start: usize) -> Option<&UnionBody> {
+    file.spec.defns.iter().find_map(|defn| {
+        if let Definition::Union { body, .. } = defn
+            && body.start == start
+        {
+            return Some(body);
+        }
+        defn.declarations().into_iter().find_map(|decl| match decl {
+            Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } => match spec {
+                TypeSpecifier::Union(body) if body.start == start => Some(body),
+                _ => None,
+            },
+            _ => None,
+        })
+    })
+}
+
+// The whitespace starting the line `offset` is on
+fn indent_at(text: &str, offset: usize) -> &str {
+    let line = &text[text[..offset].rfind('\n').map_or(0, |i| i + 1)..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+// Quick fixes for the unions `unhandled-case` diagnostics flag: adding a `void` arm for each
+// member left unhandled before the closing brace, laid out like the last arm
+pub fn add_missing_arms(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    schema: &Schema,
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    if !diagnostics
+        .iter()
+        .any(|diagnostic| has_code(diagnostic, UNHANDLED_CASE))
+    {
+        return vec![];
+    }
+    let text = &file.text;
+    let mut code = text.clone().into_bytes();
+    for (start, end) in non_code_spans(text) {
+        code[start..end].fill(b' ');
+    }
+    let code = String::from_utf8(code).unwrap_or_default();
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        if !has_code(diagnostic, UNHANDLED_CASE) {
+            continue;
+        }
+        let start = file.offset_at(diagnostic.range.start, encoding);
+        let Some(body) = union_at(file, start) else {
+            continue;
+        };
+        let members = schema.unhandled_members(body);
+        let Some(last) = body.cases.last() else {
+            continue;
+        };
+        if members.is_empty() || body.default.is_some() {
+            continue;
+        }
+        // The last arm's declaration follows its last label's `:`
+        let Some(colon) = code[last.start..last.end].rfind(':') else {
+            continue;
+        };
+        let after = &code[last.start + colon + 1..last.end];
+        let decl_start = last.end - after.trim_start().len();
+        let decl_indent = after[..after.len() - after.trim_start().len()]
+            .contains('\n')
+            .then(|| indent_at(text, decl_start));
+        let mut arms =
+            scaffold::arm_lines(&members, indent_at(text, last.start), decl_indent, false)
+                .join("\n");
+        // Before the closing brace, on lines of their own
+        let brace = body.end - 1;
+        let at = if text[..brace].trim_end_matches([' ', '\t']).ends_with('\n') {
+            arms.push('\n');
+            brace - indent_at(text, brace).len()
+        } else {
+            arms.insert(0, '\n');
+            arms.push('\n');
+            brace
+        };
+        let pos = file.position_at(at, encoding);
+        let title = match members.as_slice() {
+            [member] => format!("Add an arm for `{member}`"),
+            _ => format!("Add arms for the {} unhandled members", members.len()),
+        };
+        result.push(safe_fix(
+            title,
+            uri,
+            diagnostic,
+            vec![TextEdit {
+                range: Range::new(pos, pos),
+                new_text: arms,
+            }],
+        ));
+    }
+    result
+}
+
+// The edits giving the members of the enum body between `open` and `close` in `code` (the text
+// with comments blanked out) that have no value their C value, one more than the member before
+// (0 for the first). None unless the body is a list of names and assignments with some values
+// missing, each following a member with a literal value.
+fn enum_value_edits(code: &str, open: usize, close: usize) -> Option<Vec<(usize, String)>> {
+    let mut edits = vec![];
+    let mut next = Some(0i64);
+    let mut at = open + 1;
+    for member in code[open + 1..close].split(',') {
+        let (name, value) = match member.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (member, None),
+        };
+        let id = name.trim();
+        if id.is_empty() || !id.chars().all(is_ident_char) {
+            return None;
+        }
+        match value {
+            Some(value) => next = parse_constant(value.trim()).and_then(|n| n.checked_add(1)),
+            None => {
+                let n = next?;
+                edits.push((at + name.trim_end().len(), format!(" = {n}")));
+                next = n.checked_add(1);
+            }
+        }
+        at += member.len() + 1;
+    }
+    (!edits.is_empty()).then_some(edits)
+}
+
+// Quick fixes for a syntax error in an enum written as in C, with members lacking values: giving
+// those their C values
+pub fn explicit_enum_values(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    let mut code = file.text.clone().into_bytes();
+    for (start, end) in non_code_spans(&file.text) {
+        code[start..end].fill(b' ');
+    }
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        if !has_code(diagnostic, SYNTAX_ERROR) {
+            continue;
+        }
+        let offset = file.offset_at(diagnostic.range.start, encoding);
+        let Some((open, close)) = scaffold::enclosing_body(&code, offset) else {
+            continue;
+        };
+        let Ok(code) = std::str::from_utf8(&code) else {
+            continue;
+        };
+        // `enum {` or `enum name {`
+        let is_enum = |before: &str| {
+            before
+                .strip_suffix("enum")
+                .is_some_and(|rest| !rest.ends_with(is_ident_char))
+        };
+        let header = code[..open].trim_end();
+        let name = header.trim_end_matches(is_ident_char).trim_end();
+        if !(is_enum(header) || is_enum(name)) || close == code.len() {
+            continue;
+        }
+        let Some(edits) = enum_value_edits(code, open, close) else {
+            continue;
+        };
+        let edits = edits
+            .into_iter()
+            .map(|(at, new_text)| {
+                let pos = file.position_at(at, encoding);
+                TextEdit {
+                    range: Range::new(pos, pos),
+                    new_text,
+                }
+            })
+            .collect();
+        result.push(safe_fix(
+            "Give the enum's members explicit values".to_string(),
+            uri,
+            diagnostic,
+            edits,
+        ));
+    }
+    result
+}
+
+// Quick fixes removing the definitions `unused-definition` diagnostics flag, with the comments
+// directly above them. Definitions sharing a line with other code are left alone.
+pub fn remove_unused(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        if !has_code(diagnostic, UNUSED_DEFINITION) {
+            continue;
+        }
+        let offset = file.offset_at(diagnostic.range.start, encoding);
+        let Some((defn, id)) = file.spec.defns.iter().find_map(|defn| {
+            let id = defn.id()?;
+            (id.start == offset).then_some((defn, id))
+        }) else {
+            continue;
+        };
+        let (Some(start), Some(end)) = (file.definition_start(defn), file.definition_end(defn))
+        else {
+            continue;
+        };
+        let text = &file.text;
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let mut line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+        // Only a trailing comment may follow on the last line
+        let after = text[end..line_end].trim();
+        let trailing = after.is_empty()
+            || after.starts_with("//")
+            || (after.starts_with("/*") && after.find("*/") == Some(after.len() - 2));
+        if !text[line_start..start].trim().is_empty() || !trailing {
+            continue;
+        }
+        let first = comment_start(file, line_start);
+        // Don't leave two blank lines where the definition was
+        let blank_before = first == 0 || text[..first].ends_with("\n\n");
+        if blank_before
+            && let Some(i) = text[line_end..].find('\n')
+            && text[line_end..line_end + i].trim().is_empty()
+        {
+            line_end += i + 1;
+        }
+        result.push(safe_fix(
+            format!("Remove unused `{}`", id.id),
+            uri,
+            diagnostic,
+            vec![TextEdit {
+                range: Range::new(
+                    file.position_at(first, encoding),
+                    file.position_at(line_end, encoding),
+                ),
+                new_text: String::new(),
+            }],
+        ));
+    }
+    result
+}
+
+// Merges the preferred fixes among `fixes`, those safe to apply without review, into one edit to
+// `uri`. A fix is left out if its edits overlap those of one already taken, and edits another fix
+// already makes are only made once. Returns the edit with the number of fixes in it.
+pub fn fix_all(uri: &Url, fixes: Vec<CodeAction>) -> Option<(WorkspaceEdit, usize)> {
+    let overlaps = |a: &Range, b: &Range| a.start < b.end && b.start < a.end;
+    let mut edits: Vec<TextEdit> = vec![];
+    let mut count = 0;
+    for fix in fixes {
+        if fix.is_preferred != Some(true) {
+            continue;
+        }
+        let Some(fix_edits) = fix
+            .edit
+            .and_then(|edit| edit.changes)
+            .and_then(|mut changes| changes.remove(uri))
+        else {
+            continue;
+        };
+        let new: Vec<TextEdit> = fix_edits
+            .into_iter()
+            .filter(|edit| !edits.contains(edit))
+            .collect();
+        if new.iter().any(|edit| {
+            edits
+                .iter()
+                .any(|taken| overlaps(&edit.range, &taken.range))
+        }) {
+            continue;
+        }
+        edits.extend(new);
+        count += 1;
+    }
+    (count > 0).then(|| {
+        (
+            WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), edits)])),
+                ..Default::default()
+            },
+            count,
+        )
+    })
+}
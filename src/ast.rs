@@ -1,4 +1,8 @@
-#[derive(Debug)]
+// The syntax tree. It serializes to JSON for `xdr/dumpAst`, with each identifier's byte range in
+// the text.
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
 pub enum Declaration {
     Normal {
         spec: TypeSpecifier,
@@ -33,13 +37,13 @@ pub enum Declaration {
     VOID,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Value {
     Id(Identifier),
     Const(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub enum TypeSpecifier {
     BuiltIn(String),
     Enum(EnumBody),
@@ -48,53 +52,207 @@ pub enum TypeSpecifier {
     Ident(Identifier),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct EnumAssign {
     pub id: Identifier,
     pub val: Value,
+    // Byte range from the name to the end of the value
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct EnumBody {
     pub body: Vec<EnumAssign>,
+    // Byte range from the opening brace to the closing one
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct StructBody {
     pub body: Vec<Declaration>,
+    // Byte range of each member's declaration, not counting its `;`
+    pub spans: Vec<(usize, usize)>,
+    // Byte range from the opening brace to the closing one
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct UnionBody {
     // Boxes because of recursion
     pub discriminant: Box<Declaration>,
     pub cases: Vec<CaseSpec>,
     pub default: Option<Box<Declaration>>,
+    // Byte range from `switch` to the closing brace
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CaseSpec {
     pub values: Vec<Value>,
     pub decl: Declaration,
+    // Byte range from the first `case` to the semicolon
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
+// A procedure of an RPC program version: `result id(args) = number;`
+#[derive(Debug, Clone, Serialize)]
+pub struct Procedure {
+    // None for `void`
+    pub result: Option<TypeSpecifier>,
+    pub id: Identifier,
+    // Empty for `(void)`
+    pub args: Vec<TypeSpecifier>,
+    pub number: Value,
+    // Byte range from the result type to the semicolon
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+    pub id: Identifier,
+    pub procedures: Vec<Procedure>,
+    pub number: Value,
+    // Byte range from `version` to the semicolon
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub enum Definition {
-    Constant { id: Identifier, val: String },
+    Constant {
+        id: Identifier,
+        val: String,
+    },
     TypeDef(Declaration),
-    Enum { id: Identifier, body: EnumBody },
-    Struct { id: Identifier, body: StructBody },
-    Union { id: Identifier, body: UnionBody },
+    Enum {
+        id: Identifier,
+        body: EnumBody,
+    },
+    Struct {
+        id: Identifier,
+        body: StructBody,
+    },
+    Union {
+        id: Identifier,
+        body: UnionBody,
+    },
+    Program {
+        id: Identifier,
+        versions: Vec<Version>,
+        number: Value,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Specification {
     pub defns: Vec<Definition>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Identifier {
     pub id: String,
     pub start: usize,
     pub end: usize,
 }
+
+impl Declaration {
+    pub fn id(&self) -> Option<&Identifier> {
+        match self {
+            Declaration::Normal { id, .. }
+            | Declaration::FixedArr { id, .. }
+            | Declaration::VarArr { id, .. }
+            | Declaration::FixedOpaque { id, .. }
+            | Declaration::VarOpaque { id, .. }
+            | Declaration::String { id, .. }
+            | Declaration::Optional { id, .. } => Some(id),
+            Declaration::VOID => None,
+        }
+    }
+}
+
+impl Definition {
+    pub fn id(&self) -> Option<&Identifier> {
+        match self {
+            Definition::Constant { id, .. }
+            | Definition::Enum { id, .. }
+            | Definition::Struct { id, .. }
+            | Definition::Union { id, .. }
+            | Definition::Program { id, .. } => Some(id),
+            Definition::TypeDef(decl) => decl.id(),
+        }
+    }
+
+    // The keyword the definition starts with
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Definition::Constant { .. } => "const",
+            Definition::TypeDef(_) => "typedef",
+            Definition::Enum { .. } => "enum",
+            Definition::Struct { .. } => "struct",
+            Definition::Union { .. } => "union",
+            Definition::Program { .. } => "program",
+        }
+    }
+
+    // Every declaration in the definition, including those nested in anonymous structs and unions
+    pub fn declarations(&self) -> Vec<&Declaration> {
+        let mut decls = vec![];
+        match self {
+            Definition::TypeDef(decl) => visit_decls(decl, &mut decls),
+            Definition::Struct { body, .. } => {
+                for decl in &body.body {
+                    visit_decls(decl, &mut decls);
+                }
+            }
+            Definition::Union { body, .. } => visit_union_decls(body, &mut decls),
+            _ => {}
+        }
+        decls
+    }
+}
+
+fn visit_decls<'a>(decl: &'a Declaration, out: &mut Vec<&'a Declaration>) {
+    out.push(decl);
+    match decl {
+        Declaration::Normal { spec, .. }
+        | Declaration::FixedArr { spec, .. }
+        | Declaration::VarArr { spec, .. }
+        | Declaration::Optional { spec, .. } => match spec {
+            TypeSpecifier::Struct(body) => {
+                for decl in &body.body {
+                    visit_decls(decl, out);
+                }
+            }
+            TypeSpecifier::Union(body) => visit_union_decls(body, out),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+fn visit_union_decls<'a>(body: &'a UnionBody, out: &mut Vec<&'a Declaration>) {
+    visit_decls(&body.discriminant, out);
+    for case in &body.cases {
+        visit_decls(&case.decl, out);
+    }
+    if let Some(decl) = &body.default {
+        visit_decls(decl, out);
+    }
+}
+
+// Parses a CONST token (decimal, hex, or octal)
+pub fn parse_constant(val: &str) -> Option<i64> {
+    if let Some(hex) = val.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if val.len() > 1 && val.starts_with('0') {
+        i64::from_str_radix(&val[1..], 8).ok()
+    } else {
+        val.parse().ok()
+    }
+}
@@ -0,0 +1,406 @@
+// Detection of wire-incompatible changes between two versions of a set of schemas
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ast::*;
+use crate::codegen::{Schema, SourceFile};
+use crate::scan::ScanOptions;
+
+#[derive(Debug)]
+pub struct Issue {
+    // Where to report the issue: the definition in the new schemas, or in the old ones when the
+    // type was removed
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.path.display(),
+            self.line + 1,
+            self.message
+        )
+    }
+}
+
+// The file and (0-based) line defining the named type
+fn locate(schema: &Schema, name: &str) -> (PathBuf, usize) {
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            if let Some(id) = defn.id()
+                && id.id == name
+            {
+                let line = file.text[..id.start].matches('\n').count();
+                return (file.path.clone(), line);
+            }
+        }
+    }
+    (PathBuf::new(), 0)
+}
+
+fn value_text(val: &Value) -> String {
+    match val {
+        Value::Id(id) => id.id.clone(),
+        Value::Const(val) => val.clone(),
+    }
+}
+
+pub fn spec_text(spec: &TypeSpecifier) -> String {
+    match spec {
+        TypeSpecifier::BuiltIn(name) => name.clone(),
+        TypeSpecifier::Ident(id) => id.id.clone(),
+        TypeSpecifier::Enum(_) => "enum { ... }".to_string(),
+        TypeSpecifier::Struct(_) => "struct { ... }".to_string(),
+        TypeSpecifier::Union(_) => "union { ... }".to_string(),
+    }
+}
+
+// A declaration as it would be written, minus the name
+pub fn decl_text(decl: &Declaration) -> String {
+    let bound = |size: &Option<Value>| size.as_ref().map(value_text).unwrap_or_default();
+    match decl {
+        Declaration::Normal { spec, .. } => spec_text(spec),
+        Declaration::FixedArr { spec, size, .. } => {
+            format!("{}[{}]", spec_text(spec), value_text(size))
+        }
+        Declaration::VarArr { spec, size, .. } => format!("{}<{}>", spec_text(spec), bound(size)),
+        Declaration::FixedOpaque { size, .. } => format!("opaque[{}]", value_text(size)),
+        Declaration::VarOpaque { size, .. } => format!("opaque<{}>", bound(size)),
+        Declaration::String { size, .. } => format!("string<{}>", bound(size)),
+        Declaration::Optional { spec, .. } => format!("{}*", spec_text(spec)),
+        Declaration::VOID => "void".to_string(),
+    }
+}
+
+struct Checker<'a, 'b> {
+    old: &'b Schema<'a>,
+    new: &'b Schema<'a>,
+    // Messages for the definition currently being compared
+    messages: Vec<String>,
+}
+
+pub fn check(old: &Schema, new: &Schema) -> Vec<Issue> {
+    let mut checker = Checker {
+        old,
+        new,
+        messages: vec![],
+    };
+    let mut issues = vec![];
+    for file in old.files {
+        for defn in &file.spec.defns {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            if let Definition::Constant { .. } = defn {
+                continue;
+            }
+            let name = id.id.as_str();
+            let Some(new_defn) = new.types.get(name) else {
+                let (path, line) = locate(old, name);
+                issues.push(Issue {
+                    path,
+                    line,
+                    message: format!("{} `{}` was removed", defn.keyword(), name),
+                });
+                continue;
+            };
+            checker.definition(name, defn, new_defn);
+            let (path, line) = locate(new, name);
+            issues.extend(checker.messages.drain(..).map(|message| Issue {
+                path: path.clone(),
+                line,
+                message,
+            }));
+        }
+    }
+    issues
+}
+
+impl Checker<'_, '_> {
+    fn report(&mut self, path: &str, message: impl AsRef<str>) {
+        self.messages
+            .push(format!("{}: {}", path, message.as_ref()));
+    }
+
+    fn definition(&mut self, name: &str, old: &Definition, new: &Definition) {
+        match (old, new) {
+            (Definition::TypeDef(old), Definition::TypeDef(new)) => self.decl(name, old, new),
+            (Definition::Enum { body: old, .. }, Definition::Enum { body: new, .. }) => {
+                self.enumeration(name, old, new)
+            }
+            (Definition::Struct { body: old, .. }, Definition::Struct { body: new, .. }) => {
+                self.structure(name, old, new)
+            }
+            (Definition::Union { body: old, .. }, Definition::Union { body: new, .. }) => {
+                self.union(name, old, new)
+            }
+            _ => self.report(
+                name,
+                format!("changed from {} to {}", old.keyword(), new.keyword()),
+            ),
+        }
+    }
+
+    fn enumeration(&mut self, path: &str, old: &EnumBody, new: &EnumBody) {
+        for assign in &old.body {
+            let name = &assign.id.id;
+            match new.body.iter().find(|other| other.id.id == *name) {
+                None => self.report(path, format!("member `{name}` was removed")),
+                Some(other) => {
+                    let (old_val, new_val) =
+                        (self.old.value(&assign.val), self.new.value(&other.val));
+                    if old_val != new_val {
+                        self.report(
+                            path,
+                            format!(
+                                "value of `{}` changed from {} to {}",
+                                name,
+                                value_text(&assign.val),
+                                value_text(&other.val)
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn structure(&mut self, path: &str, old: &StructBody, new: &StructBody) {
+        let names = |body: &StructBody| -> Vec<String> {
+            body.body
+                .iter()
+                .filter_map(|decl| decl.id().map(|id| id.id.clone()))
+                .collect()
+        };
+        let (old_names, new_names) = (names(old), names(new));
+        for name in &old_names {
+            if !new_names.contains(name) {
+                self.report(path, format!("field `{name}` was removed"));
+            }
+        }
+        for name in &new_names {
+            if !old_names.contains(name) {
+                self.report(path, format!("field `{name}` was added"));
+            }
+        }
+        // Fields are encoded in order, so moving one changes the encoding
+        let common = |names: &[String], other: &[String]| -> Vec<String> {
+            names
+                .iter()
+                .filter(|name| other.contains(name))
+                .cloned()
+                .collect()
+        };
+        if common(&old_names, &new_names) != common(&new_names, &old_names) {
+            self.report(path, "fields were reordered");
+        }
+        for decl in &old.body {
+            let Some(id) = decl.id() else {
+                continue;
+            };
+            let other = new
+                .body
+                .iter()
+                .find(|other| other.id().is_some_and(|other| other.id == id.id));
+            if let Some(other) = other {
+                self.decl(&format!("{}.{}", path, id.id), decl, other);
+            }
+        }
+    }
+
+    // The arm of a union handling the given discriminant value
+    fn arm<'b>(schema: &Schema, body: &'b UnionBody, val: i64) -> Option<&'b Declaration> {
+        body.cases
+            .iter()
+            .find(|case| {
+                case.values
+                    .iter()
+                    .any(|other| schema.case_value(other) == Some(val))
+            })
+            .map(|case| &case.decl)
+            .or(body.default.as_deref())
+    }
+
+    fn union(&mut self, path: &str, old: &UnionBody, new: &UnionBody) {
+        let disc = old
+            .discriminant
+            .id()
+            .map_or("discriminant".to_string(), |id| id.id.clone());
+        self.decl(
+            &format!("{path}.{disc}"),
+            &old.discriminant,
+            &new.discriminant,
+        );
+        for case in &old.cases {
+            for val in &case.values {
+                let label = format!("{}.case {}", path, value_text(val));
+                let Some(num) = self.old.case_value(val) else {
+                    continue;
+                };
+                match Self::arm(self.new, new, num) {
+                    None => self.report(
+                        path,
+                        format!("case {} is no longer handled", value_text(val)),
+                    ),
+                    Some(other) => self.decl(&label, &case.decl, other),
+                }
+            }
+        }
+        match (&old.default, &new.default) {
+            (Some(_), None) => self.report(path, "default arm was removed"),
+            (Some(old), Some(new)) => self.decl(&format!("{path}.default"), old, new),
+            _ => {}
+        }
+    }
+
+    // A size, with its value when it's a named constant
+    fn size_text(schema: &Schema, size: &Value) -> String {
+        match (size, schema.value(size)) {
+            (Value::Id(id), Some(val)) => format!("{} ({})", id.id, val),
+            _ => value_text(size),
+        }
+    }
+
+    fn bound(&mut self, path: &str, old: &Option<Value>, new: &Option<Value>) {
+        let Some(new_size) = new else {
+            return;
+        };
+        let new_max = self.new.value(new_size);
+        let narrowed = match old {
+            None => true,
+            Some(old_size) => match (self.old.value(old_size), new_max) {
+                (Some(old_max), Some(new_max)) => new_max < old_max,
+                _ => false,
+            },
+        };
+        if narrowed {
+            let old_text = old.as_ref().map_or("unbounded".to_string(), |size| {
+                Self::size_text(self.old, size)
+            });
+            let new_text = Self::size_text(self.new, new_size);
+            self.report(
+                path,
+                format!("bound narrowed from {old_text} to {new_text}"),
+            );
+        }
+    }
+
+    fn length(&mut self, path: &str, old: &Value, new: &Value) {
+        if self.old.value(old) != self.new.value(new) {
+            self.report(
+                path,
+                format!(
+                    "length changed from {} to {}",
+                    Self::size_text(self.old, old),
+                    Self::size_text(self.new, new)
+                ),
+            );
+        }
+    }
+
+    fn decl(&mut self, path: &str, old: &Declaration, new: &Declaration) {
+        match (old, new) {
+            (Declaration::Normal { spec: old, .. }, Declaration::Normal { spec: new, .. })
+            | (Declaration::Optional { spec: old, .. }, Declaration::Optional { spec: new, .. }) => {
+                self.spec(path, old, new)
+            }
+            (
+                Declaration::FixedArr {
+                    spec: old,
+                    size: old_size,
+                    ..
+                },
+                Declaration::FixedArr {
+                    spec: new,
+                    size: new_size,
+                    ..
+                },
+            ) => {
+                self.length(path, old_size, new_size);
+                self.spec(path, old, new);
+            }
+            (
+                Declaration::VarArr {
+                    spec: old,
+                    size: old_size,
+                    ..
+                },
+                Declaration::VarArr {
+                    spec: new,
+                    size: new_size,
+                    ..
+                },
+            ) => {
+                self.bound(path, old_size, new_size);
+                self.spec(path, old, new);
+            }
+            (
+                Declaration::FixedOpaque { size: old, .. },
+                Declaration::FixedOpaque { size: new, .. },
+            ) => self.length(path, old, new),
+            (
+                Declaration::VarOpaque { size: old, .. },
+                Declaration::VarOpaque { size: new, .. },
+            )
+            | (Declaration::String { size: old, .. }, Declaration::String { size: new, .. }) => {
+                self.bound(path, old, new)
+            }
+            (Declaration::VOID, Declaration::VOID) => {}
+            _ => self.report(
+                path,
+                format!("changed from `{}` to `{}`", decl_text(old), decl_text(new)),
+            ),
+        }
+    }
+
+    fn spec(&mut self, path: &str, old: &TypeSpecifier, new: &TypeSpecifier) {
+        // Aliases don't affect the encoding, so compare what they stand for
+        match (self.old.resolve(old), self.new.resolve(new)) {
+            (TypeSpecifier::BuiltIn(a), TypeSpecifier::BuiltIn(b)) if a == b => {}
+            // Named types are compared on their own
+            (TypeSpecifier::Ident(a), TypeSpecifier::Ident(b)) if a.id == b.id => {}
+            (TypeSpecifier::Enum(a), TypeSpecifier::Enum(b)) => self.enumeration(path, a, b),
+            (TypeSpecifier::Struct(a), TypeSpecifier::Struct(b)) => self.structure(path, a, b),
+            (TypeSpecifier::Union(a), TypeSpecifier::Union(b)) => self.union(path, a, b),
+            (a, b) => self.report(
+                path,
+                format!("type changed from `{}` to `{}`", spec_text(a), spec_text(b)),
+            ),
+        }
+    }
+}
+
+fn git(root: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(args)
+        .output()
+        .map_err(|err| format!("git: {err}"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8(output.stdout).map_err(|err| format!("git: {err}"))
+}
+
+// Parses the schema files under `root` as of the given git revision
+pub fn load_git_ref(root: &Path, rev: &str, scan: &ScanOptions) -> Result<Vec<SourceFile>, String> {
+    let listing = git(root, &["ls-tree", "-r", "--name-only", rev])?;
+    let mut files = vec![];
+    for name in listing
+        .lines()
+        .filter(|name| scan.is_schema(Path::new(name)))
+    {
+        let text = git(root, &["show", &format!("{rev}:./{name}")])?;
+        match SourceFile::parse(root.join(name), text) {
+            Ok(file) => files.push(file),
+            // The old version not parsing shouldn't stop us from comparing the rest
+            Err(err) => eprintln!("{rev}: {err}"),
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,186 @@
+// The on-disk index cache
+//
+// The indexes of files read from disk are saved when the server shuts down and loaded at the next
+// start, so only files whose modification time or size changed in between are parsed again. The
+// cache lives in the user's cache directory (`$XDG_CACHE_HOME/xdr-ls`, or `~/.cache/xdr-ls`), one
+// JSON file per workspace and position encoding, since the indexes hold positions. One written by
+// another version of the server, or with other dialect extensions registered, is ignored, as its
+// indexes may not match what this one finds.
+//
+// Servers running side by side on a workspace share the cache: each saves it once the workspace
+// is indexed as well as at shutdown, merging in what the others saved rather than overwriting it,
+// and picks up what they saved before indexing files again. The CLI parses whole files rather than
+// indexing them, so it neither reads nor writes the cache.
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dialect;
+use crate::index::DocumentIndex;
+use crate::position::Encoding;
+
+// Bumped whenever what's indexed changes shape or meaning
+const FORMAT: &str = concat!(env!("CARGO_PKG_VERSION"), "/2");
+
+// `FORMAT`, with the dialect extensions registered, which change what's indexed
+fn format() -> String {
+    let mut format = FORMAT.to_string();
+    for name in dialect::registered_names() {
+        format.push('+');
+        format.push_str(name);
+    }
+    format
+}
+
+// What a file's cached index is only good for: its modification time (nanoseconds since the
+// epoch) and size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    modified: u64,
+    len: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> Option<Stamp> {
+        let meta = fs::metadata(path).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            modified: modified.as_nanos() as u64,
+            len: meta.len(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    format: String,
+    encoding: Encoding,
+    entries: HashMap<PathBuf, (Stamp, DocumentIndex)>,
+}
+
+#[derive(Debug)]
+pub struct IndexCache {
+    file: PathBuf,
+    encoding: Encoding,
+    // Shared by the threads indexing in parallel
+    entries: Mutex<HashMap<PathBuf, (Stamp, DocumentIndex)>>,
+    // The cache file as last read or written, to tell when another server has saved it since
+    seen: Mutex<Option<Stamp>>,
+}
+
+impl IndexCache {
+    // The cache for the workspace at `root` with positions in `encoding`, empty if there's none
+    // yet (or it can't be read)
+    pub fn load(root: &Path, encoding: Encoding) -> IndexCache {
+        let file = cache_dir().join(format!("{:016x}.json", hash(root, encoding)));
+        let cache = IndexCache {
+            file,
+            encoding,
+            entries: Mutex::default(),
+            seen: Mutex::default(),
+        };
+        cache.reload();
+        cache
+    }
+
+    // Takes in the entries another server saved since the cache file was last read or written,
+    // for files whose index here is missing or out of date
+    pub fn reload(&self) {
+        let stamp = Stamp::of(&self.file);
+        {
+            let mut seen = self.seen.lock().unwrap_or_else(|err| err.into_inner());
+            if stamp.is_none() || *seen == stamp {
+                return;
+            }
+            *seen = stamp;
+        }
+        let Some(saved) = self.read() else {
+            return;
+        };
+        let mut entries = self.lock();
+        for (path, (stamp, index)) in saved {
+            let current = match entries.get(&path) {
+                Some((cached, _)) if *cached == stamp => continue,
+                Some(_) => Stamp::of(&path),
+                None => None,
+            };
+            if current.is_none_or(|current| current == stamp) {
+                entries.insert(path, (stamp, index));
+            }
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    // The cached index of `path`, if it was cached when the file was as `stamp` says
+    pub fn get(&self, path: &Path, stamp: Stamp) -> Option<DocumentIndex> {
+        match self.lock().get(path) {
+            Some((cached, index)) if *cached == stamp => Some(index.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, path: PathBuf, stamp: Stamp, index: DocumentIndex) {
+        self.lock().insert(path, (stamp, index));
+    }
+
+    // Writes the cache out, with what other servers saved merged in, leaving out files that no
+    // longer exist
+    pub fn save(&self) -> io::Result<()> {
+        self.reload();
+        let mut entries = self.lock().clone();
+        entries.retain(|path, _| path.is_file());
+        let saved = Saved {
+            format: format(),
+            encoding: self.encoding,
+            entries,
+        };
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written whole and then renamed, so a crash midway leaves the old cache, and named for
+        // this process so servers saving at once don't write over each other's halves
+        let partial = self
+            .file
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&partial, serde_json::to_vec(&saved)?)?;
+        fs::rename(&partial, &self.file)?;
+        *self.seen.lock().unwrap_or_else(|err| err.into_inner()) = Stamp::of(&self.file);
+        Ok(())
+    }
+
+    // The entries in the cache file, if it's one this server can use
+    fn read(&self) -> Option<HashMap<PathBuf, (Stamp, DocumentIndex)>> {
+        let bytes = fs::read(&self.file).ok()?;
+        let saved = serde_json::from_slice::<Saved>(&bytes).ok()?;
+        (saved.format == format() && saved.encoding == self.encoding).then_some(saved.entries)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, (Stamp, DocumentIndex)>> {
+        // A thread that panicked mid-insert leaves nothing half-done worth throwing away
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("xdr-ls")
+}
+
+fn hash(root: &Path, encoding: Encoding) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    root.hash(&mut hasher);
+    encoding.hash(&mut hasher);
+    hasher.finish()
+}
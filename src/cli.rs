@@ -0,0 +1,1135 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use regex::Regex;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position};
+
+use crate::codec::{self, Decoded};
+use crate::codegen::size::WireSize;
+use crate::codegen::{Schema, SourceFile, load_files, parse_files};
+use crate::dialect::Dialect;
+use crate::expand::expand;
+use crate::includes::visit_references;
+use crate::index::visit_identifiers;
+use crate::position::{Encoding, offset_at};
+use crate::scan::{ScanOptions, get_xdr_files};
+use crate::since::ProtocolVersion;
+use crate::{breaking, lint, rename as renaming, stdlib};
+
+const DECODE_USAGE: &str = "usage: xdr-ls decode --type <T> [options] <file>
+
+Decodes the XDR data in <file> (- for stdin) as an instance of <T>.
+
+options:
+    -t, --type <T>          type to decode
+    -s, --schema <path>     .x file or directory to load types from (repeatable; default: .)
+    -f, --format <fmt>      text (default) or json
+        --tree              with --format json, print the field tree with byte ranges
+                            instead of the plain value";
+
+const ENCODE_USAGE: &str = "usage: xdr-ls encode --type <T> [options] <file>
+
+Encodes the JSON document in <file> (- for stdin) as an instance of <T>.
+
+options:
+    -t, --type <T>          type to encode
+    -s, --schema <path>     .x file or directory to load types from (repeatable; default: .)
+    -o, --out <file>        write the encoding to <file> instead of stdout
+        --hex               write the encoding as hex text";
+
+const BREAKING_USAGE: &str = "usage: xdr-ls breaking [options] <old> <new>
+
+Reports wire-incompatible changes between the .x files under <old> and <new> (files or
+directories). Exits with a failure status if any are found.
+
+options:
+    -f, --format <fmt>      text (default) or json";
+
+const STATS_USAGE: &str = "usage: xdr-ls stats [options] [paths...]
+
+Summarizes the .x files under [paths] (default: .): definitions per kind, the largest types by
+wire size, the most-referenced names, names that aren't defined anywhere, and which files failed
+to parse.
+
+options:
+    -f, --format <fmt>      text (default) or json
+    -n, --top <n>           entries in each ranking (default: 10)";
+
+const RENAME_USAGE: &str = "usage: xdr-ls rename [options] <old> <new>
+
+Renames <old> to <new> everywhere it's used or defined, like the editor's rename.
+
+options:
+    -s, --schema <path>     .x file or directory to rename in (repeatable; default: .)
+        --in-comments       also rename whole-word mentions in comments and % lines
+    -n, --dry-run           print a unified diff instead of editing the files
+    -f, --format <fmt>      text (default) or json, listing the edits made (or that would be)";
+
+const RESOLVE_USAGE: &str = "usage: xdr-ls resolve [options] <T>
+
+Prints the definition of <T> fully expanded: typedefs followed, the types it refers to written
+inline, constants evaluated, and each declaration annotated with its wire size.
+
+options:
+    -s, --schema <path>     .x file or directory to load types from (repeatable; default: .)";
+
+const CHECK_USAGE: &str = "usage: xdr-ls check [options] [paths...]
+
+Reports the problems the language server would in the .x files under [paths] (default: .): syntax
+errors, undefined and duplicate names, union cases, and the rest of the lint rules. Exits with a
+failure status if there are errors.
+
+options:
+    -f, --format <fmt>      text (default), json, or sarif
+        --deny-warnings     fail on warnings too
+        --include-aware     check names against %#include directives (as includeAware)
+        --dialect <name>    xdrpp or rpcgen (default: detected for each file)
+        --target-version <v>
+                            report uses of definitions newer than <v> (as targetVersion)
+        --root-type <T>     report types <T> doesn't lead to (repeatable; as rootTypes)
+        --unhandled-cases   report unions leaving enum members unhandled (as unhandledCases)
+        --unused-exempt <re>
+                            don't report unused types and constants whose names match <re>
+                            (as unusedExempt)
+        --std               resolve names from the rpcgen standard library (as standardLibrary)";
+
+// Version of the reports printed with `--format json`, bumped when a change would break
+// consumers; adding fields doesn't
+const JSON_VERSION: u64 = 1;
+
+// Lines of context around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+// Reads a file, or stdin for `-`
+fn read_input(path: &str) -> Result<Vec<u8>, String> {
+    if path == "-" {
+        let mut bytes = vec![];
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("stdin: {err}"))?;
+        Ok(bytes)
+    } else {
+        fs::read(path).map_err(|err| format!("{path}: {err}"))
+    }
+}
+
+// Prints a report object with its schema version
+fn print_report(report: serde_json::Value) {
+    let mut object = serde_json::Map::new();
+    object.insert("version".to_string(), JSON_VERSION.into());
+    if let serde_json::Value::Object(fields) = report {
+        object.extend(fields);
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&object).unwrap_or_default()
+    );
+}
+
+fn print_tree(decoded: &Decoded, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let range = format!("[{}..{})", decoded.start, decoded.end);
+    if decoded.children.is_empty() {
+        println!(
+            "{}{}: {} = {}  {}",
+            indent, decoded.name, decoded.ty, decoded.value, range
+        );
+    } else {
+        println!("{}{}: {}  {}", indent, decoded.name, decoded.ty, range);
+        for child in &decoded.children {
+            print_tree(child, depth + 1);
+        }
+    }
+}
+
+pub fn decode(args: &[String]) -> ExitCode {
+    let mut ty: Option<String> = None;
+    let mut format = "text".to_string();
+    let mut tree = false;
+    let mut schema_paths: Vec<PathBuf> = vec![];
+    let mut input: Option<String> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-t" | "--type" => match iter.next() {
+                Some(name) => ty = Some(name.clone()),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-s" | "--schema" => match iter.next() {
+                Some(path) => schema_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-f" | "--format" => match iter.next() {
+                Some(name) if name == "text" || name == "json" => format = name.clone(),
+                _ => {
+                    eprintln!("{arg} requires text or json");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--tree" => tree = true,
+            "-h" | "--help" => {
+                println!("{DECODE_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            "-" => input = Some(arg.clone()),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{DECODE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => {
+                eprintln!("unexpected argument {arg}\n\n{DECODE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let (Some(ty), Some(input)) = (ty, input) else {
+        eprintln!("{DECODE_USAGE}");
+        return ExitCode::FAILURE;
+    };
+    if schema_paths.is_empty() {
+        schema_paths.push(PathBuf::from("."));
+    }
+
+    let bytes = match read_input(&input) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let files = load_files(&schema_paths, &ScanOptions::default());
+    let schema = Schema::new(&files);
+    if !schema.types.contains_key(ty.as_str()) {
+        eprintln!("unknown type `{ty}`");
+        return ExitCode::FAILURE;
+    }
+    let decoded = match codec::decode(&schema, &ty, &bytes) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            eprintln!("{input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if format == "json" {
+        let json = if tree {
+            decoded.to_json()
+        } else {
+            decoded.value
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json).unwrap_or_default()
+        );
+    } else {
+        print_tree(&decoded, 0);
+    }
+    ExitCode::SUCCESS
+}
+
+pub fn encode(args: &[String]) -> ExitCode {
+    let mut ty: Option<String> = None;
+    let mut out: Option<PathBuf> = None;
+    let mut hex = false;
+    let mut schema_paths: Vec<PathBuf> = vec![];
+    let mut input: Option<String> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-t" | "--type" => match iter.next() {
+                Some(name) => ty = Some(name.clone()),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-s" | "--schema" => match iter.next() {
+                Some(path) => schema_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-o" | "--out" => match iter.next() {
+                Some(path) => out = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--hex" => hex = true,
+            "-h" | "--help" => {
+                println!("{ENCODE_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            "-" => input = Some(arg.clone()),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{ENCODE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => {
+                eprintln!("unexpected argument {arg}\n\n{ENCODE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let (Some(ty), Some(input)) = (ty, input) else {
+        eprintln!("{ENCODE_USAGE}");
+        return ExitCode::FAILURE;
+    };
+    if schema_paths.is_empty() {
+        schema_paths.push(PathBuf::from("."));
+    }
+
+    let value: serde_json::Value = match read_input(&input)
+        .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| format!("{input}: {err}")))
+    {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let files = load_files(&schema_paths, &ScanOptions::default());
+    let schema = Schema::new(&files);
+    if !schema.types.contains_key(ty.as_str()) {
+        eprintln!("unknown type `{ty}`");
+        return ExitCode::FAILURE;
+    }
+    let mut bytes = match codec::encode(&schema, &ty, &value) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if hex {
+        bytes = (codec::to_hex(&bytes) + "\n").into_bytes();
+    }
+    let written = match &out {
+        Some(path) => fs::write(path, &bytes).map_err(|err| format!("{}: {}", path.display(), err)),
+        None => std::io::stdout()
+            .write_all(&bytes)
+            .map_err(|err| format!("stdout: {err}")),
+    };
+    if let Err(err) = written {
+        eprintln!("{err}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+pub fn breaking(args: &[String]) -> ExitCode {
+    let mut format = "text".to_string();
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-f" | "--format" => match iter.next() {
+                Some(name) if name == "text" || name == "json" => format = name.clone(),
+                _ => {
+                    eprintln!("{arg} requires text or json");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{BREAKING_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{BREAKING_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [old, new] = paths.as_slice() else {
+        eprintln!("{BREAKING_USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let old_files = load_files(std::slice::from_ref(old), &ScanOptions::default());
+    let new_files = load_files(std::slice::from_ref(new), &ScanOptions::default());
+    let issues = breaking::check(&Schema::new(&old_files), &Schema::new(&new_files));
+    if format == "json" {
+        let issues: Vec<serde_json::Value> = issues
+            .iter()
+            .map(|issue| {
+                serde_json::json!({
+                    "path": issue.path,
+                    "line": issue.line + 1,
+                    "message": issue.message,
+                })
+            })
+            .collect();
+        print_report(serde_json::json!({ "issues": issues }));
+    } else {
+        for issue in &issues {
+            println!("{issue}");
+        }
+    }
+    if issues.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// Orders wire sizes by maximum (unbounded is largest), then minimum
+fn size_key(size: &WireSize) -> (u64, u64) {
+    (size.max().unwrap_or(u64::MAX), size.min())
+}
+
+pub fn stats(args: &[String]) -> ExitCode {
+    let mut format = "text".to_string();
+    let mut top = 10;
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-f" | "--format" => match iter.next() {
+                Some(name) if name == "text" || name == "json" => format = name.clone(),
+                _ => {
+                    eprintln!("{arg} requires text or json");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-n" | "--top" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(n) => top = n,
+                None => {
+                    eprintln!("{arg} requires a number");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{STATS_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{STATS_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    let mut files: Vec<SourceFile> = vec![];
+    let mut parse_status = vec![];
+    for (path, file) in parse_files(&paths, &ScanOptions::default()) {
+        let error = file.as_ref().err().cloned();
+        parse_status.push(serde_json::json!({ "path": path, "error": error }));
+        if let Ok(file) = file {
+            files.push(file);
+        }
+    }
+    let schema = Schema::new(&files);
+
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut sizes = vec![];
+    let mut defined: HashSet<String> = HashSet::new();
+    for file in &files {
+        for defn in &file.spec.defns {
+            *kinds.entry(defn.keyword()).or_default() += 1;
+            if let (Some(id), Some(size)) = (defn.id(), schema.definition_size(defn)) {
+                sizes.push((id.id.as_str(), size));
+            }
+        }
+        visit_identifiers(&file.spec, &mut |id, is_defn| {
+            if is_defn {
+                defined.insert(id.id.clone());
+            }
+        });
+        for (id, _) in file.extension_definitions() {
+            defined.insert(id.id.clone());
+        }
+    }
+    sizes.sort_by(|a, b| size_key(&b.1).cmp(&size_key(&a.1)).then(a.0.cmp(b.0)));
+    sizes.truncate(top);
+
+    let mut references: HashMap<&str, usize> = HashMap::new();
+    let mut unresolved = vec![];
+    for file in &files {
+        let mut refs = vec![];
+        visit_references(&file.spec, &mut refs);
+        for (id, _) in refs {
+            *references.entry(&id.id).or_default() += 1;
+            // `TRUE` and `FALSE` are the case labels of bool unions
+            if !defined.contains(id.id.as_str()) && id.id != "TRUE" && id.id != "FALSE" {
+                let line = file.line_at(id.start) + 1;
+                unresolved.push((id.id.as_str(), &file.path, line));
+            }
+        }
+    }
+    let mut referenced: Vec<(&str, usize)> = references
+        .into_iter()
+        .filter(|(name, _)| defined.contains(*name))
+        .collect();
+    referenced.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    referenced.truncate(top);
+
+    if format == "json" {
+        print_report(serde_json::json!({
+            "definitions": kinds,
+            "largestTypes": sizes
+                .iter()
+                .map(|(name, size)| serde_json::json!({
+                    "name": name,
+                    "wireSize": { "min": size.min(), "max": size.max() },
+                }))
+                .collect::<Vec<_>>(),
+            "mostReferenced": referenced
+                .iter()
+                .map(|(name, count)| serde_json::json!({ "name": name, "references": count }))
+                .collect::<Vec<_>>(),
+            "unresolved": unresolved
+                .iter()
+                .map(|(name, path, line)| serde_json::json!({
+                    "name": name,
+                    "path": path,
+                    "line": line,
+                }))
+                .collect::<Vec<_>>(),
+            "files": parse_status,
+        }));
+    } else {
+        println!("definitions:");
+        for (kind, count) in &kinds {
+            println!("    {kind:<10}{count:>8}");
+        }
+        println!("largest types:");
+        for (name, size) in &sizes {
+            println!("    {name:<40}{size}");
+        }
+        println!("most referenced:");
+        for (name, count) in &referenced {
+            println!("    {name:<40}{count:>8}");
+        }
+        println!("unresolved names:");
+        for (name, path, line) in &unresolved {
+            println!("    {name:<40}{}:{line}", path.display());
+        }
+        let failed: Vec<_> = parse_status
+            .iter()
+            .filter_map(|status| status["error"].as_str())
+            .collect();
+        println!(
+            "files: {} parsed, {} failed",
+            parse_status.len() - failed.len(),
+            failed.len()
+        );
+        for error in failed {
+            println!("    {}", error.replace('\n', "\n    "));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+// A unified diff between two versions of a file with the same number of lines, as a rename
+// produces
+fn line_diff(path: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    let mut diff = format!("--- a/{path}\n+++ b/{path}\n");
+    let mut i = 0;
+    while i < changed.len() {
+        // Changes whose context overlaps share a hunk
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] <= 2 * DIFF_CONTEXT + 1 {
+            j += 1;
+        }
+        let start = changed[i].saturating_sub(DIFF_CONTEXT);
+        let end = (changed[j] + DIFF_CONTEXT + 1).min(old.len());
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start + 1,
+            end - start,
+            start + 1,
+            end - start
+        ));
+        for line in start..end {
+            if old[line] == new[line] {
+                diff.push_str(&format!(" {}\n", old[line]));
+            } else {
+                diff.push_str(&format!("-{}\n+{}\n", old[line], new[line]));
+            }
+        }
+        i = j + 1;
+    }
+    diff
+}
+
+// Writes each file to a temporary file next to it, then renames them all over the originals,
+// so a failed write leaves every file untouched
+fn write_all(files: &[(PathBuf, String)]) -> Result<(), String> {
+    let temp = |path: &PathBuf| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!(".{name}.xdr-ls-tmp"))
+    };
+    for (i, (path, text)) in files.iter().enumerate() {
+        if let Err(err) = fs::write(temp(path), text) {
+            for (path, _) in &files[..=i] {
+                let _ = fs::remove_file(temp(path));
+            }
+            return Err(format!("{}: {}", path.display(), err));
+        }
+    }
+    for (path, _) in files {
+        fs::rename(temp(path), path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    Ok(())
+}
+
+pub fn rename(args: &[String]) -> ExitCode {
+    let mut schema_paths: Vec<PathBuf> = vec![];
+    let mut in_comments = false;
+    let mut dry_run = false;
+    let mut format = "text".to_string();
+    let mut names: Vec<String> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-s" | "--schema" => match iter.next() {
+                Some(path) => schema_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--in-comments" => in_comments = true,
+            "-n" | "--dry-run" => dry_run = true,
+            "-f" | "--format" => match iter.next() {
+                Some(name) if name == "text" || name == "json" => format = name.clone(),
+                _ => {
+                    eprintln!("{arg} requires text or json");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{RENAME_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{RENAME_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ => names.push(arg.clone()),
+        }
+    }
+    let [old, new] = names.as_slice() else {
+        eprintln!("{RENAME_USAGE}");
+        return ExitCode::FAILURE;
+    };
+    if !renaming::is_valid_name(new) {
+        eprintln!("`{new}` is not a valid identifier");
+        return ExitCode::FAILURE;
+    }
+    if schema_paths.is_empty() {
+        schema_paths.push(PathBuf::from("."));
+    }
+
+    // A file that doesn't parse can't be renamed in, and renaming everywhere else would leave
+    // the schema inconsistent
+    let mut files = vec![];
+    let mut failed = false;
+    for (_, file) in parse_files(&schema_paths, &ScanOptions::default()) {
+        match file {
+            Ok(file) => files.push(file),
+            Err(err) => {
+                eprintln!("{err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+
+    let mut changed = vec![];
+    for file in &files {
+        let mut ranges = vec![];
+        visit_identifiers(&file.spec, &mut |id, _| {
+            if id.id == *old {
+                ranges.push((id.start, id.end));
+            }
+        });
+        for (id, _) in file.extension_definitions() {
+            if id.id == *old {
+                ranges.push((id.start, id.end));
+            }
+        }
+        if in_comments {
+            ranges.extend(
+                renaming::comment_occurrences(&file.text, old, Encoding::Utf8)
+                    .into_iter()
+                    .map(|range| {
+                        (
+                            file.offset_at(range.start, Encoding::Utf8),
+                            file.offset_at(range.end, Encoding::Utf8),
+                        )
+                    }),
+            );
+        }
+        if ranges.is_empty() {
+            continue;
+        }
+        ranges.sort();
+        ranges.dedup();
+        let mut text = file.text.clone();
+        for &(start, end) in ranges.iter().rev() {
+            text.replace_range(start..end, new);
+        }
+        changed.push((file, ranges, text));
+    }
+    if changed.is_empty() {
+        eprintln!("`{old}` isn't used or defined anywhere");
+        return ExitCode::FAILURE;
+    }
+
+    if format == "json" {
+        let files: Vec<serde_json::Value> = changed
+            .iter()
+            .map(|(file, ranges, _)| {
+                let edits: Vec<serde_json::Value> = ranges
+                    .iter()
+                    .map(|&(start, end)| {
+                        let pos = file.position_at(start, Encoding::Utf8);
+                        serde_json::json!({
+                            "line": pos.line + 1,
+                            "column": pos.character + 1,
+                            "length": end - start,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({ "path": file.path, "edits": edits })
+            })
+            .collect();
+        print_report(serde_json::json!({
+            "old": old,
+            "new": new,
+            "dryRun": dry_run,
+            "files": files,
+        }));
+    } else if dry_run {
+        for (file, _, text) in &changed {
+            let path = file.path.display().to_string();
+            print!("{}", line_diff(&path, &file.text, text));
+        }
+    }
+    if !dry_run {
+        let changed: Vec<(PathBuf, String)> = changed
+            .into_iter()
+            .map(|(file, _, text)| (file.path.clone(), text))
+            .collect();
+        if let Err(err) = write_all(&changed) {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+pub fn resolve(args: &[String]) -> ExitCode {
+    let mut ty: Option<String> = None;
+    let mut schema_paths: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-s" | "--schema" => match iter.next() {
+                Some(path) => schema_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{RESOLVE_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{RESOLVE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ if ty.is_none() => ty = Some(arg.clone()),
+            _ => {
+                eprintln!("unexpected argument {arg}\n\n{RESOLVE_USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(ty) = ty else {
+        eprintln!("{RESOLVE_USAGE}");
+        return ExitCode::FAILURE;
+    };
+    if schema_paths.is_empty() {
+        schema_paths.push(PathBuf::from("."));
+    }
+
+    let files = load_files(&schema_paths, &ScanOptions::default());
+    let schema = Schema::new(&files);
+    match expand(&schema, &ty) {
+        Some(text) => {
+            print!("{text}");
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("unknown type `{ty}`");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// A diagnostic from `check`, located for printing
+struct Problem {
+    path: String,
+    // 1-based, with columns counting characters
+    line: usize,
+    column: usize,
+    end_line: usize,
+    end_column: usize,
+    severity: &'static str,
+    code: String,
+    message: String,
+    // Where the diagnostic links to: (path, line, column, message)
+    related: Vec<(String, usize, usize, String)>,
+}
+
+// How to show `path`: relative to the current directory if it's inside it
+fn display_path(path: &Path) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    path.strip_prefix(&cwd)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The 1-based line and character column of `pos` in `text`
+fn line_column(text: &str, pos: Position) -> (usize, usize) {
+    let line = text.lines().nth(pos.line as usize).unwrap_or_default();
+    let offset = offset_at(line, Position::new(0, pos.character), Encoding::Utf8);
+    (pos.line as usize + 1, line[..offset].chars().count() + 1)
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        _ => "hint",
+    }
+}
+
+fn sarif_report(problems: &[Problem]) -> serde_json::Value {
+    let rules: BTreeSet<&str> = problems
+        .iter()
+        .map(|problem| problem.code.as_str())
+        .collect();
+    let results: Vec<serde_json::Value> = problems
+        .iter()
+        .map(|problem| {
+            serde_json::json!({
+                "ruleId": problem.code,
+                "level": match problem.severity {
+                    "error" => "error",
+                    "warning" => "warning",
+                    _ => "note",
+                },
+                "message": { "text": problem.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": problem.path },
+                        "region": {
+                            "startLine": problem.line,
+                            "startColumn": problem.column,
+                            "endLine": problem.end_line,
+                            "endColumn": problem.end_column,
+                        },
+                    },
+                }],
+                "relatedLocations": problem.related.iter().map(|(path, line, column, message)| {
+                    serde_json::json!({
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": path },
+                            "region": { "startLine": line, "startColumn": column },
+                        },
+                        "message": { "text": message },
+                    })
+                }).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "xdr-ls",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|id| serde_json::json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+// `xdr-ls check`, with `extra_rules` run alongside the built-in ones
+pub fn check(args: &[String], extra_rules: Vec<Box<dyn lint::LintRule>>) -> ExitCode {
+    let mut format = "text".to_string();
+    let mut deny_warnings = false;
+    let mut include_aware = false;
+    let mut dialect = None;
+    let mut target_version = None;
+    let mut root_types = vec![];
+    let mut unhandled_cases = false;
+    let mut unused_exempt = None;
+    let mut with_library = false;
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-f" | "--format" => match iter.next() {
+                Some(name) if ["text", "json", "sarif"].contains(&name.as_str()) => {
+                    format = name.clone()
+                }
+                _ => {
+                    eprintln!("{arg} requires text, json, or sarif");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--deny-warnings" => deny_warnings = true,
+            "--include-aware" => include_aware = true,
+            "--dialect" => match iter.next().and_then(|name| Dialect::from_name(name)) {
+                Some(name) => dialect = Some(name),
+                None => {
+                    eprintln!("{arg} requires xdrpp or rpcgen");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--target-version" => match iter.next().and_then(|v| ProtocolVersion::parse(v)) {
+                Some(version) => target_version = Some(version),
+                None => {
+                    eprintln!("{arg} requires a version like 1.2");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--root-type" => match iter.next() {
+                Some(ty) => root_types.push(ty.clone()),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--unhandled-cases" => unhandled_cases = true,
+            "--unused-exempt" => match iter.next().map(|re| Regex::new(re)) {
+                Some(Ok(re)) => unused_exempt = Some(re),
+                Some(Err(err)) => {
+                    eprintln!("{arg}: {err}");
+                    return ExitCode::FAILURE;
+                }
+                None => {
+                    eprintln!("{arg} requires a regular expression");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--std" => with_library = true,
+            "-h" | "--help" => {
+                println!("{CHECK_USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{CHECK_USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    // Absolute paths, as the server has, so diagnostics can link to other files
+    let mut found: Vec<PathBuf> = vec![];
+    for path in &paths {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        if path.is_dir() {
+            let scan = ScanOptions::default();
+            for warning in get_xdr_files(&path, &scan, &mut |path| found.push(path.clone())) {
+                eprintln!("{warning}");
+            }
+        } else {
+            found.push(path);
+        }
+    }
+    found.sort();
+    found.dedup();
+    if with_library {
+        match stdlib::write() {
+            Ok(path) => found.insert(0, path),
+            Err(err) => eprintln!("could not write the standard library: {err}"),
+        }
+    }
+
+    let mut unreadable = 0;
+    let mut texts: HashMap<PathBuf, String> = HashMap::new();
+    let mut files = vec![];
+    let mut diagnostics: Vec<(PathBuf, Diagnostic)> = vec![];
+    for path in found {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("{}: {err}", display_path(&path));
+                unreadable += 1;
+                continue;
+            }
+        };
+        texts.insert(path.clone(), text.clone());
+        if let Some(diagnostic) = lint::syntax_error(&text, Encoding::Utf8) {
+            diagnostics.push((path.clone(), diagnostic));
+        }
+        if let Some(file) = SourceFile::parse_recovering(path, text) {
+            files.push(file);
+        }
+    }
+    let root = std::env::current_dir().unwrap_or_default();
+    let workspace = lint::ResolvedWorkspace::new(&root, &files, Encoding::Utf8);
+    let mut rules = lint::Registry::builtin(
+        include_aware,
+        dialect,
+        target_version,
+        root_types,
+        unhandled_cases,
+        unused_exempt,
+    );
+    for rule in extra_rules {
+        rules.register(rule);
+    }
+    for (path, found) in rules.run(&workspace) {
+        diagnostics.extend(
+            found
+                .into_iter()
+                .map(|diagnostic| (path.clone(), diagnostic)),
+        );
+    }
+
+    let locate = |path: &Path, pos: Position| match texts.get(path) {
+        Some(text) => line_column(text, pos),
+        None => (pos.line as usize + 1, pos.character as usize + 1),
+    };
+    let mut problems: Vec<Problem> = diagnostics
+        .into_iter()
+        .filter(|(path, _)| !stdlib::is_library(path))
+        .map(|(path, diagnostic)| {
+            let (line, column) = locate(&path, diagnostic.range.start);
+            let (end_line, end_column) = locate(&path, diagnostic.range.end);
+            let related = diagnostic
+                .related_information
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|info| {
+                    let path = info.location.uri.to_file_path().ok()?;
+                    let (line, column) = locate(&path, info.location.range.start);
+                    Some((display_path(&path), line, column, info.message))
+                })
+                .collect();
+            Problem {
+                path: display_path(&path),
+                line,
+                column,
+                end_line,
+                end_column,
+                severity: severity_name(diagnostic.severity),
+                code: match diagnostic.code {
+                    Some(NumberOrString::String(code)) => code,
+                    Some(NumberOrString::Number(code)) => code.to_string(),
+                    None => String::new(),
+                },
+                message: diagnostic.message,
+                related,
+            }
+        })
+        .collect();
+    problems.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    let count = |severity| {
+        problems
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count("error") + unreadable, count("warning"));
+
+    match format.as_str() {
+        "json" => {
+            let problems: Vec<serde_json::Value> = problems
+                .iter()
+                .map(|problem| {
+                    serde_json::json!({
+                        "path": problem.path,
+                        "line": problem.line,
+                        "column": problem.column,
+                        "endLine": problem.end_line,
+                        "endColumn": problem.end_column,
+                        "severity": problem.severity,
+                        "code": problem.code,
+                        "message": problem.message,
+                        "related": problem.related.iter().map(|(path, line, column, message)| {
+                            serde_json::json!({
+                                "path": path,
+                                "line": line,
+                                "column": column,
+                                "message": message,
+                            })
+                        }).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            print_report(serde_json::json!({
+                "diagnostics": problems,
+                "errors": errors,
+                "warnings": warnings,
+            }));
+        }
+        "sarif" => println!(
+            "{}",
+            serde_json::to_string_pretty(&sarif_report(&problems)).unwrap_or_default()
+        ),
+        _ => {
+            for problem in &problems {
+                println!(
+                    "{}:{}:{}: {}[{}]: {}",
+                    problem.path,
+                    problem.line,
+                    problem.column,
+                    problem.severity,
+                    problem.code,
+                    problem.message
+                );
+                for (path, line, column, message) in &problem.related {
+                    println!("    {path}:{line}:{column}: note: {message}");
+                }
+            }
+            if !problems.is_empty() {
+                eprintln!("{errors} errors, {warnings} warnings");
+            }
+        }
+    }
+    if errors > 0 || (deny_warnings && warnings > 0) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,823 @@
+// Schema-directed XDR encoding and decoding of JSON values
+//
+// Values are represented as JSON: numbers for integers and floats, booleans for `bool`, member
+// names for enums, strings for `string`, hex strings for opaque data (and `quadruple`), arrays
+// for arrays, `null` for an absent optional, and objects for structs. Unions are objects holding
+// the discriminant and (unless the arm is void) the arm, keyed by their declared names.
+use std::fmt;
+
+use serde_json::Value as Json;
+
+use crate::ast::*;
+use crate::codegen::Schema;
+
+#[derive(Debug)]
+pub struct CodecError {
+    // Dotted path to the offending value (e.g. `res.resok.data[3]`)
+    pub path: String,
+    pub message: String,
+    // Byte offset into the input (decoding only)
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(offset) = self.offset {
+            write!(f, " (at byte {offset})")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+pub fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b'=')
+    {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// Binary data given as either hex or base64 (hex wins when a string is valid as both)
+pub fn from_blob(text: &str) -> Option<Vec<u8>> {
+    from_hex(text).or_else(|| from_base64(text))
+}
+
+// Encodes `value` as an instance of the named type
+pub fn encode(schema: &Schema, ty: &str, value: &Json) -> Result<Vec<u8>, CodecError> {
+    let mut encoder = Encoder {
+        schema,
+        out: vec![],
+    };
+    encoder.named(ty, value, "")?;
+    Ok(encoder.out)
+}
+
+struct Encoder<'a, 'b> {
+    schema: &'b Schema<'a>,
+    out: Vec<u8>,
+}
+
+fn err<T>(path: &str, message: impl Into<String>) -> Result<T, CodecError> {
+    Err(CodecError {
+        path: path.to_string(),
+        message: message.into(),
+        offset: None,
+    })
+}
+
+fn field_path(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{path}.{field}")
+    }
+}
+
+impl Encoder<'_, '_> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+        let pad = (4 - bytes.len() % 4) % 4;
+        self.out.extend(std::iter::repeat_n(0, pad));
+    }
+
+    fn count(&self, size: &Value, path: &str) -> Result<usize, CodecError> {
+        match self.schema.value(size).map(usize::try_from) {
+            Some(Ok(size)) => Ok(size),
+            _ => err(path, "could not evaluate size"),
+        }
+    }
+
+    fn bound(&self, size: &Option<Value>, len: usize, path: &str) -> Result<(), CodecError> {
+        if let Some(size) = size {
+            let max = self.count(size, path)?;
+            if len > max {
+                return err(path, format!("length {len} exceeds bound {max}"));
+            }
+        }
+        Ok(())
+    }
+
+    fn named(&mut self, name: &str, v: &Json, path: &str) -> Result<(), CodecError> {
+        match self.schema.types.get(name) {
+            Some(Definition::TypeDef(decl)) => self.decl(decl, v, path),
+            Some(Definition::Enum { body, .. }) => self.enumeration(body, v, path),
+            Some(Definition::Struct { body, .. }) => self.structure(body, v, path),
+            Some(Definition::Union { body, .. }) => self.union(body, v, path),
+            _ => err(path, format!("unknown type `{name}`")),
+        }
+    }
+
+    fn decl(&mut self, decl: &Declaration, v: &Json, path: &str) -> Result<(), CodecError> {
+        match decl {
+            Declaration::Normal { spec, .. } => self.ty(spec, v, path),
+            Declaration::FixedArr { spec, size, .. } => {
+                let len = self.count(size, path)?;
+                let Some(items) = v.as_array() else {
+                    return err(path, "expected an array");
+                };
+                if items.len() != len {
+                    return err(
+                        path,
+                        format!("expected {} elements, got {}", len, items.len()),
+                    );
+                }
+                for (i, item) in items.iter().enumerate() {
+                    self.ty(spec, item, &format!("{path}[{i}]"))?;
+                }
+                Ok(())
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                let Some(items) = v.as_array() else {
+                    return err(path, "expected an array");
+                };
+                self.bound(size, items.len(), path)?;
+                self.out
+                    .extend_from_slice(&(items.len() as u32).to_be_bytes());
+                for (i, item) in items.iter().enumerate() {
+                    self.ty(spec, item, &format!("{path}[{i}]"))?;
+                }
+                Ok(())
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                let len = self.count(size, path)?;
+                let Some(bytes) = v.as_str().and_then(from_hex) else {
+                    return err(path, "expected a hex string");
+                };
+                if bytes.len() != len {
+                    return err(path, format!("expected {} bytes, got {}", len, bytes.len()));
+                }
+                self.bytes(&bytes);
+                Ok(())
+            }
+            Declaration::VarOpaque { size, .. } => {
+                let Some(bytes) = v.as_str().and_then(from_hex) else {
+                    return err(path, "expected a hex string");
+                };
+                self.bound(size, bytes.len(), path)?;
+                self.out
+                    .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+                self.bytes(&bytes);
+                Ok(())
+            }
+            Declaration::String { size, .. } => {
+                let Some(s) = v.as_str() else {
+                    return err(path, "expected a string");
+                };
+                self.bound(size, s.len(), path)?;
+                self.out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                self.bytes(s.as_bytes());
+                Ok(())
+            }
+            Declaration::Optional { spec, .. } => {
+                if v.is_null() {
+                    self.out.extend_from_slice(&0u32.to_be_bytes());
+                    Ok(())
+                } else {
+                    self.out.extend_from_slice(&1u32.to_be_bytes());
+                    self.ty(spec, v, path)
+                }
+            }
+            Declaration::VOID => Ok(()),
+        }
+    }
+
+    fn ty(&mut self, spec: &TypeSpecifier, v: &Json, path: &str) -> Result<(), CodecError> {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => self.builtin(name, v, path),
+            TypeSpecifier::Ident(id) => self.named(&id.id, v, path),
+            TypeSpecifier::Enum(body) => self.enumeration(body, v, path),
+            TypeSpecifier::Struct(body) => self.structure(body, v, path),
+            TypeSpecifier::Union(body) => self.union(body, v, path),
+        }
+    }
+
+    fn builtin(&mut self, name: &str, v: &Json, path: &str) -> Result<(), CodecError> {
+        match name {
+            "int" => match v.as_i64().and_then(|v| i32::try_from(v).ok()) {
+                Some(v) => self.out.extend_from_slice(&v.to_be_bytes()),
+                None => return err(path, "expected a 32-bit signed integer"),
+            },
+            "unsigned int" => match v.as_u64().and_then(|v| u32::try_from(v).ok()) {
+                Some(v) => self.out.extend_from_slice(&v.to_be_bytes()),
+                None => return err(path, "expected a 32-bit unsigned integer"),
+            },
+            "hyper" => match v.as_i64() {
+                Some(v) => self.out.extend_from_slice(&v.to_be_bytes()),
+                None => return err(path, "expected a 64-bit signed integer"),
+            },
+            "unsigned hyper" => match v.as_u64() {
+                Some(v) => self.out.extend_from_slice(&v.to_be_bytes()),
+                None => return err(path, "expected a 64-bit unsigned integer"),
+            },
+            "float" => match v.as_f64() {
+                Some(v) => self.out.extend_from_slice(&(v as f32).to_be_bytes()),
+                None => return err(path, "expected a number"),
+            },
+            "double" => match v.as_f64() {
+                Some(v) => self.out.extend_from_slice(&v.to_be_bytes()),
+                None => return err(path, "expected a number"),
+            },
+            "bool" => match v.as_bool() {
+                Some(v) => self.out.extend_from_slice(&(v as u32).to_be_bytes()),
+                None => return err(path, "expected a boolean"),
+            },
+            _ => match v.as_str().and_then(from_hex) {
+                Some(bytes) if bytes.len() == 16 => self.out.extend_from_slice(&bytes),
+                _ => return err(path, "expected 16 bytes of hex for a quadruple"),
+            },
+        }
+        Ok(())
+    }
+
+    // The numeric value of an enum given either a member name or a number
+    fn enum_value(&self, body: &EnumBody, v: &Json, path: &str) -> Result<i64, CodecError> {
+        let found = body.body.iter().find_map(|assign| {
+            let val = self.schema.value(&assign.val)?;
+            let matches = match v {
+                Json::String(name) => *name == assign.id.id,
+                _ => v.as_i64() == Some(val),
+            };
+            matches.then_some(val)
+        });
+        match found {
+            Some(val) => Ok(val),
+            None => err(path, format!("{v} is not a member of the enum")),
+        }
+    }
+
+    fn enumeration(&mut self, body: &EnumBody, v: &Json, path: &str) -> Result<(), CodecError> {
+        let val = self.enum_value(body, v, path)?;
+        self.out.extend_from_slice(&(val as i32).to_be_bytes());
+        Ok(())
+    }
+
+    fn structure(&mut self, body: &StructBody, v: &Json, path: &str) -> Result<(), CodecError> {
+        let Some(obj) = v.as_object() else {
+            return err(path, "expected an object");
+        };
+        for key in obj.keys() {
+            if !body
+                .body
+                .iter()
+                .any(|decl| decl.id().is_some_and(|id| id.id == *key))
+            {
+                return err(path, format!("unknown field `{key}`"));
+            }
+        }
+        for decl in &body.body {
+            let Some(id) = decl.id() else {
+                continue;
+            };
+            let path = field_path(path, &id.id);
+            match obj.get(&id.id) {
+                Some(field) => self.decl(decl, field, &path)?,
+                None => return err(&path, "missing field"),
+            }
+        }
+        Ok(())
+    }
+
+    // The numeric value of a discriminant
+    fn discriminant(&self, decl: &Declaration, v: &Json, path: &str) -> Result<i64, CodecError> {
+        if let Declaration::Normal { spec, .. } = decl {
+            match self.schema.resolve(spec) {
+                TypeSpecifier::Enum(body) => return self.enum_value(body, v, path),
+                TypeSpecifier::Ident(id) => {
+                    if let Some(Definition::Enum { body, .. }) =
+                        self.schema.types.get(id.id.as_str())
+                    {
+                        return self.enum_value(body, v, path);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match v {
+            Json::Bool(b) => Ok(*b as i64),
+            _ => match v.as_i64() {
+                Some(v) => Ok(v),
+                None => err(path, "expected an integer discriminant"),
+            },
+        }
+    }
+
+    fn union(&mut self, body: &UnionBody, v: &Json, path: &str) -> Result<(), CodecError> {
+        let Some(obj) = v.as_object() else {
+            return err(path, "expected an object");
+        };
+        let Some(disc_id) = body.discriminant.id() else {
+            return err(path, "union has no discriminant");
+        };
+        let disc_path = field_path(path, &disc_id.id);
+        let Some(disc) = obj.get(&disc_id.id) else {
+            return err(&disc_path, "missing discriminant");
+        };
+        self.decl(&body.discriminant, disc, &disc_path)?;
+        let val = self.discriminant(&body.discriminant, disc, &disc_path)?;
+        let arm = body
+            .cases
+            .iter()
+            .find(|case| {
+                case.values
+                    .iter()
+                    .any(|v| self.schema.case_value(v) == Some(val))
+            })
+            .map(|case| &case.decl)
+            .or(body.default.as_deref());
+        let Some(arm) = arm else {
+            return err(&disc_path, format!("no arm for discriminant {disc}"));
+        };
+        let expected = arm.id().map(|id| id.id.as_str());
+        for key in obj.keys() {
+            if *key != disc_id.id && Some(key.as_str()) != expected {
+                return err(path, format!("unexpected field `{key}` for this arm"));
+            }
+        }
+        match arm.id() {
+            Some(id) => {
+                let path = field_path(path, &id.id);
+                match obj.get(&id.id) {
+                    Some(field) => self.decl(arm, field, &path),
+                    None => err(&path, "missing arm"),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// A decoded value along with the bytes it was decoded from
+#[derive(Debug)]
+pub struct Decoded {
+    // Field name (or `[i]` for array elements)
+    pub name: String,
+    // The XDR type as it would be written in a declaration (e.g. `opaque<32>` or `point*`)
+    pub ty: String,
+    // The value in the same JSON representation accepted by `encode`
+    pub value: Json,
+    // Byte range within the input, including any padding
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Decoded>,
+}
+
+impl Decoded {
+    // The field tree, with values only on the leaves (compound values are spelled out by their
+    // children)
+    pub fn to_json(&self) -> Json {
+        let mut obj = serde_json::Map::new();
+        obj.insert("name".to_string(), Json::from(self.name.clone()));
+        obj.insert("type".to_string(), Json::from(self.ty.clone()));
+        obj.insert("start".to_string(), Json::from(self.start));
+        obj.insert("end".to_string(), Json::from(self.end));
+        if self.children.is_empty() {
+            obj.insert("value".to_string(), self.value.clone());
+        } else {
+            let children = self.children.iter().map(Decoded::to_json).collect();
+            obj.insert("children".to_string(), Json::Array(children));
+        }
+        Json::Object(obj)
+    }
+}
+
+// Past this nesting depth decoding fails rather than risking the stack (e.g. on a typedef cycle)
+const MAX_DEPTH: usize = 256;
+
+// Decodes `bytes` as a single instance of the named type; trailing bytes are an error
+pub fn decode(schema: &Schema, ty: &str, bytes: &[u8]) -> Result<Decoded, CodecError> {
+    let mut decoder = Decoder {
+        schema,
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    let decoded = decoder.named(ty, ty, "")?;
+    if decoder.pos < bytes.len() {
+        return Err(CodecError {
+            path: String::new(),
+            message: format!(
+                "{} trailing bytes after `{}`",
+                bytes.len() - decoder.pos,
+                ty
+            ),
+            offset: Some(decoder.pos),
+        });
+    }
+    Ok(decoded)
+}
+
+struct Decoder<'a, 'b, 'c> {
+    schema: &'b Schema<'a>,
+    bytes: &'c [u8],
+    pos: usize,
+    depth: usize,
+}
+
+fn size_text(size: &Value) -> String {
+    match size {
+        Value::Id(id) => id.id.clone(),
+        Value::Const(val) => val.clone(),
+    }
+}
+
+fn spec_text(spec: &TypeSpecifier) -> String {
+    match spec {
+        TypeSpecifier::BuiltIn(name) => name.clone(),
+        TypeSpecifier::Ident(id) => id.id.clone(),
+        TypeSpecifier::Enum(_) => "enum".to_string(),
+        TypeSpecifier::Struct(_) => "struct".to_string(),
+        TypeSpecifier::Union(_) => "union".to_string(),
+    }
+}
+
+fn element_path(path: &str, i: usize) -> String {
+    format!("{path}[{i}]")
+}
+
+impl<'c> Decoder<'_, '_, 'c> {
+    fn err<T>(
+        &self,
+        path: &str,
+        offset: usize,
+        message: impl Into<String>,
+    ) -> Result<T, CodecError> {
+        Err(CodecError {
+            path: path.to_string(),
+            message: message.into(),
+            offset: Some(offset),
+        })
+    }
+
+    fn take(&mut self, n: usize, path: &str) -> Result<&'c [u8], CodecError> {
+        let bytes: &'c [u8] = self.bytes;
+        match self.pos.checked_add(n).filter(|end| *end <= bytes.len()) {
+            Some(end) => {
+                let taken = &bytes[self.pos..end];
+                self.pos = end;
+                Ok(taken)
+            }
+            None => self.err(
+                path,
+                self.pos,
+                format!(
+                    "unexpected end of data: need {} bytes, {} left",
+                    n,
+                    bytes.len() - self.pos
+                ),
+            ),
+        }
+    }
+
+    fn word(&mut self, path: &str) -> Result<[u8; 4], CodecError> {
+        let bytes = self.take(4, path)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn u32(&mut self, path: &str) -> Result<u32, CodecError> {
+        Ok(u32::from_be_bytes(self.word(path)?))
+    }
+
+    // Opaque data of the given length followed by zero padding to a multiple of 4
+    fn opaque(&mut self, len: usize, path: &str) -> Result<&'c [u8], CodecError> {
+        let data = self.take(len, path)?;
+        let start = self.pos;
+        let pad = self.take((4 - len % 4) % 4, path)?;
+        if pad.iter().any(|b| *b != 0) {
+            return self.err(path, start, "non-zero padding");
+        }
+        Ok(data)
+    }
+
+    fn count(&self, size: &Value, path: &str) -> Result<usize, CodecError> {
+        match self.schema.value(size).map(usize::try_from) {
+            Some(Ok(size)) => Ok(size),
+            _ => self.err(
+                path,
+                self.pos,
+                format!("could not evaluate size {}", size_text(size)),
+            ),
+        }
+    }
+
+    // Reads a length prefix and checks it against the declared bound
+    fn len(&mut self, size: &Option<Value>, path: &str) -> Result<usize, CodecError> {
+        let start = self.pos;
+        let len = self.u32(path)? as usize;
+        if let Some(size) = size {
+            let max = self.count(size, path)?;
+            if len > max {
+                return self.err(path, start, format!("length {len} exceeds bound {max}"));
+            }
+        }
+        Ok(len)
+    }
+
+    fn leaf(&self, name: &str, ty: String, value: Json, start: usize) -> Decoded {
+        Decoded {
+            name: name.to_string(),
+            ty,
+            value,
+            start,
+            end: self.pos,
+            children: vec![],
+        }
+    }
+
+    fn named(&mut self, name: &str, label: &str, path: &str) -> Result<Decoded, CodecError> {
+        if self.depth >= MAX_DEPTH {
+            return self.err(path, self.pos, "nesting too deep");
+        }
+        self.depth += 1;
+        let decoded = match self.schema.types.get(name) {
+            Some(Definition::TypeDef(decl)) => self.decl(decl, label, path),
+            Some(Definition::Enum { body, .. }) => self.enumeration(body, label, path),
+            Some(Definition::Struct { body, .. }) => self.structure(body, label, path),
+            Some(Definition::Union { body, .. }) => self.union(body, label, path),
+            _ => self.err(path, self.pos, format!("unknown type `{name}`")),
+        };
+        self.depth -= 1;
+        let mut decoded = decoded?;
+        decoded.ty = name.to_string();
+        Ok(decoded)
+    }
+
+    fn elements(
+        &mut self,
+        spec: &TypeSpecifier,
+        count: usize,
+        path: &str,
+    ) -> Result<Vec<Decoded>, CodecError> {
+        let mut elements = vec![];
+        for i in 0..count {
+            let path = element_path(path, i);
+            elements.push(self.ty(spec, &format!("[{i}]"), &path)?);
+        }
+        Ok(elements)
+    }
+
+    fn decl(&mut self, decl: &Declaration, label: &str, path: &str) -> Result<Decoded, CodecError> {
+        let start = self.pos;
+        match decl {
+            Declaration::Normal { spec, .. } => self.ty(spec, label, path),
+            Declaration::FixedArr { spec, size, .. } => {
+                let count = self.count(size, path)?;
+                let children = self.elements(spec, count, path)?;
+                let ty = format!("{}[{}]", spec_text(spec), size_text(size));
+                Ok(self.compound(label, ty, children, start, Json::Array))
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                let count = self.len(size, path)?;
+                let children = self.elements(spec, count, path)?;
+                let bound = size.as_ref().map(size_text).unwrap_or_default();
+                let ty = format!("{}<{}>", spec_text(spec), bound);
+                Ok(self.compound(label, ty, children, start, Json::Array))
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                let len = self.count(size, path)?;
+                let data = self.opaque(len, path)?;
+                let ty = format!("opaque[{}]", size_text(size));
+                Ok(self.leaf(label, ty, Json::from(to_hex(data)), start))
+            }
+            Declaration::VarOpaque { size, .. } => {
+                let len = self.len(size, path)?;
+                let data = self.opaque(len, path)?;
+                let bound = size.as_ref().map(size_text).unwrap_or_default();
+                let ty = format!("opaque<{bound}>");
+                Ok(self.leaf(label, ty, Json::from(to_hex(data)), start))
+            }
+            Declaration::String { size, .. } => {
+                let len = self.len(size, path)?;
+                let data = self.opaque(len, path)?;
+                let bound = size.as_ref().map(size_text).unwrap_or_default();
+                let ty = format!("string<{bound}>");
+                let value = Json::from(String::from_utf8_lossy(data).into_owned());
+                Ok(self.leaf(label, ty, value, start))
+            }
+            Declaration::Optional { spec, .. } => {
+                let ty = format!("{}*", spec_text(spec));
+                match self.u32(path)? {
+                    0 => Ok(self.leaf(label, ty, Json::Null, start)),
+                    1 => {
+                        let mut inner = self.ty(spec, label, path)?;
+                        inner.ty = ty;
+                        inner.start = start;
+                        Ok(inner)
+                    }
+                    flag => self.err(path, start, format!("invalid optional flag {flag}")),
+                }
+            }
+            Declaration::VOID => Ok(self.leaf(label, "void".to_string(), Json::Null, start)),
+        }
+    }
+
+    fn compound(
+        &self,
+        label: &str,
+        ty: String,
+        children: Vec<Decoded>,
+        start: usize,
+        wrap: fn(Vec<Json>) -> Json,
+    ) -> Decoded {
+        let value = wrap(children.iter().map(|child| child.value.clone()).collect());
+        Decoded {
+            name: label.to_string(),
+            ty,
+            value,
+            start,
+            end: self.pos,
+            children,
+        }
+    }
+
+    fn ty(&mut self, spec: &TypeSpecifier, label: &str, path: &str) -> Result<Decoded, CodecError> {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => self.builtin(name, label, path),
+            TypeSpecifier::Ident(id) => self.named(&id.id, label, path),
+            TypeSpecifier::Enum(body) => self.enumeration(body, label, path),
+            TypeSpecifier::Struct(body) => self.structure(body, label, path),
+            TypeSpecifier::Union(body) => self.union(body, label, path),
+        }
+    }
+
+    fn builtin(&mut self, name: &str, label: &str, path: &str) -> Result<Decoded, CodecError> {
+        let start = self.pos;
+        let value = match name {
+            "int" => Json::from(i32::from_be_bytes(self.word(path)?)),
+            "unsigned int" => Json::from(self.u32(path)?),
+            "hyper" | "unsigned hyper" | "double" => {
+                let bytes = self.take(8, path)?;
+                let mut word = [0; 8];
+                word.copy_from_slice(bytes);
+                match name {
+                    "hyper" => Json::from(i64::from_be_bytes(word)),
+                    "unsigned hyper" => Json::from(u64::from_be_bytes(word)),
+                    _ => Json::from(f64::from_be_bytes(word)),
+                }
+            }
+            "float" => Json::from(f32::from_be_bytes(self.word(path)?) as f64),
+            "bool" => match self.u32(path)? {
+                0 => Json::Bool(false),
+                1 => Json::Bool(true),
+                val => return self.err(path, start, format!("invalid bool value {val}")),
+            },
+            _ => Json::from(to_hex(self.take(16, path)?)),
+        };
+        Ok(self.leaf(label, name.to_string(), value, start))
+    }
+
+    fn enumeration(
+        &mut self,
+        body: &EnumBody,
+        label: &str,
+        path: &str,
+    ) -> Result<Decoded, CodecError> {
+        let start = self.pos;
+        let val = i32::from_be_bytes(self.word(path)?) as i64;
+        let member = body
+            .body
+            .iter()
+            .find(|assign| self.schema.value(&assign.val) == Some(val));
+        match member {
+            Some(member) => {
+                let value = Json::from(member.id.id.clone());
+                Ok(self.leaf(label, "enum".to_string(), value, start))
+            }
+            None => self.err(path, start, format!("{val} is not a member of the enum")),
+        }
+    }
+
+    fn structure(
+        &mut self,
+        body: &StructBody,
+        label: &str,
+        path: &str,
+    ) -> Result<Decoded, CodecError> {
+        let start = self.pos;
+        let mut children = vec![];
+        for decl in &body.body {
+            let Some(id) = decl.id() else {
+                continue;
+            };
+            children.push(self.decl(decl, &id.id, &field_path(path, &id.id))?);
+        }
+        Ok(self.object(label, "struct", children, start))
+    }
+
+    fn object(&self, label: &str, ty: &str, children: Vec<Decoded>, start: usize) -> Decoded {
+        let value = Json::Object(
+            children
+                .iter()
+                .map(|child| (child.name.clone(), child.value.clone()))
+                .collect(),
+        );
+        Decoded {
+            name: label.to_string(),
+            ty: ty.to_string(),
+            value,
+            start,
+            end: self.pos,
+            children,
+        }
+    }
+
+    // The numeric value of a decoded discriminant
+    fn discriminant(&self, decl: &Declaration, decoded: &Decoded) -> Option<i64> {
+        match &decoded.value {
+            Json::Bool(b) => Some(*b as i64),
+            Json::String(name) => {
+                // Enum member; look it up in the discriminant's enum
+                let Declaration::Normal { spec, .. } = decl else {
+                    return None;
+                };
+                let body = match self.schema.resolve(spec) {
+                    TypeSpecifier::Enum(body) => body,
+                    TypeSpecifier::Ident(id) => match self.schema.types.get(id.id.as_str()) {
+                        Some(Definition::Enum { body, .. }) => body,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                body.body
+                    .iter()
+                    .find(|assign| assign.id.id == *name)
+                    .and_then(|assign| self.schema.value(&assign.val))
+            }
+            value => value.as_i64(),
+        }
+    }
+
+    fn union(&mut self, body: &UnionBody, label: &str, path: &str) -> Result<Decoded, CodecError> {
+        let start = self.pos;
+        let disc_name = body
+            .discriminant
+            .id()
+            .map_or("discriminant".to_string(), |id| id.id.clone());
+        let disc = self.decl(
+            &body.discriminant,
+            &disc_name,
+            &field_path(path, &disc_name),
+        )?;
+        let Some(val) = self.discriminant(&body.discriminant, &disc) else {
+            return self.err(path, start, "discriminant is not an integer or enum");
+        };
+        let arm = body
+            .cases
+            .iter()
+            .find(|case| {
+                case.values
+                    .iter()
+                    .any(|v| self.schema.case_value(v) == Some(val))
+            })
+            .map(|case| &case.decl)
+            .or(body.default.as_deref());
+        let Some(arm) = arm else {
+            return self.err(
+                path,
+                start,
+                format!("no arm for discriminant {}", disc.value),
+            );
+        };
+        let mut children = vec![disc];
+        if let Some(id) = arm.id() {
+            children.push(self.decl(arm, &id.id, &field_path(path, &id.id))?);
+        }
+        Ok(self.object(label, "union", children, start))
+    }
+}
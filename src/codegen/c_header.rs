@@ -0,0 +1,271 @@
+// C header in the layout `rpcgen -h` produces: constants as `#define`s, each type as a struct,
+// enum, or typedef of the same name, variable-length arrays as `{ u_int x_len; T *x_val; }`,
+// unions as a struct holding the discriminant and a `<name>_u` union of the arms, and prototypes
+// for the RPC stubs and XDR routines rpcgen would generate alongside it.
+use super::rpc::{c_procedures, number};
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+pub fn generate(schema: &Schema, opts: &Options) -> String {
+    let guard = include_guard(schema, opts);
+    let mut out = Emitter::new("\t");
+    out.line("/* Generated by xdr-ls. Do not edit. */");
+    out.line("");
+    out.line(format!("#ifndef _{guard}_H_RPCGEN"));
+    out.line(format!("#define _{guard}_H_RPCGEN"));
+    out.line("");
+    out.line("#include <rpc/rpc.h>");
+    out.line("");
+    out.line("#ifdef __cplusplus");
+    out.line("extern \"C\" {");
+    out.line("#endif");
+    out.line("");
+    let generator = Generator { schema };
+    let mut routines = vec![];
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            generator.definition(&mut out, defn);
+            if let Some(id) = defn.id()
+                && !matches!(
+                    defn,
+                    Definition::Constant { .. } | Definition::Program { .. }
+                )
+            {
+                // Arrays are passed as they are rather than by pointer
+                let array = matches!(
+                    defn,
+                    Definition::TypeDef(
+                        Declaration::FixedArr { .. } | Declaration::FixedOpaque { .. }
+                    )
+                );
+                routines.push((&id.id, if array { "" } else { "*" }));
+            }
+        }
+    }
+    if !routines.is_empty() {
+        out.line("/* the xdr functions */");
+        for (name, pointer) in routines {
+            out.line(format!(
+                "extern bool_t xdr_{name} (XDR *, {name}{pointer});"
+            ));
+        }
+        out.line("");
+    }
+    out.line("#ifdef __cplusplus");
+    out.line("}");
+    out.line("#endif");
+    out.line("");
+    out.line(format!("#endif /* !_{guard}_H_RPCGEN */"));
+    out.finish()
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+}
+
+// A size or enum value as written
+fn value(val: &Value) -> &str {
+    match val {
+        Value::Id(id) => &id.id,
+        Value::Const(val) => val,
+    }
+}
+
+impl Generator<'_, '_> {
+    fn definition(&self, out: &mut Emitter, defn: &Definition) {
+        match defn {
+            Definition::Constant { id, val } => {
+                out.line(format!("#define {} {}", id.id, val));
+                out.line("");
+            }
+            Definition::TypeDef(decl) => {
+                self.declaration(out, "typedef ", decl);
+                out.line("");
+            }
+            Definition::Enum { id, body } => {
+                out.open(format!("enum {} {{", id.id));
+                self.members(out, body);
+                out.close("};");
+                out.line(format!("typedef enum {0} {0};", id.id));
+                out.line("");
+            }
+            Definition::Struct { id, body } => {
+                out.open(format!("struct {} {{", id.id));
+                for decl in &body.body {
+                    self.declaration(out, "", decl);
+                }
+                out.close("};");
+                out.line(format!("typedef struct {0} {0};", id.id));
+                out.line("");
+            }
+            Definition::Union { id, body } => {
+                out.open(format!("struct {} {{", id.id));
+                self.union_members(out, &id.id, body);
+                out.close("};");
+                out.line(format!("typedef struct {0} {0};", id.id));
+                out.line("");
+            }
+            Definition::Program {
+                id,
+                versions,
+                number: program,
+            } => {
+                out.line(format!("#define {} {}", id.id, value(program)));
+                out.line("");
+                for version in versions {
+                    let vers = number(self.schema, &version.number);
+                    out.line(format!(
+                        "#define {} {}",
+                        version.id.id,
+                        value(&version.number)
+                    ));
+                    let procs = c_procedures(out, version, &vers);
+                    for proc in &version.procedures {
+                        out.line(format!("#define {} {}", proc.id.id, value(&proc.number)));
+                        let Some(proc) = procs.iter().find(|c| std::ptr::eq(c.proc, proc)) else {
+                            continue;
+                        };
+                        let (arg, _) = &proc.arg;
+                        let (result, _) = &proc.result;
+                        out.line(format!(
+                            "extern {result} * {}({arg} *, CLIENT *);",
+                            proc.name
+                        ));
+                        out.line(format!(
+                            "extern {result} * {}_svc({arg} *, struct svc_req *);",
+                            proc.name
+                        ));
+                    }
+                    out.line(format!(
+                        "extern int {}_{}_freeresult (SVCXPRT *, xdrproc_t, caddr_t);",
+                        id.id.to_lowercase(),
+                        vers
+                    ));
+                    out.line("");
+                }
+            }
+        }
+    }
+
+    fn members(&self, out: &mut Emitter, body: &EnumBody) {
+        for assign in &body.body {
+            out.line(format!("{} = {},", assign.id.id, value(&assign.val)));
+        }
+    }
+
+    // The discriminant and the `<name>_u` union of the arms; void arms have no member
+    fn union_members(&self, out: &mut Emitter, name: &str, body: &UnionBody) {
+        self.declaration(out, "", &body.discriminant);
+        let arms: Vec<&Declaration> = body
+            .cases
+            .iter()
+            .map(|case| &case.decl)
+            .chain(body.default.as_deref())
+            .filter(|decl| !matches!(decl, Declaration::VOID))
+            .collect();
+        if arms.is_empty() {
+            return;
+        }
+        out.open("union {");
+        for decl in arms {
+            self.declaration(out, "", decl);
+        }
+        out.close(format!("}} {name}_u;"));
+    }
+
+    // Emits `decl` as a C declaration, preceded by `prefix` (`typedef ` for a typedef)
+    fn declaration(&self, out: &mut Emitter, prefix: &str, decl: &Declaration) {
+        match decl {
+            Declaration::Normal { spec, id } => self.typed(out, prefix, spec, &id.id),
+            Declaration::FixedArr { spec, id, size } => {
+                self.typed(out, prefix, spec, &format!("{}[{}]", id.id, value(size)))
+            }
+            Declaration::VarArr { spec, id, .. } => {
+                out.open(format!("{prefix}struct {{"));
+                out.line(format!("u_int {}_len;", id.id));
+                self.typed(out, "", spec, &format!("*{}_val", id.id));
+                out.close(format!("}} {};", id.id));
+            }
+            Declaration::FixedOpaque { id, size } => {
+                out.line(format!("{prefix}char {}[{}];", id.id, value(size)))
+            }
+            Declaration::VarOpaque { id, .. } => {
+                out.open(format!("{prefix}struct {{"));
+                out.line(format!("u_int {}_len;", id.id));
+                out.line(format!("char *{}_val;", id.id));
+                out.close(format!("}} {};", id.id));
+            }
+            Declaration::String { id, .. } => out.line(format!("{prefix}char *{};", id.id)),
+            Declaration::Optional { spec, id } => {
+                self.typed(out, prefix, spec, &format!("*{}", id.id))
+            }
+            Declaration::VOID => {}
+        }
+    }
+
+    // Emits `declarator` (a name, possibly with `*` or `[n]`) with the C type for `spec`; inline
+    // types become anonymous structs and enums, which C allows even where rpcgen doesn't
+    fn typed(&self, out: &mut Emitter, prefix: &str, spec: &TypeSpecifier, declarator: &str) {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => {
+                let ty = match name.as_str() {
+                    "unsigned int" => "u_int",
+                    "hyper" => "quad_t",
+                    "unsigned hyper" => "u_quad_t",
+                    "bool" => "bool_t",
+                    "quadruple" => "long double",
+                    name => name,
+                };
+                out.line(format!("{prefix}{ty} {declarator};"));
+            }
+            // Like rpcgen, refer to structs (and unions, which are structs too) by tag, so a
+            // struct can point to itself or to one defined later
+            TypeSpecifier::Ident(id) => match self.schema.types.get(id.id.as_str()) {
+                Some(Definition::Struct { .. } | Definition::Union { .. }) => {
+                    out.line(format!("{prefix}struct {} {declarator};", id.id))
+                }
+                _ => out.line(format!("{prefix}{} {declarator};", id.id)),
+            },
+            TypeSpecifier::Enum(body) => {
+                out.open(format!("{prefix}enum {{"));
+                self.members(out, body);
+                out.close(format!("}} {declarator};"));
+            }
+            TypeSpecifier::Struct(body) => {
+                out.open(format!("{prefix}struct {{"));
+                for decl in &body.body {
+                    self.declaration(out, "", decl);
+                }
+                out.close(format!("}} {declarator};"));
+            }
+            TypeSpecifier::Union(body) => {
+                let name = declarator.trim_start_matches('*');
+                let name = name.split('[').next().unwrap_or(name);
+                out.open(format!("{prefix}struct {{"));
+                self.union_members(out, name, body);
+                out.close(format!("}} {declarator};"));
+            }
+        }
+    }
+}
+
+// Named after the output file, or the input file when there's just one and the header goes to
+// stdout
+fn include_guard(schema: &Schema, opts: &Options) -> String {
+    let stem = match (&opts.name, schema.files) {
+        (Some(name), _) => name.clone(),
+        (None, [file]) => file.path.file_stem().map_or("XDR".to_string(), |stem| {
+            stem.to_string_lossy().into_owned()
+        }),
+        _ => "XDR".to_string(),
+    };
+    stem.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,541 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::{Options, Schema, SourceFile, type_deps};
+use crate::ast::*;
+
+// The output markup; all `text` arguments have already been rendered unless noted otherwise
+trait Format {
+    fn ext(&self) -> &'static str;
+    // Escapes raw text
+    fn escape(&self, text: &str) -> String;
+    // Inline code from raw text
+    fn code(&self, text: &str) -> String;
+    fn link(&self, text: &str, href: &str) -> String;
+    fn heading(&self, out: &mut String, level: usize, text: &str, anchor: Option<&str>);
+    fn paragraph(&self, out: &mut String, text: &str);
+    // Renders a raw doc comment
+    fn doc(&self, out: &mut String, doc: &str);
+    fn table(&self, out: &mut String, head: &[&str], rows: &[Vec<String>]);
+    fn list(&self, out: &mut String, items: &[String]);
+    fn page(&self, title: &str, body: &str) -> String;
+}
+
+struct Markdown;
+
+impl Format for Markdown {
+    fn ext(&self) -> &'static str {
+        "md"
+    }
+
+    fn escape(&self, text: &str) -> String {
+        let mut out = String::new();
+        for c in text.chars() {
+            if "\\`*_[]<>|#".contains(c) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    fn code(&self, text: &str) -> String {
+        format!("`{text}`")
+    }
+
+    fn link(&self, text: &str, href: &str) -> String {
+        format!("[{text}]({href})")
+    }
+
+    fn heading(&self, out: &mut String, level: usize, text: &str, anchor: Option<&str>) {
+        if let Some(anchor) = anchor {
+            out.push_str(&format!("<a id=\"{anchor}\"></a>\n\n"));
+        }
+        out.push_str(&format!("{} {}\n\n", "#".repeat(level), text));
+    }
+
+    fn paragraph(&self, out: &mut String, text: &str) {
+        out.push_str(text);
+        out.push_str("\n\n");
+    }
+
+    fn doc(&self, out: &mut String, doc: &str) {
+        // Doc comments are commonly written in Markdown already
+        self.paragraph(out, doc);
+    }
+
+    fn table(&self, out: &mut String, head: &[&str], rows: &[Vec<String>]) {
+        out.push_str(&format!("| {} |\n", head.join(" | ")));
+        out.push_str(&format!("|{}\n", "---|".repeat(head.len())));
+        for row in rows {
+            out.push_str(&format!("| {} |\n", row.join(" | ")));
+        }
+        out.push('\n');
+    }
+
+    fn list(&self, out: &mut String, items: &[String]) {
+        for item in items {
+            out.push_str(&format!("* {item}\n"));
+        }
+        out.push('\n');
+    }
+
+    fn page(&self, _title: &str, body: &str) -> String {
+        format!("<!-- Generated by xdr-ls. Do not edit. -->\n\n{body}")
+    }
+}
+
+struct Html;
+
+impl Format for Html {
+    fn ext(&self) -> &'static str {
+        "html"
+    }
+
+    fn escape(&self, text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    fn code(&self, text: &str) -> String {
+        format!("<code>{}</code>", self.escape(text))
+    }
+
+    fn link(&self, text: &str, href: &str) -> String {
+        format!("<a href=\"{}\">{}</a>", self.escape(href), text)
+    }
+
+    fn heading(&self, out: &mut String, level: usize, text: &str, anchor: Option<&str>) {
+        match anchor {
+            Some(anchor) => out.push_str(&format!("<h{level} id=\"{anchor}\">{text}</h{level}>\n")),
+            None => out.push_str(&format!("<h{level}>{text}</h{level}>\n")),
+        }
+    }
+
+    fn paragraph(&self, out: &mut String, text: &str) {
+        out.push_str(&format!("<p>{text}</p>\n"));
+    }
+
+    fn doc(&self, out: &mut String, doc: &str) {
+        for para in doc.split("\n\n") {
+            self.paragraph(out, &self.escape(para).replace('\n', "<br>\n"));
+        }
+    }
+
+    fn table(&self, out: &mut String, head: &[&str], rows: &[Vec<String>]) {
+        out.push_str("<table>\n<tr>");
+        for cell in head {
+            out.push_str(&format!("<th>{cell}</th>"));
+        }
+        out.push_str("</tr>\n");
+        for row in rows {
+            out.push_str("<tr>");
+            for cell in row {
+                out.push_str(&format!("<td>{cell}</td>"));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+
+    fn list(&self, out: &mut String, items: &[String]) {
+        out.push_str("<ul>\n");
+        for item in items {
+            out.push_str(&format!("<li>{item}</li>\n"));
+        }
+        out.push_str("</ul>\n");
+    }
+
+    fn page(&self, title: &str, body: &str) -> String {
+        format!(
+            "<!DOCTYPE html>
+<!-- Generated by xdr-ls. Do not edit. -->
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: auto; padding: 1em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }}
+code {{ background: #f4f4f4; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+",
+            self.escape(title),
+            body
+        )
+    }
+}
+
+// Name of the page documenting `path`, relative to the directory shared by all the inputs
+fn page_name(path: &Path, root: &Path, ext: &str) -> String {
+    let path = path.strip_prefix(root).unwrap_or(path).with_extension("");
+    let name = path.to_string_lossy();
+    let name = name.trim_start_matches("./").trim_start_matches('/');
+    format!("{}.{}", name.replace(['/', '\\'], "_"), ext)
+}
+
+fn common_dir(files: &[SourceFile]) -> PathBuf {
+    let mut dirs = files.iter().filter_map(|file| file.path.parent());
+    let Some(first) = dirs.next() else {
+        return PathBuf::new();
+    };
+    let mut root = first.to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&root) && root.pop() {}
+    }
+    root
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+    fmt: &'b dyn Format,
+    // Definition name -> page it's documented on
+    pages: HashMap<&'a str, String>,
+    used_by: BTreeMap<String, BTreeSet<String>>,
+    root: PathBuf,
+}
+
+pub fn generate(schema: &Schema, opts: &Options) -> Vec<(String, String)> {
+    let fmt: &dyn Format = match opts.format.as_deref() {
+        Some("html") => &Html,
+        _ => &Markdown,
+    };
+    let mut generator = Generator {
+        schema,
+        fmt,
+        pages: HashMap::new(),
+        used_by: BTreeMap::new(),
+        root: common_dir(schema.files),
+    };
+    for file in schema.files {
+        let page = page_name(&file.path, &generator.root, fmt.ext());
+        for defn in &file.spec.defns {
+            if let Some(id) = defn.id() {
+                generator.pages.insert(&id.id, page.clone());
+                for dep in type_deps(defn) {
+                    generator
+                        .used_by
+                        .entry(dep)
+                        .or_default()
+                        .insert(id.id.clone());
+                }
+            }
+        }
+    }
+    let mut pages = vec![(format!("index.{}", fmt.ext()), generator.index())];
+    for file in schema.files {
+        pages.push((
+            page_name(&file.path, &generator.root, fmt.ext()),
+            generator.file_page(file),
+        ));
+    }
+    pages
+}
+
+impl Generator<'_, '_> {
+    fn name_link(&self, name: &str) -> String {
+        match self.pages.get(name) {
+            Some(page) => self
+                .fmt
+                .link(&self.fmt.code(name), &format!("{page}#{name}")),
+            None => self.fmt.code(name),
+        }
+    }
+
+    fn names(&self, names: Option<&BTreeSet<String>>) -> String {
+        names.map_or(String::new(), |names| {
+            names
+                .iter()
+                .map(|name| self.name_link(name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+
+    fn size(&self, defn: &Definition) -> String {
+        self.schema
+            .definition_size(defn)
+            .map_or(String::new(), |size| size.to_string())
+    }
+
+    fn index(&self) -> String {
+        let fmt = self.fmt;
+        let mut out = String::new();
+        fmt.heading(&mut out, 1, "XDR types", None);
+        fmt.heading(&mut out, 2, "Files", None);
+        let files: Vec<String> = self
+            .schema
+            .files
+            .iter()
+            .map(|file| {
+                fmt.link(
+                    &fmt.escape(&file.path.display().to_string()),
+                    &page_name(&file.path, &self.root, fmt.ext()),
+                )
+            })
+            .collect();
+        fmt.list(&mut out, &files);
+
+        fmt.heading(&mut out, 2, "Type index", None);
+        let mut rows: Vec<(String, Vec<String>)> = vec![];
+        for file in self.schema.files {
+            for defn in &file.spec.defns {
+                let Some(id) = defn.id() else {
+                    continue;
+                };
+                if let Definition::Constant { .. } | Definition::Program { .. } = defn {
+                    continue;
+                }
+                rows.push((
+                    id.id.clone(),
+                    vec![
+                        self.name_link(&id.id),
+                        defn.keyword().to_string(),
+                        fmt.escape(&file.path.display().to_string()),
+                        fmt.escape(&self.size(defn)),
+                        self.names(Some(&type_deps(defn))),
+                        self.names(self.used_by.get(&id.id)),
+                    ],
+                ));
+            }
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        let rows: Vec<Vec<String>> = rows.into_iter().map(|(_, row)| row).collect();
+        fmt.table(
+            &mut out,
+            &["Name", "Kind", "File", "Wire size", "Uses", "Used by"],
+            &rows,
+        );
+        fmt.page("XDR types", &out)
+    }
+
+    fn file_page(&self, file: &SourceFile) -> String {
+        let fmt = self.fmt;
+        let title = file.path.display().to_string();
+        let mut out = String::new();
+        fmt.heading(&mut out, 1, &fmt.escape(&title), None);
+        fmt.paragraph(
+            &mut out,
+            &fmt.link("Index", &format!("index.{}", fmt.ext())),
+        );
+        for defn in &file.spec.defns {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            fmt.heading(
+                &mut out,
+                2,
+                &format!("{} {}", defn.keyword(), fmt.code(&id.id)),
+                Some(&id.id),
+            );
+            if let Some(doc) = file.doc_comment(defn) {
+                fmt.doc(&mut out, &doc);
+            }
+            let mut facts = vec![];
+            if let Definition::Constant { val, .. } = defn {
+                facts.push(format!("Value: {}", fmt.code(val)));
+            } else if let Definition::Program { number, .. } = defn {
+                facts.push(format!("Program number: {}", self.value(number)));
+            } else {
+                facts.push(format!("Wire size: {}", fmt.escape(&self.size(defn))));
+            }
+            if let Some(users) = self.used_by.get(&id.id) {
+                facts.push(format!("Used by: {}", self.names(Some(users))));
+            }
+            fmt.list(&mut out, &facts);
+            self.body(&mut out, defn);
+        }
+        fmt.page(&title, &out)
+    }
+
+    fn body(&self, out: &mut String, defn: &Definition) {
+        let fmt = self.fmt;
+        match defn {
+            Definition::Constant { .. } => {}
+            Definition::TypeDef(decl) => {
+                fmt.paragraph(out, &format!("Type: {}", self.decl_type(decl)));
+            }
+            Definition::Enum { body, .. } => self.enum_table(out, body),
+            Definition::Struct { body, .. } => self.struct_table(out, body),
+            Definition::Union { body, .. } => self.union_table(out, body),
+            Definition::Program { versions, .. } => self.program_table(out, versions),
+        }
+    }
+
+    fn program_table(&self, out: &mut String, versions: &[Version]) {
+        let fmt = self.fmt;
+        let type_or_void = |spec: Option<&TypeSpecifier>| {
+            spec.map_or_else(|| fmt.code("void"), |spec| self.type_ref(spec))
+        };
+        let rows: Vec<Vec<String>> = versions
+            .iter()
+            .flat_map(|version| {
+                version.procedures.iter().map(move |proc| {
+                    let args: Vec<String> =
+                        proc.args.iter().map(|arg| self.type_ref(arg)).collect();
+                    vec![
+                        format!(
+                            "{} = {}",
+                            fmt.code(&version.id.id),
+                            self.value(&version.number)
+                        ),
+                        format!("{} = {}", fmt.code(&proc.id.id), self.value(&proc.number)),
+                        if args.is_empty() {
+                            type_or_void(None)
+                        } else {
+                            args.join(", ")
+                        },
+                        type_or_void(proc.result.as_ref()),
+                    ]
+                })
+            })
+            .collect();
+        fmt.table(out, &["Version", "Procedure", "Arguments", "Result"], &rows);
+    }
+
+    fn enum_table(&self, out: &mut String, body: &EnumBody) {
+        let rows: Vec<Vec<String>> = body
+            .body
+            .iter()
+            .map(|assign| vec![self.fmt.code(&assign.id.id), self.value(&assign.val)])
+            .collect();
+        self.fmt.table(out, &["Member", "Value"], &rows);
+    }
+
+    fn field_row(&self, decl: &Declaration) -> Vec<String> {
+        let mut visiting = Default::default();
+        vec![
+            decl.id().map_or(String::new(), |id| self.fmt.code(&id.id)),
+            self.decl_type(decl),
+            self.fmt
+                .escape(&self.schema.decl_size(decl, &mut visiting).to_string()),
+        ]
+    }
+
+    fn struct_table(&self, out: &mut String, body: &StructBody) {
+        let rows: Vec<Vec<String>> = body.body.iter().map(|decl| self.field_row(decl)).collect();
+        self.fmt.table(out, &["Field", "Type", "Wire size"], &rows);
+    }
+
+    fn union_table(&self, out: &mut String, body: &UnionBody) {
+        let fmt = self.fmt;
+        let mut rows = vec![];
+        let mut disc = vec!["switch".to_string()];
+        disc.extend(self.field_row(&body.discriminant));
+        rows.push(disc);
+        for case in &body.cases {
+            let values: Vec<String> = case.values.iter().map(|val| self.value(val)).collect();
+            let mut row = vec![values.join(", ")];
+            row.extend(self.field_row(&case.decl));
+            rows.push(row);
+        }
+        if let Some(decl) = &body.default {
+            let mut row = vec!["default".to_string()];
+            row.extend(self.field_row(decl));
+            rows.push(row);
+        }
+        fmt.table(out, &["Case", "Field", "Type", "Wire size"], &rows);
+    }
+
+    fn value(&self, val: &Value) -> String {
+        match val {
+            Value::Const(val) => self.fmt.code(val),
+            Value::Id(id) => match self.schema.value(val) {
+                Some(num) => format!("{} ({})", self.name_link(&id.id), num),
+                None => self.name_link(&id.id),
+            },
+        }
+    }
+
+    fn bound(&self, size: &Option<Value>) -> String {
+        size.as_ref().map_or(String::new(), |size| self.value(size))
+    }
+
+    fn decl_type(&self, decl: &Declaration) -> String {
+        let fmt = self.fmt;
+        match decl {
+            Declaration::Normal { spec, .. } => self.type_ref(spec),
+            Declaration::FixedArr { spec, size, .. } => {
+                format!(
+                    "{}{}{}{}",
+                    self.type_ref(spec),
+                    fmt.escape("["),
+                    self.value(size),
+                    fmt.escape("]")
+                )
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                format!(
+                    "{}{}{}{}",
+                    self.type_ref(spec),
+                    fmt.escape("<"),
+                    self.bound(size),
+                    fmt.escape(">")
+                )
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                format!(
+                    "{}{}{}{}",
+                    fmt.code("opaque"),
+                    fmt.escape("["),
+                    self.value(size),
+                    fmt.escape("]")
+                )
+            }
+            Declaration::VarOpaque { size, .. } => {
+                format!(
+                    "{}{}{}{}",
+                    fmt.code("opaque"),
+                    fmt.escape("<"),
+                    self.bound(size),
+                    fmt.escape(">")
+                )
+            }
+            Declaration::String { size, .. } => {
+                format!(
+                    "{}{}{}{}",
+                    fmt.code("string"),
+                    fmt.escape("<"),
+                    self.bound(size),
+                    fmt.escape(">")
+                )
+            }
+            Declaration::Optional { spec, .. } => {
+                format!("{} {}", self.type_ref(spec), fmt.escape("*"))
+            }
+            Declaration::VOID => fmt.code("void"),
+        }
+    }
+
+    fn type_ref(&self, spec: &TypeSpecifier) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => self.fmt.code(name),
+            TypeSpecifier::Ident(id) => self.name_link(&id.id),
+            TypeSpecifier::Enum(body) => {
+                let members: Vec<&str> = body.body.iter().map(|a| a.id.id.as_str()).collect();
+                self.fmt.code(&format!("enum {{ {} }}", members.join(", ")))
+            }
+            TypeSpecifier::Struct(body) => {
+                let fields: Vec<String> = body
+                    .body
+                    .iter()
+                    .map(|decl| {
+                        let name = decl.id().map_or("", |id| &id.id);
+                        format!("{} {}", self.decl_type(decl), self.fmt.escape(name))
+                    })
+                    .collect();
+                format!("struct {{ {} }}", fields.join("; "))
+            }
+            TypeSpecifier::Union(body) => {
+                format!("union switch ({})", self.decl_type(&body.discriminant))
+            }
+        }
+    }
+}
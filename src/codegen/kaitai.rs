@@ -0,0 +1,368 @@
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+// Kaitai identifiers must match [a-z][a-z0-9_]*
+fn snake(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+    }
+    out
+}
+
+// Bytes of padding needed after `len` bytes of opaque data
+fn padding(len: i64) -> i64 {
+    (4 - len % 4) % 4
+}
+
+struct Field {
+    id: String,
+    attrs: Vec<(&'static str, String)>,
+}
+
+impl Field {
+    fn new(id: String, attrs: Vec<(&'static str, String)>, cond: Option<&str>) -> Self {
+        let mut field = Field { id, attrs };
+        if let Some(cond) = cond {
+            field.attrs.push(("if", cond.to_string()));
+        }
+        field
+    }
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+    // Emitted in order; anonymous nested types are appended as they're encountered
+    types: Vec<(String, Vec<Field>)>,
+    enums: Vec<(String, Vec<(i64, String)>)>,
+}
+
+pub fn generate(schema: &Schema, opts: &Options) -> String {
+    let mut generator = Generator {
+        schema,
+        types: vec![],
+        enums: vec![],
+    };
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            generator.definition(defn);
+        }
+    }
+
+    let mut out = Emitter::new("  ");
+    out.line("# Generated by xdr-ls. Do not edit.");
+    out.open("meta:");
+    out.line("id: xdr");
+    out.line("endian: be");
+    out.close("");
+    if let Some(root) = &opts.root {
+        let attrs = generator.ident_attrs(root);
+        out.open("seq:");
+        emit_field(&mut out, &Field::new("value".to_string(), attrs, None));
+        out.close("");
+    }
+    if !generator.types.is_empty() {
+        out.open("types:");
+        for (name, fields) in &generator.types {
+            out.open(format!("{name}:"));
+            if fields.is_empty() {
+                out.line("seq: []");
+            } else {
+                out.open("seq:");
+                for field in fields {
+                    emit_field(&mut out, field);
+                }
+                out.dedent();
+            }
+            out.dedent();
+        }
+        out.close("");
+    }
+    if !generator.enums.is_empty() {
+        out.open("enums:");
+        for (name, members) in &generator.enums {
+            out.open(format!("{name}:"));
+            let mut seen = vec![];
+            for (val, member) in members {
+                // YAML keys must be unique, so aliases for the same value are dropped
+                if seen.contains(val) {
+                    out.line(format!("# {member} = {val} (alias)"));
+                } else {
+                    seen.push(*val);
+                    out.line(format!("{val}: {member}"));
+                }
+            }
+            out.dedent();
+        }
+        out.dedent();
+    }
+    out.finish()
+}
+
+fn emit_field(out: &mut Emitter, field: &Field) {
+    out.open(format!("- id: {}", field.id));
+    for (key, val) in &field.attrs {
+        out.line(format!("{key}: {val}"));
+    }
+    out.dedent();
+}
+
+impl Generator<'_, '_> {
+    fn definition(&mut self, defn: &Definition) {
+        match defn {
+            Definition::Constant { .. } | Definition::Program { .. } => {}
+            Definition::TypeDef(decl) => {
+                if let Some(id) = decl.id() {
+                    let name = snake(&id.id);
+                    let fields = self.decl_fields(decl, "value", None, &name);
+                    self.types.push((name, fields));
+                }
+            }
+            Definition::Enum { id, body } => self.enum_body(snake(&id.id), body),
+            Definition::Struct { id, body } => {
+                let name = snake(&id.id);
+                let fields = self.struct_fields(body, &name);
+                self.types.push((name, fields));
+            }
+            Definition::Union { id, body } => {
+                let name = snake(&id.id);
+                let fields = self.union_fields(body, &name);
+                self.types.push((name, fields));
+            }
+        }
+    }
+
+    fn enum_body(&mut self, name: String, body: &EnumBody) {
+        let members = body
+            .body
+            .iter()
+            .filter_map(|assign| Some((self.schema.value(&assign.val)?, snake(&assign.id.id))))
+            .collect();
+        self.enums.push((name, members));
+    }
+
+    fn value(&self, val: &Value) -> String {
+        match self.schema.value(val) {
+            Some(val) => val.to_string(),
+            None => match val {
+                Value::Const(val) => val.clone(),
+                Value::Id(id) => format!("0 # unresolved constant {}", id.id),
+            },
+        }
+    }
+
+    fn struct_fields(&mut self, body: &StructBody, owner: &str) -> Vec<Field> {
+        let mut fields = vec![];
+        for decl in &body.body {
+            if let Some(id) = decl.id() {
+                fields.extend(self.decl_fields(decl, &snake(&id.id), None, owner));
+            }
+        }
+        fields
+    }
+
+    fn union_fields(&mut self, body: &UnionBody, owner: &str) -> Vec<Field> {
+        let Some(disc) = body.discriminant.id() else {
+            return vec![];
+        };
+        let disc = snake(&disc.id);
+        let mut fields = self.decl_fields(&body.discriminant, &disc, None, owner);
+        // Whether the discriminant field was given a Kaitai enum
+        let disc_enum = fields.first().and_then(|field| {
+            field
+                .attrs
+                .iter()
+                .find(|(key, _)| *key == "enum")
+                .map(|(_, val)| val.clone())
+        });
+        let test = |val: &Value| match (&disc_enum, val) {
+            (Some(owner), Value::Id(id))
+                if self
+                    .schema
+                    .enum_of
+                    .get(id.id.as_str())
+                    .is_some_and(|name| snake(name) == *owner) =>
+            {
+                format!("{} == {}::{}", disc, owner, snake(&id.id))
+            }
+            (Some(_), _) => format!("{}.to_i == {}", disc, self.case_number(val)),
+            (None, _) => format!("{} == {}", disc, self.case_number(val)),
+        };
+        let conds: Vec<Vec<String>> = body
+            .cases
+            .iter()
+            .map(|case| case.values.iter().map(test).collect())
+            .collect();
+        for (case, tests) in body.cases.iter().zip(&conds) {
+            if let Some(id) = case.decl.id() {
+                let cond = tests.join(" or ");
+                fields.extend(self.decl_fields(&case.decl, &snake(&id.id), Some(&cond), owner));
+            }
+        }
+        if let Some(decl) = &body.default
+            && let Some(id) = decl.id()
+        {
+            let cond = format!("not ({})", conds.concat().join(" or "));
+            fields.extend(self.decl_fields(decl, &snake(&id.id), Some(&cond), owner));
+        }
+        fields
+    }
+
+    fn case_number(&self, val: &Value) -> String {
+        match val {
+            Value::Id(id) if id.id == "TRUE" => "1".to_string(),
+            Value::Id(id) if id.id == "FALSE" => "0".to_string(),
+            _ => self.value(val),
+        }
+    }
+
+    fn decl_fields(
+        &mut self,
+        decl: &Declaration,
+        id: &str,
+        cond: Option<&str>,
+        owner: &str,
+    ) -> Vec<Field> {
+        let len = format!("{id}_len");
+        let var_padding = format!("(4 - {len} % 4) % 4");
+        match decl {
+            Declaration::Normal { spec, .. } => {
+                let attrs = self.type_attrs(spec, owner, id);
+                vec![Field::new(id.to_string(), attrs, cond)]
+            }
+            Declaration::FixedArr { spec, size, .. } => {
+                let mut attrs = self.type_attrs(spec, owner, id);
+                attrs.push(("repeat", "expr".to_string()));
+                attrs.push(("repeat-expr", self.value(size)));
+                vec![Field::new(id.to_string(), attrs, cond)]
+            }
+            Declaration::VarArr { spec, .. } => {
+                let mut attrs = self.type_attrs(spec, owner, id);
+                attrs.push(("repeat", "expr".to_string()));
+                attrs.push(("repeat-expr", len.clone()));
+                vec![
+                    Field::new(len, vec![("type", "u4".to_string())], cond),
+                    Field::new(id.to_string(), attrs, cond),
+                ]
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                let mut fields = vec![Field::new(
+                    id.to_string(),
+                    vec![("size", self.value(size))],
+                    cond,
+                )];
+                let pad = self.schema.value(size).map_or(0, padding);
+                if pad != 0 {
+                    fields.push(Field::new(
+                        format!("{id}_pad"),
+                        vec![("size", pad.to_string())],
+                        cond,
+                    ));
+                }
+                fields
+            }
+            Declaration::VarOpaque { .. } | Declaration::String { .. } => {
+                let mut attrs = vec![];
+                if let Declaration::String { .. } = decl {
+                    attrs.push(("type", "str".to_string()));
+                    attrs.push(("encoding", "UTF-8".to_string()));
+                }
+                attrs.push(("size", len.clone()));
+                vec![
+                    Field::new(len, vec![("type", "u4".to_string())], cond),
+                    Field::new(id.to_string(), attrs, cond),
+                    Field::new(format!("{id}_pad"), vec![("size", var_padding)], cond),
+                ]
+            }
+            Declaration::Optional { spec, .. } => {
+                let present = format!("{id}_present");
+                let inner = match cond {
+                    Some(cond) => format!("({}) and {} != 0", cond, present),
+                    None => format!("{present} != 0"),
+                };
+                let attrs = self.type_attrs(spec, owner, id);
+                vec![
+                    Field::new(present, vec![("type", "u4".to_string())], cond),
+                    Field::new(id.to_string(), attrs, Some(&inner)),
+                ]
+            }
+            Declaration::VOID => vec![],
+        }
+    }
+
+    fn builtin_attrs(name: &str) -> Vec<(&'static str, String)> {
+        let ty = match name {
+            "int" => "s4",
+            "unsigned int" | "bool" => "u4",
+            "hyper" => "s8",
+            "unsigned hyper" => "u8",
+            "float" => "f4",
+            "double" => "f8",
+            // Kaitai has no quadruple precision floats, so leave it as raw bytes
+            _ => return vec![("size", "16".to_string())],
+        };
+        vec![("type", ty.to_string())]
+    }
+
+    fn ident_attrs(&self, name: &str) -> Vec<(&'static str, String)> {
+        match self.schema.types.get(name) {
+            Some(Definition::Enum { id, .. }) => {
+                vec![("type", "s4".to_string()), ("enum", snake(&id.id))]
+            }
+            Some(Definition::TypeDef(Declaration::Normal { spec, .. })) => {
+                // Inline aliases of builtins and enums rather than wrapping them in a type
+                match self.schema.resolve(spec) {
+                    TypeSpecifier::BuiltIn(builtin) => Self::builtin_attrs(builtin),
+                    TypeSpecifier::Ident(id)
+                        if matches!(
+                            self.schema.types.get(id.id.as_str()),
+                            Some(Definition::Enum { .. })
+                        ) =>
+                    {
+                        self.ident_attrs(&id.id)
+                    }
+                    _ => vec![("type", snake(name))],
+                }
+            }
+            _ => vec![("type", snake(name))],
+        }
+    }
+
+    fn type_attrs(
+        &mut self,
+        spec: &TypeSpecifier,
+        owner: &str,
+        field: &str,
+    ) -> Vec<(&'static str, String)> {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => Self::builtin_attrs(name),
+            TypeSpecifier::Ident(id) => self.ident_attrs(&id.id),
+            TypeSpecifier::Enum(body) => {
+                let name = format!("{owner}_{field}");
+                self.enum_body(name.clone(), body);
+                vec![("type", "s4".to_string()), ("enum", name)]
+            }
+            TypeSpecifier::Struct(body) => {
+                let name = format!("{owner}_{field}");
+                let fields = self.struct_fields(body, &name);
+                self.types.push((name.clone(), fields));
+                vec![("type", name)]
+            }
+            TypeSpecifier::Union(body) => {
+                let name = format!("{owner}_{field}");
+                let fields = self.union_fields(body, &name);
+                self.types.push((name.clone(), fields));
+                vec![("type", name)]
+            }
+        }
+    }
+}
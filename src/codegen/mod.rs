@@ -0,0 +1,810 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use tower_lsp::lsp_types::{Position, SymbolKind};
+
+use crate::ast::*;
+use crate::dialect::{self, ExtensionItem, ExtensionKind};
+use crate::position::{Encoding, Lines};
+use crate::scan::{ScanOptions, get_xdr_files};
+use crate::xdr;
+
+mod c_header;
+mod docs;
+mod kaitai;
+mod proto;
+mod rpc;
+mod rust;
+pub mod size;
+mod typescript;
+mod vectors;
+mod wireshark;
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    pub spec: Specification,
+    // What dialect extensions found in the text
+    pub extensions: Vec<ExtensionItem>,
+    // The spans error recovery left out, empty unless parsed by `parse_recovering`
+    pub skipped: Vec<(usize, usize)>,
+    // Where the text's lines start, for the position conversions below
+    pub lines: Lines,
+}
+
+impl SourceFile {
+    // Parses a file's text. Never panics: any input, however malformed, gives either a
+    // specification or an error (checked by the fuzz target in `fuzz/`).
+    pub fn parse(path: PathBuf, text: String) -> Result<Self, String> {
+        let (masked, extensions) = dialect::preprocess(&text);
+        match xdr::SpecificationParser::new().parse(&masked) {
+            Ok(spec) => Ok(SourceFile {
+                path,
+                lines: Lines::new(&text),
+                text,
+                spec,
+                extensions,
+                skipped: vec![],
+            }),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    // Parses what it can of a file's text, leaving out the definitions holding syntax errors (see
+    // `recovery`), for editor features that shouldn't go dark over a typo
+    pub fn parse_recovering(path: PathBuf, text: String) -> Option<Self> {
+        let (masked, extensions) = dialect::preprocess(&text);
+        let (spec, skipped) = crate::recovery::parse_with_gaps(&masked)?;
+        Some(SourceFile {
+            path,
+            lines: Lines::new(&text),
+            text,
+            spec,
+            extensions,
+            skipped,
+        })
+    }
+
+    // The position of byte `offset` in the text
+    pub fn position_at(&self, offset: usize, encoding: Encoding) -> Position {
+        self.lines.position_at(&self.text, offset, encoding)
+    }
+
+    // The byte offset of `pos` in the text
+    pub fn offset_at(&self, pos: Position, encoding: Encoding) -> usize {
+        self.lines.offset_at(&self.text, pos, encoding)
+    }
+
+    // The line byte `offset` is on, counting from 0
+    pub fn line_at(&self, offset: usize) -> u32 {
+        self.lines.line_at(&self.text, offset)
+    }
+
+    // Names defined by dialect extensions rather than the grammar
+    pub fn extension_definitions(&self) -> impl Iterator<Item = (&Identifier, SymbolKind)> {
+        self.extensions.iter().filter_map(|item| match &item.kind {
+            ExtensionKind::Definition { id, kind } => Some((id, *kind)),
+            _ => None,
+        })
+    }
+
+    // The `(name, args)` of the annotations of each definition, in the order of `spec.defns`:
+    // those between it and the one before, not counting those trailing the line the one before
+    // ends on
+    pub fn annotations(&self) -> Vec<Vec<(&str, &str)>> {
+        let ends = self.definition_ends();
+        self.spec
+            .defns
+            .iter()
+            .map(|defn| self.annotations_after(defn, &ends))
+            .collect()
+    }
+
+    // Where each definition ends, sorted
+    fn definition_ends(&self) -> Vec<usize> {
+        let mut ends: Vec<usize> = self
+            .spec
+            .defns
+            .iter()
+            .filter_map(|defn| self.definition_end(defn))
+            .collect();
+        ends.sort_unstable();
+        ends
+    }
+
+    // The annotations of `defn`, given the sorted `ends` of every definition
+    fn annotations_after(&self, defn: &Definition, ends: &[usize]) -> Vec<(&str, &str)> {
+        let Some(start) = self.definition_start(defn) else {
+            return vec![];
+        };
+        let after = ends[..ends.partition_point(|&end| end <= start)]
+            .last()
+            .map_or(0, |&end| {
+                self.text[end..start].find('\n').map_or(start, |i| end + i)
+            });
+        // The items are in source order
+        let first = self.extensions.partition_point(|item| item.start < after);
+        self.extensions[first..]
+            .iter()
+            .take_while(|item| item.start < start)
+            .filter(|item| item.end <= start)
+            .filter_map(|item| match &item.kind {
+                ExtensionKind::Annotation { name, args } => Some((name.as_str(), args.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Byte offset of the keyword starting a definition
+    pub fn definition_start(&self, defn: &Definition) -> Option<usize> {
+        let id = defn.id()?;
+        self.text[..id.start].rfind(defn.keyword())
+    }
+
+    // Byte offset just past the `;` ending a definition, skipping comments and nested braces
+    pub fn definition_end(&self, defn: &Definition) -> Option<usize> {
+        let id = defn.id()?;
+        let bytes = self.text.as_bytes();
+        let mut depth = 0usize;
+        let mut i = id.end;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                    i = self.text[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                    continue;
+                }
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    i = self.text[i + 2..]
+                        .find("*/")
+                        .map_or(bytes.len(), |end| i + 2 + end + 2);
+                    continue;
+                }
+                b'{' => depth += 1,
+                b'}' => depth = depth.saturating_sub(1),
+                b';' if depth == 0 => return Some(i + 1),
+                _ => {}
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // The comment immediately preceding a definition (with comment markers stripped), if any.
+    // Lint suppression comments in between are skipped.
+    pub fn doc_comment(&self, defn: &Definition) -> Option<String> {
+        self.doc_comment_before(self.definition_start(defn)?)
+    }
+
+    // The comment on the lines immediately before byte offset `start`, such as the start of an
+    // enum member, read as for definitions
+    pub fn doc_comment_before(&self, mut start: usize) -> Option<String> {
+        loop {
+            let before = self.text[..start].trim_end();
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let line = before[line_start..].trim_start();
+            let comment = line.starts_with("/*") || line.starts_with("//");
+            if !comment
+                || !line.contains(crate::lint::DIRECTIVE)
+                || self.text[before.len()..start].matches('\n').count() > 1
+            {
+                break;
+            }
+            start = line_start;
+        }
+        let before = self.text[..start].trim_end();
+        // A blank line separates the comment from the definition
+        if self.text[before.len()..start].matches('\n').count() > 1 {
+            return None;
+        }
+        let lines: Vec<&str> = if let Some(body) = before.strip_suffix("*/") {
+            let open = body.rfind("/*")?;
+            let line_start = body[..open].rfind('\n').map_or(0, |i| i + 1);
+            // Trailing comment on a line of code
+            if !body[line_start..open].trim().is_empty() {
+                return None;
+            }
+            body[open + 2..]
+                .lines()
+                .map(|line| line.trim().trim_start_matches('*').trim())
+                .collect()
+        } else {
+            let mut lines: Vec<&str> = before
+                .lines()
+                .rev()
+                .map_while(|line| line.trim().strip_prefix("//"))
+                .map(|line| line.trim_start_matches('/').trim())
+                .collect();
+            lines.reverse();
+            lines
+        };
+        let doc = lines.join("\n").trim().to_string();
+        if doc.is_empty() { None } else { Some(doc) }
+    }
+}
+
+// Lookup tables shared by the generators
+pub struct Schema<'a> {
+    pub files: &'a [SourceFile],
+    // Names introduced by `const` definitions
+    pub constants: HashSet<&'a str>,
+    // Constants, enum members, and the names of RPC programs, versions, and procedures -> value
+    pub consts: HashMap<&'a str, i64>,
+    // Enum member -> name of the enum it belongs to (only for named enums)
+    pub enum_of: HashMap<&'a str, &'a str>,
+    // Type name -> definition (constants and programs excluded)
+    pub types: HashMap<&'a str, &'a Definition>,
+    // Program, version, and procedure name -> the program or version it's part of (None for
+    // programs)
+    pub rpc_of: HashMap<&'a str, Option<&'a str>>,
+}
+
+impl<'a> Schema<'a> {
+    pub fn new(files: &'a [SourceFile]) -> Self {
+        let mut schema = Schema {
+            files,
+            constants: HashSet::new(),
+            consts: HashMap::new(),
+            enum_of: HashMap::new(),
+            types: HashMap::new(),
+            rpc_of: HashMap::new(),
+        };
+        // Enum values may refer to constants (or other enum members) defined later in the
+        // workspace, so keep resolving until we stop making progress
+        let mut pending: Vec<(&str, &Value)> = vec![];
+        for defn in files.iter().flat_map(|file| file.spec.defns.iter()) {
+            match defn {
+                Definition::Constant { id, val } => {
+                    schema.constants.insert(&id.id);
+                    if let Some(val) = parse_constant(val) {
+                        schema.consts.insert(&id.id, val);
+                    }
+                }
+                Definition::Enum { id, body } => {
+                    schema.types.insert(&id.id, defn);
+                    for assign in &body.body {
+                        schema.enum_of.insert(&assign.id.id, &id.id);
+                        pending.push((&assign.id.id, &assign.val));
+                    }
+                }
+                Definition::Program {
+                    id,
+                    versions,
+                    number,
+                } => {
+                    schema.rpc_of.insert(&id.id, None);
+                    pending.push((&id.id, number));
+                    for version in versions {
+                        schema.rpc_of.insert(&version.id.id, Some(&id.id));
+                        pending.push((&version.id.id, &version.number));
+                        for proc in &version.procedures {
+                            schema.rpc_of.insert(&proc.id.id, Some(&version.id.id));
+                            pending.push((&proc.id.id, &proc.number));
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(id) = defn.id() {
+                        schema.types.insert(&id.id, defn);
+                    }
+                }
+            }
+        }
+        visit_anonymous_enums(files, &mut |body| {
+            for assign in &body.body {
+                pending.push((&assign.id.id, &assign.val));
+            }
+        });
+        loop {
+            let before = pending.len();
+            pending.retain(|(name, val)| match schema.value(val) {
+                Some(val) => {
+                    schema.consts.insert(name, val);
+                    false
+                }
+                None => true,
+            });
+            if pending.is_empty() || pending.len() == before {
+                break;
+            }
+        }
+        schema
+    }
+
+    pub fn value(&self, val: &Value) -> Option<i64> {
+        match val {
+            Value::Const(val) => parse_constant(val),
+            Value::Id(id) => self.consts.get(id.id.as_str()).copied(),
+        }
+    }
+
+    // The value of a union case label; rpcgen's TRUE and FALSE are allowed for bool
+    // discriminants even when they aren't defined in the workspace
+    pub fn case_value(&self, val: &Value) -> Option<i64> {
+        match val {
+            Value::Id(id) if id.id == "TRUE" && !self.consts.contains_key("TRUE") => Some(1),
+            Value::Id(id) if id.id == "FALSE" && !self.consts.contains_key("FALSE") => Some(0),
+            _ => self.value(val),
+        }
+    }
+
+    // Follows typedefs of the form `typedef T name;` to the underlying type specifier
+    pub fn resolve<'b>(&'b self, spec: &'b TypeSpecifier) -> &'b TypeSpecifier {
+        let mut spec = spec;
+        // Bound the number of steps so a typedef cycle can't hang us
+        for _ in 0..64 {
+            match spec {
+                TypeSpecifier::Ident(id) => match self.types.get(id.id.as_str()) {
+                    Some(Definition::TypeDef(Declaration::Normal { spec: inner, .. })) => {
+                        spec = inner
+                    }
+                    _ => return spec,
+                },
+                _ => return spec,
+            }
+        }
+        spec
+    }
+
+    // The enum a union switches on, and its name unless it's anonymous
+    pub fn discriminant_enum<'b>(
+        &'b self,
+        body: &'b UnionBody,
+    ) -> Option<(Option<&'b str>, &'b EnumBody)> {
+        let Declaration::Normal { spec, .. } = body.discriminant.as_ref() else {
+            return None;
+        };
+        match self.resolve(spec) {
+            TypeSpecifier::Enum(body) => Some((None, body)),
+            TypeSpecifier::Ident(id) => match self.types.get(id.id.as_str()) {
+                Some(Definition::Enum { id, body }) => Some((Some(&id.id), body)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Every union switching on the enum `name`, top-level or nested in another definition
+    pub fn unions_on(&self, name: &str) -> Vec<SwitchingUnion<'a>> {
+        let mut result = vec![];
+        for file in self.files {
+            for defn in &file.spec.defns {
+                let Some(id) = defn.id() else {
+                    continue;
+                };
+                let mut unions = vec![];
+                if let Definition::Union { body, .. } = defn {
+                    unions.push((None, body));
+                }
+                for decl in defn.declarations() {
+                    let (Declaration::Normal { spec, .. }
+                    | Declaration::FixedArr { spec, .. }
+                    | Declaration::VarArr { spec, .. }
+                    | Declaration::Optional { spec, .. }) = decl
+                    else {
+                        continue;
+                    };
+                    if let TypeSpecifier::Union(body) = spec {
+                        // A typedef's own declaration isn't a field
+                        let top =
+                            matches!(defn, Definition::TypeDef(top) if std::ptr::eq(top, decl));
+                        unions.push(((!top).then(|| decl.id()).flatten(), body));
+                    }
+                }
+                for (field, body) in unions {
+                    if self
+                        .discriminant_enum(body)
+                        .and_then(|(enum_name, _)| enum_name)
+                        == Some(name)
+                    {
+                        result.push(SwitchingUnion {
+                            file,
+                            defn: id,
+                            field,
+                            body,
+                        });
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // The members of the enum a union switches on that none of its cases name (its default arm
+    // aside)
+    pub fn unhandled_members<'b>(&'b self, body: &'b UnionBody) -> Vec<&'b str> {
+        let Some((_, enum_body)) = self.discriminant_enum(body) else {
+            return vec![];
+        };
+        let handled: HashSet<i64> = body
+            .cases
+            .iter()
+            .flat_map(|case| &case.values)
+            .filter_map(|value| self.case_value(value))
+            .collect();
+        enum_body
+            .body
+            .iter()
+            .filter(|assign| {
+                self.consts
+                    .get(assign.id.id.as_str())
+                    .is_some_and(|value| !handled.contains(value))
+            })
+            .map(|assign| assign.id.id.as_str())
+            .collect()
+    }
+}
+
+// A union found by `Schema::unions_on`
+pub struct SwitchingUnion<'a> {
+    pub file: &'a SourceFile,
+    // The definition the union is, or is part of
+    pub defn: &'a Identifier,
+    // The field the union is the type of, when it's nested
+    pub field: Option<&'a Identifier>,
+    pub body: &'a UnionBody,
+}
+
+// Small helper for emitting indented source text
+pub struct Emitter {
+    out: String,
+    depth: usize,
+    indent: &'static str,
+}
+
+impl Emitter {
+    pub fn new(indent: &'static str) -> Self {
+        Emitter {
+            out: String::new(),
+            depth: 0,
+            indent,
+        }
+    }
+
+    pub fn line(&mut self, line: impl AsRef<str>) {
+        let line = line.as_ref();
+        if !line.is_empty() {
+            for _ in 0..self.depth {
+                self.out.push_str(self.indent);
+            }
+            self.out.push_str(line);
+        }
+        self.out.push('\n');
+    }
+
+    // Emits a line and indents everything after it
+    pub fn open(&mut self, line: impl AsRef<str>) {
+        self.line(line);
+        self.depth += 1;
+    }
+
+    pub fn indent(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn dedent(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    // Dedents and then emits a line
+    pub fn close(&mut self, line: impl AsRef<str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.line(line);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+// Names of the types a definition refers to
+pub fn type_deps(defn: &Definition) -> BTreeSet<String> {
+    fn decl(decl: &Declaration, deps: &mut BTreeSet<String>) {
+        match decl {
+            Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } => ty(spec, deps),
+            _ => {}
+        }
+    }
+    fn ty(spec: &TypeSpecifier, deps: &mut BTreeSet<String>) {
+        match spec {
+            TypeSpecifier::Ident(id) => {
+                deps.insert(id.id.clone());
+            }
+            TypeSpecifier::Struct(body) => body.body.iter().for_each(|d| decl(d, deps)),
+            TypeSpecifier::Union(body) => union(body, deps),
+            _ => {}
+        }
+    }
+    fn union(body: &UnionBody, deps: &mut BTreeSet<String>) {
+        decl(&body.discriminant, deps);
+        for case in &body.cases {
+            decl(&case.decl, deps);
+        }
+        if let Some(d) = &body.default {
+            decl(d, deps);
+        }
+    }
+    let mut deps = BTreeSet::new();
+    match defn {
+        Definition::TypeDef(d) => decl(d, &mut deps),
+        Definition::Struct { body, .. } => body.body.iter().for_each(|d| decl(d, &mut deps)),
+        Definition::Union { body, .. } => union(body, &mut deps),
+        // The argument and result types of the procedures
+        Definition::Program { versions, .. } => {
+            for proc in versions.iter().flat_map(|version| &version.procedures) {
+                proc.result
+                    .iter()
+                    .chain(&proc.args)
+                    .for_each(|spec| ty(spec, &mut deps));
+            }
+        }
+        _ => {}
+    }
+    deps
+}
+
+fn visit_anonymous_enums<'a>(files: &'a [SourceFile], cb: &mut dyn FnMut(&'a EnumBody)) {
+    fn visit_decl<'a>(decl: &'a Declaration, cb: &mut dyn FnMut(&'a EnumBody)) {
+        match decl {
+            Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } => visit_type(spec, cb),
+            _ => {}
+        }
+    }
+    fn visit_type<'a>(spec: &'a TypeSpecifier, cb: &mut dyn FnMut(&'a EnumBody)) {
+        match spec {
+            TypeSpecifier::Enum(body) => cb(body),
+            TypeSpecifier::Struct(body) => visit_struct(body, cb),
+            TypeSpecifier::Union(body) => visit_union(body, cb),
+            _ => {}
+        }
+    }
+    fn visit_struct<'a>(body: &'a StructBody, cb: &mut dyn FnMut(&'a EnumBody)) {
+        for decl in &body.body {
+            visit_decl(decl, cb);
+        }
+    }
+    fn visit_union<'a>(body: &'a UnionBody, cb: &mut dyn FnMut(&'a EnumBody)) {
+        visit_decl(&body.discriminant, cb);
+        for case in &body.cases {
+            visit_decl(&case.decl, cb);
+        }
+        if let Some(decl) = &body.default {
+            visit_decl(decl, cb);
+        }
+    }
+    for file in files {
+        for defn in &file.spec.defns {
+            match defn {
+                Definition::TypeDef(decl) => visit_decl(decl, cb),
+                Definition::Struct { body, .. } => visit_struct(body, cb),
+                Definition::Union { body, .. } => visit_union(body, cb),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Parses every XDR file under the given paths, reporting (and skipping) files that fail to parse
+// Each `.x` file under `paths` (sorted), parsed if it can be read and parsed
+pub fn parse_files(
+    paths: &[PathBuf],
+    scan: &ScanOptions,
+) -> Vec<(PathBuf, Result<SourceFile, String>)> {
+    let mut found: Vec<PathBuf> = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let warnings = get_xdr_files(path, scan, &mut |path| found.push(path.to_path_buf()));
+            for warning in warnings {
+                eprintln!("{warning}");
+            }
+        } else {
+            found.push(path.clone());
+        }
+    }
+    found.sort();
+    found
+        .into_iter()
+        .map(|path| {
+            let file = fs::read_to_string(&path)
+                .map_err(|err| format!("{}: {}", path.display(), err))
+                .and_then(|text| SourceFile::parse(path.clone(), text));
+            (path, file)
+        })
+        .collect()
+}
+
+pub fn load_files(paths: &[PathBuf], scan: &ScanOptions) -> Vec<SourceFile> {
+    let mut files = vec![];
+    for (_, file) in parse_files(paths, scan) {
+        match file {
+            Ok(file) => files.push(file),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+    files
+}
+
+const USAGE: &str = "usage: xdr-ls gen <target> [options] [paths...]
+
+targets:
+    typescript [--codec]    TypeScript types (and DataView encoders/decoders with --codec)
+    kaitai [--type <T>]     Kaitai Struct spec (parsing <T> at the top level)
+    proto                   best-effort protobuf (proto3) schema
+    docs [--format <fmt>]   documentation pages (markdown or html) written to the
+                            directory given by --out (default: xdr-docs)
+    vectors --type <T> [--count <n>] [--seed <n>]
+                            random instances of <T> with their encodings (JSON)
+    wireshark [--type <T>]  Wireshark Lua dissector (decoding payloads as <T> by default)
+    rpc-stubs [--format <fmt>]
+                            client and server stubs for the RPC programs (typescript,
+                            or c to go with rpcgen's header and XDR routines)
+    rust                    Rust types deriving serde's Serialize and Deserialize
+    c-header                C header laid out like rpcgen -h output
+
+options:
+    -o, --out <file>        write output to <file> instead of stdout";
+
+#[derive(Default)]
+pub struct Options {
+    // Emit encoders/decoders alongside the types
+    pub codec: bool,
+    // Type to treat as the top-level message
+    pub root: Option<String>,
+    // Output flavor for generators supporting several
+    pub format: Option<String>,
+    // Number of test vectors
+    pub count: Option<usize>,
+    pub seed: Option<u64>,
+    // Name of the output file without its extension, for names derived from it
+    pub name: Option<String>,
+}
+
+pub fn run(args: &[String]) -> ExitCode {
+    let Some(target) = args.first() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    if target == "-h" || target == "--help" {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let mut out: Option<PathBuf> = None;
+    let mut opts = Options::default();
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--out" => match iter.next() {
+                Some(path) => out = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-t" | "--type" => match iter.next() {
+                Some(name) => opts.root = Some(name.clone()),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-f" | "--format" => match iter.next() {
+                Some(format) => opts.format = Some(format.clone()),
+                None => {
+                    eprintln!("{arg} requires an argument");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-n" | "--count" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(count) => opts.count = Some(count),
+                None => {
+                    eprintln!("{arg} requires a number");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--seed" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(seed) => opts.seed = Some(seed),
+                None => {
+                    eprintln!("{arg} requires a number");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--codec" => opts.codec = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    opts.name = out
+        .as_deref()
+        .and_then(Path::file_stem)
+        .map(|stem| stem.to_string_lossy().into_owned());
+    let files = load_files(&paths, &ScanOptions::default());
+    let schema = Schema::new(&files);
+    if target == "docs" {
+        let dir = out.unwrap_or(PathBuf::from("xdr-docs"));
+        if let Err(err) = fs::create_dir_all(&dir) {
+            eprintln!("{}: {}", dir.display(), err);
+            return ExitCode::FAILURE;
+        }
+        for (name, page) in docs::generate(&schema, &opts) {
+            let path = dir.join(name);
+            if let Err(err) = fs::write(&path, page) {
+                eprintln!("{}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+    let output = match generate(target, &schema, &opts) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match out {
+        Some(path) => {
+            if let Err(err) = fs::write(&path, output) {
+                eprintln!("{}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{output}"),
+    }
+    ExitCode::SUCCESS
+}
+
+// Runs a single-output generator (everything but `docs`)
+pub fn generate(target: &str, schema: &Schema, opts: &Options) -> Result<String, String> {
+    match target {
+        "typescript" | "ts" => Ok(typescript::generate(schema, opts)),
+        "kaitai" | "ksy" => Ok(kaitai::generate(schema, opts)),
+        "proto" | "protobuf" => Ok(proto::generate(schema, opts)),
+        "wireshark" | "lua" => Ok(wireshark::generate(schema, opts)),
+        "vectors" => vectors::generate(schema, opts),
+        "rpc-stubs" | "rpc" => rpc::generate(schema, opts),
+        "rust" | "rs" => Ok(rust::generate(schema, opts)),
+        "c-header" | "h" => Ok(c_header::generate(schema, opts)),
+        _ => Err(format!("unknown target {target}")),
+    }
+}
+
+// Documentation pages as (file name, contents)
+pub fn generate_docs(schema: &Schema, opts: &Options) -> Vec<(String, String)> {
+    docs::generate(schema, opts)
+}
+
+// File extension for a single-output generator's output
+pub fn extension(target: &str, format: Option<&str>) -> Option<&'static str> {
+    match target {
+        "rpc-stubs" | "rpc" if format == Some("c") => Some("c"),
+        "rpc-stubs" | "rpc" => Some("ts"),
+        "typescript" | "ts" => Some("ts"),
+        "kaitai" | "ksy" => Some("ksy"),
+        "proto" | "protobuf" => Some("proto"),
+        "wireshark" | "lua" => Some("lua"),
+        "vectors" => Some("json"),
+        "rust" | "rs" => Some("rs"),
+        "c-header" | "h" => Some("h"),
+        _ => None,
+    }
+}
@@ -0,0 +1,348 @@
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+pub fn generate(schema: &Schema, _opts: &Options) -> String {
+    let mut out = Emitter::new("  ");
+    out.line("// Generated by xdr-ls. Do not edit.");
+    out.line("//");
+    out.line("// This is a best-effort conversion: comments mark XDR constructs that protobuf");
+    out.line("// can't express directly.");
+    out.line("");
+    out.line("syntax = \"proto3\";");
+    out.line("");
+    let generator = Generator { schema };
+    for file in schema.files {
+        out.line(format!("// {}", file.path.display()));
+        out.line("");
+        for defn in &file.spec.defns {
+            generator.definition(&mut out, defn);
+        }
+    }
+    out.finish()
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+}
+
+fn camel(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) => c.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// Appends the notes for a field as a trailing comment
+fn with_notes(line: String, notes: &[String]) -> String {
+    if notes.is_empty() {
+        line
+    } else {
+        format!("{}  // {}", line, notes.join("; "))
+    }
+}
+
+impl Generator<'_, '_> {
+    fn definition(&self, out: &mut Emitter, defn: &Definition) {
+        match defn {
+            Definition::Constant { id, val } => {
+                out.line(format!(
+                    "// const {} = {}; (protobuf has no constants)",
+                    id.id, val
+                ));
+                out.line("");
+            }
+            Definition::TypeDef(decl) => {
+                let Some(id) = decl.id() else {
+                    return;
+                };
+                match decl {
+                    // Aliases of named types and scalars are inlined where they're used
+                    Declaration::Normal {
+                        spec: TypeSpecifier::BuiltIn(_) | TypeSpecifier::Ident(_),
+                        ..
+                    }
+                    | Declaration::FixedOpaque { .. }
+                    | Declaration::VarOpaque { .. }
+                    | Declaration::String { .. } => {
+                        out.line(format!("// typedef {} is inlined where it's used", id.id));
+                        out.line("");
+                    }
+                    Declaration::Normal {
+                        spec: TypeSpecifier::Enum(body),
+                        ..
+                    } => self.enumeration(out, &id.id, body),
+                    Declaration::Normal {
+                        spec: TypeSpecifier::Struct(body),
+                        ..
+                    } => self.structure(out, &id.id, body),
+                    Declaration::Normal {
+                        spec: TypeSpecifier::Union(body),
+                        ..
+                    } => self.union(out, &id.id, body),
+                    _ => {
+                        // Protobuf has no way to alias a repeated/optional field, so wrap it
+                        out.open(format!("message {} {{", id.id));
+                        out.line("// typedef wrapper");
+                        if let Some(line) = self.field(out, decl, "value", &mut 1, false) {
+                            out.line(line);
+                        }
+                        out.close("}");
+                        out.line("");
+                    }
+                }
+            }
+            Definition::Enum { id, body } => self.enumeration(out, &id.id, body),
+            Definition::Struct { id, body } => self.structure(out, &id.id, body),
+            Definition::Union { id, body } => self.union(out, &id.id, body),
+            Definition::Program { .. } => {}
+        }
+    }
+
+    fn enumeration(&self, out: &mut Emitter, name: &str, body: &EnumBody) {
+        let values: Vec<Option<i64>> = body
+            .body
+            .iter()
+            .map(|assign| self.schema.value(&assign.val))
+            .collect();
+        out.open(format!("enum {name} {{"));
+        let known: Vec<i64> = values.iter().flatten().copied().collect();
+        if known
+            .iter()
+            .enumerate()
+            .any(|(i, val)| known[..i].contains(val))
+        {
+            out.line("option allow_alias = true;");
+        }
+        if !values.contains(&Some(0)) {
+            out.line(format!(
+                "{}_UNSPECIFIED = 0;  // not in the XDR definition; proto3 requires a zero value",
+                name.to_ascii_uppercase()
+            ));
+        }
+        for (assign, val) in body.body.iter().zip(values) {
+            match val {
+                Some(val) => out.line(format!("{} = {};", assign.id.id, val)),
+                None => out.line(format!("// {}: could not evaluate value", assign.id.id)),
+            }
+        }
+        out.close("}");
+        out.line("");
+    }
+
+    fn structure(&self, out: &mut Emitter, name: &str, body: &StructBody) {
+        out.open(format!("message {name} {{"));
+        let mut num = 1;
+        for decl in &body.body {
+            if let Some(id) = decl.id()
+                && let Some(line) = self.field(out, decl, &id.id, &mut num, false)
+            {
+                out.line(line);
+            }
+        }
+        out.close("}");
+        out.line("");
+    }
+
+    fn union(&self, out: &mut Emitter, name: &str, body: &UnionBody) {
+        out.open(format!("message {name} {{"));
+        let mut num = 1;
+        let disc = body
+            .discriminant
+            .id()
+            .map_or("discriminant".to_string(), |id| id.id.clone());
+        if let Some(line) = self.field(out, &body.discriminant, &disc, &mut num, false) {
+            out.line(line);
+        }
+        // Nested definitions can't live inside the oneof, so collect its lines separately
+        let mut arms = vec![];
+        let mut arm = |out: &mut Emitter, decl: &Declaration, label: String| match decl.id() {
+            Some(id) => {
+                arms.push(format!("// {label}"));
+                arms.extend(self.field(out, decl, &id.id, &mut num, true));
+            }
+            None => arms.push(format!("// {label}: void")),
+        };
+        for case in &body.cases {
+            let values: Vec<String> = case
+                .values
+                .iter()
+                .map(|val| match val {
+                    Value::Id(id) => id.id.clone(),
+                    Value::Const(val) => val.clone(),
+                })
+                .collect();
+            arm(out, &case.decl, format!("case {}", values.join(", ")));
+        }
+        if let Some(decl) = &body.default {
+            arm(out, decl, "default".to_string());
+        }
+        out.line(format!(
+            "// the oneof arm must agree with `{disc}`, which protobuf can't enforce"
+        ));
+        out.open("oneof arm {");
+        for line in arms {
+            out.line(line);
+        }
+        out.close("}");
+        out.close("}");
+        out.line("");
+    }
+
+    fn bound(&self, size: &Option<Value>, notes: &mut Vec<String>) {
+        if let Some(size) = size {
+            notes.push(format!("at most {}", self.size(size)));
+        }
+    }
+
+    fn size(&self, size: &Value) -> String {
+        match (self.schema.value(size), size) {
+            (Some(val), Value::Id(id)) => format!("{} ({})", id.id, val),
+            (Some(val), Value::Const(_)) => val.to_string(),
+            (None, Value::Id(id)) => id.id.clone(),
+            (None, Value::Const(val)) => val.clone(),
+        }
+    }
+
+    // Returns the line declaring a field, emitting any nested definitions it needs to `out`;
+    // `in_oneof` fields can't be repeated or optional
+    fn field(
+        &self,
+        out: &mut Emitter,
+        decl: &Declaration,
+        name: &str,
+        num: &mut u32,
+        in_oneof: bool,
+    ) -> Option<String> {
+        let mut notes = vec![];
+        let (label, ty) = match decl {
+            Declaration::Normal { spec, .. } => ("", self.type_name(out, spec, name, &mut notes)),
+            Declaration::FixedArr { spec, size, .. } => {
+                notes.push(format!("fixed length {}", self.size(size)));
+                ("repeated ", self.type_name(out, spec, name, &mut notes))
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                self.bound(size, &mut notes);
+                ("repeated ", self.type_name(out, spec, name, &mut notes))
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                notes.push(format!("fixed length {}", self.size(size)));
+                ("", "bytes".to_string())
+            }
+            Declaration::VarOpaque { size, .. } => {
+                self.bound(size, &mut notes);
+                ("", "bytes".to_string())
+            }
+            Declaration::String { size, .. } => {
+                self.bound(size, &mut notes);
+                ("", "string".to_string())
+            }
+            Declaration::Optional { spec, .. } => {
+                ("optional ", self.type_name(out, spec, name, &mut notes))
+            }
+            Declaration::VOID => return None,
+        };
+        let ty = if in_oneof && label == "repeated " {
+            // oneof members can't be repeated, so wrap the list in a message
+            let wrapper = format!("{}List", camel(name));
+            out.open(format!("message {wrapper} {{"));
+            out.line(with_notes(format!("repeated {ty} value = 1;"), &notes));
+            out.close("}");
+            notes.clear();
+            wrapper
+        } else if !in_oneof {
+            format!("{label}{ty}")
+        } else {
+            ty
+        };
+        *num += 1;
+        Some(with_notes(
+            format!("{} {} = {};", ty, name, *num - 1),
+            &notes,
+        ))
+    }
+
+    fn type_name(
+        &self,
+        out: &mut Emitter,
+        spec: &TypeSpecifier,
+        field: &str,
+        notes: &mut Vec<String>,
+    ) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => match name.as_str() {
+                "int" => "int32",
+                "unsigned int" => "uint32",
+                "hyper" => "int64",
+                "unsigned hyper" => "uint64",
+                "float" => "float",
+                "double" => "double",
+                "bool" => "bool",
+                _ => {
+                    notes.push("quadruple has no protobuf equivalent; 16 raw bytes".to_string());
+                    "bytes"
+                }
+            }
+            .to_string(),
+            TypeSpecifier::Ident(id) => {
+                // Follow aliases of other named types (bounded in case of a typedef cycle)
+                let mut name = &id.id;
+                for _ in 0..64 {
+                    match self.schema.types.get(name.as_str()) {
+                        Some(Definition::TypeDef(Declaration::Normal {
+                            spec: TypeSpecifier::Ident(inner),
+                            ..
+                        })) => name = &inner.id,
+                        _ => break,
+                    }
+                }
+                let Some(Definition::TypeDef(decl)) = self.schema.types.get(name.as_str()) else {
+                    return name.clone();
+                };
+                match decl {
+                    Declaration::Normal {
+                        spec: spec @ TypeSpecifier::BuiltIn(_),
+                        ..
+                    } => {
+                        notes.push(format!("typedef {}", id.id));
+                        self.type_name(out, spec, field, &mut vec![])
+                    }
+                    Declaration::FixedOpaque { size, .. } => {
+                        notes.push(format!("{}: fixed length {}", id.id, self.size(size)));
+                        "bytes".to_string()
+                    }
+                    Declaration::VarOpaque { size, .. } => {
+                        notes.push(format!("typedef {}", id.id));
+                        self.bound(size, notes);
+                        "bytes".to_string()
+                    }
+                    Declaration::String { size, .. } => {
+                        notes.push(format!("typedef {}", id.id));
+                        self.bound(size, notes);
+                        "string".to_string()
+                    }
+                    _ => name.clone(),
+                }
+            }
+            TypeSpecifier::Enum(body) => {
+                let name = camel(field);
+                self.enumeration(out, &name, body);
+                name
+            }
+            TypeSpecifier::Struct(body) => {
+                let name = camel(field);
+                self.structure(out, &name, body);
+                name
+            }
+            TypeSpecifier::Union(body) => {
+                let name = camel(field);
+                self.union(out, &name, body);
+                name
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// RPC client and server stubs
+//
+// TypeScript stubs are self-contained, carrying the types and codecs with them. C stubs follow
+// `rpcgen -l` and `rpcgen -m`: they expect the header and XDR routines rpcgen generates from the
+// same file, and a server implementing one `<proc>_<vers>_svc` function per procedure.
+use super::{Emitter, Options, Schema, typescript};
+use crate::ast::*;
+
+pub fn generate(schema: &Schema, opts: &Options) -> Result<String, String> {
+    match opts.format.as_deref() {
+        None | Some("typescript" | "ts") => Ok(typescript::rpc_stubs(schema)),
+        Some("c") => Ok(c_stubs(schema)),
+        Some(format) => Err(format!("unknown rpc-stubs format {format}")),
+    }
+}
+
+// The C type and XDR routine for an argument or result type; None for inline definitions, which
+// rpcgen doesn't allow there either
+fn c_type(spec: &TypeSpecifier) -> Option<(String, String)> {
+    let (ty, routine) = match spec {
+        TypeSpecifier::BuiltIn(name) => match name.as_str() {
+            "int" => ("int", "int"),
+            "unsigned int" => ("u_int", "u_int"),
+            "hyper" => ("quad_t", "quad_t"),
+            "unsigned hyper" => ("u_quad_t", "u_quad_t"),
+            "float" => ("float", "float"),
+            "double" => ("double", "double"),
+            "bool" => ("bool_t", "bool"),
+            _ => return None,
+        },
+        TypeSpecifier::Ident(id) => return Some((id.id.clone(), format!("xdr_{}", id.id))),
+        _ => return None,
+    };
+    Some((ty.to_string(), format!("xdr_{routine}")))
+}
+
+// A program, version, or procedure number as C source
+pub fn number(schema: &Schema, val: &Value) -> String {
+    match (schema.value(val), val) {
+        (Some(n), _) => n.to_string(),
+        (None, Value::Const(text)) => text.clone(),
+        (None, Value::Id(id)) => id.id.clone(),
+    }
+}
+
+pub struct CProcedure<'a> {
+    pub proc: &'a Procedure,
+    // rpcgen's names for the client stub and the server function
+    pub name: String,
+    pub arg: (String, String),
+    pub result: (String, String),
+}
+
+// The procedures of a version rpcgen's default (single argument) calling convention supports
+pub fn c_procedures<'a>(
+    out: &mut Emitter,
+    version: &'a Version,
+    vers: &str,
+) -> Vec<CProcedure<'a>> {
+    let void = || ("void".to_string(), "xdr_void".to_string());
+    let mut procs = vec![];
+    for proc in &version.procedures {
+        let arg = match proc.args.as_slice() {
+            [] => Some(void()),
+            [arg] => c_type(arg),
+            _ => {
+                out.line(format!(
+                    "/* {} takes several arguments, which need rpcgen -N */",
+                    proc.id.id
+                ));
+                continue;
+            }
+        };
+        let result = match &proc.result {
+            Some(ty) => c_type(ty),
+            None => Some(void()),
+        };
+        let (Some(arg), Some(result)) = (arg, result) else {
+            out.line(format!(
+                "/* {} uses an inline type, which rpcgen doesn't support */",
+                proc.id.id
+            ));
+            continue;
+        };
+        procs.push(CProcedure {
+            proc,
+            name: format!("{}_{}", proc.id.id.to_lowercase(), vers),
+            arg,
+            result,
+        });
+    }
+    procs
+}
+
+fn c_stubs(schema: &Schema) -> String {
+    let mut out = Emitter::new("\t");
+    out.line("/* Generated by xdr-ls. Do not edit. */");
+    out.line("");
+    out.line("#include <stdio.h>");
+    out.line("#include <stdlib.h>");
+    out.line("#include <string.h>");
+    out.line("#include <rpc/rpc.h>");
+    for file in schema.files {
+        let has_program = file
+            .spec
+            .defns
+            .iter()
+            .any(|defn| matches!(defn, Definition::Program { .. }));
+        if let (true, Some(stem)) = (has_program, file.path.file_stem()) {
+            out.line(format!("#include \"{}.h\"", stem.to_string_lossy()));
+        }
+    }
+    out.line("");
+    out.line("static struct timeval TIMEOUT = { 25, 0 };");
+    out.line("");
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            let Definition::Program { id, versions, .. } = defn else {
+                continue;
+            };
+            for version in versions {
+                let vers = number(schema, &version.number);
+                let procs = c_procedures(&mut out, version, &vers);
+                c_client(&mut out, &procs);
+                c_server(&mut out, &id.id, &vers, &procs);
+            }
+        }
+    }
+    out.finish()
+}
+
+fn c_client(out: &mut Emitter, procs: &[CProcedure]) {
+    for proc in procs {
+        let (arg, arg_xdr) = &proc.arg;
+        let (result, result_xdr) = &proc.result;
+        let void_result = result == "void";
+        out.line(format!("{} *", if void_result { "void" } else { result }));
+        out.line(format!("{}({} *argp, CLIENT *clnt)", proc.name, arg));
+        out.open("{");
+        out.line(format!(
+            "static {} clnt_res;",
+            if void_result { "char" } else { result }
+        ));
+        out.line("");
+        out.line("memset((char *)&clnt_res, 0, sizeof(clnt_res));");
+        out.open(format!("if (clnt_call(clnt, {},", proc.proc.id.id));
+        out.line(format!("(xdrproc_t) {arg_xdr}, (caddr_t) argp,"));
+        out.line(format!("(xdrproc_t) {result_xdr}, (caddr_t) &clnt_res,"));
+        out.line("TIMEOUT) != RPC_SUCCESS) {");
+        out.line("return (NULL);");
+        out.close("}");
+        out.line(if void_result {
+            "return ((void *)&clnt_res);"
+        } else {
+            "return (&clnt_res);"
+        });
+        out.close("}");
+        out.line("");
+    }
+}
+
+fn c_server(out: &mut Emitter, program: &str, vers: &str, procs: &[CProcedure]) {
+    for proc in procs {
+        let (arg, _) = &proc.arg;
+        let (result, _) = &proc.result;
+        out.line(format!(
+            "extern {} *{}_svc({} *, struct svc_req *);",
+            if result == "void" { "void" } else { result },
+            proc.name,
+            arg
+        ));
+    }
+    out.line("");
+    out.line("void");
+    out.line(format!(
+        "{}_{}(struct svc_req *rqstp, SVCXPRT *transp)",
+        program.to_lowercase(),
+        vers
+    ));
+    out.open("{");
+    out.open("union {");
+    for proc in procs.iter().filter(|proc| proc.arg.0 != "void") {
+        out.line(format!("{} {}_arg;", proc.arg.0, proc.name));
+    }
+    // An empty union isn't valid C
+    out.line("int unused;");
+    out.close("} argument;");
+    out.line("char *result;");
+    out.line("xdrproc_t _xdr_argument, _xdr_result;");
+    out.line("char *(*local)(char *, struct svc_req *);");
+    out.line("");
+    out.line("switch (rqstp->rq_proc) {");
+    for proc in procs {
+        out.line(format!("case {}:", proc.proc.id.id));
+        out.indent();
+        out.line(format!("_xdr_argument = (xdrproc_t) {};", proc.arg.1));
+        out.line(format!("_xdr_result = (xdrproc_t) {};", proc.result.1));
+        out.line(format!(
+            "local = (char *(*)(char *, struct svc_req *)) {}_svc;",
+            proc.name
+        ));
+        out.line("break;");
+        out.dedent();
+        out.line("");
+    }
+    out.line("default:");
+    out.indent();
+    out.line("svcerr_noproc(transp);");
+    out.line("return;");
+    out.dedent();
+    out.line("}");
+    out.line("memset((char *)&argument, 0, sizeof(argument));");
+    out.open("if (!svc_getargs(transp, (xdrproc_t) _xdr_argument, (caddr_t) &argument)) {");
+    out.line("svcerr_decode(transp);");
+    out.line("return;");
+    out.close("}");
+    out.line("result = (*local)((char *)&argument, rqstp);");
+    out.open("if (result != NULL && !svc_sendreply(transp, (xdrproc_t) _xdr_result, result)) {");
+    out.line("svcerr_systemerr(transp);");
+    out.close("}");
+    out.open("if (!svc_freeargs(transp, (xdrproc_t) _xdr_argument, (caddr_t) &argument)) {");
+    out.line("fprintf(stderr, \"%s\", \"unable to free arguments\");");
+    out.line("exit(1);");
+    out.close("}");
+    out.close("}");
+    out.line("");
+}
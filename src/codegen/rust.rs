@@ -0,0 +1,249 @@
+// Rust types for serde-based XDR codecs such as serde-xdr. Names are kept as the XDR spells them.
+// Enums go through serde_repr so they encode as their values rather than their indices, and
+// variable-length opaque data is a serde_bytes `ByteBuf`. Inline types get a definition of their
+// own, named after where they appear (`Outer_field`).
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+// Serde derives fixed-size arrays only up to this length; longer ones become a `Vec`
+const MAX_ARRAY: i64 = 32;
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+pub fn generate(schema: &Schema, _opts: &Options) -> String {
+    let mut out = Emitter::new("    ");
+    out.line("// Generated by xdr-ls. Do not edit.");
+    out.line("//");
+    out.line(
+        "// A union encodes its arm by variant index, so one whose case values don't count up",
+    );
+    out.line("// from 0 in order needs hand-written Serialize and Deserialize impls.");
+    out.line(
+        "#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals, unused_imports)]",
+    );
+    out.line("");
+    out.line("use serde::{Deserialize, Serialize};");
+    out.line("use serde_bytes::ByteBuf;");
+    out.line("use serde_repr::{Deserialize_repr, Serialize_repr};");
+    out.line("");
+    let generator = Generator { schema };
+    for file in schema.files {
+        out.line(format!("// {}", file.path.display()));
+        out.line("");
+        for defn in &file.spec.defns {
+            generator.definition(&mut out, defn);
+        }
+    }
+    out.finish()
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+}
+
+fn field_name(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+impl Generator<'_, '_> {
+    fn definition(&self, out: &mut Emitter, defn: &Definition) {
+        let mut nested = vec![];
+        match defn {
+            Definition::Constant { id, .. } => {
+                out.line(match self.schema.consts.get(id.id.as_str()) {
+                    Some(val) => format!("pub const {}: i64 = {};", id.id, val),
+                    None => format!("// const {}: could not evaluate value", id.id),
+                });
+                out.line("");
+            }
+            Definition::TypeDef(decl) => match decl {
+                // An inline definition takes the typedef's name rather than being aliased
+                Declaration::Normal {
+                    spec: TypeSpecifier::Enum(body),
+                    id,
+                } => self.enumeration(out, &id.id, body),
+                Declaration::Normal {
+                    spec: TypeSpecifier::Struct(body),
+                    id,
+                } => self.structure(out, &id.id, body, &mut nested),
+                Declaration::Normal {
+                    spec: TypeSpecifier::Union(body),
+                    id,
+                } => self.union(out, &id.id, body, &mut nested),
+                _ => {
+                    if let Some(id) = decl.id()
+                        && let Some(ty) = self.field_type(decl, &id.id, &mut nested)
+                    {
+                        out.line(format!("pub type {} = {};", id.id, ty));
+                        out.line("");
+                    }
+                }
+            },
+            Definition::Enum { id, body } => self.enumeration(out, &id.id, body),
+            Definition::Struct { id, body } => self.structure(out, &id.id, body, &mut nested),
+            Definition::Union { id, body } => self.union(out, &id.id, body, &mut nested),
+            Definition::Program { versions, .. } => {
+                // Just the numbers; there are no stubs to hang them on
+                let names = std::iter::once(defn.id())
+                    .flatten()
+                    .chain(versions.iter().map(|version| &version.id))
+                    .chain(
+                        versions
+                            .iter()
+                            .flat_map(|version| version.procedures.iter().map(|proc| &proc.id)),
+                    );
+                for id in names {
+                    if let Some(val) = self.schema.consts.get(id.id.as_str()) {
+                        out.line(format!("pub const {}: u32 = {};", id.id, val));
+                    }
+                }
+                out.line("");
+            }
+        }
+        // Definitions of inline types, which may have nested ones of their own
+        while !nested.is_empty() {
+            for (name, spec) in std::mem::take(&mut nested) {
+                match spec {
+                    TypeSpecifier::Enum(body) => self.enumeration(out, &name, body),
+                    TypeSpecifier::Struct(body) => self.structure(out, &name, body, &mut nested),
+                    TypeSpecifier::Union(body) => self.union(out, &name, body, &mut nested),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn enumeration(&self, out: &mut Emitter, name: &str, body: &EnumBody) {
+        out.line(
+            "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr)]",
+        );
+        out.line("#[repr(i32)]");
+        out.open(format!("pub enum {name} {{"));
+        for assign in &body.body {
+            match self.schema.consts.get(assign.id.id.as_str()) {
+                Some(val) => out.line(format!("{} = {},", assign.id.id, val)),
+                None => out.line(format!("// {}: could not evaluate value", assign.id.id)),
+            }
+        }
+        out.close("}");
+        out.line("");
+    }
+
+    fn structure<'s>(
+        &self,
+        out: &mut Emitter,
+        name: &str,
+        body: &'s StructBody,
+        nested: &mut Vec<(String, &'s TypeSpecifier)>,
+    ) {
+        out.line("#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]");
+        out.open(format!("pub struct {name} {{"));
+        for decl in &body.body {
+            if let Some(id) = decl.id()
+                && let Some(ty) = self.field_type(decl, &format!("{name}_{}", id.id), nested)
+            {
+                out.line(format!("pub {}: {},", field_name(&id.id), ty));
+            }
+        }
+        out.close("}");
+        out.line("");
+    }
+
+    // One variant per case label, named after it, carrying the arm's value
+    fn union<'s>(
+        &self,
+        out: &mut Emitter,
+        name: &str,
+        body: &'s UnionBody,
+        nested: &mut Vec<(String, &'s TypeSpecifier)>,
+    ) {
+        out.line("#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]");
+        out.open(format!("pub enum {name} {{"));
+        // Labels sharing a case share its type, so it's only worked out (and queued) once
+        let mut arm = |decl: &'s Declaration| {
+            decl.id()
+                .and_then(|id| self.field_type(decl, &format!("{name}_{}", id.id), nested))
+                .map_or(String::new(), |ty| format!("({ty})"))
+        };
+        for case in &body.cases {
+            let ty = arm(&case.decl);
+            for val in &case.values {
+                match val {
+                    Value::Id(id) => out.line(format!("{}{ty},", id.id)),
+                    Value::Const(val) => out.line(format!("Case{}{ty},", val.replace('-', "Neg"))),
+                }
+            }
+        }
+        if let Some(decl) = &body.default {
+            out.line(format!("Default{},", arm(decl)));
+        }
+        out.close("}");
+        out.line("");
+    }
+
+    // The Rust type of a declaration (None for void); inline types are queued on `nested` under
+    // the name `owner`
+    fn field_type<'s>(
+        &self,
+        decl: &'s Declaration,
+        owner: &str,
+        nested: &mut Vec<(String, &'s TypeSpecifier)>,
+    ) -> Option<String> {
+        let array = |ty: String, size: &Value| match self.schema.value(size) {
+            Some(len) if (0..=MAX_ARRAY).contains(&len) => format!("[{ty}; {len}]"),
+            _ => format!("Vec<{ty}>"),
+        };
+        Some(match decl {
+            Declaration::Normal { spec, .. } => self.type_name(spec, owner, nested),
+            Declaration::FixedArr { spec, size, .. } => {
+                array(self.type_name(spec, owner, nested), size)
+            }
+            Declaration::VarArr { spec, .. } => {
+                format!("Vec<{}>", self.type_name(spec, owner, nested))
+            }
+            Declaration::FixedOpaque { size, .. } => array("u8".to_string(), size),
+            Declaration::VarOpaque { .. } => "ByteBuf".to_string(),
+            Declaration::String { .. } => "String".to_string(),
+            Declaration::Optional { spec, .. } => {
+                format!("Option<Box<{}>>", self.type_name(spec, owner, nested))
+            }
+            Declaration::VOID => return None,
+        })
+    }
+
+    fn type_name<'s>(
+        &self,
+        spec: &'s TypeSpecifier,
+        owner: &str,
+        nested: &mut Vec<(String, &'s TypeSpecifier)>,
+    ) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => match name.as_str() {
+                "int" => "i32",
+                "unsigned int" => "u32",
+                "hyper" => "i64",
+                "unsigned hyper" => "u64",
+                "float" => "f32",
+                "double" => "f64",
+                "bool" => "bool",
+                // No 128-bit float in Rust; keep the raw bytes
+                _ => "[u8; 16]",
+            }
+            .to_string(),
+            TypeSpecifier::Ident(id) => id.id.clone(),
+            _ => {
+                nested.push((owner.to_string(), spec));
+                owner.to_string()
+            }
+        }
+    }
+}
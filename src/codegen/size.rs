@@ -0,0 +1,200 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::Schema;
+use crate::ast::*;
+
+// Encoded size of a type per RFC 4506 (everything is padded to a multiple of 4 bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireSize {
+    Fixed(u64),
+    // `max` is None when the size is unbounded (or couldn't be determined)
+    Variable { min: u64, max: Option<u64> },
+}
+
+impl WireSize {
+    pub fn min(&self) -> u64 {
+        match self {
+            WireSize::Fixed(size) => *size,
+            WireSize::Variable { min, .. } => *min,
+        }
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        match self {
+            WireSize::Fixed(size) => Some(*size),
+            WireSize::Variable { max, .. } => *max,
+        }
+    }
+
+    fn new(min: u64, max: Option<u64>) -> Self {
+        if max == Some(min) {
+            WireSize::Fixed(min)
+        } else {
+            WireSize::Variable { min, max }
+        }
+    }
+
+    // Sequential composition (struct members)
+    fn then(self, other: WireSize) -> WireSize {
+        WireSize::new(
+            self.min() + other.min(),
+            self.max().zip(other.max()).map(|(a, b)| a + b),
+        )
+    }
+
+    // Alternatives (union arms)
+    fn or(self, other: WireSize) -> WireSize {
+        WireSize::new(
+            self.min().min(other.min()),
+            self.max().zip(other.max()).map(|(a, b)| a.max(b)),
+        )
+    }
+
+    fn times(self, count: u64) -> WireSize {
+        WireSize::new(
+            self.min() * count,
+            self.max().map(|max| max.saturating_mul(count)),
+        )
+    }
+}
+
+impl fmt::Display for WireSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireSize::Fixed(size) => write!(f, "{size} bytes"),
+            WireSize::Variable {
+                min,
+                max: Some(max),
+            } => write!(f, "{min} to {max} bytes"),
+            WireSize::Variable { min, max: None } => write!(f, "at least {min} bytes"),
+        }
+    }
+}
+
+fn padded(len: u64) -> u64 {
+    len.div_ceil(4) * 4
+}
+
+const UNKNOWN: WireSize = WireSize::Variable { min: 0, max: None };
+
+// Past this many named types deep (e.g. a long chain of optional pointers) a type is sized as
+// unknown rather than risking the stack
+const MAX_DEPTH: usize = 256;
+
+impl Schema<'_> {
+    pub fn definition_size(&self, defn: &Definition) -> Option<WireSize> {
+        let mut visiting = HashSet::new();
+        if let Some(id) = defn.id() {
+            visiting.insert(id.id.clone());
+        }
+        match defn {
+            Definition::Constant { .. } | Definition::Program { .. } => None,
+            Definition::TypeDef(decl) => Some(self.decl_size(decl, &mut visiting)),
+            Definition::Enum { .. } => Some(WireSize::Fixed(4)),
+            Definition::Struct { body, .. } => Some(self.struct_size(body, &mut visiting)),
+            Definition::Union { body, .. } => Some(self.union_size(body, &mut visiting)),
+        }
+    }
+
+    pub fn decl_size(&self, decl: &Declaration, visiting: &mut HashSet<String>) -> WireSize {
+        let count = |size: &Value| self.value(size).and_then(|n| u64::try_from(n).ok());
+        match decl {
+            Declaration::Normal { spec, .. } => self.type_size(spec, visiting),
+            Declaration::FixedArr { spec, size, .. } => match count(size) {
+                Some(n) => self.type_size(spec, visiting).times(n),
+                None => UNKNOWN,
+            },
+            Declaration::VarArr { spec, size, .. } => {
+                let elem = self.type_size(spec, visiting);
+                let max = size
+                    .as_ref()
+                    .and_then(count)
+                    .and_then(|n| elem.times(n).max());
+                WireSize::new(4, max.map(|max| max + 4))
+            }
+            Declaration::FixedOpaque { size, .. } => match count(size) {
+                Some(n) => WireSize::Fixed(padded(n)),
+                None => UNKNOWN,
+            },
+            Declaration::VarOpaque { size, .. } | Declaration::String { size, .. } => {
+                let max = size.as_ref().and_then(count);
+                WireSize::new(4, max.map(|n| 4 + padded(n)))
+            }
+            Declaration::Optional { spec, .. } => {
+                let inner = self.type_size(spec, visiting);
+                WireSize::new(4, inner.max().map(|max| max + 4))
+            }
+            Declaration::VOID => WireSize::Fixed(0),
+        }
+    }
+
+    // The padding that follows the data of an `opaque` or `string` declaration, as the fewest and
+    // most bytes there can be: fixed for `opaque[N]`, and dependent on the length for variable
+    // data. None if there's never any.
+    pub fn padding(&self, decl: &Declaration) -> Option<(u64, u64)> {
+        let count = |size: &Value| self.value(size).and_then(|n| u64::try_from(n).ok());
+        match decl {
+            Declaration::FixedOpaque { size, .. } => {
+                let n = count(size)?;
+                let pad = padded(n) - n;
+                (pad > 0).then_some((pad, pad))
+            }
+            Declaration::VarOpaque { size, .. } | Declaration::String { size, .. } => {
+                let max = size.as_ref().and_then(count);
+                (max != Some(0)).then_some((0, 3))
+            }
+            _ => None,
+        }
+    }
+
+    fn type_size(&self, spec: &TypeSpecifier, visiting: &mut HashSet<String>) -> WireSize {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => WireSize::Fixed(match name.as_str() {
+                "hyper" | "unsigned hyper" | "double" => 8,
+                "quadruple" => 16,
+                _ => 4,
+            }),
+            TypeSpecifier::Enum(_) => WireSize::Fixed(4),
+            TypeSpecifier::Struct(body) => self.struct_size(body, visiting),
+            TypeSpecifier::Union(body) => self.union_size(body, visiting),
+            TypeSpecifier::Ident(id) => {
+                if visiting.len() >= MAX_DEPTH {
+                    return UNKNOWN;
+                }
+                // Recursive types (e.g. linked lists) have no upper bound
+                if !visiting.insert(id.id.clone()) {
+                    return UNKNOWN;
+                }
+                let size = match self.types.get(id.id.as_str()) {
+                    Some(Definition::TypeDef(decl)) => self.decl_size(decl, visiting),
+                    Some(Definition::Enum { .. }) => WireSize::Fixed(4),
+                    Some(Definition::Struct { body, .. }) => self.struct_size(body, visiting),
+                    Some(Definition::Union { body, .. }) => self.union_size(body, visiting),
+                    _ => UNKNOWN,
+                };
+                visiting.remove(&id.id);
+                size
+            }
+        }
+    }
+
+    fn struct_size(&self, body: &StructBody, visiting: &mut HashSet<String>) -> WireSize {
+        body.body.iter().fold(WireSize::Fixed(0), |acc, decl| {
+            acc.then(self.decl_size(decl, visiting))
+        })
+    }
+
+    fn union_size(&self, body: &UnionBody, visiting: &mut HashSet<String>) -> WireSize {
+        let disc = self.decl_size(&body.discriminant, visiting);
+        let arms = body
+            .cases
+            .iter()
+            .map(|case| &case.decl)
+            .chain(body.default.as_deref())
+            .map(|decl| self.decl_size(decl, visiting))
+            .reduce(WireSize::or)
+            .unwrap_or(WireSize::Fixed(0));
+        disc.then(arms)
+    }
+}
@@ -0,0 +1,764 @@
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+const RUNTIME: &str = r#"export class XdrWriter {
+  private buf = new Uint8Array(64);
+  private view = new DataView(this.buf.buffer);
+  private pos = 0;
+
+  private reserve(n: number) {
+    if (this.pos + n <= this.buf.length) return;
+    let size = this.buf.length * 2;
+    while (size < this.pos + n) size *= 2;
+    const buf = new Uint8Array(size);
+    buf.set(this.buf);
+    this.buf = buf;
+    this.view = new DataView(buf.buffer);
+  }
+
+  int(v: number) { this.reserve(4); this.view.setInt32(this.pos, v); this.pos += 4; }
+  uint(v: number) { this.reserve(4); this.view.setUint32(this.pos, v); this.pos += 4; }
+  hyper(v: bigint) { this.reserve(8); this.view.setBigInt64(this.pos, v); this.pos += 8; }
+  uhyper(v: bigint) { this.reserve(8); this.view.setBigUint64(this.pos, v); this.pos += 8; }
+  float(v: number) { this.reserve(4); this.view.setFloat32(this.pos, v); this.pos += 4; }
+  double(v: number) { this.reserve(8); this.view.setFloat64(this.pos, v); this.pos += 8; }
+  bool(v: boolean) { this.int(v ? 1 : 0); }
+
+  opaque(v: Uint8Array, len: number) {
+    if (v.length !== len) throw new Error(`XDR: expected ${len} bytes, got ${v.length}`);
+    this.bytes(v);
+  }
+  varOpaque(v: Uint8Array, max?: number) {
+    if (max !== undefined && v.length > max) throw new Error(`XDR: ${v.length} bytes exceeds bound ${max}`);
+    this.uint(v.length);
+    this.bytes(v);
+  }
+  string(v: string, max?: number) { this.varOpaque(new TextEncoder().encode(v), max); }
+
+  array<T>(v: T[], len: number, f: (x: T) => void) {
+    if (v.length !== len) throw new Error(`XDR: expected ${len} elements, got ${v.length}`);
+    v.forEach(f);
+  }
+  varArray<T>(v: T[], max: number | undefined, f: (x: T) => void) {
+    if (max !== undefined && v.length > max) throw new Error(`XDR: ${v.length} elements exceeds bound ${max}`);
+    this.uint(v.length);
+    v.forEach(f);
+  }
+  optional<T>(v: T | null, f: (x: T) => void) {
+    this.bool(v !== null);
+    if (v !== null) f(v);
+  }
+
+  private bytes(b: Uint8Array) {
+    const padded = (b.length + 3) & ~3;
+    this.reserve(padded);
+    this.buf.set(b, this.pos);
+    this.buf.fill(0, this.pos + b.length, this.pos + padded);
+    this.pos += padded;
+  }
+
+  finish(): Uint8Array { return this.buf.slice(0, this.pos); }
+}
+
+export class XdrReader {
+  private pos = 0;
+
+  constructor(private view: DataView) {}
+
+  static from(bytes: Uint8Array): XdrReader {
+    return new XdrReader(new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength));
+  }
+
+  private need(n: number) {
+    if (this.pos + n > this.view.byteLength) throw new Error(`XDR: unexpected end of input at offset ${this.pos}`);
+  }
+
+  int(): number { this.need(4); const v = this.view.getInt32(this.pos); this.pos += 4; return v; }
+  uint(): number { this.need(4); const v = this.view.getUint32(this.pos); this.pos += 4; return v; }
+  hyper(): bigint { this.need(8); const v = this.view.getBigInt64(this.pos); this.pos += 8; return v; }
+  uhyper(): bigint { this.need(8); const v = this.view.getBigUint64(this.pos); this.pos += 8; return v; }
+  float(): number { this.need(4); const v = this.view.getFloat32(this.pos); this.pos += 4; return v; }
+  double(): number { this.need(8); const v = this.view.getFloat64(this.pos); this.pos += 8; return v; }
+  bool(): boolean {
+    const v = this.int();
+    if (v !== 0 && v !== 1) throw new Error(`XDR: invalid bool ${v} at offset ${this.pos - 4}`);
+    return v === 1;
+  }
+
+  opaque(len: number): Uint8Array {
+    const padded = (len + 3) & ~3;
+    this.need(padded);
+    const v = new Uint8Array(this.view.buffer, this.view.byteOffset + this.pos, len).slice();
+    this.pos += padded;
+    return v;
+  }
+  varOpaque(max?: number): Uint8Array {
+    const len = this.uint();
+    if (max !== undefined && len > max) throw new Error(`XDR: length ${len} exceeds bound ${max}`);
+    return this.opaque(len);
+  }
+  string(max?: number): string { return new TextDecoder().decode(this.varOpaque(max)); }
+
+  array<T>(len: number, f: () => T): T[] {
+    const v: T[] = [];
+    for (let i = 0; i < len; i++) v.push(f());
+    return v;
+  }
+  varArray<T>(max: number | undefined, f: () => T): T[] {
+    const len = this.uint();
+    if (max !== undefined && len > max) throw new Error(`XDR: length ${len} exceeds bound ${max}`);
+    return this.array(len, f);
+  }
+  optional<T>(f: () => T): T | null { return this.bool() ? f() : null; }
+
+  done() {
+    if (this.pos !== this.view.byteLength) throw new Error(`XDR: ${this.view.byteLength - this.pos} trailing bytes`);
+  }
+}
+"#;
+
+pub fn generate(schema: &Schema, opts: &Options) -> String {
+    let mut out = Emitter::new("  ");
+    out.line("// Generated by xdr-ls. Do not edit.");
+    out.line("");
+    if opts.codec {
+        for line in RUNTIME.lines() {
+            out.line(line);
+        }
+    }
+    let generator = Generator { schema };
+    for file in schema.files {
+        out.line(format!("// {}", file.path.display()));
+        out.line("");
+        for defn in &file.spec.defns {
+            generator.definition(&mut out, defn, opts.codec);
+        }
+    }
+    out.finish()
+}
+
+// Types and codecs followed by, for each program version, a client class calling through an
+// `RpcTransport`, the interface a server implements, and a function dispatching calls to it
+pub fn rpc_stubs(schema: &Schema) -> String {
+    let opts = Options {
+        codec: true,
+        ..Options::default()
+    };
+    let mut out = Emitter::new("  ");
+    for line in generate(schema, &opts).lines() {
+        out.line(line);
+    }
+    out.line("// RPC stubs");
+    out.line("");
+    out.open("export interface RpcTransport {");
+    out.line("// Sends a call and resolves with the results of a successful reply");
+    out.line(
+        "call(prog: number, vers: number, proc: number, args: Uint8Array): Promise<Uint8Array>;",
+    );
+    out.close("}");
+    out.line("");
+    let generator = Generator { schema };
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            if let Definition::Program {
+                versions, number, ..
+            } = defn
+            {
+                for version in versions {
+                    generator.version(&mut out, version, number);
+                }
+            }
+        }
+    }
+    out.finish()
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+}
+
+fn builtin_type(name: &str) -> &'static str {
+    match name {
+        "hyper" | "unsigned hyper" => "bigint",
+        "bool" => "boolean",
+        _ => "number",
+    }
+}
+
+fn builtin_method(name: &str) -> &'static str {
+    match name {
+        "int" => "int",
+        "unsigned int" => "uint",
+        "hyper" => "hyper",
+        "unsigned hyper" => "uhyper",
+        "float" => "float",
+        "bool" => "bool",
+        // TypeScript has no quadruple precision type, so decode as a double
+        _ => "double",
+    }
+}
+
+fn array_of(ty: String) -> String {
+    if ty.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{ty}[]")
+    } else {
+        format!("Array<{ty}>")
+    }
+}
+
+impl Generator<'_, '_> {
+    fn definition(&self, out: &mut Emitter, defn: &Definition, codec: bool) {
+        match defn {
+            Definition::Constant { id, val } => {
+                let val = parse_constant(val).map_or(val.clone(), |v| v.to_string());
+                out.line(format!("export const {} = {};", id.id, val));
+                out.line("");
+            }
+            Definition::TypeDef(decl) => {
+                let (Some(id), Some(ty)) = (decl.id(), self.decl_type(decl)) else {
+                    return;
+                };
+                out.line(format!("export type {} = {};", id.id, ty));
+                out.line("");
+                if codec {
+                    out.open(format!(
+                        "export function encode{0}(w: XdrWriter, v: {0}) {{",
+                        id.id
+                    ));
+                    self.encode_decl(out, decl, "v", 0);
+                    out.close("}");
+                    out.line("");
+                    out.open(format!(
+                        "export function decode{0}(r: XdrReader): {0} {{",
+                        id.id
+                    ));
+                    out.line(format!("return {};", self.decode_decl(decl, 0)));
+                    out.close("}");
+                    out.line("");
+                }
+            }
+            Definition::Enum { id, body } => {
+                out.open(format!("export enum {} {{", id.id));
+                for assign in &body.body {
+                    out.line(format!("{} = {},", assign.id.id, self.value(&assign.val)));
+                }
+                out.close("}");
+                out.line("");
+                if codec {
+                    out.open(format!(
+                        "export function encode{0}(w: XdrWriter, v: {0}) {{",
+                        id.id
+                    ));
+                    out.line("w.int(v);");
+                    out.close("}");
+                    out.line("");
+                    out.open(format!(
+                        "export function decode{0}(r: XdrReader): {0} {{",
+                        id.id
+                    ));
+                    out.line("const v = r.int();");
+                    out.line(format!(
+                        "if ({}[v] === undefined) throw new Error(`XDR: invalid {} value ${{v}}`);",
+                        id.id, id.id
+                    ));
+                    out.line(format!("return v as {};", id.id));
+                    out.close("}");
+                    out.line("");
+                }
+            }
+            Definition::Struct { id, body } => {
+                out.open(format!("export interface {} {{", id.id));
+                for decl in &body.body {
+                    if let (Some(field), Some(ty)) = (decl.id(), self.decl_type(decl)) {
+                        out.line(format!("{}: {};", field.id, ty));
+                    }
+                }
+                out.close("}");
+                out.line("");
+                if codec {
+                    out.open(format!(
+                        "export function encode{0}(w: XdrWriter, v: {0}) {{",
+                        id.id
+                    ));
+                    self.encode_struct(out, body, "v", 0);
+                    out.close("}");
+                    out.line("");
+                    out.open(format!(
+                        "export function decode{0}(r: XdrReader): {0} {{",
+                        id.id
+                    ));
+                    out.open("return {");
+                    for decl in &body.body {
+                        if let Some(field) = decl.id() {
+                            out.line(format!("{}: {},", field.id, self.decode_decl(decl, 0)));
+                        }
+                    }
+                    out.close("};");
+                    out.close("}");
+                    out.line("");
+                }
+            }
+            Definition::Union { id, body } => {
+                out.open(format!("export type {} =", id.id));
+                let arms = self.union_arms(body);
+                let last = arms.len().saturating_sub(1);
+                for (i, arm) in arms.into_iter().enumerate() {
+                    out.line(format!("| {}{}", arm, if i == last { ";" } else { "" }));
+                }
+                out.close("");
+                if codec {
+                    out.open(format!(
+                        "export function encode{0}(w: XdrWriter, v: {0}) {{",
+                        id.id
+                    ));
+                    self.encode_union(out, body, "v", 0);
+                    out.close("}");
+                    out.line("");
+                    out.open(format!(
+                        "export function decode{0}(r: XdrReader): {0} {{",
+                        id.id
+                    ));
+                    self.decode_union(out, body, &id.id, 0);
+                    out.close("}");
+                    out.line("");
+                }
+            }
+            Definition::Program { .. } => {}
+        }
+    }
+
+    fn version(&self, out: &mut Emitter, version: &Version, prog: &Value) {
+        let name = &version.id.id;
+        let (prog, vers) = (self.value(prog), self.value(&version.number));
+        let params = |proc: &Procedure| {
+            proc.args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| format!("arg{}: {}", i, self.type_spec(arg)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let result = |proc: &Procedure| {
+            proc.result
+                .as_ref()
+                .map_or("void".to_string(), |ty| self.type_spec(ty))
+        };
+
+        out.open(format!("export class {name}Client {{"));
+        out.line("constructor(private transport: RpcTransport) {}");
+        for proc in &version.procedures {
+            out.line("");
+            out.open(format!(
+                "async {}({}): Promise<{}> {{",
+                proc.id.id,
+                params(proc),
+                result(proc)
+            ));
+            out.line("const w = new XdrWriter();");
+            for (i, arg) in proc.args.iter().enumerate() {
+                self.encode_type(out, arg, &format!("arg{i}"), 0);
+            }
+            let call = format!(
+                "await this.transport.call({prog}, {vers}, {}, w.finish())",
+                self.value(&proc.number)
+            );
+            if let Some(ty) = &proc.result {
+                out.line(format!("const r = XdrReader.from({call});"));
+                out.line(format!("const result = {};", self.decode_type(ty, 0)));
+                out.line("r.done();");
+                out.line("return result;");
+            } else {
+                out.line(format!("{call};"));
+            }
+            out.close("}");
+        }
+        out.close("}");
+        out.line("");
+
+        out.open(format!("export interface {name}Server {{"));
+        for proc in &version.procedures {
+            let result = result(proc);
+            out.line(format!(
+                "{}({}): {} | Promise<{}>;",
+                proc.id.id,
+                params(proc),
+                result,
+                result
+            ));
+        }
+        out.close("}");
+        out.line("");
+
+        out.line("// Decodes a call's arguments, runs the procedure, and encodes its results; null for an");
+        out.line("// unknown procedure");
+        out.open(format!(
+            "export async function dispatch{name}(server: {name}Server, proc: number, args: Uint8Array): Promise<Uint8Array | null> {{"
+        ));
+        out.line("const r = XdrReader.from(args);");
+        out.line("const w = new XdrWriter();");
+        out.open("switch (proc) {");
+        for proc in &version.procedures {
+            out.open(format!("case {}: {{", self.value(&proc.number)));
+            for (i, arg) in proc.args.iter().enumerate() {
+                out.line(format!("const arg{} = {};", i, self.decode_type(arg, 0)));
+            }
+            out.line("r.done();");
+            let args = (0..proc.args.len())
+                .map(|i| format!("arg{i}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let call = format!("await server.{}({})", proc.id.id, args);
+            match &proc.result {
+                Some(ty) => {
+                    out.line(format!("const result = {call};"));
+                    self.encode_type(out, ty, "result", 0);
+                }
+                None => out.line(format!("{call};")),
+            }
+            out.line("break;");
+            out.close("}");
+        }
+        out.line("default:");
+        out.indent();
+        out.line("return null;");
+        out.dedent();
+        out.close("}");
+        out.line("return w.finish();");
+        out.close("}");
+        out.line("");
+    }
+
+    // The value of a constant or enum member as a TypeScript expression
+    fn value(&self, val: &Value) -> String {
+        match val {
+            Value::Const(val) => parse_constant(val).map_or(val.clone(), |v| v.to_string()),
+            Value::Id(id) => match self.schema.enum_of.get(id.id.as_str()) {
+                Some(owner) => format!("{}.{}", owner, id.id),
+                None => match self.schema.consts.get(id.id.as_str()) {
+                    // Members of anonymous enums have no TypeScript name
+                    Some(val) if !self.is_constant(&id.id) => val.to_string(),
+                    _ => id.id.clone(),
+                },
+            },
+        }
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        self.schema.constants.contains(name)
+    }
+
+    fn bound(&self, size: &Option<Value>) -> String {
+        size.as_ref()
+            .map_or("undefined".to_string(), |size| self.value(size))
+    }
+
+    fn decl_type(&self, decl: &Declaration) -> Option<String> {
+        Some(match decl {
+            Declaration::Normal { spec, .. } => self.type_spec(spec),
+            Declaration::FixedArr { spec, .. } | Declaration::VarArr { spec, .. } => {
+                array_of(self.type_spec(spec))
+            }
+            Declaration::FixedOpaque { .. } | Declaration::VarOpaque { .. } => {
+                "Uint8Array".to_string()
+            }
+            Declaration::String { .. } => "string".to_string(),
+            Declaration::Optional { spec, .. } => format!("{} | null", self.type_spec(spec)),
+            Declaration::VOID => return None,
+        })
+    }
+
+    fn type_spec(&self, spec: &TypeSpecifier) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => builtin_type(name).to_string(),
+            TypeSpecifier::Ident(id) => id.id.clone(),
+            TypeSpecifier::Enum(body) => body
+                .body
+                .iter()
+                .map(|assign| self.value(&assign.val))
+                .collect::<Vec<_>>()
+                .join(" | "),
+            TypeSpecifier::Struct(body) => {
+                let fields: Vec<String> = body
+                    .body
+                    .iter()
+                    .filter_map(|decl| {
+                        Some(format!("{}: {}", decl.id()?.id, self.decl_type(decl)?))
+                    })
+                    .collect();
+                format!("{{ {} }}", fields.join("; "))
+            }
+            TypeSpecifier::Union(body) => format!("({})", self.union_arms(body).join(" | ")),
+        }
+    }
+
+    // Whether the discriminant of a union is a boolean
+    fn is_bool_union(&self, body: &UnionBody) -> bool {
+        matches!(
+            &*body.discriminant,
+            Declaration::Normal { spec, .. }
+                if matches!(self.schema.resolve(spec), TypeSpecifier::BuiltIn(name) if name == "bool")
+        )
+    }
+
+    fn case_value(&self, body: &UnionBody, val: &Value) -> String {
+        if self.is_bool_union(body) {
+            let truthy = match val {
+                Value::Id(id) => id.id == "TRUE",
+                Value::Const(val) => val != "0",
+            };
+            truthy.to_string()
+        } else {
+            self.value(val)
+        }
+    }
+
+    // The type of a case value when used as a discriminant
+    fn case_type(&self, body: &UnionBody, val: &Value) -> String {
+        match val {
+            Value::Id(id) if !self.is_bool_union(body) && self.is_constant(&id.id) => {
+                format!("typeof {}", id.id)
+            }
+            _ => self.case_value(body, val),
+        }
+    }
+
+    fn union_arms(&self, body: &UnionBody) -> Vec<String> {
+        let disc = body
+            .discriminant
+            .id()
+            .map_or("discriminant".to_string(), |id| id.id.clone());
+        let mut arms = vec![];
+        for case in &body.cases {
+            let values: Vec<String> = case
+                .values
+                .iter()
+                .map(|val| self.case_type(body, val))
+                .collect();
+            arms.push(self.arm(&disc, values.join(" | "), &case.decl));
+        }
+        if let Some(decl) = &body.default {
+            let ty = self
+                .decl_type(&body.discriminant)
+                .unwrap_or("number".to_string());
+            arms.push(self.arm(&disc, ty, decl));
+        }
+        arms
+    }
+
+    fn arm(&self, disc: &str, disc_type: String, decl: &Declaration) -> String {
+        match (decl.id(), self.decl_type(decl)) {
+            (Some(id), Some(ty)) => format!("{{ {}: {}; {}: {} }}", disc, disc_type, id.id, ty),
+            _ => format!("{{ {}: {} }}", disc, disc_type),
+        }
+    }
+
+    fn encode_decl(&self, out: &mut Emitter, decl: &Declaration, v: &str, depth: usize) {
+        let x = format!("x{depth}");
+        match decl {
+            Declaration::Normal { spec, .. } => self.encode_type(out, spec, v, depth),
+            Declaration::FixedArr { spec, size, .. } => {
+                out.open(format!(
+                    "w.array({}, {}, ({}) => {{",
+                    v,
+                    self.value(size),
+                    x
+                ));
+                self.encode_type(out, spec, &x, depth + 1);
+                out.close("});");
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                out.open(format!(
+                    "w.varArray({}, {}, ({}) => {{",
+                    v,
+                    self.bound(size),
+                    x
+                ));
+                self.encode_type(out, spec, &x, depth + 1);
+                out.close("});");
+            }
+            Declaration::FixedOpaque { size, .. } => {
+                out.line(format!("w.opaque({}, {});", v, self.value(size)))
+            }
+            Declaration::VarOpaque { size, .. } => {
+                out.line(format!("w.varOpaque({}, {});", v, self.bound(size)))
+            }
+            Declaration::String { size, .. } => {
+                out.line(format!("w.string({}, {});", v, self.bound(size)))
+            }
+            Declaration::Optional { spec, .. } => {
+                out.open(format!("w.optional({}, ({}) => {{", v, x));
+                self.encode_type(out, spec, &x, depth + 1);
+                out.close("});");
+            }
+            Declaration::VOID => {}
+        }
+    }
+
+    fn encode_type(&self, out: &mut Emitter, spec: &TypeSpecifier, v: &str, depth: usize) {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => out.line(format!("w.{}({});", builtin_method(name), v)),
+            TypeSpecifier::Ident(id) => out.line(format!("encode{}(w, {});", id.id, v)),
+            TypeSpecifier::Enum(_) => out.line(format!("w.int({v});")),
+            TypeSpecifier::Struct(body) => self.encode_struct(out, body, v, depth),
+            TypeSpecifier::Union(body) => self.encode_union(out, body, v, depth),
+        }
+    }
+
+    fn encode_struct(&self, out: &mut Emitter, body: &StructBody, v: &str, depth: usize) {
+        for decl in &body.body {
+            if let Some(field) = decl.id() {
+                self.encode_decl(out, decl, &format!("{}.{}", v, field.id), depth);
+            }
+        }
+    }
+
+    fn encode_union(&self, out: &mut Emitter, body: &UnionBody, v: &str, depth: usize) {
+        let Some(disc) = body.discriminant.id() else {
+            return;
+        };
+        let disc = format!("{}.{}", v, disc.id);
+        self.encode_decl(out, &body.discriminant, &disc, depth);
+        // With a default arm the discriminant can't narrow the union, so arm fields are
+        // accessed without type checking
+        let arm_value = |id: &Identifier| {
+            if body.default.is_some() {
+                format!("({} as any).{}", v, id.id)
+            } else {
+                format!("{}.{}", v, id.id)
+            }
+        };
+        out.open(format!("switch ({disc}) {{"));
+        for case in &body.cases {
+            for val in &case.values {
+                out.line(format!("case {}:", self.case_value(body, val)));
+            }
+            out.indent();
+            if let Some(id) = case.decl.id() {
+                self.encode_decl(out, &case.decl, &arm_value(id), depth);
+            }
+            out.line("break;");
+            out.dedent();
+        }
+        out.open("default:");
+        match &body.default {
+            Some(decl) => {
+                if let Some(id) = decl.id() {
+                    self.encode_decl(out, decl, &arm_value(id), depth);
+                }
+                out.line("break;");
+            }
+            None => out.line(format!(
+                "throw new Error(`XDR: invalid discriminant ${{{disc}}}`);"
+            )),
+        }
+        out.dedent();
+        out.close("}");
+    }
+
+    fn decode_union(&self, out: &mut Emitter, body: &UnionBody, ty: &str, depth: usize) {
+        let Some(disc) = body.discriminant.id() else {
+            return;
+        };
+        let d = format!("d{depth}");
+        let arm = |decl: &Declaration| match decl.id() {
+            Some(id) => format!(
+                "return {{ {}: {}, {}: {} }} as {};",
+                disc.id,
+                d,
+                id.id,
+                self.decode_decl(decl, depth),
+                ty
+            ),
+            None => format!("return {{ {}: {} }} as {};", disc.id, d, ty),
+        };
+        out.line(format!(
+            "const {} = {};",
+            d,
+            self.decode_decl(&body.discriminant, depth)
+        ));
+        out.open(format!("switch ({d}) {{"));
+        for case in &body.cases {
+            for val in &case.values {
+                out.line(format!("case {}:", self.case_value(body, val)));
+            }
+            out.indent();
+            out.line(arm(&case.decl));
+            out.dedent();
+        }
+        out.open("default:");
+        match &body.default {
+            Some(decl) => out.line(arm(decl)),
+            None => out.line(format!(
+                "throw new Error(`XDR: invalid discriminant ${{{d}}}`);"
+            )),
+        }
+        out.dedent();
+        out.close("}");
+    }
+
+    fn decode_decl(&self, decl: &Declaration, depth: usize) -> String {
+        match decl {
+            Declaration::Normal { spec, .. } => self.decode_type(spec, depth),
+            Declaration::FixedArr { spec, size, .. } => format!(
+                "r.array({}, () => {})",
+                self.value(size),
+                self.decode_type(spec, depth + 1)
+            ),
+            Declaration::VarArr { spec, size, .. } => format!(
+                "r.varArray({}, () => {})",
+                self.bound(size),
+                self.decode_type(spec, depth + 1)
+            ),
+            Declaration::FixedOpaque { size, .. } => format!("r.opaque({})", self.value(size)),
+            Declaration::VarOpaque { size, .. } => format!("r.varOpaque({})", self.bound(size)),
+            Declaration::String { size, .. } => format!("r.string({})", self.bound(size)),
+            Declaration::Optional { spec, .. } => {
+                format!("r.optional(() => {})", self.decode_type(spec, depth + 1))
+            }
+            Declaration::VOID => "undefined".to_string(),
+        }
+    }
+
+    fn decode_type(&self, spec: &TypeSpecifier, depth: usize) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => format!("r.{}()", builtin_method(name)),
+            TypeSpecifier::Ident(id) => format!("decode{}(r)", id.id),
+            TypeSpecifier::Enum(body) => {
+                let ty = self.type_spec(spec);
+                let values: Vec<String> = body
+                    .body
+                    .iter()
+                    .map(|assign| self.value(&assign.val))
+                    .collect();
+                format!(
+                    "((v) => {{ if (![{}].includes(v)) throw new Error(`XDR: invalid enum value ${{v}}`); return v as {}; }})(r.int())",
+                    values.join(", "),
+                    ty
+                )
+            }
+            TypeSpecifier::Struct(body) => {
+                let fields: Vec<String> = body
+                    .body
+                    .iter()
+                    .filter_map(|decl| {
+                        Some(format!(
+                            "{}: {}",
+                            decl.id()?.id,
+                            self.decode_decl(decl, depth)
+                        ))
+                    })
+                    .collect();
+                format!("{{ {} }}", fields.join(", "))
+            }
+            TypeSpecifier::Union(body) => {
+                let ty = self.type_spec(spec);
+                let mut inner = Emitter::new("");
+                self.decode_union(&mut inner, body, &ty, depth + 1);
+                format!(
+                    "(() => {{ {} }})()",
+                    inner.finish().lines().collect::<Vec<_>>().join(" ")
+                )
+            }
+        }
+    }
+}
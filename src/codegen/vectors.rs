@@ -0,0 +1,263 @@
+use serde_json::{Map, Value as Json, json};
+
+use super::{Options, Schema};
+use crate::ast::*;
+use crate::codec;
+
+// Caps applied to unbounded (or very large) variable-length data
+const MAX_ELEMENTS: u64 = 8;
+const MAX_BYTES: u64 = 16;
+// Past this nesting depth, optionals are absent and variable-length arrays are empty so that
+// recursive types terminate
+const MAX_DEPTH: usize = 6;
+
+// xorshift64*; good enough for test data and reproducible from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // Uniform-ish in 0..=max
+    fn upto(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(n) => self.next() % n,
+            None => self.next(),
+        }
+    }
+}
+
+pub fn generate(schema: &Schema, opts: &Options) -> Result<String, String> {
+    let Some(root) = &opts.root else {
+        return Err("vectors requires --type".to_string());
+    };
+    if !schema.types.contains_key(root.as_str()) {
+        return Err(format!("unknown type `{root}`"));
+    }
+    let seed = opts.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+    });
+    let mut generator = Generator {
+        schema,
+        // xorshift gets stuck at zero
+        rng: Rng(seed.max(1)),
+        depth: 0,
+    };
+    let mut vectors = vec![];
+    for _ in 0..opts.count.unwrap_or(10) {
+        let value = generator.named(root);
+        let bytes = codec::encode(schema, root, &value).map_err(|err| err.to_string())?;
+        vectors.push(json!({ "value": value, "hex": codec::to_hex(&bytes) }));
+    }
+    let output = json!({ "type": root, "seed": seed, "vectors": vectors });
+    Ok(serde_json::to_string_pretty(&output).unwrap_or_default() + "\n")
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+    rng: Rng,
+    depth: usize,
+}
+
+impl Generator<'_, '_> {
+    fn named(&mut self, name: &str) -> Json {
+        self.depth += 1;
+        let value = match self.schema.types.get(name) {
+            Some(Definition::TypeDef(decl)) => self.decl(decl),
+            Some(Definition::Enum { body, .. }) => self.enumeration(body),
+            Some(Definition::Struct { body, .. }) => self.structure(body),
+            Some(Definition::Union { body, .. }) => self.union(body),
+            _ => Json::Null,
+        };
+        self.depth -= 1;
+        value
+    }
+
+    fn len(&mut self, size: &Option<Value>, cap: u64) -> u64 {
+        if self.depth > MAX_DEPTH {
+            return 0;
+        }
+        let max = size
+            .as_ref()
+            .and_then(|size| self.schema.value(size))
+            .map_or(cap, |max| (max.max(0) as u64).min(cap));
+        self.rng.upto(max)
+    }
+
+    fn count(&self, size: &Value) -> u64 {
+        self.schema.value(size).map_or(0, |n| n.max(0) as u64)
+    }
+
+    fn hex(&mut self, len: u64) -> Json {
+        let bytes: Vec<u8> = (0..len).map(|_| self.rng.next() as u8).collect();
+        Json::String(codec::to_hex(&bytes))
+    }
+
+    fn decl(&mut self, decl: &Declaration) -> Json {
+        match decl {
+            Declaration::Normal { spec, .. } => self.ty(spec),
+            Declaration::FixedArr { spec, size, .. } => {
+                Json::Array((0..self.count(size)).map(|_| self.ty(spec)).collect())
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                let len = self.len(size, MAX_ELEMENTS);
+                Json::Array((0..len).map(|_| self.ty(spec)).collect())
+            }
+            Declaration::FixedOpaque { size, .. } => self.hex(self.count(size)),
+            Declaration::VarOpaque { size, .. } => {
+                let len = self.len(size, MAX_BYTES);
+                self.hex(len)
+            }
+            Declaration::String { size, .. } => {
+                const CHARS: &[u8] =
+                    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+                let len = self.len(size, MAX_BYTES);
+                let s: String = (0..len)
+                    .map(|_| CHARS[self.rng.upto(CHARS.len() as u64 - 1) as usize] as char)
+                    .collect();
+                Json::String(s)
+            }
+            Declaration::Optional { spec, .. } => {
+                if self.depth > MAX_DEPTH || self.rng.upto(1) == 0 {
+                    Json::Null
+                } else {
+                    self.ty(spec)
+                }
+            }
+            Declaration::VOID => Json::Null,
+        }
+    }
+
+    fn ty(&mut self, spec: &TypeSpecifier) -> Json {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => match name.as_str() {
+                "int" => json!(self.rng.next() as i32),
+                "unsigned int" => json!(self.rng.next() as u32),
+                "hyper" => json!(self.rng.next() as i64),
+                "unsigned hyper" => json!(self.rng.next()),
+                // Multiples of 1/8 are exactly representable, so values survive a round trip
+                "float" | "double" => json!((self.rng.upto(2000) as f64 - 1000.0) / 8.0),
+                "bool" => json!(self.rng.upto(1) == 1),
+                _ => self.hex(16),
+            },
+            TypeSpecifier::Ident(id) => self.named(&id.id),
+            TypeSpecifier::Enum(body) => self.enumeration(body),
+            TypeSpecifier::Struct(body) => self.structure(body),
+            TypeSpecifier::Union(body) => self.union(body),
+        }
+    }
+
+    fn enumeration(&mut self, body: &EnumBody) -> Json {
+        let i = self.rng.upto(body.body.len().saturating_sub(1) as u64) as usize;
+        body.body
+            .get(i)
+            .map_or(Json::Null, |assign| Json::String(assign.id.id.clone()))
+    }
+
+    fn structure(&mut self, body: &StructBody) -> Json {
+        let mut obj = Map::new();
+        for decl in &body.body {
+            if let Some(id) = decl.id() {
+                obj.insert(id.id.clone(), self.decl(decl));
+            }
+        }
+        Json::Object(obj)
+    }
+
+    // The enum a discriminant is drawn from, if any
+    fn disc_enum<'s>(&'s self, decl: &'s Declaration) -> Option<&'s EnumBody> {
+        let Declaration::Normal { spec, .. } = decl else {
+            return None;
+        };
+        match self.schema.resolve(spec) {
+            TypeSpecifier::Enum(body) => Some(body),
+            TypeSpecifier::Ident(id) => match self.schema.types.get(id.id.as_str()) {
+                Some(Definition::Enum { body, .. }) => Some(body),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn is_bool(&self, decl: &Declaration) -> bool {
+        matches!(
+            decl,
+            Declaration::Normal { spec, .. }
+                if matches!(self.schema.resolve(spec), TypeSpecifier::BuiltIn(name) if name == "bool")
+        )
+    }
+
+    // The JSON form of a discriminant with the given numeric value
+    fn disc_json(&self, decl: &Declaration, val: i64) -> Json {
+        if let Some(body) = self.disc_enum(decl) {
+            let member = body
+                .body
+                .iter()
+                .find(|assign| self.schema.value(&assign.val) == Some(val));
+            if let Some(member) = member {
+                return Json::String(member.id.id.clone());
+            }
+        }
+        if self.is_bool(decl) {
+            json!(val != 0)
+        } else {
+            json!(val)
+        }
+    }
+
+    fn union(&mut self, body: &UnionBody) -> Json {
+        let covered: Vec<i64> = body
+            .cases
+            .iter()
+            .flat_map(|case| &case.values)
+            .filter_map(|val| self.schema.case_value(val))
+            .collect();
+        // A discriminant value that selects the default arm
+        let default_value = body.default.as_ref().and_then(|_| {
+            let candidates: Vec<i64> = match self.disc_enum(&body.discriminant) {
+                Some(enum_body) => enum_body
+                    .body
+                    .iter()
+                    .filter_map(|assign| self.schema.value(&assign.val))
+                    .collect(),
+                None if self.is_bool(&body.discriminant) => vec![0, 1],
+                None => (0..=covered.len() as i64).collect(),
+            };
+            candidates.into_iter().find(|val| !covered.contains(val))
+        });
+        let choices = body.cases.len() + default_value.is_some() as usize;
+        if choices == 0 {
+            return Json::Null;
+        }
+        let choice = self.rng.upto(choices as u64 - 1) as usize;
+        let (val, arm) = match body.cases.get(choice) {
+            Some(case) => {
+                let i = self.rng.upto(case.values.len().saturating_sub(1) as u64) as usize;
+                let val = case
+                    .values
+                    .get(i)
+                    .and_then(|val| self.schema.case_value(val))
+                    .unwrap_or(0);
+                (val, &case.decl)
+            }
+            None => match (default_value, &body.default) {
+                (Some(val), Some(decl)) => (val, &**decl),
+                _ => return Json::Null,
+            },
+        };
+        let mut obj = Map::new();
+        if let Some(id) = body.discriminant.id() {
+            obj.insert(id.id.clone(), self.disc_json(&body.discriminant, val));
+        }
+        if let Some(id) = arm.id() {
+            obj.insert(id.id.clone(), self.decl(arm));
+        }
+        Json::Object(obj)
+    }
+}
@@ -0,0 +1,490 @@
+use super::{Emitter, Options, Schema};
+use crate::ast::*;
+
+// Runtime shared by the generated decoders. Every decoder has the signature
+// `(tvb, tree, off, label) -> off, value`, where `value` is the decoded number for integers,
+// bools and enums (so unions can pick an arm) and nil otherwise.
+const RUNTIME: &str = r#"local xdr = Proto("xdr", "XDR")
+
+local function pad(n)
+  return (4 - n % 4) % 4
+end
+
+local function scalar(tvb, tree, off, len, label, value, text)
+  tree:add(xdr, tvb(off, len), label .. ": " .. text)
+  return off + len, value
+end
+
+local function malformed(item, message)
+  item:add_expert_info(PI_MALFORMED, PI_ERROR, message)
+end
+
+-- Builtin types
+local P = {}
+P["int"] = function(tvb, tree, off, label)
+  local v = tvb(off, 4):int()
+  return scalar(tvb, tree, off, 4, label, v, tostring(v))
+end
+P["unsigned int"] = function(tvb, tree, off, label)
+  local v = tvb(off, 4):uint()
+  return scalar(tvb, tree, off, 4, label, v, tostring(v))
+end
+P["hyper"] = function(tvb, tree, off, label)
+  local v = tvb(off, 8):int64()
+  return scalar(tvb, tree, off, 8, label, v, tostring(v))
+end
+P["unsigned hyper"] = function(tvb, tree, off, label)
+  local v = tvb(off, 8):uint64()
+  return scalar(tvb, tree, off, 8, label, v, tostring(v))
+end
+P["float"] = function(tvb, tree, off, label)
+  return scalar(tvb, tree, off, 4, label, nil, tostring(tvb(off, 4):float()))
+end
+P["double"] = function(tvb, tree, off, label)
+  return scalar(tvb, tree, off, 8, label, nil, tostring(tvb(off, 8):float()))
+end
+P["quadruple"] = function(tvb, tree, off, label)
+  return scalar(tvb, tree, off, 16, label, nil, tvb(off, 16):bytes():tohex())
+end
+P["bool"] = function(tvb, tree, off, label)
+  local v = tvb(off, 4):uint()
+  return scalar(tvb, tree, off, 4, label, v, v ~= 0 and "TRUE" or "FALSE")
+end
+
+local function enum(names)
+  return function(tvb, tree, off, label)
+    local v = tvb(off, 4):int()
+    return scalar(tvb, tree, off, 4, label, v, (names[v] or "unknown") .. " (" .. v .. ")")
+  end
+end
+
+local function unknown(name)
+  return function()
+    error("undefined type " .. name)
+  end
+end
+
+local function fixed_opaque(tvb, tree, off, n, label)
+  local text = n > 0 and tvb(off, n):bytes():tohex() or ""
+  tree:add(xdr, tvb(off, n + pad(n)), label .. ": " .. text)
+  return off + n + pad(n)
+end
+
+local function var_opaque(tvb, tree, off, max, label, is_string)
+  local n = tvb(off, 4):uint()
+  local item = tree:add(xdr, tvb(off, 4), label)
+  if max and n > max then
+    item:append_text(": length " .. n .. " exceeds bound " .. max)
+    malformed(item, "length exceeds bound")
+    error("length exceeds bound")
+  end
+  local text = ""
+  if n > 0 then
+    text = is_string and tvb(off + 4, n):string() or tvb(off + 4, n):bytes():tohex()
+  end
+  item:set_len(4 + n + pad(n))
+  item:append_text(" (" .. n .. " bytes): " .. text)
+  return off + 4 + n + pad(n)
+end
+
+-- Decoders for the workspace's types, keyed by name; anonymous types are keyed by
+-- `owner.field`
+local T = {}
+"#;
+
+const DISSECTOR: &str = r#"xdr.prefs.type = Pref.enum("Type", DEFAULT_TYPE, "Type to decode each payload as",
+  TYPE_PREFS, false)
+xdr.prefs.port = Pref.uint("Port", 0, "UDP and TCP port to decode as XDR (0 to disable)")
+
+local registered_port = 0
+
+function xdr.prefs_changed()
+  if registered_port ~= 0 then
+    DissectorTable.get("udp.port"):remove(registered_port, xdr)
+    DissectorTable.get("tcp.port"):remove(registered_port, xdr)
+  end
+  registered_port = xdr.prefs.port
+  if registered_port ~= 0 then
+    DissectorTable.get("udp.port"):add(registered_port, xdr)
+    DissectorTable.get("tcp.port"):add(registered_port, xdr)
+  end
+end
+
+function xdr.dissector(tvb, pinfo, tree)
+  local name = TYPES[xdr.prefs.type]
+  pinfo.cols.protocol = "XDR"
+  pinfo.cols.info = name
+  local item = tree:add(xdr, tvb(), "XDR " .. name)
+  local ok, off = pcall(T[name], tvb, item, 0, name)
+  if not ok then
+    malformed(item, "truncated or malformed: " .. tostring(off))
+  elseif off < tvb:len() then
+    item:add_expert_info(PI_PROTOCOL, PI_WARN, (tvb:len() - off) .. " trailing bytes")
+  end
+  return tvb:len()
+end
+
+-- Other dissectors (for example, one for an RPC program) can decode these types with
+-- `xdr_types[name](tvb, tree, offset, label)`
+xdr_types = T
+"#;
+
+pub fn generate(schema: &Schema, opts: &Options) -> String {
+    let mut generator = Generator {
+        schema,
+        decoders: vec![],
+    };
+    let mut names = vec![];
+    for file in schema.files {
+        for defn in &file.spec.defns {
+            if let Some(name) = generator.definition(defn) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut out = Emitter::new("  ");
+    out.line("-- Generated by xdr-ls. Do not edit.");
+    out.line("--");
+    out.line("-- Load with `wireshark -X lua_script:<this file>` (or copy it to the plugins");
+    out.line("-- directory), then pick the type and port under Preferences > Protocols > XDR.");
+    out.line("");
+    let mut text = out.finish();
+    text.push_str(RUNTIME);
+    for decoder in generator.decoders {
+        text.push('\n');
+        text.push_str(&decoder);
+    }
+
+    let mut out = Emitter::new("  ");
+    out.line("");
+    out.open("local TYPES = {");
+    for name in &names {
+        out.line(format!("\"{name}\","));
+    }
+    out.close("}");
+    out.open("local TYPE_PREFS = {");
+    for (i, name) in names.iter().enumerate() {
+        out.line(format!("{{ {}, \"{}\", {} }},", i + 1, name, i + 1));
+    }
+    out.close("}");
+    let default = opts
+        .root
+        .as_ref()
+        .and_then(|root| names.iter().position(|name| name == root))
+        .map_or(1, |i| i + 1);
+    out.line(format!("local DEFAULT_TYPE = {default}"));
+    out.line("");
+    text.push_str(&out.finish());
+    text.push_str(DISSECTOR);
+    text
+}
+
+struct Generator<'a, 'b> {
+    schema: &'b Schema<'a>,
+    // The source of each `T[...]` definition, in order
+    decoders: Vec<String>,
+}
+
+// `label .. "[" .. i .. "]"` and friends
+fn element_label(label: &str) -> String {
+    format!("{label} .. \"[\" .. i .. \"]\"")
+}
+
+impl Generator<'_, '_> {
+    // Adds the decoder for a definition, returning the name of the type it decodes
+    fn definition(&mut self, defn: &Definition) -> Option<String> {
+        match defn {
+            Definition::Constant { .. } | Definition::Program { .. } => None,
+            Definition::TypeDef(decl) => {
+                let id = decl.id()?;
+                let mut out = Emitter::new("  ");
+                out.open(format!(
+                    "T[\"{}\"] = function(tvb, tree, off, label)",
+                    id.id
+                ));
+                match decl {
+                    // Pass the value through so aliases can be used as discriminants
+                    Declaration::Normal { spec, .. } => {
+                        let decoder = self.decoder(spec, &id.id, "value");
+                        out.line(format!("return {decoder}(tvb, tree, off, label)"));
+                    }
+                    _ => {
+                        self.decl(&mut out, decl, "tree", "label", &id.id);
+                        out.line("return off");
+                    }
+                }
+                out.close("end");
+                self.decoders.push(out.finish());
+                Some(id.id.clone())
+            }
+            Definition::Enum { id, body } => {
+                let names = self.enum_names(body);
+                self.decoders
+                    .push(format!("T[\"{}\"] = enum({})\n", id.id, names));
+                Some(id.id.clone())
+            }
+            Definition::Struct { id, body } => {
+                self.structure(&id.id, body);
+                Some(id.id.clone())
+            }
+            Definition::Union { id, body } => {
+                self.union(&id.id, body);
+                Some(id.id.clone())
+            }
+        }
+    }
+
+    // A Lua table literal mapping enum values to member names
+    fn enum_names(&self, body: &EnumBody) -> String {
+        let mut members: Vec<(i64, Vec<&str>)> = vec![];
+        for assign in &body.body {
+            let Some(val) = self.schema.value(&assign.val) else {
+                continue;
+            };
+            // Aliases for the same value are shown together
+            match members.iter_mut().find(|(v, _)| *v == val) {
+                Some((_, names)) => names.push(&assign.id.id),
+                None => members.push((val, vec![&assign.id.id])),
+            }
+        }
+        let entries: Vec<String> = members
+            .iter()
+            .map(|(val, names)| format!("[{}] = \"{}\"", val, names.join("/")))
+            .collect();
+        format!("{{ {} }}", entries.join(", "))
+    }
+
+    fn open_compound(out: &mut Emitter, name: &str) {
+        out.open(format!("T[\"{name}\"] = function(tvb, tree, off, label)"));
+        out.line("local sub = tree:add(xdr, tvb(off, 0), label)");
+        out.line("local start = off");
+    }
+
+    fn close_compound(&mut self, mut out: Emitter) {
+        out.line("sub:set_len(off - start)");
+        out.line("return off");
+        out.close("end");
+        self.decoders.push(out.finish());
+    }
+
+    fn structure(&mut self, name: &str, body: &StructBody) {
+        let mut out = Emitter::new("  ");
+        Self::open_compound(&mut out, name);
+        for decl in &body.body {
+            if let Some(id) = decl.id() {
+                self.decl(&mut out, decl, "sub", &format!("\"{}\"", id.id), name);
+            }
+        }
+        self.close_compound(out);
+    }
+
+    fn union(&mut self, name: &str, body: &UnionBody) {
+        let mut out = Emitter::new("  ");
+        Self::open_compound(&mut out, name);
+        let disc = body
+            .discriminant
+            .id()
+            .map_or("discriminant".to_string(), |id| id.id.clone());
+        match &*body.discriminant {
+            Declaration::Normal { spec, .. } => {
+                let decoder = self.decoder(spec, name, &disc);
+                out.line("local d");
+                out.line(format!("off, d = {decoder}(tvb, sub, off, \"{disc}\")"));
+            }
+            _ => {
+                out.line(format!(
+                    "error(\"discriminant {disc} is not an integer or enum\")"
+                ));
+                self.close_compound(out);
+                return;
+            }
+        }
+        let mut first = true;
+        for case in &body.cases {
+            let values: Vec<String> = case
+                .values
+                .iter()
+                .map(|val| match self.schema.case_value(val) {
+                    Some(val) => format!("d == {val}"),
+                    // Never matches, but keeps the arm visible in the output
+                    None => format!("false --[[ unresolved {} ]]", value_text(val)),
+                })
+                .collect();
+            let keyword = if first { "if" } else { "elseif" };
+            first = false;
+            out.open(format!("{} {} then", keyword, values.join(" or ")));
+            self.arm(&mut out, &case.decl, name);
+            out.dedent();
+        }
+        let default = body.default.as_deref();
+        match (first, default) {
+            (true, Some(decl)) => self.arm(&mut out, decl, name),
+            (true, None) => {
+                out.line("malformed(sub, \"no arm for discriminant \" .. tostring(d))");
+            }
+            (false, Some(decl)) => {
+                out.open("else");
+                self.arm(&mut out, decl, name);
+                out.close("end");
+            }
+            (false, None) => {
+                out.open("else");
+                out.line("malformed(sub, \"no arm for discriminant \" .. tostring(d))");
+                out.close("end");
+            }
+        }
+        self.close_compound(out);
+    }
+
+    fn arm(&mut self, out: &mut Emitter, decl: &Declaration, owner: &str) {
+        match decl.id() {
+            Some(id) => self.decl(out, decl, "sub", &format!("\"{}\"", id.id), owner),
+            None => out.line("-- void"),
+        }
+    }
+
+    // The Lua expression naming the decoder for a type, hoisting anonymous types into their own
+    // `T["owner.field"]` entries
+    fn decoder(&mut self, spec: &TypeSpecifier, owner: &str, field: &str) -> String {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => format!("P[\"{name}\"]"),
+            TypeSpecifier::Ident(id) => {
+                if self.schema.types.contains_key(id.id.as_str()) {
+                    format!("T[\"{}\"]", id.id)
+                } else {
+                    format!("unknown(\"{}\")", id.id)
+                }
+            }
+            TypeSpecifier::Enum(body) => format!("enum({})", self.enum_names(body)),
+            TypeSpecifier::Struct(body) => {
+                let name = format!("{owner}.{field}");
+                self.structure(&name, body);
+                format!("T[\"{name}\"]")
+            }
+            TypeSpecifier::Union(body) => {
+                let name = format!("{owner}.{field}");
+                self.union(&name, body);
+                format!("T[\"{name}\"]")
+            }
+        }
+    }
+
+    fn size(&self, size: &Value) -> Option<i64> {
+        self.schema.value(size).filter(|size| *size >= 0)
+    }
+
+    fn max(&self, size: &Option<Value>) -> String {
+        size.as_ref()
+            .and_then(|size| self.size(size))
+            .map_or("nil".to_string(), |max| max.to_string())
+    }
+
+    // Emits statements decoding `decl` into `tree` and advancing `off`
+    fn decl(
+        &mut self,
+        out: &mut Emitter,
+        decl: &Declaration,
+        tree: &str,
+        label: &str,
+        owner: &str,
+    ) {
+        let field = decl.id().map_or("value", |id| id.id.as_str()).to_string();
+        let unresolved = |out: &mut Emitter, size: &Value| {
+            out.line(format!(
+                "error(\"could not evaluate size {}\")",
+                value_text(size)
+            ));
+        };
+        match decl {
+            Declaration::Normal { spec, .. } => {
+                let decoder = self.decoder(spec, owner, &field);
+                out.line(format!("off = {decoder}(tvb, {tree}, off, {label})"));
+            }
+            Declaration::FixedArr { spec, size, .. } => {
+                let Some(n) = self.size(size) else {
+                    return unresolved(out, size);
+                };
+                let decoder = self.decoder(spec, owner, &field);
+                out.open("do");
+                out.line(format!(
+                    "local arr = {tree}:add(xdr, tvb(off, 0), {label} .. \" [{n}]\")"
+                ));
+                out.line("local arr_start = off");
+                out.open(format!("for i = 0, {} do", n - 1));
+                out.line(format!(
+                    "off = {}(tvb, arr, off, {})",
+                    decoder,
+                    element_label(label)
+                ));
+                out.close("end");
+                out.line("arr:set_len(off - arr_start)");
+                out.close("end");
+            }
+            Declaration::VarArr { spec, size, .. } => {
+                let decoder = self.decoder(spec, owner, &field);
+                let max = self.max(size);
+                out.open("do");
+                out.line("local n = tvb(off, 4):uint()");
+                out.line(format!(
+                    "local arr = {tree}:add(xdr, tvb(off, 4), {label} .. \" [\" .. n .. \"]\")"
+                ));
+                if max != "nil" {
+                    out.open(format!("if n > {max} then"));
+                    out.line(format!("malformed(arr, \"length exceeds bound {max}\")"));
+                    out.line("error(\"length exceeds bound\")");
+                    out.close("end");
+                }
+                out.line("local arr_start = off");
+                out.line("off = off + 4");
+                out.open("for i = 0, n - 1 do");
+                out.line(format!(
+                    "off = {}(tvb, arr, off, {})",
+                    decoder,
+                    element_label(label)
+                ));
+                out.close("end");
+                out.line("arr:set_len(off - arr_start)");
+                out.close("end");
+            }
+            Declaration::FixedOpaque { size, .. } => match self.size(size) {
+                Some(n) => out.line(format!(
+                    "off = fixed_opaque(tvb, {tree}, off, {n}, {label})"
+                )),
+                None => unresolved(out, size),
+            },
+            Declaration::VarOpaque { size, .. } => out.line(format!(
+                "off = var_opaque(tvb, {}, off, {}, {}, false)",
+                tree,
+                self.max(size),
+                label
+            )),
+            Declaration::String { size, .. } => out.line(format!(
+                "off = var_opaque(tvb, {}, off, {}, {}, true)",
+                tree,
+                self.max(size),
+                label
+            )),
+            Declaration::Optional { spec, .. } => {
+                let decoder = self.decoder(spec, owner, &field);
+                out.open("if tvb(off, 4):uint() == 0 then");
+                out.line(format!(
+                    "{tree}:add(xdr, tvb(off, 4), {label} .. \": (absent)\")"
+                ));
+                out.line("off = off + 4");
+                out.close("else");
+                out.indent();
+                out.line(format!("off = {decoder}(tvb, {tree}, off + 4, {label})"));
+                out.close("end");
+            }
+            Declaration::VOID => {}
+        }
+    }
+}
+
+fn value_text(val: &Value) -> String {
+    match val {
+        Value::Id(id) => id.id.clone(),
+        Value::Const(val) => val.clone(),
+    }
+}
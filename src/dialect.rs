@@ -0,0 +1,268 @@
+// The variants of XDR that files may be written in, beyond RFC 4506
+//
+// xdrpp adds `%` passthrough lines and `namespace` blocks, which the grammar accepts. rpcgen
+// (RFC 5531) adds `program` blocks describing RPC programs.
+//
+// Syntax the grammar doesn't accept is claimed before parsing by a `DialectExtension`. Each one
+// scans a file's text for the spans it owns; the spans are blanked out (byte for byte, so
+// positions don't move) and the items found are indexed alongside the parsed definitions. Syntax
+// of your own is plugged in by implementing the trait and passing it to `register`, from any crate
+// using this one, before anything is parsed.
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+
+use tower_lsp::lsp_types::SymbolKind;
+
+use crate::ast::Identifier;
+use crate::rename::non_code_spans;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Xdrpp,
+    Rpcgen,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdrpp" => Some(Dialect::Xdrpp),
+            "rpcgen" => Some(Dialect::Rpcgen),
+            _ => None,
+        }
+    }
+
+    // The dialect a file's text shows it's written in: rpcgen for `program` blocks or C
+    // preprocessor lines, xdrpp for `namespace` blocks or `%` lines, and None for plain RFC 4506
+    pub fn detect(text: &str) -> Option<Self> {
+        let comments = non_code_spans(text);
+        let mut xdrpp = false;
+        let mut line_start = 0;
+        for line in text.split_inclusive('\n') {
+            let start = line_start;
+            line_start += line.len();
+            let body = line.trim_start();
+            if in_spans(&comments, start + line.len() - body.len()) {
+                continue;
+            }
+            let keyword = |word: &str| {
+                body.strip_prefix(word)
+                    .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+            };
+            if keyword("program") || body.starts_with('#') {
+                return Some(Dialect::Rpcgen);
+            }
+            xdrpp |= keyword("namespace") || body.starts_with('%');
+        }
+        xdrpp.then_some(Dialect::Xdrpp)
+    }
+
+    // The dialect of a file: the configured one, or else the one it's detected to be written in
+    pub fn of(configured: Option<Self>, text: &str) -> Self {
+        configured
+            .or_else(|| Self::detect(text))
+            .unwrap_or_default()
+    }
+}
+
+// A `program` block following RFC 5531's conventions: a number in the range for user-defined
+// programs, version 1, and procedure 0 as the no-op NULL procedure
+pub const PROGRAM_SNIPPET: &str = "program ${1:NAME}_PROG {
+\tversion ${1:NAME}_VERS {
+\t\tvoid ${1:NAME}_NULL(void) = 0;
+\t\t${2:result} ${3:PROCEDURE}(${4:argument}) = ${5:1};
+\t} = ${6:1};
+} = ${7:0x20000000};";
+
+pub trait DialectExtension: Send + Sync {
+    // What the extension is called, which the index cache is keyed by (see `registered_names`)
+    fn name(&self) -> &'static str;
+
+    // The items in `text`, in any order. Spans must not split characters.
+    fn scan(&self, text: &str) -> Vec<ExtensionItem>;
+}
+
+#[derive(Debug, Clone)]
+pub enum ExtensionKind {
+    // A definition the grammar doesn't know, indexed as a symbol of the given kind
+    Definition { id: Identifier, kind: SymbolKind },
+    // An `@name args` annotation in a comment, belonging to the definition after it
+    Annotation { name: String, args: String },
+    // Syntax that's only skipped
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtensionItem {
+    pub start: usize,
+    pub end: usize,
+    pub kind: ExtensionKind,
+}
+
+// The built-in extensions
+pub const EXTENSIONS: &[&dyn DialectExtension] = &[&Preprocessor, &Annotations];
+
+// Extensions added by `register`, run after the built-in ones
+static REGISTERED: RwLock<Vec<Arc<dyn DialectExtension>>> = RwLock::new(Vec::new());
+
+// Adds an extension for every file parsed from then on, by the server or anything else in the
+// process. Files parsed before keep what they were parsed with, so register extensions first.
+pub fn register(extension: Box<dyn DialectExtension>) {
+    REGISTERED
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Arc::from(extension));
+}
+
+// The names of the registered extensions, in order: indexes cached by a run with others are of
+// no use, as they could be missing or have extra items
+pub fn registered_names() -> Vec<&'static str> {
+    registered()
+        .iter()
+        .map(|extension| extension.name())
+        .collect()
+}
+
+fn registered() -> Vec<Arc<dyn DialectExtension>> {
+    REGISTERED
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+// Runs every extension over `text`, returning the text to parse, with the claimed spans blanked
+// out, and the items found in source order
+pub fn preprocess(text: &str) -> (Cow<'_, str>, Vec<ExtensionItem>) {
+    let registered = registered();
+    let mut items: Vec<ExtensionItem> = EXTENSIONS
+        .iter()
+        .copied()
+        .chain(registered.iter().map(|extension| &**extension))
+        .flat_map(|extension| extension.scan(text))
+        .filter(|item| {
+            item.start <= item.end
+                && text.is_char_boundary(item.start)
+                && text.is_char_boundary(item.end)
+        })
+        .collect();
+    if items.is_empty() {
+        return (Cow::Borrowed(text), items);
+    }
+    items.sort_by_key(|item| item.start);
+    let mut bytes = text.as_bytes().to_vec();
+    for item in &items {
+        for byte in &mut bytes[item.start..item.end] {
+            if *byte != b'\n' && *byte != b'\r' {
+                *byte = b' ';
+            }
+        }
+    }
+    // Whole characters were replaced with ASCII, so this can't fail
+    let masked = String::from_utf8(bytes).unwrap_or_else(|_| text.to_string());
+    (Cow::Owned(masked), items)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn in_spans(spans: &[(usize, usize)], offset: usize) -> bool {
+    spans
+        .iter()
+        .any(|&(start, end)| start <= offset && offset < end)
+}
+
+// C preprocessor lines, which rpcgen runs its input through. `#define NAME ...` defines a
+// constant; other directives are skipped, so both sides of an `#ifdef` are seen.
+struct Preprocessor;
+
+impl DialectExtension for Preprocessor {
+    fn name(&self) -> &'static str {
+        "preprocessor"
+    }
+
+    fn scan(&self, text: &str) -> Vec<ExtensionItem> {
+        let comments = non_code_spans(text);
+        let mut items = vec![];
+        let mut line_start = 0;
+        for line in text.split_inclusive('\n') {
+            let start = line_start;
+            line_start += line.len();
+            let body = line.trim_end_matches(['\n', '\r']);
+            let indent = body.len() - body.trim_start().len();
+            if !body[indent..].starts_with('#') || in_spans(&comments, start + indent) {
+                continue;
+            }
+            let end = start + body.len();
+            let kind = body[indent + 1..]
+                .trim_start()
+                .strip_prefix("define")
+                .filter(|rest| rest.starts_with([' ', '\t']))
+                .and_then(|rest| {
+                    let name_start = end - rest.trim_start().len();
+                    let name_len = text[name_start..end]
+                        .find(|c| !is_ident_char(c))
+                        .unwrap_or(end - name_start);
+                    (name_len > 0).then(|| ExtensionKind::Definition {
+                        id: Identifier {
+                            id: text[name_start..name_start + name_len].to_string(),
+                            start: name_start,
+                            end: name_start + name_len,
+                        },
+                        kind: SymbolKind::CONSTANT,
+                    })
+                })
+                .unwrap_or(ExtensionKind::Skipped);
+            items.push(ExtensionItem {
+                start: start + indent,
+                end,
+                kind,
+            });
+        }
+        items
+    }
+}
+
+// `@name args` annotations in comments, with the arguments running to the end of the line or
+// comment
+struct Annotations;
+
+impl DialectExtension for Annotations {
+    fn name(&self) -> &'static str {
+        "annotations"
+    }
+
+    fn scan(&self, text: &str) -> Vec<ExtensionItem> {
+        let mut items = vec![];
+        for (start, end) in non_code_spans(text) {
+            let comment = &text[start..end];
+            if !comment.starts_with('/') {
+                continue;
+            }
+            for (i, _) in comment.match_indices('@') {
+                let before = comment[..i].chars().next_back();
+                if before.is_some_and(|c| !c.is_whitespace() && c != '*' && c != '/') {
+                    continue;
+                }
+                let rest = &comment[i + 1..];
+                let name_len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+                if name_len == 0 {
+                    continue;
+                }
+                let line = &rest[name_len..];
+                let line = &line[..line.find('\n').unwrap_or(line.len())];
+                let line = &line[..line.find("*/").unwrap_or(line.len())];
+                let args = line.trim_end();
+                items.push(ExtensionItem {
+                    start: start + i,
+                    end: start + i + 1 + name_len + args.len(),
+                    kind: ExtensionKind::Annotation {
+                        name: rest[..name_len].to_string(),
+                        args: args.trim().to_string(),
+                    },
+                });
+            }
+        }
+        items
+    }
+}
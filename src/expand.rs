@@ -0,0 +1,268 @@
+// Expanded type definitions
+//
+// A type written out with nothing left to look up: typedefs are followed, the structs, unions, and
+// enums it refers to are written inline, constants are replaced by their values, and every
+// declaration is annotated with its wire size. A type referring back to one being expanded (a
+// linked list) is left as a name, as is one nested too deep.
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::codegen::{Emitter, Schema};
+
+// Past this many named types deep (e.g. a long chain of optional pointers) types are left as
+// names rather than risking the stack
+const MAX_DEPTH: usize = 256;
+
+struct Expander<'a, 'b> {
+    schema: &'b Schema<'a>,
+    out: Emitter,
+    // Names being expanded, which aren't expanded again
+    stack: Vec<&'b str>,
+}
+
+// The text of a value, evaluated when it can be
+fn value_text(schema: &Schema, val: &Value) -> String {
+    match (schema.value(val), val) {
+        (Some(n), _) => n.to_string(),
+        (None, Value::Const(text)) => text.clone(),
+        (None, Value::Id(id)) => id.id.clone(),
+    }
+}
+
+impl<'a, 'b> Expander<'a, 'b> {
+    fn bound(&self, size: &Option<Value>) -> String {
+        size.as_ref()
+            .map(|size| value_text(self.schema, size))
+            .unwrap_or_default()
+    }
+
+    fn size_comment(&self, decl: &Declaration) -> String {
+        let mut visiting: HashSet<String> =
+            self.stack.iter().map(|name| name.to_string()).collect();
+        format!(" /* {} */", self.schema.decl_size(decl, &mut visiting))
+    }
+
+    // Writes `decl` as a declaration of `name`, starting with `prefix` (`typedef `)
+    fn declaration(&mut self, prefix: &str, decl: &'b Declaration, name: &str) {
+        let comment = self.size_comment(decl);
+        let (spec, tail) = match decl {
+            Declaration::Normal { spec, .. } => {
+                // `typedef T[N] name;` used as `name field;` becomes `T field[N];`
+                if let TypeSpecifier::Ident(id) = spec
+                    && let Some(Definition::TypeDef(inner)) = self.schema.types.get(id.id.as_str())
+                    && !self.stack.contains(&id.id.as_str())
+                    && self.stack.len() < MAX_DEPTH
+                {
+                    self.stack.push(&id.id);
+                    self.declaration(prefix, inner, name);
+                    self.stack.pop();
+                    return;
+                }
+                (spec, name.to_string())
+            }
+            Declaration::FixedArr { spec, size, .. } => (
+                self.schema.resolve(spec),
+                format!("{name}[{}]", value_text(self.schema, size)),
+            ),
+            Declaration::VarArr { spec, size, .. } => (
+                self.schema.resolve(spec),
+                format!("{name}<{}>", self.bound(size)),
+            ),
+            Declaration::Optional { spec, .. } => (self.schema.resolve(spec), format!("*{name}")),
+            Declaration::FixedOpaque { size, .. } => {
+                let size = value_text(self.schema, size);
+                self.out
+                    .line(format!("{prefix}opaque {name}[{size}];{comment}"));
+                return;
+            }
+            Declaration::VarOpaque { size, .. } => {
+                let size = self.bound(size);
+                self.out
+                    .line(format!("{prefix}opaque {name}<{size}>;{comment}"));
+                return;
+            }
+            Declaration::String { size, .. } => {
+                let size = self.bound(size);
+                self.out
+                    .line(format!("{prefix}string {name}<{size}>;{comment}"));
+                return;
+            }
+            Declaration::VOID => {
+                self.out.line("void;");
+                return;
+            }
+        };
+        self.type_spec(prefix, spec, &format!("{tail};{comment}"));
+    }
+
+    // Writes a type followed by `tail`, inline if it's a struct, union, or enum
+    fn type_spec(&mut self, prefix: &str, spec: &'b TypeSpecifier, tail: &str) {
+        match spec {
+            TypeSpecifier::BuiltIn(name) => self.out.line(format!("{prefix}{name} {tail}")),
+            TypeSpecifier::Enum(body) => {
+                self.out.open(format!("{prefix}enum {{"));
+                self.enum_body(body);
+                self.out.close(format!("}} {tail}"));
+            }
+            TypeSpecifier::Struct(body) => {
+                self.out.open(format!("{prefix}struct {{"));
+                self.struct_body(body);
+                self.out.close(format!("}} {tail}"));
+            }
+            TypeSpecifier::Union(body) => {
+                self.union_header(&format!("{prefix}union"), body, "");
+                self.union_body(body);
+                self.out.close(format!("}} {tail}"));
+            }
+            TypeSpecifier::Ident(id) => {
+                let defn = self.schema.types.get(id.id.as_str()).copied();
+                if self.stack.contains(&id.id.as_str()) || self.stack.len() >= MAX_DEPTH {
+                    self.out.line(format!("{prefix}{} {tail}", id.id));
+                    return;
+                }
+                let inline = match defn {
+                    Some(Definition::Enum { body, .. }) => Inline::Enum(body),
+                    Some(Definition::Struct { body, .. }) => Inline::Struct(body),
+                    Some(Definition::Union { body, .. }) => Inline::Union(body),
+                    // Undefined, or a typedef of an array (which a declaration can't nest)
+                    _ => {
+                        self.out.line(format!("{prefix}{} {tail}", id.id));
+                        return;
+                    }
+                };
+                self.stack.push(&id.id);
+                match inline {
+                    Inline::Enum(body) => {
+                        self.out.open(format!("{prefix}enum {{ /* {} */", id.id));
+                        self.enum_body(body);
+                    }
+                    Inline::Struct(body) => {
+                        self.out.open(format!("{prefix}struct {{ /* {} */", id.id));
+                        self.struct_body(body);
+                    }
+                    Inline::Union(body) => {
+                        self.union_header(
+                            &format!("{prefix}union"),
+                            body,
+                            &format!(" /* {} */", id.id),
+                        );
+                        self.union_body(body);
+                    }
+                }
+                self.stack.pop();
+                self.out.close(format!("}} {tail}"));
+            }
+        }
+    }
+
+    fn enum_body(&mut self, body: &EnumBody) {
+        for (i, assign) in body.body.iter().enumerate() {
+            let comma = if i + 1 < body.body.len() { "," } else { "" };
+            self.out.line(format!(
+                "{} = {}{comma}",
+                assign.id.id,
+                value_text(self.schema, &assign.val)
+            ));
+        }
+    }
+
+    fn struct_body(&mut self, body: &'b StructBody) {
+        for decl in &body.body {
+            let name = decl.id().map_or("", |id| id.id.as_str());
+            self.declaration("", decl, name);
+        }
+    }
+
+    // `union switch (type name) {`, with the discriminant's typedefs followed and `comment` after
+    fn union_header(&mut self, prefix: &str, body: &UnionBody, comment: &str) {
+        let disc = match body.discriminant.as_ref() {
+            Declaration::Normal { spec, id } => {
+                let ty = match self.schema.resolve(spec) {
+                    TypeSpecifier::BuiltIn(name) => name.clone(),
+                    TypeSpecifier::Ident(id) => id.id.clone(),
+                    _ => "enum { ... }".to_string(),
+                };
+                format!("{ty} {}", id.id)
+            }
+            _ => "void".to_string(),
+        };
+        self.out
+            .open(format!("{prefix} switch ({disc}) {{{comment}"));
+    }
+
+    fn union_body(&mut self, body: &'b UnionBody) {
+        self.out.dedent();
+        for case in &body.cases {
+            for val in &case.values {
+                // Enum members and constants are written as their values, named in a comment
+                match (self.schema.case_value(val), val) {
+                    (Some(n), Value::Id(id)) => self.out.line(format!("case {n}: /* {} */", id.id)),
+                    _ => self
+                        .out
+                        .line(format!("case {}:", value_text(self.schema, val))),
+                }
+            }
+            self.arm(&case.decl);
+        }
+        if let Some(decl) = &body.default {
+            self.out.line("default:");
+            self.arm(decl);
+        }
+        self.out.indent();
+    }
+
+    fn arm(&mut self, decl: &'b Declaration) {
+        self.out.indent();
+        let name = decl.id().map_or("", |id| id.id.as_str());
+        self.declaration("", decl, name);
+        self.out.dedent();
+    }
+}
+
+// The body of a named type being written inline
+enum Inline<'b> {
+    Enum(&'b EnumBody),
+    Struct(&'b StructBody),
+    Union(&'b UnionBody),
+}
+
+// The expanded definition of the type or constant `name`, or None if there's no such definition
+pub fn expand(schema: &Schema, name: &str) -> Option<String> {
+    if schema.constants.contains(name) {
+        let val = schema
+            .consts
+            .get(name)
+            .map_or("?".to_string(), i64::to_string);
+        return Some(format!("const {name} = {val};\n"));
+    }
+    let (name, defn) = schema.types.get_key_value(name)?;
+    let mut expander = Expander {
+        schema,
+        out: Emitter::new("    "),
+        stack: vec![name],
+    };
+    let size = schema
+        .definition_size(defn)
+        .map(|size| format!(" /* {size} */"))
+        .unwrap_or_default();
+    match defn {
+        Definition::TypeDef(decl) => expander.declaration("typedef ", decl, name),
+        Definition::Enum { body, .. } => {
+            expander.out.open(format!("enum {name} {{"));
+            expander.enum_body(body);
+            expander.out.close(format!("}};{size}"));
+        }
+        Definition::Struct { body, .. } => {
+            expander.out.open(format!("struct {name} {{"));
+            expander.struct_body(body);
+            expander.out.close(format!("}};{size}"));
+        }
+        Definition::Union { body, .. } => {
+            expander.union_header(&format!("union {name}"), body, "");
+            expander.union_body(body);
+            expander.out.close(format!("}};{size}"));
+        }
+        Definition::Constant { .. } | Definition::Program { .. } => return None,
+    }
+    Some(expander.out.finish())
+}
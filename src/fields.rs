@@ -0,0 +1,145 @@
+// Resolution of dotted field paths such as `READ3res.resok.data`
+//
+// The first segment names a type; each later segment names a field (or union arm, or the
+// discriminant) of the type before it. Typedefs, arrays, and optionals are looked through, so
+// `list.next.value` works on a linked list.
+use crate::ast::*;
+use crate::breaking::decl_text;
+use crate::codegen::{Schema, SourceFile};
+
+// Bound on typedef chains followed for one segment, so a typedef cycle can't hang us
+const MAX_ALIASES: usize = 64;
+
+pub struct Field<'a> {
+    // The file the field (or, for a bare type name, the type) is declared in
+    pub file: &'a SourceFile,
+    pub id: &'a Identifier,
+    // The declared type, as written
+    pub ty: String,
+    // The type with typedefs followed
+    pub resolved: String,
+}
+
+fn file_of<'a>(schema: &Schema<'a>, defn: &Definition) -> Option<&'a SourceFile> {
+    schema.files.iter().find(|file| {
+        file.spec
+            .defns
+            .iter()
+            .any(|other| std::ptr::eq(other, defn))
+    })
+}
+
+fn union_members(body: &UnionBody) -> Vec<&Declaration> {
+    std::iter::once(&*body.discriminant)
+        .chain(body.cases.iter().map(|case| &case.decl))
+        .chain(body.default.as_deref())
+        .collect()
+}
+
+fn defn_members<'a>(
+    schema: &Schema<'a>,
+    file: &'a SourceFile,
+    defn: &'a Definition,
+    depth: usize,
+) -> (&'a SourceFile, Vec<&'a Declaration>) {
+    match defn {
+        Definition::Struct { body, .. } => (file, body.body.iter().collect()),
+        Definition::Union { body, .. } => (file, union_members(body)),
+        Definition::TypeDef(decl) => decl_members(schema, file, decl, depth),
+        _ => (file, vec![]),
+    }
+}
+
+fn decl_members<'a>(
+    schema: &Schema<'a>,
+    file: &'a SourceFile,
+    decl: &'a Declaration,
+    depth: usize,
+) -> (&'a SourceFile, Vec<&'a Declaration>) {
+    let (Declaration::Normal { spec, .. }
+    | Declaration::FixedArr { spec, .. }
+    | Declaration::VarArr { spec, .. }
+    | Declaration::Optional { spec, .. }) = decl
+    else {
+        return (file, vec![]);
+    };
+    match spec {
+        TypeSpecifier::Struct(body) => (file, body.body.iter().collect()),
+        TypeSpecifier::Union(body) => (file, union_members(body)),
+        TypeSpecifier::Ident(id) if depth < MAX_ALIASES => match schema.types.get(id.id.as_str()) {
+            Some(defn) => {
+                let file = file_of(schema, defn).unwrap_or(file);
+                defn_members(schema, file, defn, depth + 1)
+            }
+            None => (file, vec![]),
+        },
+        _ => (file, vec![]),
+    }
+}
+
+// The declaration with `typedef T name;` chains followed
+fn resolved_decl<'a>(schema: &Schema<'a>, decl: &'a Declaration) -> &'a Declaration {
+    let mut decl = decl;
+    for _ in 0..MAX_ALIASES {
+        match decl {
+            Declaration::Normal {
+                spec: TypeSpecifier::Ident(id),
+                ..
+            } => match schema.types.get(id.id.as_str()) {
+                Some(Definition::TypeDef(inner)) => decl = inner,
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    decl
+}
+
+pub fn resolve<'a>(schema: &Schema<'a>, path: &str) -> Result<Field<'a>, String> {
+    let mut segments = path.split('.');
+    let name = segments.next().unwrap_or_default();
+    let Some(defn) = schema.types.get(name).copied() else {
+        return Err(format!("unknown type `{name}`"));
+    };
+    let (Some(file), Some(id)) = (file_of(schema, defn), defn.id()) else {
+        return Err(format!("unknown type `{name}`"));
+    };
+    let kind = match defn {
+        Definition::TypeDef(decl) => decl_text(decl),
+        Definition::Enum { .. } => "enum".to_string(),
+        Definition::Struct { .. } => "struct".to_string(),
+        Definition::Union { .. } => "union".to_string(),
+        Definition::Constant { .. } => "const".to_string(),
+        Definition::Program { .. } => "program".to_string(),
+    };
+    let resolved = match defn {
+        Definition::TypeDef(decl) => decl_text(resolved_decl(schema, decl)),
+        _ => kind.clone(),
+    };
+    let mut field = Field {
+        file,
+        id,
+        ty: kind,
+        resolved,
+    };
+    let (mut file, mut members) = defn_members(schema, file, defn, 0);
+    let mut walked = name.to_string();
+    for segment in segments {
+        let Some(decl) = members
+            .iter()
+            .find(|decl| decl.id().is_some_and(|id| id.id == segment))
+        else {
+            return Err(format!("`{walked}` has no field `{segment}`"));
+        };
+        field = Field {
+            file,
+            id: decl.id().unwrap_or(id),
+            ty: decl_text(decl),
+            resolved: decl_text(resolved_decl(schema, decl)),
+        };
+        walked.push('.');
+        walked.push_str(segment);
+        (file, members) = decl_members(schema, file, decl, 0);
+    }
+    Ok(field)
+}
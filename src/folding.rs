@@ -0,0 +1,54 @@
+// Folding ranges
+//
+// Every brace-delimited body spanning lines folds (structs, unions, enums, programs and their
+// versions, namespaces), along with comments spanning lines. Braces are matched in the text rather
+// than read from the syntax tree, so a file that doesn't parse still folds.
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::position::line_starts;
+use crate::rename::non_code_spans;
+
+// A range folding lines `start` to `end`, or None if that's a single line
+fn fold(start: usize, end: usize, kind: Option<FoldingRangeKind>) -> Option<FoldingRange> {
+    (start < end).then(|| FoldingRange {
+        start_line: start as u32,
+        end_line: end as u32,
+        kind,
+        ..Default::default()
+    })
+}
+
+pub fn folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let lines = line_starts(text);
+    let line_of = |offset: usize| lines.partition_point(|&start| start <= offset) - 1;
+    let mut ranges = vec![];
+    let mut open: Vec<usize> = vec![];
+    let mut code_start = 0;
+    let mut spans = non_code_spans(text);
+    spans.push((text.len(), text.len()));
+    for (start, end) in spans {
+        for (i, c) in text[code_start..start].char_indices() {
+            match c {
+                '{' => open.push(line_of(code_start + i)),
+                // The closing brace's line stays visible
+                '}' => {
+                    if let Some(first) = open.pop() {
+                        let last = line_of(code_start + i).saturating_sub(1);
+                        ranges.extend(fold(first, last, None));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if text[start..end].starts_with("/*") {
+            ranges.extend(fold(
+                line_of(start),
+                line_of(end.saturating_sub(1).max(start)),
+                Some(FoldingRangeKind::Comment),
+            ));
+        }
+        code_start = end;
+    }
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+    ranges
+}
@@ -0,0 +1,655 @@
+// Document formatting: reindents a file and normalizes the spacing between tokens, keeping
+// comments, `%` passthrough lines, and single blank lines. The file is read as the dialect
+// extensions see it, and the lines holding what they claim are copied. Everything from a
+// `/* xdr-fmt: off */` comment to the end of the line holding the next `/* xdr-fmt: on */` is
+// copied as it is, so hand-aligned tables survive.
+//
+// Lines longer than the maximum width are wrapped where the grammar allows a break: after the
+// opening parenthesis of a union's discriminant, after the `=` of a constant or enum value, and
+// after commas outside enum bodies. The rest of the line goes on the next one, two levels deeper
+// than the line it continues.
+//
+// How union arms are laid out varies between codebases, so it's configurable: whether an arm's
+// declaration goes on the line of its (last) case label, how far the labels are indented from
+// the `union` line, and whether arms are separated by blank lines. So is whether the names of
+// struct members on consecutive lines are lined up.
+use crate::dialect;
+use crate::xdr::SpecificationParser;
+
+const FMT_OFF: &str = "xdr-fmt: off";
+const FMT_ON: &str = "xdr-fmt: on";
+
+// Layout choices, from the settings
+#[derive(Debug, Clone)]
+pub struct Style {
+    // Lines longer than this are wrapped if they can be; 0 for no limit
+    pub max_width: usize,
+    // Put each union arm's declaration on the line of its last case label
+    pub case_same_line: bool,
+    // Levels of indentation of case labels below the `union` line
+    pub case_indent: usize,
+    // Put a blank line between union arms
+    pub blank_between_arms: bool,
+    // Line up the names of struct members declared on consecutive lines
+    pub align_fields: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            max_width: 100,
+            case_same_line: false,
+            case_indent: 1,
+            blank_between_arms: false,
+            align_fields: false,
+        }
+    }
+}
+
+impl Style {
+    pub fn from_init_options(options: Option<&serde_json::Value>) -> Self {
+        let mut style = Style::default();
+        let Some(options) = options else {
+            return style;
+        };
+        if let Some(width) = options
+            .get("formatLineWidth")
+            .and_then(serde_json::Value::as_u64)
+        {
+            style.max_width = width as usize;
+        }
+        if let Some(layout) = options
+            .get("formatCaseLayout")
+            .and_then(serde_json::Value::as_str)
+        {
+            style.case_same_line = layout == "sameLine";
+        }
+        if let Some(indent) = options
+            .get("formatCaseIndent")
+            .and_then(serde_json::Value::as_u64)
+        {
+            style.case_indent = indent as usize;
+        }
+        if let Some(blank) = options
+            .get("formatBlankLineBetweenArms")
+            .and_then(serde_json::Value::as_bool)
+        {
+            style.blank_between_arms = blank;
+        }
+        if let Some(align) = options
+            .get("formatAlignFields")
+            .and_then(serde_json::Value::as_bool)
+        {
+            style.align_fields = align;
+        }
+        style
+    }
+}
+
+pub struct Options {
+    // One level of indentation
+    pub indent: String,
+    // Columns a tab takes up
+    pub tab_width: usize,
+    pub style: Style,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Word,
+    Punct(char),
+    LineComment,
+    BlockComment,
+    Passthrough,
+}
+
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    start: usize,
+    // Line breaks between the previous token and this one
+    newlines: usize,
+}
+
+impl Token<'_> {
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(
+            self.kind,
+            Kind::LineComment | Kind::BlockComment | Kind::Passthrough
+        )
+    }
+
+    fn is_directive(&self, directive: &str) -> bool {
+        self.kind == Kind::BlockComment && self.text[2..self.text.len() - 2].trim() == directive
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Splits the text into tokens, or gives up on a character the lexer wouldn't accept
+fn tokenize(text: &str) -> Option<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut newlines = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        if c.is_whitespace() {
+            newlines += usize::from(c == '\n');
+            i += c.len_utf8();
+            continue;
+        }
+        let (kind, len) = if rest.starts_with("//") {
+            (Kind::LineComment, rest.find('\n').unwrap_or(rest.len()))
+        } else if c == '%' {
+            (Kind::Passthrough, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            (Kind::BlockComment, body.find("*/")? + 4)
+        } else if is_word_char(c)
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let len = 1 + rest[1..]
+                .find(|c| !is_word_char(c))
+                .unwrap_or(rest.len() - 1);
+            (Kind::Word, len)
+        } else if "{}()[]<>*,:;=".contains(c) {
+            (Kind::Punct(c), 1)
+        } else {
+            return None;
+        };
+        tokens.push(Token {
+            kind,
+            text: rest[..len].trim_end(),
+            start: i,
+            newlines,
+        });
+        newlines = 0;
+        i += len;
+    }
+    Some(tokens)
+}
+
+fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(text: &str, offset: usize) -> usize {
+    text[offset..]
+        .find('\n')
+        .map_or(text.len(), |i| offset + i + 1)
+}
+
+// What a pair of braces encloses, which decides how its contents are laid out
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    Enum,
+    Union,
+    Struct,
+    // Closed without a semicolon
+    Namespace,
+    Other,
+}
+
+// Where a union body is, between its arms
+#[derive(Clone, Copy, PartialEq)]
+enum Arm {
+    // Before a case label or arm
+    Between,
+    // In a `case X` or `default` label, before its colon
+    Label,
+    // In the declaration of an arm, and whether it's indented below its labels
+    Body(bool),
+    // After the declaration of an arm
+    Ended,
+}
+
+struct Printer<'a> {
+    options: &'a Options,
+    source: &'a str,
+    out: String,
+    // Tokens aren't written in a region with formatting turned off, but still update the layout
+    muted: bool,
+    depth: usize,
+    // Whether the next token starts a line. The line break itself is written with that token, so
+    // a comment on the same line in the source can still go before it.
+    line_start: bool,
+    // Whether the next line is preceded by a blank one
+    blank: bool,
+    // Where the current line could be broken if it gets too long: offsets into `out`, and the
+    // length of the space there that the break replaces
+    breaks: Vec<(usize, usize)>,
+    prev: Option<&'a str>,
+    prev_kind: Option<Kind>,
+    // The block the next `{` opens, from the keyword that introduced it
+    pending: Block,
+    // Enclosing blocks, with the depth and union arm state to restore after each
+    blocks: Vec<(Block, usize, Arm)>,
+    arm: Arm,
+    // The start of the line of the struct member being written, and where the space before its
+    // name is, for aligning names
+    member_line: Option<usize>,
+    member_gap: Option<usize>,
+    // The members written on a line of their own, by their line and gap
+    members: Vec<(usize, usize)>,
+}
+
+impl<'a> Printer<'a> {
+    fn write(&mut self, text: &str) {
+        if !self.muted {
+            self.out.push_str(text);
+        }
+    }
+
+    fn newline(&mut self) {
+        self.line_start = true;
+    }
+
+    // Writes the line break owed by `newline`, and a blank line after it if asked
+    fn break_line(&mut self, blank: bool) {
+        if self.muted || self.out.is_empty() {
+            return;
+        }
+        if !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        if blank && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.out.len() - line_start(&self.out, self.out.len())
+    }
+
+    fn block(&self) -> Block {
+        self.blocks
+            .last()
+            .map_or(Block::Other, |&(block, ..)| block)
+    }
+
+    // Whether a space goes between the previous token and `next` on the same line
+    fn spaced(&self, next: Kind) -> bool {
+        match (self.prev_kind, next) {
+            (None, _) => false,
+            (_, Kind::Punct(';' | ',' | ':' | ')' | ']' | '>' | '[' | '<')) => false,
+            (_, Kind::Punct('(')) => self.prev == Some("switch"),
+            (Some(Kind::Punct('(' | '[' | '<' | '*')), _) => false,
+            _ => true,
+        }
+    }
+
+    // Writes the indentation or space before a token
+    fn start_token(&mut self, token: &Token) {
+        let trailing = token.newlines == 0
+            && matches!(token.kind, Kind::LineComment | Kind::BlockComment)
+            && self.prev_kind.is_some();
+        if self.line_start && trailing {
+            // A comment after the code on its line stays there
+            self.write(" ");
+            return;
+        }
+        if self.line_start {
+            self.breaks.clear();
+            let after_open = self.prev_kind == Some(Kind::Punct('{'));
+            let kept = token.newlines > 1 && !after_open && token.kind != Kind::Punct('}');
+            let blank = std::mem::take(&mut self.blank);
+            self.break_line(kept || blank);
+            if token.kind != Kind::Passthrough {
+                self.write(&self.options.indent.repeat(self.depth));
+            }
+            let member = !self.muted && self.block() == Block::Struct && token.kind == Kind::Word;
+            self.member_line = member.then(|| line_start(&self.out, self.out.len()));
+            self.member_gap = None;
+        } else {
+            let at = self.out.len();
+            if self.spaced(token.kind) {
+                self.write(" ");
+                if matches!(token.kind, Kind::Word | Kind::Punct('*')) {
+                    self.member_gap = Some(at);
+                }
+            }
+            if !self.muted && matches!(self.prev_kind, Some(Kind::Punct('(' | '=' | ',' | ':'))) {
+                self.breaks.push((at, self.out.len() - at));
+            }
+        }
+        self.line_start = false;
+    }
+
+    // The width of the current line, with tabs taking up `tab_width` columns
+    fn width(&self) -> usize {
+        self.out[line_start(&self.out, self.out.len())..]
+            .chars()
+            .map(|c| if c == '\t' { self.options.tab_width } else { 1 })
+            .sum()
+    }
+
+    // Breaks the current line at the last place it can be if it's too long
+    fn wrap(&mut self) {
+        let max_width = self.options.style.max_width;
+        if max_width == 0 || self.width() <= max_width {
+            return;
+        }
+        let Some((at, len)) = self.breaks.pop() else {
+            return;
+        };
+        let continuation = format!("\n{}", self.options.indent.repeat(self.depth + 2));
+        self.out.replace_range(at..at + len, &continuation);
+        self.breaks.clear();
+    }
+
+    fn token(&mut self, token: &Token<'a>) {
+        // Line breaks the layout doesn't decide: before a comment on its own line, around
+        // passthrough lines, and after a comment that ended its line
+        let after_comment = self.prev_kind == Some(Kind::BlockComment);
+        if token.kind == Kind::Passthrough
+            || (token.newlines > 0 && (token.is_comment() || after_comment))
+        {
+            self.newline();
+        }
+        if token.is_comment() {
+            if token.newlines > 0 && self.block() == Block::Union && self.arm == Arm::Ended {
+                // A comment on the next arm goes after the blank line before it
+                self.between_arms();
+            }
+            self.start_token(token);
+            self.write_comment(token);
+            if token.kind != Kind::BlockComment {
+                self.newline();
+            }
+            self.prev = Some(token.text);
+            self.prev_kind = Some(token.kind);
+            return;
+        }
+
+        let mut closes_namespace = false;
+        if token.kind == Kind::Punct('}') {
+            self.newline();
+            if let Some((block, depth, arm)) = self.blocks.pop() {
+                self.depth = depth;
+                self.arm = arm;
+                closes_namespace = block == Block::Namespace;
+            }
+        } else if self.block() == Block::Union {
+            match (self.arm, token.text) {
+                (_, "case" | "default") => {
+                    if self.arm == Arm::Ended {
+                        self.between_arms();
+                    }
+                    if self.prev_kind != Some(Kind::BlockComment) {
+                        self.newline();
+                    }
+                    self.arm = Arm::Label;
+                }
+                (Arm::Between | Arm::Ended, _) => {
+                    // An arm that doesn't fit on its label's line, or isn't meant to, goes
+                    // below it
+                    if !self.options.style.case_same_line {
+                        self.newline();
+                    }
+                    if self.line_start {
+                        self.depth += 1;
+                    }
+                    self.arm = Arm::Body(self.line_start);
+                }
+                _ => {}
+            }
+        }
+        self.start_token(token);
+        self.write(token.text);
+        self.wrap();
+        match token.kind {
+            Kind::Word => match token.text {
+                "enum" => self.pending = Block::Enum,
+                "union" => self.pending = Block::Union,
+                "namespace" => self.pending = Block::Namespace,
+                "struct" => self.pending = Block::Struct,
+                _ => {}
+            },
+            Kind::Punct('{') => {
+                self.blocks.push((self.pending, self.depth, self.arm));
+                self.depth += match self.pending {
+                    Block::Union => self.options.style.case_indent,
+                    _ => 1,
+                };
+                self.pending = Block::Other;
+                self.arm = Arm::Between;
+                self.newline();
+            }
+            Kind::Punct(';') => {
+                if let (Some(line), Some(gap)) = (self.member_line.take(), self.member_gap)
+                    && self.block() == Block::Struct
+                    && !self.out[line..].contains('\n')
+                {
+                    self.members.push((line, gap));
+                }
+                if self.block() == Block::Union
+                    && let Arm::Body(indented) = self.arm
+                {
+                    if indented {
+                        self.depth -= 1;
+                    }
+                    self.arm = Arm::Ended;
+                }
+                self.newline();
+            }
+            Kind::Punct('}') if closes_namespace => self.newline(),
+            Kind::Punct(',') if self.block() == Block::Enum => self.newline(),
+            Kind::Punct(':') if self.block() == Block::Union && self.arm == Arm::Label => {
+                self.arm = Arm::Between;
+                if !self.options.style.case_same_line {
+                    self.newline();
+                }
+            }
+            _ => {}
+        }
+        self.prev = Some(token.text);
+        self.prev_kind = Some(token.kind);
+    }
+
+    // Copies the source from `start` to `end` as it is, extended to the end of the line of a
+    // token running past it, taking the tokens from `i` in it into account for the layout.
+    // Returns where the copy ends.
+    fn copy(&mut self, tokens: &[Token<'a>], i: &mut usize, start: usize, mut end: usize) -> usize {
+        self.muted = true;
+        while let Some(token) = tokens.get(*i).filter(|token| token.start < end) {
+            if token.end() > end {
+                end = line_end(self.source, token.end());
+            }
+            self.token(token);
+            *i += 1;
+        }
+        self.muted = false;
+        self.out.push_str(&self.source[start..end]);
+        self.line_start = self.out.ends_with('\n');
+        end
+    }
+
+    // Moves on to the next union arm, after the previous one's declaration
+    fn between_arms(&mut self) {
+        self.arm = Arm::Between;
+        self.blank = self.options.style.blank_between_arms;
+    }
+
+    // Writes a comment, moving the lines after its first along with it
+    fn write_comment(&mut self, token: &Token) {
+        let column = self.column();
+        let original = token.start - line_start(self.source, token.start);
+        let mut lines = token.text.split('\n');
+        self.write(lines.next().unwrap_or_default());
+        for line in lines {
+            let line = line.trim_end();
+            self.write("\n");
+            match line.get(..original) {
+                Some(indent) if indent.trim().is_empty() => {
+                    self.write(&" ".repeat(column));
+                    self.write(&line[original..]);
+                }
+                _ => self.write(line),
+            }
+        }
+    }
+}
+
+// Pads the gaps before the names of members on consecutive lines with the same indentation so
+// the names line up
+fn align(out: &mut String, members: &[(usize, usize)]) {
+    let indent = |out: &str, line: usize| {
+        out[line..]
+            .find(|c: char| c != ' ' && c != '\t')
+            .unwrap_or_default()
+    };
+    let mut groups: Vec<Vec<(usize, usize)>> = vec![];
+    for &(line, gap) in members {
+        match groups.last_mut() {
+            Some(group)
+                if group.last().is_some_and(|&(prev, _)| {
+                    line_end(out, prev) == line && indent(out, prev) == indent(out, line)
+                }) =>
+            {
+                group.push((line, gap));
+            }
+            _ => groups.push(vec![(line, gap)]),
+        }
+    }
+    for group in groups.iter().rev() {
+        let column = group
+            .iter()
+            .map(|(line, gap)| gap - line)
+            .max()
+            .unwrap_or_default();
+        for (line, gap) in group.iter().rev() {
+            out.insert_str(*gap, &" ".repeat(column - (gap - line)));
+        }
+    }
+}
+
+// The lines holding spans a dialect extension claimed, as ranges of whole lines with those next to
+// each other merged, to be copied as they are. A range taking in part of a token (an annotation
+// in a comment running over several lines) takes in all its lines.
+fn masked_lines(
+    text: &str,
+    items: &[dialect::ExtensionItem],
+    tokens: &[Token],
+) -> Vec<(usize, usize)> {
+    let mut lines: Vec<(usize, usize)> = vec![];
+    for item in items {
+        let before = tokens.partition_point(|token| token.start < item.start);
+        let start = before
+            .checked_sub(1)
+            .map(|i| &tokens[i])
+            .filter(|token| item.start < token.end())
+            .map_or(item.start, |token| token.start);
+        let start = line_start(text, start);
+        let last = text[item.start..item.end]
+            .char_indices()
+            .last()
+            .map_or(item.start, |(i, _)| item.start + i);
+        let through = tokens.partition_point(|token| token.start <= last);
+        let end = through
+            .checked_sub(1)
+            .map_or(last, |i| tokens[i].end().max(last));
+        let end = line_end(text, end);
+        match lines.last_mut() {
+            Some(last) if start <= last.1 => *last = (last.0.min(start), last.1.max(end)),
+            _ => lines.push((start, end)),
+        }
+    }
+    lines
+}
+
+// The formatted text, or `None` if the file doesn't parse. Lines holding what a dialect extension
+// claims (preprocessor directives, annotations) are copied as they are.
+pub fn format(text: &str, options: &Options) -> Option<String> {
+    let (masked, items) = dialect::preprocess(text);
+    SpecificationParser::new().parse(&masked).ok()?;
+    let mut tokens = tokenize(&masked)?;
+    let mut verbatim = masked_lines(text, &items, &tokens);
+    // The first token after copied lines starts a line, with a blank one before it only if the
+    // source has one after them
+    let mut ends = verbatim.iter().map(|&(_, end)| end).peekable();
+    for token in &mut tokens {
+        let mut after = None;
+        while let Some(end) = ends.next_if(|&end| end <= token.start) {
+            after = Some(end);
+        }
+        if let Some(end) = after {
+            token.newlines = 1 + masked[end..token.start].matches('\n').count();
+        }
+    }
+    verbatim.reverse();
+    let mut printer = Printer {
+        options,
+        source: text,
+        out: String::with_capacity(text.len()),
+        muted: false,
+        depth: 0,
+        line_start: true,
+        blank: false,
+        breaks: vec![],
+        prev: None,
+        prev_kind: None,
+        pending: Block::Other,
+        blocks: vec![],
+        arm: Arm::Between,
+        member_line: None,
+        member_gap: None,
+        members: vec![],
+    };
+    // How far the source has been copied as it is
+    let mut copied = 0;
+    let mut i = 0;
+    loop {
+        let next = tokens.get(i);
+        if let Some(&(start, end)) = verbatim.last()
+            && next.is_none_or(|token| token.start >= start)
+        {
+            verbatim.pop();
+            let start = start.max(copied);
+            if start >= end {
+                continue;
+            }
+            let blank = start > 0 && text[line_start(text, start - 1)..start].trim().is_empty();
+            printer.break_line(blank);
+            copied = printer.copy(&tokens, &mut i, start, end);
+            continue;
+        }
+        let Some(off) = next else {
+            break;
+        };
+        if !off.is_directive(FMT_OFF) {
+            printer.token(off);
+            i += 1;
+            continue;
+        }
+        // Copied from the start of the directive's line, unless code comes before it there
+        let own_line = off.newlines > 0 || i == 0;
+        let start = if own_line {
+            line_start(text, off.start)
+        } else {
+            off.start
+        };
+        let end = tokens[i + 1..]
+            .iter()
+            .find(|token| token.is_directive(FMT_ON))
+            .map_or(text.len(), |on| line_end(text, on.end()));
+        if own_line {
+            printer.break_line(off.newlines > 1);
+        } else {
+            printer.write(" ");
+        }
+        copied = printer.copy(&tokens, &mut i, start, end);
+    }
+    printer.break_line(false);
+    if options.style.align_fields {
+        align(&mut printer.out, &printer.members);
+    }
+    SpecificationParser::new()
+        .parse(&dialect::preprocess(&printer.out).0)
+        .ok()?;
+    Some(printer.out)
+}
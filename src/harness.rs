@@ -0,0 +1,340 @@
+// An in-process harness for driving the language server
+//
+// `Harness` runs `server::Backend` in memory, with no subprocess or transport: requests and
+// notifications go straight to the service, and what the server sends the client is answered (with
+// a null result) and kept, so tests can check the diagnostics it published. Documents are usually
+// `untitled:` ones, which only exist in the editor, so no workspace is needed on disk.
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tower_lsp::LspService;
+use tower_lsp::jsonrpc::{Request, RequestBuilder, Response};
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, Initialized, Notification, PublishDiagnostics,
+};
+use tower_lsp::lsp_types::request::{GotoDefinition, Initialize, Shutdown};
+use tower_lsp::lsp_types::*;
+use tower_service::Service;
+
+use crate::lint::LintRule;
+use crate::server::{self, Backend};
+
+// How long `expect_diagnostics` waits for the server to publish what's expected
+const DIAGNOSTICS_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Harness {
+    service: LspService<Backend>,
+    next_id: i64,
+    // The version each open document was last sent at
+    versions: HashMap<Url, i32>,
+    // The diagnostics last published for each document
+    diagnostics: Arc<Mutex<HashMap<Url, Vec<Diagnostic>>>>,
+}
+
+impl Harness {
+    // A server initialized for the workspace at `root`, if any, with `options` as its
+    // initialization options (settings)
+    pub async fn start(root: Option<&std::path::Path>, options: Option<serde_json::Value>) -> Self {
+        Self::start_with_rules(root, options, vec![]).await
+    }
+
+    // `start`, with lint rules of the test's own run alongside the built-in ones
+    pub async fn start_with_rules(
+        root: Option<&std::path::Path>,
+        options: Option<serde_json::Value>,
+        rules: Vec<Box<dyn LintRule>>,
+    ) -> Self {
+        let (service, socket) = server::service(Arc::new(AtomicBool::new(false)), rules);
+        let diagnostics: Arc<Mutex<HashMap<Url, Vec<Diagnostic>>>> = Arc::default();
+        let published = diagnostics.clone();
+        let (mut requests, mut responses) = socket.split();
+        tokio::spawn(async move {
+            while let Some(request) = requests.next().await {
+                let (method, id, params) = request.into_parts();
+                if method == PublishDiagnostics::METHOD
+                    && let Some(params) = params
+                    && let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(params)
+                {
+                    published
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .insert(params.uri, params.diagnostics);
+                }
+                if let Some(id) = id
+                    && responses
+                        .send(Response::from_ok(id, serde_json::Value::Null))
+                        .await
+                        .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let mut harness = Harness {
+            service,
+            next_id: 0,
+            versions: HashMap::new(),
+            diagnostics,
+        };
+        #[allow(deprecated)]
+        let params = InitializeParams {
+            root_uri: root.and_then(|root| Url::from_directory_path(root).ok()),
+            initialization_options: options,
+            ..Default::default()
+        };
+        harness.request::<Initialize>(params).await;
+        harness.notify::<Initialized>(InitializedParams {}).await;
+        harness
+    }
+
+    // Sends a request, returning its result; panics if it fails
+    pub async fn request<R: request::Request>(&mut self, params: R::Params) -> R::Result
+    where
+        R::Params: Serialize,
+        R::Result: DeserializeOwned,
+    {
+        self.next_id += 1;
+        let request = with_params(Request::build(R::METHOD).id(self.next_id), params);
+        let response = self.call(request).await;
+        let Some(response) = response else {
+            panic!("no response to `{}`", R::METHOD);
+        };
+        match response.into_parts().1 {
+            Ok(result) => serde_json::from_value(result)
+                .unwrap_or_else(|err| panic!("bad result for `{}`: {err}", R::METHOD)),
+            Err(err) => panic!("`{}` failed: {err}", R::METHOD),
+        }
+    }
+
+    // Sends a notification, returning once the server has handled it
+    pub async fn notify<N: Notification>(&mut self, params: N::Params)
+    where
+        N::Params: Serialize,
+    {
+        self.call(with_params(Request::build(N::METHOD), params))
+            .await;
+    }
+
+    // Opens a document in the editor with the given text
+    pub async fn open(&mut self, uri: &Url, text: &str) {
+        self.versions.insert(uri.clone(), 1);
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "xdr".to_string(),
+                1,
+                text.to_string(),
+            ),
+        })
+        .await;
+    }
+
+    // Replaces the text of an open document
+    pub async fn change(&mut self, uri: &Url, text: &str) {
+        let version = self.versions.entry(uri.clone()).or_default();
+        *version += 1;
+        let version = *version;
+        self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.to_string(),
+            }],
+        })
+        .await;
+    }
+
+    // Where the name at `at` in `uri` is defined
+    pub async fn goto_definition(&mut self, uri: &Url, at: Position) -> Vec<Location> {
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                at,
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        match self.request::<GotoDefinition>(params).await {
+            None => vec![],
+            Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+            Some(GotoDefinitionResponse::Array(locations)) => locations,
+            Some(GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| Location::new(link.target_uri, link.target_selection_range))
+                .collect(),
+        }
+    }
+
+    // The diagnostics last published for `uri`
+    pub fn diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        self.diagnostics
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(uri)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Waits for the server to publish diagnostics for `uri` with exactly the `expected` lines
+    // (0-based) and codes, in order; panics with what it published if it doesn't in time
+    pub async fn expect_diagnostics(&self, uri: &Url, expected: &[(u32, &str)]) {
+        let summary = |diagnostics: Vec<Diagnostic>| -> Vec<(u32, String)> {
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| {
+                    let code = match diagnostic.code {
+                        Some(NumberOrString::String(code)) => code,
+                        Some(NumberOrString::Number(code)) => code.to_string(),
+                        None => String::new(),
+                    };
+                    (diagnostic.range.start.line, code)
+                })
+                .collect()
+        };
+        let expected: Vec<(u32, String)> = expected
+            .iter()
+            .map(|&(line, code)| (line, code.to_string()))
+            .collect();
+        let deadline = tokio::time::Instant::now() + DIAGNOSTICS_TIMEOUT;
+        loop {
+            let found = summary(self.diagnostics(uri));
+            if found == expected {
+                return;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                assert_eq!(found, expected, "diagnostics for {uri}");
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    // Asks the server to shut down
+    pub async fn shutdown(mut self) {
+        self.request::<Shutdown>(()).await;
+    }
+
+    async fn call(&mut self, request: Request) -> Option<Response> {
+        std::future::poll_fn(|cx| self.service.poll_ready(cx))
+            .await
+            .expect("the server has exited");
+        self.service
+            .call(request)
+            .await
+            .expect("the server has exited")
+    }
+}
+
+// A message with `params`, left out when they're `()`, as the protocol wants for those without
+fn with_params(builder: RequestBuilder, params: impl Serialize) -> Request {
+    match serde_json::to_value(params).expect("parameters serialize") {
+        serde_json::Value::Null => builder.finish(),
+        params => builder.params(params).finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::notification::DidChangeConfiguration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn goes_to_a_definition_in_an_open_document() {
+        let mut harness = Harness::start(None, None).await;
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        harness
+            .open(&uri, "typedef int Foo;\ntypedef Foo Bar;\n")
+            .await;
+        let found = harness.goto_definition(&uri, Position::new(1, 9)).await;
+        assert_eq!(
+            found,
+            vec![Location::new(
+                uri.clone(),
+                Range::new(Position::new(0, 12), Position::new(0, 15))
+            )]
+        );
+        harness.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn publishes_diagnostics_after_a_change() {
+        let mut harness = Harness::start(None, None).await;
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        harness.open(&uri, "typedef int Foo;\n").await;
+        harness
+            .expect_diagnostics(&uri, &[(0, "unused-definition")])
+            .await;
+        harness.change(&uri, "typedef int Foo;\nstruct {\n").await;
+        harness
+            .expect_diagnostics(&uri, &[(1, "syntax-error"), (0, "unused-definition")])
+            .await;
+        harness.shutdown().await;
+    }
+
+    // Flags every typedef
+    struct NoTypedefs;
+
+    impl LintRule for NoTypedefs {
+        fn name(&self) -> &'static str {
+            "no-typedefs"
+        }
+
+        fn default_severity(&self) -> DiagnosticSeverity {
+            DiagnosticSeverity::WARNING
+        }
+
+        fn check(
+            &self,
+            workspace: &crate::lint::ResolvedWorkspace,
+        ) -> HashMap<std::path::PathBuf, Vec<Diagnostic>> {
+            let mut result: HashMap<std::path::PathBuf, Vec<Diagnostic>> = HashMap::new();
+            for file in workspace.files {
+                for defn in &file.spec.defns {
+                    if let crate::ast::Definition::TypeDef(decl) = defn
+                        && let Some(id) = decl.id()
+                    {
+                        result
+                            .entry(file.path.clone())
+                            .or_default()
+                            .push(Diagnostic {
+                                range: Range::new(
+                                    file.position_at(id.start, workspace.encoding),
+                                    file.position_at(id.end, workspace.encoding),
+                                ),
+                                message: "typedef".to_string(),
+                                ..Default::default()
+                            });
+                    }
+                }
+            }
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_rules_given_to_the_server_after_settings_change() {
+        let rules: Vec<Box<dyn LintRule>> = vec![Box::new(NoTypedefs)];
+        let mut harness = Harness::start_with_rules(None, None, rules).await;
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        harness.open(&uri, "typedef int Foo;\n").await;
+        harness
+            .expect_diagnostics(&uri, &[(0, "unused-definition"), (0, "no-typedefs")])
+            .await;
+        harness
+            .notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+                settings: serde_json::json!({ "xdr": { "disabledRules": ["unused-definition"] } }),
+            })
+            .await;
+        harness.change(&uri, "typedef int Bar;\n").await;
+        harness
+            .expect_diagnostics(&uri, &[(0, "no-typedefs")])
+            .await;
+        harness.shutdown().await;
+    }
+}
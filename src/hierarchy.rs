@@ -0,0 +1,215 @@
+// Type hierarchy
+//
+// XDR has no inheritance, so the hierarchy is built from typedefs. A typedef's supertype is the
+// type it's declared with (through arrays and optionals), down to a struct, union, or enum, or a
+// built-in type, which end the chain. A type's subtypes are what builds on it: the typedefs
+// declared with it, and the structs and unions with members of it. The subtypes of a built-in
+// type are just the typedefs, as nearly every definition has an `int` somewhere.
+use serde_json::json;
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::codegen::SourceFile;
+use crate::position::Encoding;
+use crate::symbols;
+
+// What a declaration builds on
+#[derive(Clone, Copy, PartialEq)]
+enum Base<'a> {
+    Named(&'a str),
+    BuiltIn(&'a str),
+}
+
+fn base(decl: &Declaration) -> Option<Base<'_>> {
+    match decl {
+        Declaration::Normal { spec, .. }
+        | Declaration::FixedArr { spec, .. }
+        | Declaration::VarArr { spec, .. }
+        | Declaration::Optional { spec, .. } => match spec {
+            TypeSpecifier::Ident(id) => Some(Base::Named(&id.id)),
+            TypeSpecifier::BuiltIn(name) => Some(Base::BuiltIn(name)),
+            _ => None,
+        },
+        Declaration::FixedOpaque { .. } | Declaration::VarOpaque { .. } => {
+            Some(Base::BuiltIn("opaque"))
+        }
+        Declaration::String { .. } => Some(Base::BuiltIn("string")),
+        Declaration::VOID => None,
+    }
+}
+
+fn range(file: &SourceFile, start: usize, end: usize, encoding: Encoding) -> Range {
+    Range::new(
+        file.position_at(start, encoding),
+        file.position_at(end, encoding),
+    )
+}
+
+// The item for a type definition, with `detail` saying how it relates to the item it was found
+// from
+fn definition_item(
+    file: &SourceFile,
+    uri: &Url,
+    defn: &Definition,
+    detail: Option<String>,
+    encoding: Encoding,
+) -> Option<TypeHierarchyItem> {
+    let id = defn.id()?;
+    let start = file.definition_start(defn)?;
+    let end = file.definition_end(defn)?;
+    Some(TypeHierarchyItem {
+        name: id.id.clone(),
+        kind: symbols::kind(defn),
+        tags: None,
+        detail,
+        uri: uri.clone(),
+        range: range(file, start, end, encoding),
+        selection_range: range(file, id.start, id.end, encoding),
+        data: Some(json!({ "name": id.id })),
+    })
+}
+
+// A built-in type has no definition of its own, so its item points at the typedef it was found
+// from
+fn builtin_item(
+    file: &SourceFile,
+    uri: &Url,
+    defn: &Definition,
+    name: &str,
+    encoding: Encoding,
+) -> Option<TypeHierarchyItem> {
+    let id = defn.id()?;
+    let start = file.definition_start(defn)?;
+    let end = file.definition_end(defn)?;
+    Some(TypeHierarchyItem {
+        name: name.to_string(),
+        kind: SymbolKind::TYPE_PARAMETER,
+        tags: None,
+        detail: Some("built-in".to_string()),
+        uri: uri.clone(),
+        range: range(file, start, end, encoding),
+        selection_range: range(file, id.start, id.end, encoding),
+        data: Some(json!({ "name": name, "builtin": true })),
+    })
+}
+
+fn is_type(defn: &Definition) -> bool {
+    !matches!(
+        defn,
+        Definition::Constant { .. } | Definition::Program { .. }
+    )
+}
+
+fn definitions<'a>(
+    files: &'a [SourceFile],
+    uris: &'a [Url],
+) -> impl Iterator<Item = (&'a SourceFile, &'a Url, &'a Definition)> {
+    files
+        .iter()
+        .zip(uris)
+        .flat_map(|(file, uri)| file.spec.defns.iter().map(move |defn| (file, uri, defn)))
+}
+
+// The item for the type named `name`, if the workspace defines one
+pub fn prepare(
+    files: &[SourceFile],
+    uris: &[Url],
+    name: &str,
+    encoding: Encoding,
+) -> Option<TypeHierarchyItem> {
+    definitions(files, uris)
+        .find(|(_, _, defn)| is_type(defn) && defn.id().is_some_and(|id| id.id == name))
+        .and_then(|(file, uri, defn)| definition_item(file, uri, defn, None, encoding))
+}
+
+// The name an item stands for, and whether it's a built-in type
+pub fn item_name(item: &TypeHierarchyItem) -> (String, bool) {
+    let data = item.data.as_ref();
+    let name = data
+        .and_then(|data| data.get("name"))
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or(&item.name);
+    let builtin = data
+        .and_then(|data| data.get("builtin"))
+        .and_then(serde_json::Value::as_bool)
+        == Some(true);
+    (name.to_string(), builtin)
+}
+
+// What the typedef `name` is declared with; nothing for other types
+pub fn supertypes(
+    files: &[SourceFile],
+    uris: &[Url],
+    name: &str,
+    encoding: Encoding,
+) -> Vec<TypeHierarchyItem> {
+    let mut result = vec![];
+    for (file, uri, defn) in definitions(files, uris) {
+        let Definition::TypeDef(decl) = defn else {
+            continue;
+        };
+        if decl.id().is_none_or(|id| id.id != name) {
+            continue;
+        }
+        match base(decl) {
+            Some(Base::Named(base)) => result.extend(
+                definitions(files, uris)
+                    .filter(|(_, _, defn)| {
+                        is_type(defn) && defn.id().is_some_and(|id| id.id == base)
+                    })
+                    .filter_map(|(file, uri, defn)| {
+                        definition_item(file, uri, defn, None, encoding)
+                    }),
+            ),
+            Some(Base::BuiltIn(base)) => {
+                result.extend(builtin_item(file, uri, defn, base, encoding))
+            }
+            None => {}
+        }
+    }
+    result
+}
+
+// The typedefs declared with the type `name`, and (unless it's built in) the structs and unions
+// with members of it, named in the detail
+pub fn subtypes(
+    files: &[SourceFile],
+    uris: &[Url],
+    name: &str,
+    builtin: bool,
+    encoding: Encoding,
+) -> Vec<TypeHierarchyItem> {
+    let wanted = if builtin {
+        Base::BuiltIn(name)
+    } else {
+        Base::Named(name)
+    };
+    let mut result = vec![];
+    for (file, uri, defn) in definitions(files, uris) {
+        match defn {
+            Definition::TypeDef(decl) if base(decl) == Some(wanted) => {
+                result.extend(definition_item(
+                    file,
+                    uri,
+                    defn,
+                    Some("typedef".to_string()),
+                    encoding,
+                ));
+            }
+            Definition::Struct { .. } | Definition::Union { .. } if !builtin => {
+                let members: Vec<&str> = defn
+                    .declarations()
+                    .into_iter()
+                    .filter(|decl| base(decl) == Some(wanted))
+                    .filter_map(|decl| decl.id().map(|id| id.id.as_str()))
+                    .collect();
+                if !members.is_empty() {
+                    let detail = format!("via {}", members.join(", "));
+                    result.extend(definition_item(file, uri, defn, Some(detail), encoding));
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
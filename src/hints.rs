@@ -0,0 +1,238 @@
+// Inlay hints
+//
+// XDR pads opaque data and strings to a multiple of 4 bytes. The padding isn't written anywhere
+// in the schema, and forgetting it is the usual bug in hand-written decoders, so each `opaque` or
+// `string` declaration that can be padded gets a hint after its length.
+//
+// Long structs, unions, and enums also get a hint after their closing `};` naming the definition
+// it ends, and with the `wireSizeHints` setting structs, unions, and typedefs get one with their
+// encoded size.
+//
+// Enum members whose value is a name get the number it evaluates to, and a union discriminant
+// declared with a typedef gets the type the typedef stands for.
+use tower_lsp::lsp_types::*;
+
+use crate::ast::{Declaration, Definition, EnumBody, TypeSpecifier, UnionBody, Value};
+use crate::codegen::{Schema, SourceFile};
+use crate::position::Encoding;
+
+fn bytes(n: u64) -> String {
+    if n == 1 {
+        "1 byte".to_string()
+    } else {
+        format!("{n} bytes")
+    }
+}
+
+// Padding hints for the declarations in `file` between byte offsets `start` and `end`
+pub fn padding(
+    schema: &Schema,
+    file: &SourceFile,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+) -> Vec<InlayHint> {
+    let mut result = vec![];
+    for decl in file.spec.defns.iter().flat_map(|defn| defn.declarations()) {
+        let (Some(id), Some((min, max))) = (decl.id(), schema.padding(decl)) else {
+            continue;
+        };
+        // After the `]` or `>` closing the length
+        let Some(close) = file.text[id.end..].find([']', '>']).map(|i| id.end + i + 1) else {
+            continue;
+        };
+        if close < start || close > end {
+            continue;
+        }
+        let label = if min == max {
+            format!("+{} padding", bytes(min))
+        } else {
+            format!("+{min}–{} padding", bytes(max))
+        };
+        result.push(InlayHint {
+            position: file.position_at(close, encoding),
+            label: InlayHintLabel::String(label),
+            kind: None,
+            text_edits: None,
+            tooltip: Some(InlayHintTooltip::String(
+                "XDR pads opaque data and strings with zero bytes to a multiple of 4".to_string(),
+            )),
+            padding_left: Some(true),
+            padding_right: None,
+            data: None,
+        });
+    }
+    result
+}
+
+// End-of-block hints for the structs, unions, and enums in `file` spanning at least `min_lines`
+// lines and ending between byte offsets `start` and `end`
+pub fn block_ends(
+    file: &SourceFile,
+    min_lines: usize,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+) -> Vec<InlayHint> {
+    let mut result = vec![];
+    for defn in &file.spec.defns {
+        if !matches!(
+            defn,
+            Definition::Struct { .. } | Definition::Union { .. } | Definition::Enum { .. }
+        ) {
+            continue;
+        }
+        let (Some(id), Some(defn_start), Some(defn_end)) = (
+            defn.id(),
+            file.definition_start(defn),
+            file.definition_end(defn),
+        ) else {
+            continue;
+        };
+        if defn_end < start || defn_end > end {
+            continue;
+        }
+        let lines = file.text[defn_start..defn_end].matches('\n').count() + 1;
+        if lines < min_lines {
+            continue;
+        }
+        result.push(after(
+            file,
+            defn_end,
+            format!("// end {} {}", defn.keyword(), id.id),
+            encoding,
+        ));
+    }
+    result
+}
+
+// Wire size hints for the structs, unions, and typedefs in `file` ending between byte offsets
+// `start` and `end`
+pub fn wire_sizes(
+    schema: &Schema,
+    file: &SourceFile,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+) -> Vec<InlayHint> {
+    let mut result = vec![];
+    for defn in &file.spec.defns {
+        if !matches!(
+            defn,
+            Definition::Struct { .. } | Definition::Union { .. } | Definition::TypeDef(_)
+        ) {
+            continue;
+        }
+        let (Some(defn_end), Some(size)) =
+            (file.definition_end(defn), schema.definition_size(defn))
+        else {
+            continue;
+        };
+        if defn_end < start || defn_end > end {
+            continue;
+        }
+        let mut hint = after(file, defn_end, format!("// {size}"), encoding);
+        hint.tooltip = Some(InlayHintTooltip::String(
+            "Encoded size, counting XDR's padding to a multiple of 4 bytes".to_string(),
+        ));
+        result.push(hint);
+    }
+    result
+}
+
+// A hint after byte offset `at`, with a space before it
+fn after(file: &SourceFile, at: usize, label: String, encoding: Encoding) -> InlayHint {
+    InlayHint {
+        position: file.position_at(at, encoding),
+        label: InlayHintLabel::String(label),
+        kind: None,
+        text_edits: None,
+        tooltip: None,
+        padding_left: Some(true),
+        padding_right: None,
+        data: None,
+    }
+}
+
+// The enums and unions of `file`, named or inline
+fn bodies(file: &SourceFile) -> (Vec<&EnumBody>, Vec<&UnionBody>) {
+    let mut enums = vec![];
+    let mut unions = vec![];
+    for defn in &file.spec.defns {
+        match defn {
+            Definition::Enum { body, .. } => enums.push(body),
+            Definition::Union { body, .. } => unions.push(body),
+            _ => {}
+        }
+        for decl in defn.declarations() {
+            if let Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } = decl
+            {
+                match spec {
+                    TypeSpecifier::Enum(body) => enums.push(body),
+                    TypeSpecifier::Union(body) => unions.push(body),
+                    _ => {}
+                }
+            }
+        }
+    }
+    (enums, unions)
+}
+
+// Value hints for the enum members in `file` between byte offsets `start` and `end` whose value
+// is a constant or another member
+pub fn enum_values(
+    schema: &Schema,
+    file: &SourceFile,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+) -> Vec<InlayHint> {
+    let (enums, _) = bodies(file);
+    enums
+        .into_iter()
+        .flat_map(|body| &body.body)
+        .filter_map(|assign| {
+            let Value::Id(id) = &assign.val else {
+                return None;
+            };
+            let value = schema.value(&assign.val)?;
+            (start <= id.end && id.end <= end)
+                .then(|| after(file, id.end, format!("= {value}"), encoding))
+        })
+        .collect()
+}
+
+// Type hints for the union discriminants in `file` between byte offsets `start` and `end` declared
+// with a typedef
+pub fn discriminants(
+    schema: &Schema,
+    file: &SourceFile,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+) -> Vec<InlayHint> {
+    let (_, unions) = bodies(file);
+    unions
+        .into_iter()
+        .filter_map(|body| {
+            let Declaration::Normal {
+                spec: spec @ TypeSpecifier::Ident(id),
+                ..
+            } = body.discriminant.as_ref()
+            else {
+                return None;
+            };
+            let resolved = match schema.resolve(spec) {
+                TypeSpecifier::BuiltIn(name) => name.clone(),
+                TypeSpecifier::Ident(resolved) if resolved.id != id.id => resolved.id.clone(),
+                TypeSpecifier::Enum(_) => "enum { ... }".to_string(),
+                _ => return None,
+            };
+            (start <= id.end && id.end <= end)
+                .then(|| after(file, id.end, format!(": {resolved}"), encoding))
+        })
+        .collect()
+}
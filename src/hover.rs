@@ -0,0 +1,394 @@
+// Hover text
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::Url;
+
+use crate::ast::*;
+use crate::codegen::{self, Schema, SourceFile};
+use crate::since;
+
+// Types listed by name in a "used by" summary; the rest are only counted
+const USED_BY_LISTED: usize = 20;
+
+// How much a hover on a name shows, from the `hoverVerbosity` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    // The definition's first line, like `struct Point`
+    Signature,
+    // The whole definition
+    Body,
+    // The whole definition, its documentation, wire size, and usage
+    #[default]
+    Full,
+}
+
+impl Verbosity {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "signature" => Some(Verbosity::Signature),
+            "body" => Some(Verbosity::Body),
+            "full" => Some(Verbosity::Full),
+            _ => None,
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The byte range of the identifier or number at `offset`
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let offset = offset.min(text.len());
+    let start = text[..offset]
+        .char_indices()
+        .rfind(|&(_, c)| !is_word_char(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = text[offset..]
+        .find(|c| !is_word_char(c))
+        .map_or(text.len(), |i| offset + i);
+    if start == end {
+        return None;
+    }
+    // Negative case values are allowed
+    let start = if text[..start].ends_with('-') {
+        start - 1
+    } else {
+        start
+    };
+    Some((start, end))
+}
+
+// The innermost union body around `offset`
+fn union_at(file: &SourceFile, offset: usize) -> Option<&UnionBody> {
+    fn visit_decl<'a>(decl: &'a Declaration, offset: usize, found: &mut Option<&'a UnionBody>) {
+        match decl {
+            Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } => visit_type(spec, offset, found),
+            _ => {}
+        }
+    }
+    fn visit_type<'a>(spec: &'a TypeSpecifier, offset: usize, found: &mut Option<&'a UnionBody>) {
+        match spec {
+            TypeSpecifier::Struct(body) => {
+                for decl in &body.body {
+                    visit_decl(decl, offset, found);
+                }
+            }
+            TypeSpecifier::Union(body) => visit_union(body, offset, found),
+            _ => {}
+        }
+    }
+    fn visit_union<'a>(body: &'a UnionBody, offset: usize, found: &mut Option<&'a UnionBody>) {
+        if !(body.start..body.end).contains(&offset) {
+            return;
+        }
+        *found = Some(body);
+        for case in &body.cases {
+            visit_decl(&case.decl, offset, found);
+        }
+        if let Some(decl) = &body.default {
+            visit_decl(decl, offset, found);
+        }
+    }
+    let mut found = None;
+    for defn in &file.spec.defns {
+        match defn {
+            Definition::TypeDef(decl) => visit_decl(decl, offset, &mut found),
+            Definition::Struct { body, .. } => {
+                for decl in &body.body {
+                    visit_decl(decl, offset, &mut found);
+                }
+            }
+            Definition::Union { body, .. } => visit_union(body, offset, &mut found),
+            _ => {}
+        }
+    }
+    found
+}
+
+fn enum_name(name: Option<&str>) -> String {
+    name.map_or("the anonymous enum".to_string(), |name| {
+        format!("enum `{name}`")
+    })
+}
+
+// For a case label value: the enum member a number stands for, or the value of a name and the
+// enum it belongs to. Returns the byte range of the value with the Markdown text.
+pub fn case_value(
+    schema: &Schema,
+    file: &SourceFile,
+    offset: usize,
+) -> Option<(usize, usize, String)> {
+    let (start, end) = word_at(&file.text, offset)?;
+    let before = file.text[..start].trim_end();
+    if !before.ends_with("case") || before[..before.len() - 4].ends_with(is_word_char) {
+        return None;
+    }
+    let word = &file.text[start..end];
+    let union = union_at(file, offset)?;
+    let discriminant = schema.discriminant_enum(union);
+    let text = if word.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+        let value = parse_constant(word)?;
+        let (name, body) = discriminant?;
+        let members: Vec<String> = body
+            .body
+            .iter()
+            .filter(|assign| schema.consts.get(assign.id.id.as_str()) == Some(&value))
+            .map(|assign| format!("`{}`", assign.id.id))
+            .collect();
+        if members.is_empty() {
+            format!("No member of {} has the value `{word}`", enum_name(name))
+        } else {
+            format!("{} in {}", members.join(", "), enum_name(name))
+        }
+    } else {
+        let value = schema.case_value(&Value::Id(Identifier {
+            id: word.to_string(),
+            start,
+            end,
+        }))?;
+        let member = discriminant
+            .is_some_and(|(_, body)| body.body.iter().any(|assign| assign.id.id == word));
+        let owner = match (discriminant, schema.enum_of.get(word)) {
+            (Some((None, _)), _) if member => "member of the anonymous enum".to_string(),
+            (_, Some(owner)) => format!("member of enum `{owner}`"),
+            _ if schema.constants.contains(word) => "constant".to_string(),
+            _ => "bool".to_string(),
+        };
+        match discriminant {
+            Some((name, _)) if !member => format!(
+                "`{word} = {value}` ({owner}), not a member of {}",
+                enum_name(name)
+            ),
+            _ => format!("`{word} = {value}` ({owner})"),
+        }
+    };
+    Some((start, end, text))
+}
+
+// For the name of an RPC program, version, or procedure: its number, and those of the version
+// and program it's part of. Returns the byte range of the name with the Markdown text.
+pub fn rpc_number(
+    schema: &Schema,
+    file: &SourceFile,
+    offset: usize,
+) -> Option<(usize, usize, String)> {
+    let (start, end) = word_at(&file.text, offset)?;
+    let mut chain = vec![&file.text[start..end]];
+    while let Some(owner) = *schema.rpc_of.get(chain[chain.len() - 1])? {
+        // Programs are at most two levels up
+        if chain.len() == 3 {
+            break;
+        }
+        chain.push(owner);
+    }
+    let kinds = ["program", "version", "procedure"];
+    let parts: Vec<String> = chain
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let kind = kinds[chain.len() - 1 - i];
+            match schema.consts.get(name) {
+                Some(value) => format!("{kind} `{name} = {value}`"),
+                None => format!("{kind} `{name}`"),
+            }
+        })
+        .collect();
+    Some((start, end, parts.join(" of ")))
+}
+
+// For a type name: how many definitions use it directly, and in how many files, with links to
+// them. `uris` are those of `schema.files`. Returns the byte range of the name with the Markdown
+// text.
+pub fn used_by(
+    schema: &Schema,
+    uris: &[Url],
+    file: &SourceFile,
+    offset: usize,
+) -> Option<(usize, usize, String)> {
+    let (start, end) = word_at(&file.text, offset)?;
+    let name = &file.text[start..end];
+    schema.types.get(name)?;
+    let mut users = 0;
+    let mut links = vec![];
+    let mut files = std::collections::BTreeSet::new();
+    for (source, uri) in schema.files.iter().zip(uris) {
+        for defn in &source.spec.defns {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            if !codegen::type_deps(defn).contains(name) {
+                continue;
+            }
+            users += 1;
+            files.insert(uri);
+            if links.len() < USED_BY_LISTED {
+                let line = source.line_at(id.start) + 1;
+                links.push(format!("[`{}`]({uri}#L{line})", id.id));
+            }
+        }
+    }
+    if users == 0 {
+        return Some((start, end, format!("`{name}` isn't used by any type")));
+    }
+    let plural = |n: usize, what: &str| {
+        if n == 1 {
+            format!("1 {what}")
+        } else {
+            format!("{n} {what}s")
+        }
+    };
+    let mut text = format!(
+        "`{name}` is used by {} in {}\n\n{}",
+        plural(users, "type"),
+        plural(files.len(), "file"),
+        links.join(", ")
+    );
+    if users > links.len() {
+        text.push_str(&format!(" and {} more", users - links.len()));
+    }
+    Some((start, end, text))
+}
+
+// For a name annotated with `@since`, or one referring to an annotated definition: the version
+// it appeared in. Returns the byte range of the name with the Markdown text.
+pub fn since(
+    files: &[SourceFile],
+    file: &SourceFile,
+    offset: usize,
+) -> Option<(usize, usize, String)> {
+    let (start, end) = word_at(&file.text, offset)?;
+    let version = since::since_of(files, file, start, end)?;
+    Some((start, end, format!("Since version `{version}`")))
+}
+
+// The definition of `name`, preferring the workspace's over the standard library's (which comes
+// first)
+fn find_definition<'a>(
+    files: &'a [SourceFile],
+    name: &str,
+) -> Option<(&'a SourceFile, &'a Definition)> {
+    files.iter().rev().find_map(|file| {
+        file.spec
+            .defns
+            .iter()
+            .find(|defn| defn.id().is_some_and(|id| id.id == name))
+            .map(|defn| (file, defn))
+    })
+}
+
+// A definition, as much of it as `verbosity` asks for, in a code block
+fn definition_code(source: &SourceFile, defn: &Definition, verbosity: Verbosity) -> Option<String> {
+    let text = &source.text[source.definition_start(defn)?..source.definition_end(defn)?];
+    let code = match (verbosity, text.find('{')) {
+        // Up to the body, like `union Result switch (Status status)`
+        (Verbosity::Signature, Some(brace)) => text[..brace].trim_end().to_string(),
+        (Verbosity::Signature, None) => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        _ => text.to_string(),
+    };
+    Some(format!("```xdr\n{code}\n```"))
+}
+
+// An evaluated constant in decimal and hex, like `255 (0xff)`. Negative values that fit XDR's
+// 32-bit `int` are shown in hex as they're encoded.
+pub fn value_display(value: i64) -> String {
+    match i32::try_from(value) {
+        Ok(value) if value < 0 => format!("{value} ({:#x})", value as u32),
+        _ if value < 0 => format!("{value}"),
+        _ => format!("{value} ({value:#x})"),
+    }
+}
+
+// The value of the member `name` of the enum `owner` as written
+fn member_value(schema: &Schema, owner: &str, name: &str) -> Option<String> {
+    let Some(Definition::Enum { body, .. }) = schema.types.get(owner) else {
+        return None;
+    };
+    let assign = body.body.iter().find(|assign| assign.id.id == name)?;
+    Some(match &assign.val {
+        Value::Const(text) => text.clone(),
+        Value::Id(id) => id.id.clone(),
+    })
+}
+
+// The comment before the enum member `name` of `owner`
+fn member_doc(files: &[SourceFile], owner: &str, name: &str) -> Option<String> {
+    let (source, Definition::Enum { body, .. }) = find_definition(files, owner)? else {
+        return None;
+    };
+    let assign = body.body.iter().find(|assign| assign.id.id == name)?;
+    source.doc_comment_before(assign.start)
+}
+
+// The documentation of every definition and enum member that has a comment before it, by name,
+// for completion items
+pub fn documentation(files: &[SourceFile]) -> HashMap<&str, String> {
+    let mut docs = HashMap::new();
+    for file in files {
+        for defn in &file.spec.defns {
+            if let Some(id) = defn.id()
+                && let Some(doc) = file.doc_comment(defn)
+            {
+                docs.insert(id.id.as_str(), doc);
+            }
+            if let Definition::Enum { body, .. } = defn {
+                for assign in &body.body {
+                    if let Some(doc) = file.doc_comment_before(assign.start) {
+                        docs.insert(assign.id.id.as_str(), doc);
+                    }
+                }
+            }
+        }
+    }
+    docs
+}
+
+// For the name of a definition: the definition, as much of it as `verbosity` asks for, in a code
+// block, then its value if it's a constant and (when full) its documentation and wire size. For an
+// enum member, the member as written, its value, the enum it belongs to, and (when full) its
+// documentation, then (unless just the signature is asked for) the enum's definition. Returns the byte range of the name with the
+// Markdown text.
+pub fn definition(
+    schema: &Schema,
+    file: &SourceFile,
+    offset: usize,
+    verbosity: Verbosity,
+) -> Option<(usize, usize, String)> {
+    let (start, end) = word_at(&file.text, offset)?;
+    let name = &file.text[start..end];
+    let Some((source, defn)) = find_definition(schema.files, name) else {
+        let owner = schema.enum_of.get(name)?;
+        let written = member_value(schema, owner, name)?;
+        let mut parts = vec![format!("```xdr\n{name} = {written}\n```")];
+        // Unresolved when it refers to something undefined, or to itself through others
+        if let Some(&value) = schema.consts.get(name) {
+            parts.push(format!("Value: {}", value_display(value)));
+        }
+        parts.push(format!("Member of enum `{owner}`"));
+        if verbosity == Verbosity::Full {
+            parts.extend(member_doc(schema.files, owner, name));
+        }
+        if verbosity != Verbosity::Signature
+            && let Some((source, defn)) = find_definition(schema.files, owner)
+        {
+            parts.extend(definition_code(source, defn, verbosity));
+        }
+        return Some((start, end, parts.join("\n\n")));
+    };
+    let mut parts = vec![definition_code(source, defn, verbosity)?];
+    if let Definition::Constant { id, .. } = defn
+        && let Some(&value) = schema.consts.get(id.id.as_str())
+    {
+        parts.push(format!("Value: {}", value_display(value)));
+    }
+    if verbosity == Verbosity::Full {
+        parts.extend(source.doc_comment(defn));
+        if let Some(size) = schema.definition_size(defn) {
+            parts.push(format!("Wire size: {size}"));
+        }
+    }
+    Some((start, end, parts.join("\n\n")))
+}
@@ -0,0 +1,709 @@
+// `%#include` handling
+//
+// xdrpp passes `%` lines through to the generated C++, so `%#include "foo.h"` makes the types
+// from `foo.x` available. An include is resolved to the workspace `.x` file with the same path
+// (relative to any directory) once `.h` is replaced with `.x`.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::codegen::SourceFile;
+use crate::position::{Encoding, column};
+use crate::stdlib;
+
+pub const MISSING_INCLUDE: &str = "missing-include";
+pub const CONFLICTING_DEFINITION: &str = "conflicting-definition";
+pub const INCLUDE_CYCLE: &str = "include-cycle";
+
+#[derive(Debug, Clone)]
+pub struct Include {
+    // 0-based line of the directive
+    pub line: u32,
+    // The included path, as written
+    pub path: String,
+}
+
+pub fn parse_includes(text: &str) -> Vec<Include> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let rest = text.trim().strip_prefix('%')?.trim_start();
+            let rest = rest.strip_prefix("#")?.trim_start();
+            let rest = rest.strip_prefix("include")?.trim();
+            let path = rest
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+                .or_else(|| {
+                    rest.strip_prefix('<')
+                        .and_then(|rest| rest.strip_suffix('>'))
+                })?;
+            Some(Include {
+                line: line as u32,
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+// The workspace file an include refers to, whichever schema extension it has
+pub fn resolve<'a>(include: &str, files: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    let target = Path::new(include).with_extension("");
+    files
+        .iter()
+        .find(|file| file.with_extension("").ends_with(&target))
+}
+
+// The `.x` file an include refers to: the one at the included path relative to `dir` (the
+// including file's directory) or one of `bases`, or failing that the workspace file `resolve`
+// finds
+pub fn locate(include: &str, dir: &Path, bases: &[PathBuf], files: &[PathBuf]) -> Option<PathBuf> {
+    let target = Path::new(include).with_extension("x");
+    std::iter::once(dir)
+        .chain(bases.iter().map(PathBuf::as_path))
+        .map(|base| base.join(&target))
+        .find(|path| path.is_file())
+        .or_else(|| resolve(include, files).cloned())
+}
+
+// Links from the paths of a file's includes to the files they refer to
+pub fn document_links(
+    text: &str,
+    path: &Path,
+    bases: &[PathBuf],
+    files: &[PathBuf],
+    encoding: Encoding,
+) -> Vec<DocumentLink> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let lines: Vec<&str> = text.lines().collect();
+    parse_includes(text)
+        .into_iter()
+        .filter_map(|include| {
+            let target = locate(&include.path, dir, bases, files)?;
+            let line = lines[include.line as usize];
+            let start = line.find(['"', '<'])? + 1;
+            let end = start + include.path.len();
+            Some(DocumentLink {
+                range: Range::new(
+                    Position::new(include.line, column(&line[..start], encoding)),
+                    Position::new(include.line, column(&line[..end], encoding)),
+                ),
+                tooltip: Some(target.to_string_lossy().into_owned()),
+                target: Url::from_file_path(&target).ok(),
+                data: None,
+            })
+        })
+        .collect()
+}
+
+// The includes of each file that resolve to workspace files, for walking the include graph
+// without going back to disk
+pub struct IncludeGraph<'a> {
+    edges: HashMap<&'a Path, Vec<(Include, &'a Path)>>,
+}
+
+impl<'a> IncludeGraph<'a> {
+    pub fn new(files: &'a [SourceFile]) -> Self {
+        let paths: Vec<PathBuf> = files.iter().map(|file| file.path.clone()).collect();
+        let mut edges = HashMap::new();
+        for file in files {
+            let targets = parse_includes(&file.text)
+                .into_iter()
+                .filter_map(|include| {
+                    let target = resolve(&include.path, &paths)?;
+                    let target = files.iter().find(|file| &file.path == target)?;
+                    Some((include, target.path.as_path()))
+                })
+                .collect();
+            edges.insert(file.path.as_path(), targets);
+        }
+        IncludeGraph { edges }
+    }
+
+    pub fn includes(&self, path: &Path) -> &[(Include, &'a Path)] {
+        self.edges.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    // Like `reachable`, but from the parsed files
+    pub fn reachable(&self, start: &'a Path) -> HashSet<&'a Path> {
+        let mut seen = HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(path) = stack.pop() {
+            for (_, target) in self.includes(path) {
+                if seen.insert(target) {
+                    stack.push(target);
+                }
+            }
+        }
+        seen
+    }
+
+    // The shortest chain of includes leading from `from` to `to`, starting with `from` and
+    // ending with `to`
+    pub fn path(&self, from: &'a Path, to: &Path) -> Option<Vec<&'a Path>> {
+        let mut parent: HashMap<&'a Path, &'a Path> = HashMap::new();
+        let mut queue = std::collections::VecDeque::from([from]);
+        let mut seen = HashSet::from([from]);
+        while let Some(path) = queue.pop_front() {
+            if path == to {
+                let mut chain = vec![path];
+                while let Some(prev) = parent.get(chain[chain.len() - 1]) {
+                    chain.push(prev);
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for (_, target) in self.includes(path) {
+                if seen.insert(target) {
+                    parent.insert(target, path);
+                    queue.push_back(target);
+                }
+            }
+        }
+        None
+    }
+}
+
+// Every file whose definitions are visible from `start` through its includes, including itself
+pub fn reachable(start: &Path, files: &[PathBuf]) -> HashSet<PathBuf> {
+    let mut seen = HashSet::from([start.to_path_buf()]);
+    let mut stack = vec![start.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        for include in parse_includes(&text) {
+            if let Some(file) = resolve(&include.path, files)
+                && seen.insert(file.clone())
+            {
+                stack.push(file.clone());
+            }
+        }
+    }
+    seen
+}
+
+// The path to write in an include of `target`: its generated header relative to the workspace
+// root
+pub fn include_path(root: &Path, target: &Path) -> String {
+    let relative = target
+        .strip_prefix(root)
+        .unwrap_or(Path::new(target.file_name().unwrap_or_default()));
+    relative
+        .with_extension("h")
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Where the path starts in `line`, if it ends inside the quotes of an include being typed
+pub fn typed_include_start(line: &str) -> Option<usize> {
+    let rest = line.trim_start().strip_prefix('%')?.trim_start();
+    let rest = rest.strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("include")?.trim_start();
+    let path = rest.strip_prefix('"')?;
+    (!path.contains('"')).then(|| line.len() - path.len())
+}
+
+pub struct PathCompletion {
+    // The next path component, with a trailing `/` for directories
+    pub label: String,
+    // The `.x` file, unless the component is a directory
+    pub file: Option<PathBuf>,
+}
+
+// Completions of the component being typed in an include path: the directories and `.x` files
+// (as headers) among `files` in the typed directory, relative to any of `bases`
+pub fn path_completions(typed: &str, bases: &[PathBuf], files: &[PathBuf]) -> Vec<PathCompletion> {
+    let dir = typed.rfind('/').map_or("", |i| &typed[..i]);
+    let mut seen = HashSet::new();
+    let mut completions = vec![];
+    for base in bases {
+        let dir = base.join(dir);
+        for file in files {
+            let Ok(rest) = file.strip_prefix(&dir) else {
+                continue;
+            };
+            let mut components = rest.components();
+            let Some(name) = components.next() else {
+                continue;
+            };
+            let name = Path::new(name.as_os_str());
+            let (label, file) = if components.next().is_some() {
+                (format!("{}/", name.to_string_lossy()), None)
+            } else {
+                let header = name.with_extension("h");
+                (header.to_string_lossy().into_owned(), Some(file.clone()))
+            };
+            if seen.insert(label.clone()) {
+                completions.push(PathCompletion { label, file });
+            }
+        }
+    }
+    completions
+}
+
+// An edit adding `%#include "path"` to `text`. Existing includes are kept in order: if they're
+// sorted the new one goes in its sorted position, otherwise after the last one. Without any
+// includes it goes after the file's leading comment.
+pub fn include_edit(text: &str, path: &str) -> TextEdit {
+    let includes = parse_includes(text);
+    let line = if includes.is_empty() {
+        leading_comment_end(text)
+    } else if includes.windows(2).all(|pair| pair[0].path <= pair[1].path) {
+        includes
+            .iter()
+            .find(|include| include.path.as_str() > path)
+            .map_or(includes[includes.len() - 1].line + 1, |include| {
+                include.line
+            })
+    } else {
+        includes[includes.len() - 1].line + 1
+    };
+    let pos = Position { line, character: 0 };
+    TextEdit {
+        range: Range {
+            start: pos,
+            end: pos,
+        },
+        new_text: format!("%#include \"{path}\"\n"),
+    }
+}
+
+// The first line after any comment and blank lines at the top of a file
+fn leading_comment_end(text: &str) -> u32 {
+    let mut in_block = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if in_block || line.starts_with("/*") {
+            in_block = !line.contains("*/");
+        } else if !line.is_empty() && !line.starts_with("//") {
+            return i as u32;
+        }
+    }
+    text.lines().count() as u32
+}
+
+// Names used by a file: type names and constants in sizes, values, and case labels. Unlike
+// `visit_identifiers`, field names aren't included.
+// Whether a name is used as a type or as a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    Type,
+    Value,
+}
+
+// The names `spec` uses, in source order
+pub fn visit_references<'a>(spec: &'a Specification, out: &mut Vec<(&'a Identifier, RefKind)>) {
+    for defn in &spec.defns {
+        match defn {
+            Definition::Constant { .. } => {}
+            Definition::TypeDef(decl) => visit_decl_refs(decl, out),
+            Definition::Enum { body, .. } => visit_enum_refs(body, out),
+            Definition::Struct { body, .. } => {
+                for decl in &body.body {
+                    visit_decl_refs(decl, out);
+                }
+            }
+            Definition::Union { body, .. } => visit_union_refs(body, out),
+            Definition::Program {
+                versions, number, ..
+            } => visit_program_refs(versions, number, out),
+        }
+    }
+}
+
+fn visit_decl_refs<'a>(decl: &'a Declaration, out: &mut Vec<(&'a Identifier, RefKind)>) {
+    let size = match decl {
+        Declaration::Normal { spec, .. } | Declaration::Optional { spec, .. } => {
+            visit_type_refs(spec, out);
+            None
+        }
+        Declaration::FixedArr { spec, size, .. } => {
+            visit_type_refs(spec, out);
+            Some(size)
+        }
+        Declaration::VarArr { spec, size, .. } => {
+            visit_type_refs(spec, out);
+            size.as_ref()
+        }
+        Declaration::FixedOpaque { size, .. } => Some(size),
+        Declaration::VarOpaque { size, .. } | Declaration::String { size, .. } => size.as_ref(),
+        Declaration::VOID => None,
+    };
+    if let Some(Value::Id(id)) = size {
+        out.push((id, RefKind::Value));
+    }
+}
+
+fn visit_type_refs<'a>(spec: &'a TypeSpecifier, out: &mut Vec<(&'a Identifier, RefKind)>) {
+    match spec {
+        TypeSpecifier::BuiltIn(_) => {}
+        TypeSpecifier::Enum(body) => visit_enum_refs(body, out),
+        TypeSpecifier::Struct(body) => {
+            for decl in &body.body {
+                visit_decl_refs(decl, out);
+            }
+        }
+        TypeSpecifier::Union(body) => visit_union_refs(body, out),
+        TypeSpecifier::Ident(id) => out.push((id, RefKind::Type)),
+    }
+}
+
+fn visit_enum_refs<'a>(body: &'a EnumBody, out: &mut Vec<(&'a Identifier, RefKind)>) {
+    for assign in &body.body {
+        if let Value::Id(id) = &assign.val {
+            out.push((id, RefKind::Value));
+        }
+    }
+}
+
+fn visit_union_refs<'a>(body: &'a UnionBody, out: &mut Vec<(&'a Identifier, RefKind)>) {
+    visit_decl_refs(&body.discriminant, out);
+    for case in &body.cases {
+        for val in &case.values {
+            if let Value::Id(id) = val {
+                out.push((id, RefKind::Value));
+            }
+        }
+        visit_decl_refs(&case.decl, out);
+    }
+    if let Some(decl) = &body.default {
+        visit_decl_refs(decl, out);
+    }
+}
+
+fn visit_program_refs<'a>(
+    versions: &'a [Version],
+    number: &'a Value,
+    out: &mut Vec<(&'a Identifier, RefKind)>,
+) {
+    for version in versions {
+        for proc in &version.procedures {
+            for spec in proc.result.iter().chain(&proc.args) {
+                visit_type_refs(spec, out);
+            }
+            if let Value::Id(id) = &proc.number {
+                out.push((id, RefKind::Value));
+            }
+        }
+        if let Value::Id(id) = &version.number {
+            out.push((id, RefKind::Value));
+        }
+    }
+    if let Value::Id(id) = number {
+        out.push((id, RefKind::Value));
+    }
+}
+
+// Warnings for names `file` uses that are defined in the workspace, but not in any file its
+// includes reach. `defined_in` maps each name to the files defining it. Each diagnostic's data
+// holds the path to include (`{ "include": "xdr/foo.h" }`) for the quick fix.
+pub fn missing_includes(
+    file: &SourceFile,
+    root: &Path,
+    graph: &IncludeGraph,
+    defined_in: &HashMap<String, Vec<PathBuf>>,
+    encoding: Encoding,
+) -> Vec<Diagnostic> {
+    let reachable = graph.reachable(&file.path);
+    let mut refs = vec![];
+    visit_references(&file.spec, &mut refs);
+    let mut result = vec![];
+    for (id, _) in refs {
+        let Some(defns) = defined_in.get(&id.id) else {
+            // Undefined names are someone else's problem
+            continue;
+        };
+        if defns
+            .iter()
+            .any(|defn| reachable.contains(defn.as_path()) || stdlib::is_library(defn))
+        {
+            continue;
+        }
+        let target = &defns[0];
+        let include = include_path(root, target);
+        result.push(Diagnostic {
+            range: Range {
+                start: file.position_at(id.start, encoding),
+                end: file.position_at(id.end, encoding),
+            },
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(MISSING_INCLUDE.to_string())),
+            source: Some("xdr-ls".to_string()),
+            message: format!(
+                "`{}` is defined in {}, which isn't included; add `%#include \"{}\"`",
+                id.id,
+                target
+                    .file_name()
+                    .map_or("another file".into(), |name| name.to_string_lossy()),
+                include
+            ),
+            data: Some(serde_json::json!({ "include": include })),
+            ..Default::default()
+        });
+    }
+    result
+}
+
+// Quick fixes adding the include named by each missing-include diagnostic
+pub fn add_include(text: &str, uri: &Url, diagnostics: &[Diagnostic]) -> Vec<CodeAction> {
+    let mut seen = HashSet::new();
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        if diagnostic.code != Some(NumberOrString::String(MISSING_INCLUDE.to_string())) {
+            continue;
+        }
+        let Some(path) = diagnostic
+            .data
+            .as_ref()
+            .and_then(|data| data.get("include"))
+            .and_then(serde_json::Value::as_str)
+        else {
+            continue;
+        };
+        if !seen.insert(path) {
+            continue;
+        }
+        result.push(CodeAction {
+            title: format!("Add `%#include \"{path}\"`"),
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(
+                    uri.clone(),
+                    vec![include_edit(text, path)],
+                )])),
+                ..Default::default()
+            }),
+            is_preferred: Some(true),
+            ..Default::default()
+        });
+    }
+    result
+}
+
+// A definition's source with comments dropped and tokens separated by single spaces, so that
+// definitions differing only in layout compare equal
+fn normalized_definition(file: &SourceFile, defn: &Definition) -> Option<String> {
+    let text = &file.text[file.definition_start(defn)?..file.definition_end(defn)?];
+    let mut tokens: Vec<&str> = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            rest = rest.find("*/").map_or("", |end| &rest[end + 2..]);
+        } else if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else {
+            let len = if c.is_alphanumeric() || c == '_' {
+                rest.find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            tokens.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    Some(tokens.join(" "))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or(path.display().to_string(), |name| {
+        name.to_string_lossy().into_owned()
+    })
+}
+
+// Errors for names defined differently by two files that are both reachable from some file.
+// rpcgen sees both definitions when compiling that file, so each conflict is reported on the
+// includes leading to the definitions as well as on the definitions themselves.
+pub fn conflicts(files: &[SourceFile], encoding: Encoding) -> HashMap<PathBuf, Vec<Diagnostic>> {
+    struct Defn<'a> {
+        path: &'a Path,
+        text: String,
+        range: Range,
+    }
+    let graph = IncludeGraph::new(files);
+    let mut defns: HashMap<&str, Vec<Defn>> = HashMap::new();
+    for file in files {
+        for defn in &file.spec.defns {
+            let (Some(id), Some(text)) = (defn.id(), normalized_definition(file, defn)) else {
+                continue;
+            };
+            defns.entry(&id.id).or_default().push(Defn {
+                path: &file.path,
+                text,
+                range: Range {
+                    start: file.position_at(id.start, encoding),
+                    end: file.position_at(id.end, encoding),
+                },
+            });
+        }
+    }
+
+    let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+    let mut seen = HashSet::new();
+    // Keyed so a definition is reported once per conflicting definition, not once per file
+    // including both
+    let mut report =
+        |key: &str, path: &Path, range: Range, message: String, related: Vec<Location>| {
+            if !seen.insert((
+                path.to_path_buf(),
+                range.start.line,
+                range.start.character,
+                key.to_string(),
+            )) {
+                return;
+            }
+            result
+                .entry(path.to_path_buf())
+                .or_default()
+                .push(Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(CONFLICTING_DEFINITION.to_string())),
+                    source: Some("xdr-ls".to_string()),
+                    message,
+                    related_information: Some(
+                        related
+                            .into_iter()
+                            .map(|location| DiagnosticRelatedInformation {
+                                location,
+                                message: "defined here".to_string(),
+                            })
+                            .collect(),
+                    ),
+                    ..Default::default()
+                });
+        };
+    let location = |defn: &Defn| {
+        Url::from_file_path(defn.path).ok().map(|uri| Location {
+            uri,
+            range: defn.range,
+        })
+    };
+
+    // Sorted so the reports don't depend on hash order
+    let mut names: Vec<&&str> = defns.keys().collect();
+    names.sort();
+    for file in files {
+        let reachable = graph.reachable(&file.path);
+        let reach_of: Vec<(&Include, HashSet<&Path>)> = graph
+            .includes(&file.path)
+            .iter()
+            .map(|(include, target)| (include, graph.reachable(target)))
+            .collect();
+        for name in &names {
+            let visible: Vec<&Defn> = defns[**name]
+                .iter()
+                .filter(|defn| reachable.contains(defn.path))
+                .collect();
+            let Some(first) = visible.first() else {
+                continue;
+            };
+            for other in &visible[1..] {
+                if other.text == first.text {
+                    continue;
+                }
+                let related: Vec<Location> = [first, other]
+                    .into_iter()
+                    .filter_map(|defn| location(defn))
+                    .collect();
+                for (include, reach) in &reach_of {
+                    if !reach.contains(first.path) && !reach.contains(other.path) {
+                        continue;
+                    }
+                    let line = file.text.lines().nth(include.line as usize).unwrap_or("");
+                    report(
+                        name,
+                        &file.path,
+                        Range {
+                            start: Position {
+                                line: include.line,
+                                character: 0,
+                            },
+                            end: Position {
+                                line: include.line,
+                                character: column(line, encoding),
+                            },
+                        },
+                        format!(
+                            "`{}` is defined differently in {} and {}, which are both included here",
+                            name,
+                            file_name(first.path),
+                            file_name(other.path)
+                        ),
+                        related.clone(),
+                    );
+                }
+                for (defn, elsewhere) in [(first, other), (other, first)] {
+                    let how = if elsewhere.path == file.path {
+                        "which includes this file".to_string()
+                    } else if defn.path == file.path {
+                        "which this file includes".to_string()
+                    } else {
+                        format!("and {} includes both", file_name(&file.path))
+                    };
+                    report(
+                        &elsewhere.path.to_string_lossy(),
+                        defn.path,
+                        defn.range,
+                        format!(
+                            "`{}` is defined differently in {}, {}",
+                            name,
+                            file_name(elsewhere.path),
+                            how
+                        ),
+                        related.clone(),
+                    );
+                }
+            }
+        }
+    }
+    result
+}
+
+// Warnings on each include that is part of a cycle (`a.x` includes `b.x`, which includes
+// `a.x`), naming the files around the shortest such cycle
+pub fn cycles(files: &[SourceFile], encoding: Encoding) -> HashMap<PathBuf, Vec<Diagnostic>> {
+    let graph = IncludeGraph::new(files);
+    let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+    for file in files {
+        for (include, target) in graph.includes(&file.path) {
+            let Some(back) = graph.path(target, &file.path) else {
+                continue;
+            };
+            let chain: Vec<String> = std::iter::once(file.path.as_path())
+                .chain(back)
+                .map(file_name)
+                .collect();
+            let line = file.text.lines().nth(include.line as usize).unwrap_or("");
+            result
+                .entry(file.path.clone())
+                .or_default()
+                .push(Diagnostic {
+                    range: Range {
+                        start: Position {
+                            line: include.line,
+                            character: 0,
+                        },
+                        end: Position {
+                            line: include.line,
+                            character: column(line, encoding),
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(INCLUDE_CYCLE.to_string())),
+                    source: Some("xdr-ls".to_string()),
+                    message: format!("Include cycle: {}", chain.join(" -> ")),
+                    ..Default::default()
+                });
+        }
+    }
+    result
+}
@@ -0,0 +1,428 @@
+// The index of a document: where each name in it is defined and used, its symbols, and the
+// types names are declared with, for answering navigation requests without reparsing
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::cache::{IndexCache, Stamp};
+use crate::position::Encoding;
+use crate::{dialect, position, recovery, symbols};
+
+// A name on a line of a document: its columns, and what it names (or, for declared types, the
+// type it's declared with)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub start: u32,
+    pub end: u32,
+    pub val: String,
+}
+
+// What indexing one document finds
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DocumentIndex {
+    // Used to find the identifier at a given location: line -> identifiers, sorted
+    pub identifiers: HashMap<u32, Vec<Token>>,
+    // Used to find references to a given identifier: name -> where the document refers to it
+    // (unsorted). `references_to` merges the documents.
+    pub refs: HashMap<String, Vec<Location>>,
+    // Used to find where identifiers are defined: name -> where the document defines it.
+    // `definition_of` picks among the documents.
+    pub defns: HashMap<String, Location>,
+    // Definitions, for workspace symbol search
+    pub symbols: Vec<SymbolInformation>,
+    // Fields of the structs and unions, which workspace symbol search also covers
+    pub fields: Vec<SymbolInformation>,
+    // Used to find the type of a declared name: line -> names with the type as their value,
+    // sorted
+    pub declared_types: HashMap<u32, Vec<Token>>,
+    pub parsed: bool,
+    pub micros: u64,
+}
+
+impl DocumentIndex {
+    pub fn new(uri: &Url, text: &str, encoding: Encoding) -> Self {
+        let mut index = DocumentIndex::default();
+        let start = Instant::now();
+        index.parsed = parse_file(uri, text, encoding, &mut index).is_some();
+        index.micros = start.elapsed().as_micros() as u64;
+        index
+    }
+
+    // The document's entry for the metrics notification
+    pub fn metrics(&self, uri: &Url) -> serde_json::Value {
+        serde_json::json!({
+            "uri": uri,
+            "parseMicros": self.micros,
+            "symbols": self.symbols.len(),
+            "errors": if self.parsed { 0 } else { 1 },
+        })
+    }
+}
+
+// Reads and indexes files on every core, returning those that could be read. Files unchanged since
+// `cache` took their index are taken from it rather than parsed, and the rest are added to it.
+pub fn index_in_parallel(
+    paths: &[PathBuf],
+    cache: Option<&IndexCache>,
+    encoding: Encoding,
+) -> Vec<(Url, String, DocumentIndex)> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = paths.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk)
+            .map(|paths| {
+                scope.spawn(move || {
+                    paths
+                        .iter()
+                        .filter_map(|path| {
+                            let uri = Url::from_file_path(path).ok()?;
+                            // Taken before reading, so a write in between makes it stale
+                            let stamp = cache.and_then(|_| Stamp::of(path));
+                            let text = fs::read_to_string(path).ok()?;
+                            if let (Some(cache), Some(stamp)) = (cache, stamp) {
+                                if let Some(mut index) = cache.get(path, stamp) {
+                                    index.micros = 0;
+                                    return Some((uri, text, index));
+                                }
+                                let index = DocumentIndex::new(&uri, &text, encoding);
+                                cache.insert(path.clone(), stamp, index.clone());
+                                return Some((uri, text, index));
+                            }
+                            let index = DocumentIndex::new(&uri, &text, encoding);
+                            Some((uri, text, index))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+pub fn visit_identifiers(spec: &Specification, cb: &mut dyn FnMut(&Identifier, bool)) {
+    for defn in &spec.defns {
+        visit_identifiers_defn(defn, cb);
+    }
+}
+
+pub fn visit_identifiers_defn(defn: &Definition, cb: &mut dyn FnMut(&Identifier, bool)) {
+    match defn {
+        Definition::Constant { id, .. } => {
+            cb(id, true);
+        }
+        Definition::TypeDef(decl) => {
+            visit_identifiers_decl(decl, true, cb);
+        }
+        Definition::Enum { id, body } => {
+            cb(id, true);
+            visit_identifiers_enum(body, cb);
+        }
+        Definition::Struct { id, body } => {
+            cb(id, true);
+            visit_identifiers_struct(body, cb);
+        }
+        Definition::Union { id, body } => {
+            cb(id, true);
+            visit_identifiers_union(body, cb);
+        }
+        Definition::Program {
+            id,
+            versions,
+            number,
+        } => {
+            cb(id, true);
+            for version in versions {
+                cb(&version.id, true);
+                for proc in &version.procedures {
+                    if let Some(result) = &proc.result {
+                        visit_identifiers_type(result, cb);
+                    }
+                    cb(&proc.id, true);
+                    for arg in &proc.args {
+                        visit_identifiers_type(arg, cb);
+                    }
+                    visit_identifiers_val(&proc.number, cb);
+                }
+                visit_identifiers_val(&version.number, cb);
+            }
+            visit_identifiers_val(number, cb);
+        }
+    }
+}
+
+fn visit_identifiers_decl(
+    decl: &Declaration,
+    in_defn: bool,
+    cb: &mut dyn FnMut(&Identifier, bool),
+) {
+    match decl {
+        Declaration::Normal { spec, id } | Declaration::Optional { spec, id } => {
+            visit_identifiers_type(spec, cb);
+            cb(id, in_defn);
+        }
+        Declaration::FixedArr { spec, id, size } => {
+            visit_identifiers_type(spec, cb);
+            cb(id, in_defn);
+            visit_identifiers_val(size, cb);
+        }
+        Declaration::VarArr { spec, id, size } => {
+            visit_identifiers_type(spec, cb);
+            cb(id, in_defn);
+            if let Some(size) = size {
+                visit_identifiers_val(size, cb)
+            }
+        }
+        Declaration::FixedOpaque { id, size } => {
+            cb(id, in_defn);
+            visit_identifiers_val(size, cb)
+        }
+        Declaration::VarOpaque { id, size } | Declaration::String { id, size } => {
+            cb(id, in_defn);
+            if let Some(size) = size {
+                visit_identifiers_val(size, cb)
+            }
+        }
+        Declaration::VOID => {}
+    }
+}
+
+fn visit_identifiers_enum(body: &EnumBody, cb: &mut dyn FnMut(&Identifier, bool)) {
+    for EnumAssign { id, val, .. } in &body.body {
+        cb(id, true);
+        visit_identifiers_val(val, cb);
+    }
+}
+
+fn visit_identifiers_struct(body: &StructBody, cb: &mut dyn FnMut(&Identifier, bool)) {
+    for decl in &body.body {
+        visit_identifiers_decl(decl, false, cb);
+    }
+}
+
+fn visit_identifiers_union(body: &UnionBody, cb: &mut dyn FnMut(&Identifier, bool)) {
+    visit_identifiers_decl(&body.discriminant, false, cb);
+    for CaseSpec { values, decl, .. } in &body.cases {
+        for val in values {
+            visit_identifiers_val(val, cb);
+        }
+        visit_identifiers_decl(decl, false, cb);
+    }
+    if let Some(decl) = &body.default {
+        visit_identifiers_decl(decl, false, cb);
+    }
+}
+
+fn visit_identifiers_val(val: &Value, cb: &mut dyn FnMut(&Identifier, bool)) {
+    if let Value::Id(id) = val {
+        cb(id, false);
+    }
+}
+
+fn visit_identifiers_type(body: &TypeSpecifier, cb: &mut dyn FnMut(&Identifier, bool)) {
+    match body {
+        TypeSpecifier::BuiltIn(_) => {}
+        TypeSpecifier::Enum(body) => visit_identifiers_enum(body, cb),
+        TypeSpecifier::Struct(body) => visit_identifiers_struct(body, cb),
+        TypeSpecifier::Union(body) => visit_identifiers_union(body, cb),
+        TypeSpecifier::Ident(id) => cb(id, false),
+    }
+}
+
+// Calls `cb` with each name declared with a named type, and that type: fields, union arms, and
+// typedefs, along with procedures (their result type) and enum members (their enum)
+fn visit_declared_types(spec: &Specification, cb: &mut dyn FnMut(&Identifier, &str)) {
+    for defn in &spec.defns {
+        for decl in defn.declarations() {
+            if let Declaration::Normal { spec, id }
+            | Declaration::FixedArr { spec, id, .. }
+            | Declaration::VarArr { spec, id, .. }
+            | Declaration::Optional { spec, id } = decl
+                && let TypeSpecifier::Ident(ty) = spec
+            {
+                cb(id, &ty.id);
+            }
+        }
+        match defn {
+            Definition::Enum { id, body } => {
+                for assign in &body.body {
+                    cb(&assign.id, &id.id);
+                }
+            }
+            Definition::Program { versions, .. } => {
+                for proc in versions.iter().flat_map(|version| &version.procedures) {
+                    if let Some(TypeSpecifier::Ident(ty)) = &proc.result {
+                        cb(&proc.id, &ty.id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// A name as written: without the struct or union a field's qualified name (`S::f`) scopes it by
+pub fn unscoped(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+// The identifier at `pos`, given a document's identifiers by line
+pub fn token_at(identifiers: &HashMap<u32, Vec<Token>>, pos: Position) -> Option<&Token> {
+    let Position {
+        line,
+        character: ch,
+    } = pos;
+    let idents = identifiers.get(&line)?;
+    let index = idents.partition_point(|i| i.start <= ch);
+    let token = idents.get(index.checked_sub(1)?)?;
+    (token.start <= ch && ch <= token.end).then_some(token)
+}
+
+// Returns None if nothing in the file parses. Definitions holding syntax errors are left out, and
+// `lint::syntax_error` reports the first.
+fn parse_file(uri: &Url, file: &str, encoding: Encoding, index: &mut DocumentIndex) -> Option<()> {
+    let (masked, extensions) = dialect::preprocess(file);
+    let spec = recovery::parse(&masked)?;
+    let extension_defns: Vec<(&Identifier, SymbolKind)> = extensions
+        .iter()
+        .filter_map(|item| match &item.kind {
+            dialect::ExtensionKind::Definition { id, kind } => Some((id, *kind)),
+            _ => None,
+        })
+        .collect();
+
+    // Symbol kinds for top-level definitions and the versions and procedures of programs, and
+    // what each enum member, version, and procedure belongs to; anything else defined is an enum
+    // member
+    let mut kinds: HashMap<&str, SymbolKind> = HashMap::new();
+    let mut containers: HashMap<&str, &str> = HashMap::new();
+    for &(id, kind) in &extension_defns {
+        kinds.insert(&id.id, kind);
+    }
+    for defn in &spec.defns {
+        if let Some(id) = defn.id() {
+            kinds.insert(&id.id, symbols::kind(defn));
+        }
+        if let Definition::Enum { id, body } = defn {
+            for assign in &body.body {
+                containers.insert(&assign.id.id, &id.id);
+            }
+        }
+        if let Definition::Program { id, versions, .. } = defn {
+            for version in versions {
+                kinds.insert(&version.id.id, SymbolKind::NAMESPACE);
+                containers.insert(&version.id.id, &id.id);
+                for proc in &version.procedures {
+                    kinds.insert(&proc.id.id, SymbolKind::FUNCTION);
+                    containers.insert(&proc.id.id, &version.id.id);
+                }
+            }
+        }
+    }
+
+    // Fields and union arms are scoped by the struct or union they're part of, so each is a
+    // definition of its own under its qualified name, like `nfs_fh3::data`, found by where it's
+    // declared
+    index
+        .fields
+        .extend(symbols::field_symbols(uri, file, encoding, &spec));
+    let members: BTreeMap<Position, String> = index
+        .fields
+        .iter()
+        .map(|field| {
+            let container = field.container_name.as_deref().unwrap_or_default();
+            (
+                field.location.range.start,
+                format!("{container}::{}", field.name),
+            )
+        })
+        .collect();
+
+    // Collect line numbers
+    let line_locs = position::line_starts(file);
+    let mut record = |id: &Identifier, is_defn: bool| {
+        let start = id.start;
+        // The first line starts at 0, so this is at least 1
+        let line = line_locs.partition_point(|x| x <= &start) - 1;
+        let scol = position::column(&file[line_locs[line]..id.start], encoding);
+        let ecol = scol + position::column(&file[id.start..id.end], encoding);
+        let loc = Location {
+            uri: uri.clone(),
+            range: Range {
+                start: Position {
+                    line: line as u32,
+                    character: scol,
+                },
+                end: Position {
+                    line: line as u32,
+                    character: ecol,
+                },
+            },
+        };
+
+        let member = members.get(&loc.range.start);
+        index
+            .identifiers
+            .entry(line as u32)
+            .or_default()
+            .push(Token {
+                start: scol,
+                end: ecol,
+                val: member.unwrap_or(&id.id).clone(),
+            });
+        if let Some(member) = member {
+            index.defns.insert(member.clone(), loc);
+        } else if is_defn {
+            // `deprecated` is itself deprecated in favor of `tags`
+            #[allow(deprecated)]
+            index.symbols.push(SymbolInformation {
+                name: id.id.clone(),
+                kind: kinds
+                    .get(id.id.as_str())
+                    .copied()
+                    .unwrap_or(SymbolKind::ENUM_MEMBER),
+                tags: None,
+                deprecated: None,
+                location: loc.clone(),
+                container_name: containers.get(id.id.as_str()).map(|name| name.to_string()),
+            });
+            index.defns.insert(id.id.clone(), loc);
+        } else {
+            // Note: this way we can handle when the client requests references not including
+            // definition location
+            index.refs.entry(id.id.clone()).or_default().push(loc);
+        }
+    };
+    visit_identifiers(&spec, &mut record);
+    for (id, _) in extension_defns {
+        record(id, true);
+    }
+    visit_declared_types(&spec, &mut |id, ty| {
+        let line = line_locs.partition_point(|x| x <= &id.start) - 1;
+        index
+            .declared_types
+            .entry(line as u32)
+            .or_default()
+            .push(Token {
+                start: position::column(&file[line_locs[line]..id.start], encoding),
+                end: position::column(&file[line_locs[line]..id.end], encoding),
+                val: ty.to_string(),
+            });
+    });
+    for tokens in index
+        .identifiers
+        .values_mut()
+        .chain(index.declared_types.values_mut())
+    {
+        tokens.sort_by_key(|t| t.start);
+    }
+    Some(())
+}
@@ -0,0 +1,48 @@
+// The XDR language tooling behind `xdr-ls`, as a library: the parser and syntax tree (`ast`), the
+// per-document index (`index`), the workspace scan, and the analyses built on them (lint rules,
+// code generation, the wire codec, formatting, and the rest), and the language server on top of
+// them (`server`), with a harness to drive it in memory (`harness`), and the command line's
+// subcommands (`cli`). The `xdr-ls` binary serves it over a transport.
+use lalrpop_util::lalrpop_mod;
+
+lalrpop_mod!(pub xdr);
+pub mod actions;
+pub mod ast;
+pub mod breaking;
+pub mod cache;
+pub mod cli;
+pub mod codec;
+pub mod codegen;
+pub mod dialect;
+pub mod expand;
+pub mod fields;
+pub mod folding;
+pub mod format;
+pub mod harness;
+pub mod hierarchy;
+pub mod hints;
+pub mod hover;
+pub mod includes;
+pub mod index;
+pub mod lint;
+pub mod position;
+pub mod reachability;
+pub mod recovery;
+pub mod rename;
+pub mod scaffold;
+pub mod scan;
+pub mod selection;
+pub mod semantic;
+pub mod server;
+pub mod since;
+pub mod stdlib;
+pub mod symbols;
+
+// Parses a file's text, handling the dialect extensions (`#define` lines, rpcgen's `program`
+// blocks, annotations) as the server does. The error is the parser's, with byte offsets.
+pub fn parse(text: &str) -> Result<ast::Specification, String> {
+    let (masked, _) = dialect::preprocess(text);
+    xdr::SpecificationParser::new()
+        .parse(&masked)
+        .map_err(|err| err.to_string())
+}
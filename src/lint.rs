@@ -0,0 +1,1216 @@
+// Lint rules and suppression
+//
+// Each check is a `LintRule` run over the whole workspace. Built-in rules are registered in
+// `Registry::builtin`. Rules of your own implement `LintRule` and are handed to
+// `server::service` or `cli::check`, which run them alongside the built-in ones.
+//
+// A `/* xdr-lint: allow(code, ...) */` comment (or the `//` form) suppresses the diagnostics with
+// those codes on one item. The item is the definition the diagnostic is in, or the line it's on
+// outside definitions; the comment goes on its own line just before the item, or trails the
+// item's first or last line.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use lalrpop_util::ParseError;
+use regex::Regex;
+use tower_lsp::lsp_types::*;
+
+use crate::ast::{Declaration, Definition, Identifier, TypeSpecifier, UnionBody, Value};
+use crate::codegen::{Schema, SourceFile};
+use crate::dialect::{self, Dialect};
+use crate::includes::{self, IncludeGraph};
+use crate::position::{Encoding, column, position_at};
+use crate::reachability;
+use crate::rename::non_code_spans;
+use crate::since::{self, ProtocolVersion};
+use crate::stdlib;
+
+pub const DIRECTIVE: &str = "xdr-lint:";
+
+pub const SYNTAX_ERROR: &str = "syntax-error";
+
+// What rules check: the workspace's parsed files and how they include each other
+pub struct ResolvedWorkspace<'a> {
+    pub root: &'a Path,
+    pub files: &'a [SourceFile],
+    pub graph: IncludeGraph<'a>,
+    // The files defining each name
+    pub defined_in: HashMap<String, Vec<PathBuf>>,
+    // What the columns of diagnostics count
+    pub encoding: Encoding,
+}
+
+impl<'a> ResolvedWorkspace<'a> {
+    pub fn new(root: &'a Path, files: &'a [SourceFile], encoding: Encoding) -> Self {
+        let mut defined_in: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for file in files {
+            crate::index::visit_identifiers(&file.spec, &mut |id, is_defn| {
+                if is_defn {
+                    defined_in
+                        .entry(id.id.clone())
+                        .or_default()
+                        .push(file.path.clone());
+                }
+            });
+            for (id, _) in file.extension_definitions() {
+                defined_in
+                    .entry(id.id.clone())
+                    .or_default()
+                    .push(file.path.clone());
+            }
+        }
+        ResolvedWorkspace {
+            root,
+            files,
+            graph: IncludeGraph::new(files),
+            defined_in,
+            encoding,
+        }
+    }
+}
+
+// The error that keeps `text` from parsing, if it doesn't. Syntax errors can't be suppressed. The
+// lint rules check the rest of a file with one (see `SourceFile::parse_recovering`), but report
+// nothing in the spans recovery left out.
+pub fn syntax_error(text: &str, encoding: Encoding) -> Option<Diagnostic> {
+    let (masked, _) = dialect::preprocess(text);
+    let err = crate::xdr::SpecificationParser::new()
+        .parse(&masked)
+        .err()?;
+    let expected = |expected: &[String]| match expected {
+        [] => String::new(),
+        [one] => format!(", expected {one}"),
+        _ => format!(", expected one of {}", expected.join(", ")),
+    };
+    let (start, end, message) = match err {
+        ParseError::InvalidToken { location } => {
+            let len = text[location..].chars().next().map_or(0, char::len_utf8);
+            (location, location + len, "Invalid token".to_string())
+        }
+        ParseError::UnrecognizedEof {
+            location,
+            expected: e,
+        } => (
+            location,
+            location,
+            format!("Unexpected end of file{}", expected(&e)),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected: e,
+        } => (
+            start,
+            end,
+            format!("Unexpected `{}`{}", token.1, expected(&e)),
+        ),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => (start, end, format!("Unexpected `{}`", token.1)),
+        ParseError::User { error } => (0, 0, error.to_string()),
+    };
+    Some(Diagnostic {
+        range: Range::new(
+            position_at(text, start, encoding),
+            position_at(text, end, encoding),
+        ),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: Some(NumberOrString::String(SYNTAX_ERROR.to_string())),
+        source: Some("xdr-ls".to_string()),
+        message,
+        ..Default::default()
+    })
+}
+
+pub trait LintRule: Send + Sync {
+    // The diagnostic code, which is also what suppression comments name
+    fn name(&self) -> &'static str;
+
+    fn default_severity(&self) -> DiagnosticSeverity;
+
+    // Diagnostics by file. The code, source, and severity are filled in for those that leave them
+    // unset.
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>>;
+}
+
+struct MissingInclude;
+
+impl LintRule for MissingInclude {
+    fn name(&self) -> &'static str {
+        includes::MISSING_INCLUDE
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        workspace
+            .files
+            .iter()
+            .map(|file| {
+                let diagnostics = includes::missing_includes(
+                    file,
+                    workspace.root,
+                    &workspace.graph,
+                    &workspace.defined_in,
+                    workspace.encoding,
+                );
+                (file.path.clone(), diagnostics)
+            })
+            .collect()
+    }
+}
+
+struct ConflictingDefinition;
+
+impl LintRule for ConflictingDefinition {
+    fn name(&self) -> &'static str {
+        includes::CONFLICTING_DEFINITION
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::ERROR
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        includes::conflicts(workspace.files, workspace.encoding)
+    }
+}
+
+struct IncludeCycle;
+
+impl LintRule for IncludeCycle {
+    fn name(&self) -> &'static str {
+        includes::INCLUDE_CYCLE
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        includes::cycles(workspace.files, workspace.encoding)
+    }
+}
+
+// Procedures of a version, versions of a program, or programs in the workspace numbered the same.
+// Each is flagged, with links to the others. Only files in the rpcgen dialect, as configured or
+// detected, take part.
+struct DuplicateRpcNumber(Option<Dialect>);
+
+impl DuplicateRpcNumber {
+    // Flags the items sharing a number; `within` names what they're numbered in
+    fn check_numbers(
+        schema: &Schema,
+        encoding: Encoding,
+        what: &str,
+        within: &str,
+        items: &[(&SourceFile, &Identifier, &Value)],
+        out: &mut HashMap<PathBuf, Vec<Diagnostic>>,
+    ) {
+        let location = |file: &SourceFile, id: &Identifier| {
+            Url::from_file_path(&file.path).ok().map(|uri| Location {
+                uri,
+                range: Range::new(
+                    file.position_at(id.start, encoding),
+                    file.position_at(id.end, encoding),
+                ),
+            })
+        };
+        let mut by_number: HashMap<i64, Vec<(&SourceFile, &Identifier)>> = HashMap::new();
+        for &(file, id, number) in items {
+            if let Some(number) = schema.value(number) {
+                by_number.entry(number).or_default().push((file, id));
+            }
+        }
+        for (number, items) in by_number {
+            if items.len() < 2 {
+                continue;
+            }
+            for (i, &(file, id)) in items.iter().enumerate() {
+                let others: Vec<_> = items
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, other)| (j != i).then_some(other))
+                    .collect();
+                let names: Vec<&str> = others.iter().map(|(_, other)| other.id.as_str()).collect();
+                let related = others
+                    .iter()
+                    .filter_map(|&&(file, other)| {
+                        Some(DiagnosticRelatedInformation {
+                            location: location(file, other)?,
+                            message: format!("also numbered {number}"),
+                        })
+                    })
+                    .collect();
+                out.entry(file.path.clone()).or_default().push(Diagnostic {
+                    range: Range::new(
+                        file.position_at(id.start, encoding),
+                        file.position_at(id.end, encoding),
+                    ),
+                    message: format!(
+                        "{what} number {number}{within} is also used by `{}`",
+                        names.join("`, `")
+                    ),
+                    related_information: Some(related),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+}
+
+impl LintRule for DuplicateRpcNumber {
+    fn name(&self) -> &'static str {
+        "duplicate-rpc-number"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::ERROR
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let schema = Schema::new(workspace.files);
+        let mut result = HashMap::new();
+        let mut programs = vec![];
+        for file in workspace.files {
+            if Dialect::of(self.0, &file.text) != Dialect::Rpcgen {
+                continue;
+            }
+            for defn in &file.spec.defns {
+                let Definition::Program {
+                    id,
+                    versions,
+                    number,
+                } = defn
+                else {
+                    continue;
+                };
+                programs.push((file, id, number));
+                let numbers: Vec<_> = versions
+                    .iter()
+                    .map(|version| (file, &version.id, &version.number))
+                    .collect();
+                let within = format!(" of `{}`", id.id);
+                Self::check_numbers(
+                    &schema,
+                    workspace.encoding,
+                    "Version",
+                    &within,
+                    &numbers,
+                    &mut result,
+                );
+                for version in versions {
+                    let numbers: Vec<_> = version
+                        .procedures
+                        .iter()
+                        .map(|proc| (file, &proc.id, &proc.number))
+                        .collect();
+                    let within = format!(" of `{}`", version.id.id);
+                    Self::check_numbers(
+                        &schema,
+                        workspace.encoding,
+                        "Procedure",
+                        &within,
+                        &numbers,
+                        &mut result,
+                    );
+                }
+            }
+        }
+        Self::check_numbers(
+            &schema,
+            workspace.encoding,
+            "Program",
+            "",
+            &programs,
+            &mut result,
+        );
+        result
+    }
+}
+
+// Definitions and members whose `@since` version is newer than the one the workspace targets
+struct NewerThanTarget(ProtocolVersion);
+
+impl LintRule for NewerThanTarget {
+    fn name(&self) -> &'static str {
+        "newer-than-target"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            for item in since::annotated(file) {
+                let Some(version) = ProtocolVersion::parse(item.since) else {
+                    continue;
+                };
+                if version <= self.0 {
+                    continue;
+                }
+                result
+                    .entry(file.path.clone())
+                    .or_default()
+                    .push(Diagnostic {
+                        range: Range::new(
+                            file.position_at(item.id.start, workspace.encoding),
+                            file.position_at(item.id.end, workspace.encoding),
+                        ),
+                        message: format!(
+                            "`{}` is since version {version}, newer than the target version {}",
+                            item.id.id, self.0
+                        ),
+                        ..Default::default()
+                    });
+            }
+        }
+        result
+    }
+}
+
+// Names used but defined nowhere in the workspace. `TRUE` and `FALSE` are the case labels of
+// bool unions.
+struct UndefinedName;
+
+pub const UNDEFINED_NAME: &str = "undefined-name";
+
+impl LintRule for UndefinedName {
+    fn name(&self) -> &'static str {
+        UNDEFINED_NAME
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            let mut refs = vec![];
+            includes::visit_references(&file.spec, &mut refs);
+            for (id, _) in refs {
+                if workspace.defined_in.contains_key(&id.id)
+                    || matches!(id.id.as_str(), "TRUE" | "FALSE")
+                {
+                    continue;
+                }
+                result
+                    .entry(file.path.clone())
+                    .or_default()
+                    .push(Diagnostic {
+                        range: Range::new(
+                            file.position_at(id.start, workspace.encoding),
+                            file.position_at(id.end, workspace.encoding),
+                        ),
+                        message: format!("`{}` isn't defined anywhere in the workspace", id.id),
+                        ..Default::default()
+                    });
+            }
+        }
+        result
+    }
+}
+
+// Names defined more than once in the workspace, each flagged with links to the others. Versions
+// and procedures are left out, as versions of a program commonly repeat procedure names, and so
+// is the standard library, which the workspace can replace.
+struct DuplicateDefinition;
+
+impl LintRule for DuplicateDefinition {
+    fn name(&self) -> &'static str {
+        "duplicate-definition"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        // Name -> where it's defined, by byte range
+        let mut defns: HashMap<String, Vec<(&SourceFile, usize, usize)>> = HashMap::new();
+        for file in workspace.files {
+            if stdlib::is_library(&file.path) {
+                continue;
+            }
+            let mut rpc_names = HashSet::new();
+            for defn in &file.spec.defns {
+                if let Definition::Program { versions, .. } = defn {
+                    for version in versions {
+                        rpc_names.insert(version.id.start);
+                        rpc_names.extend(version.procedures.iter().map(|proc| proc.id.start));
+                    }
+                }
+            }
+            crate::index::visit_identifiers(&file.spec, &mut |id, is_defn| {
+                if is_defn && !rpc_names.contains(&id.start) {
+                    defns
+                        .entry(id.id.clone())
+                        .or_default()
+                        .push((file, id.start, id.end));
+                }
+            });
+        }
+        let range = |file: &SourceFile, start: usize, end: usize| {
+            Range::new(
+                file.position_at(start, workspace.encoding),
+                file.position_at(end, workspace.encoding),
+            )
+        };
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for (name, found) in defns {
+            if found.len() < 2 {
+                continue;
+            }
+            for (i, &(file, start, end)) in found.iter().enumerate() {
+                let related = found
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .filter_map(|(_, &(file, start, end))| {
+                        Some(DiagnosticRelatedInformation {
+                            location: Location {
+                                uri: Url::from_file_path(&file.path).ok()?,
+                                range: range(file, start, end),
+                            },
+                            message: "also defined here".to_string(),
+                        })
+                    })
+                    .collect();
+                result
+                    .entry(file.path.clone())
+                    .or_default()
+                    .push(Diagnostic {
+                        range: range(file, start, end),
+                        message: format!("`{name}` is defined {} times", found.len()),
+                        related_information: Some(related),
+                        ..Default::default()
+                    });
+            }
+        }
+        result
+    }
+}
+
+// The byte ranges of a union's case labels in order, found in `masked` (the file's text with
+// comments blanked out), as literals don't record where they are. Nested unions' labels are
+// skipped.
+fn case_labels(masked: &str, body: &UnionBody) -> Vec<(usize, usize)> {
+    let bytes = masked.as_bytes();
+    let is_ident = |i: usize| {
+        bytes
+            .get(i)
+            .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_')
+    };
+    let mut labels = vec![];
+    let mut depth = 0;
+    let mut i = body.start;
+    while i < body.end {
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b'c' if depth == 1
+                && masked[i..].starts_with("case")
+                && (i == 0 || !is_ident(i - 1))
+                && !is_ident(i + 4) =>
+            {
+                let Some(colon) = masked[i..body.end].find(':') else {
+                    break;
+                };
+                let label = &masked[i + 4..i + colon];
+                let start = i + 4 + (label.len() - label.trim_start().len());
+                labels.push((start, i + 4 + label.trim_end().len()));
+                i += colon;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    labels
+}
+
+// A case value with its byte range
+type CaseValue<'a> = (&'a Value, usize, usize);
+
+// Every union in a file's definitions, top-level or nested, with its case values
+fn unions_with_labels(file: &SourceFile) -> Vec<(&UnionBody, Vec<CaseValue<'_>>)> {
+    let mut masked = file.text.clone().into_bytes();
+    for (start, end) in non_code_spans(&file.text) {
+        masked[start..end].fill(b' ');
+    }
+    let masked = String::from_utf8(masked).unwrap_or_default();
+    let mut result = vec![];
+    for defn in &file.spec.defns {
+        let mut unions = vec![];
+        if let Definition::Union { body, .. } = defn {
+            unions.push(body);
+        }
+        for decl in defn.declarations() {
+            if let Declaration::Normal { spec, .. }
+            | Declaration::FixedArr { spec, .. }
+            | Declaration::VarArr { spec, .. }
+            | Declaration::Optional { spec, .. } = decl
+                && let TypeSpecifier::Union(body) = spec
+            {
+                unions.push(body);
+            }
+        }
+        for body in unions {
+            let values: Vec<&Value> = body.cases.iter().flat_map(|case| &case.values).collect();
+            let labels = case_labels(&masked, body);
+            let aligned = labels.len() == values.len();
+            let ranges = values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| match (value, labels.get(i)) {
+                    (Value::Id(id), _) => (value, id.start, id.end),
+                    (_, Some(&(start, end))) if aligned => (value, start, end),
+                    // Fall back to the `switch` keyword
+                    _ => (value, body.start, body.start + "switch".len()),
+                })
+                .collect();
+            result.push((body, ranges));
+        }
+    }
+    result
+}
+
+// Case values of unions switching on an enum that aren't members of it: names from elsewhere, or
+// numbers no member has
+struct CaseNotInEnum;
+
+impl LintRule for CaseNotInEnum {
+    fn name(&self) -> &'static str {
+        "case-not-in-enum"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let schema = Schema::new(workspace.files);
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            if stdlib::is_library(&file.path) {
+                continue;
+            }
+            for (body, values) in unions_with_labels(file) {
+                let Some((name, enum_body)) = schema.discriminant_enum(body) else {
+                    continue;
+                };
+                let enum_name = name.map_or("the anonymous enum".to_string(), |name| {
+                    format!("enum `{name}`")
+                });
+                for (value, start, end) in values {
+                    let message = match value {
+                        Value::Id(id) => {
+                            if enum_body.body.iter().any(|assign| assign.id.id == id.id)
+                                || !workspace.defined_in.contains_key(&id.id)
+                            {
+                                continue;
+                            }
+                            format!("`{}` isn't a member of {enum_name}", id.id)
+                        }
+                        Value::Const(literal) => {
+                            let value = schema.value(value);
+                            if value.is_none()
+                                || enum_body.body.iter().any(|assign| {
+                                    schema.consts.get(assign.id.id.as_str()) == value.as_ref()
+                                })
+                            {
+                                continue;
+                            }
+                            format!("No member of {enum_name} has the value `{literal}`")
+                        }
+                    };
+                    result
+                        .entry(file.path.clone())
+                        .or_default()
+                        .push(Diagnostic {
+                            range: Range::new(
+                                file.position_at(start, workspace.encoding),
+                                file.position_at(end, workspace.encoding),
+                            ),
+                            message,
+                            ..Default::default()
+                        });
+                }
+            }
+        }
+        result
+    }
+}
+
+// Case values a union handles more than once, each flagged after the first with a link to it
+struct DuplicateCase;
+
+impl LintRule for DuplicateCase {
+    fn name(&self) -> &'static str {
+        "duplicate-case"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::ERROR
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let schema = Schema::new(workspace.files);
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            if stdlib::is_library(&file.path) {
+                continue;
+            }
+            let range = |start: usize, end: usize| {
+                Range::new(
+                    file.position_at(start, workspace.encoding),
+                    file.position_at(end, workspace.encoding),
+                )
+            };
+            for (_, values) in unions_with_labels(file) {
+                // Value -> where it's first handled
+                let mut seen: HashMap<i64, (usize, usize)> = HashMap::new();
+                for (value, start, end) in values {
+                    let Some(number) = schema.case_value(value) else {
+                        continue;
+                    };
+                    let Some(&(first_start, first_end)) = seen.get(&number) else {
+                        seen.insert(number, (start, end));
+                        continue;
+                    };
+                    let related = Url::from_file_path(&file.path).ok().map(|uri| {
+                        vec![DiagnosticRelatedInformation {
+                            location: Location {
+                                uri,
+                                range: range(first_start, first_end),
+                            },
+                            message: "first handled here".to_string(),
+                        }]
+                    });
+                    result
+                        .entry(file.path.clone())
+                        .or_default()
+                        .push(Diagnostic {
+                            range: range(start, end),
+                            message: match (
+                                &file.text[start..end],
+                                &file.text[first_start..first_end],
+                            ) {
+                                (label, first) if label == first => {
+                                    format!("`{label}` is already handled")
+                                }
+                                (label, first) => {
+                                    format!("`{label}` is already handled by `{first}`")
+                                }
+                            },
+                            related_information: related,
+                            ..Default::default()
+                        });
+                }
+            }
+        }
+        result
+    }
+}
+
+// Unions switching on an enum without a default arm that leave members unhandled
+struct UnhandledCase;
+
+pub const UNHANDLED_CASE: &str = "unhandled-case";
+
+impl LintRule for UnhandledCase {
+    fn name(&self) -> &'static str {
+        UNHANDLED_CASE
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::WARNING
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let schema = Schema::new(workspace.files);
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            if stdlib::is_library(&file.path) {
+                continue;
+            }
+            for (body, _) in unions_with_labels(file) {
+                if body.default.is_some() {
+                    continue;
+                }
+                let unhandled = schema.unhandled_members(body);
+                if unhandled.is_empty() {
+                    continue;
+                }
+                let names: Vec<String> = unhandled.iter().map(|name| format!("`{name}`")).collect();
+                result
+                    .entry(file.path.clone())
+                    .or_default()
+                    .push(Diagnostic {
+                        range: Range::new(
+                            file.position_at(body.start, workspace.encoding),
+                            file.position_at(body.start + "switch".len(), workspace.encoding),
+                        ),
+                        message: format!("No case or default arm handles {}", names.join(", ")),
+                        ..Default::default()
+                    });
+            }
+        }
+        result
+    }
+}
+
+// Types no root type leads to, when roots are declared
+struct Unreachable(Vec<String>);
+
+impl LintRule for Unreachable {
+    fn name(&self) -> &'static str {
+        "unreachable-definition"
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::HINT
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for (file, defn) in reachability::unreachable(workspace.files, &self.0).unwrap_or_default()
+        {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            result
+                .entry(file.path.clone())
+                .or_default()
+                .push(Diagnostic {
+                    range: Range::new(
+                        file.position_at(id.start, workspace.encoding),
+                        file.position_at(id.end, workspace.encoding),
+                    ),
+                    message: format!("`{}` isn't reachable from any root type", id.id),
+                    tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    ..Default::default()
+                });
+        }
+        result
+    }
+}
+
+// Types and constants nothing refers to, except those whose names match `exempt` and those the
+// reachability rule already reports
+struct UnusedDefinition {
+    root_types: Vec<String>,
+    exempt: Option<Regex>,
+}
+
+pub const UNUSED_DEFINITION: &str = "unused-definition";
+
+impl LintRule for UnusedDefinition {
+    fn name(&self) -> &'static str {
+        UNUSED_DEFINITION
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::HINT
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let unreachable: HashSet<*const Definition> =
+            reachability::unreachable(workspace.files, &self.root_types)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(_, defn)| defn as *const Definition)
+                .collect();
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for (file, defn) in reachability::unreferenced(workspace.files, &self.root_types) {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            if unreachable.contains(&(defn as *const Definition))
+                || self
+                    .exempt
+                    .as_ref()
+                    .is_some_and(|exempt| exempt.is_match(&id.id))
+            {
+                continue;
+            }
+            let what = if matches!(defn, Definition::Constant { .. }) {
+                "Constant"
+            } else {
+                "Type"
+            };
+            result
+                .entry(file.path.clone())
+                .or_default()
+                .push(Diagnostic {
+                    range: Range::new(
+                        file.position_at(id.start, workspace.encoding),
+                        file.position_at(id.end, workspace.encoding),
+                    ),
+                    message: format!("{what} `{}` is never used", id.id),
+                    tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    ..Default::default()
+                });
+        }
+        result
+    }
+}
+
+// Variable-length arrays, opaques, and strings declared without a bound (`<>`)
+struct UnboundedLength;
+
+pub const UNBOUNDED_LENGTH: &str = "unbounded-length";
+
+// The unbounded declarations in `file`, each with the definition it's in and the span of its `<>`
+pub fn unbounded_declarations(file: &SourceFile) -> Vec<(&Definition, &Identifier, usize, usize)> {
+    let mut result = vec![];
+    for defn in &file.spec.defns {
+        for decl in defn.declarations() {
+            let (Declaration::VarArr { id, size: None, .. }
+            | Declaration::VarOpaque { id, size: None }
+            | Declaration::String { id, size: None }) = decl
+            else {
+                continue;
+            };
+            // The `<>` follows the identifier
+            let Some(open) = file.text[id.end..].find('<').map(|i| id.end + i) else {
+                continue;
+            };
+            let Some(close) = file.text[open..].find('>').map(|i| open + i) else {
+                continue;
+            };
+            if file.text[open + 1..close].trim().is_empty() {
+                result.push((defn, id, open, close + 1));
+            }
+        }
+    }
+    result
+}
+
+impl LintRule for UnboundedLength {
+    fn name(&self) -> &'static str {
+        UNBOUNDED_LENGTH
+    }
+
+    fn default_severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::HINT
+    }
+
+    fn check(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+        for file in workspace.files {
+            if stdlib::is_library(&file.path) {
+                continue;
+            }
+            for (_, id, _, end) in unbounded_declarations(file) {
+                result
+                    .entry(file.path.clone())
+                    .or_default()
+                    .push(Diagnostic {
+                        range: Range::new(
+                            file.position_at(id.start, workspace.encoding),
+                            file.position_at(end, workspace.encoding),
+                        ),
+                        message: format!("`{}` has no length bound", id.id),
+                        ..Default::default()
+                    });
+            }
+        }
+        result
+    }
+}
+
+// Rules are shared, so the server can rebuild its registry when settings change and keep the
+// rules it was given
+#[derive(Default, Clone)]
+pub struct Registry {
+    rules: Vec<Arc<dyn LintRule>>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.rules.iter().map(|rule| rule.name()))
+            .finish()
+    }
+}
+
+impl Registry {
+    // The built-in rules. The include rules only make sense for workspaces that use `%#include`
+    // consistently, so they're opt-in; they replace the duplicate definition rule, as a name may
+    // be defined again in files never compiled together. The RPC rules come with the rpcgen
+    // dialect (configured, or detected file by file when `dialect` is None), the `@since` rule
+    // with a target version, and the unhandled case rule with `unhandled_cases`. The undefined
+    // name, union case, unbounded length, unused definition, and reachability rules are always
+    // on, the last saying nothing until roots are declared in `root_types` or with `@root`. Names
+    // matching `unused_exempt` are never reported unused.
+    pub fn builtin(
+        include_aware: bool,
+        dialect: Option<Dialect>,
+        target_version: Option<ProtocolVersion>,
+        root_types: Vec<String>,
+        unhandled_cases: bool,
+        unused_exempt: Option<Regex>,
+    ) -> Self {
+        let mut registry = Registry::default();
+        registry.register(Box::new(UndefinedName));
+        registry.register(Box::new(CaseNotInEnum));
+        registry.register(Box::new(DuplicateCase));
+        if unhandled_cases {
+            registry.register(Box::new(UnhandledCase));
+        }
+        registry.register(Box::new(UnboundedLength));
+        if include_aware {
+            registry.register(Box::new(MissingInclude));
+            registry.register(Box::new(ConflictingDefinition));
+            registry.register(Box::new(IncludeCycle));
+        } else {
+            registry.register(Box::new(DuplicateDefinition));
+        }
+        if dialect != Some(Dialect::Xdrpp) {
+            registry.register(Box::new(DuplicateRpcNumber(dialect)));
+        }
+        if let Some(target) = target_version {
+            registry.register(Box::new(NewerThanTarget(target)));
+        }
+        registry.register(Box::new(UnusedDefinition {
+            root_types: root_types.clone(),
+            exempt: unused_exempt,
+        }));
+        registry.register(Box::new(Unreachable(root_types)));
+        registry
+    }
+
+    pub fn register(&mut self, rule: Box<dyn LintRule>) {
+        self.rules.push(Arc::from(rule));
+    }
+
+    // Adds the rules of `other`
+    pub fn extend(&mut self, other: &Registry) {
+        self.rules.extend(other.rules.iter().cloned());
+    }
+
+    // Drops the rules named in `names`, for the `disabledRules` setting
+    pub fn disable(&mut self, names: &[String]) {
+        self.rules
+            .retain(|rule| !names.iter().any(|name| name == rule.name()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Runs every rule, returning the unsuppressed diagnostics for each file (empty for files
+    // without any, so stale ones get cleared)
+    pub fn run(&self, workspace: &ResolvedWorkspace) -> HashMap<PathBuf, Vec<Diagnostic>> {
+        let mut result: HashMap<PathBuf, Vec<Diagnostic>> = workspace
+            .files
+            .iter()
+            .map(|file| (file.path.clone(), vec![]))
+            .collect();
+        let files: HashMap<&Path, &SourceFile> = workspace
+            .files
+            .iter()
+            .map(|file| (file.path.as_path(), file))
+            .collect();
+        let mut items: HashMap<&Path, Items> = HashMap::new();
+        for rule in &self.rules {
+            for (path, diagnostics) in rule.check(workspace) {
+                let Some(&file) = files.get(path.as_path()) else {
+                    continue;
+                };
+                let items = items
+                    .entry(file.path.as_path())
+                    .or_insert_with(|| Items::new(file));
+                let found = result.entry(path).or_default();
+                for mut diagnostic in diagnostics {
+                    diagnostic
+                        .code
+                        .get_or_insert_with(|| NumberOrString::String(rule.name().to_string()));
+                    diagnostic
+                        .source
+                        .get_or_insert_with(|| "xdr-ls".to_string());
+                    diagnostic
+                        .severity
+                        .get_or_insert_with(|| rule.default_severity());
+                    if !items.suppressed(file, &diagnostic, workspace.encoding)
+                        && !in_skipped(file, &diagnostic, workspace.encoding)
+                    {
+                        found.push(diagnostic);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+// The codes a line's suppression comments allow
+fn allowed_on(line: &str) -> Vec<&str> {
+    let Some(comment) = line.find("/*").or_else(|| line.find("//")) else {
+        return vec![];
+    };
+    let mut codes = vec![];
+    for (i, _) in line[comment..].match_indices(DIRECTIVE) {
+        let rest = line[comment + i + DIRECTIVE.len()..].trim_start();
+        if let Some(list) = rest.strip_prefix("allow(")
+            && let Some(end) = list.find(')')
+        {
+            codes.extend(list[..end].split(',').map(str::trim));
+        }
+    }
+    codes
+}
+
+fn is_comment_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("/*") || line.starts_with("//")
+}
+
+// A file's definitions with the lines they span, built once per file so each diagnostic's
+// item is found by binary search
+struct Items<'a> {
+    lines: Vec<&'a str>,
+    // The start and end offsets, first and last lines, and name of each definition, by start
+    spans: Vec<(usize, usize, usize, usize, Option<&'a str>)>,
+}
+
+impl<'a> Items<'a> {
+    fn new(file: &'a SourceFile) -> Self {
+        let mut spans: Vec<_> = file
+            .spec
+            .defns
+            .iter()
+            .filter_map(|defn| {
+                let start = file.definition_start(defn)?;
+                let end = file.definition_end(defn)?;
+                let name = defn.id().map(|id| id.id.as_str());
+                Some((
+                    start,
+                    end,
+                    file.line_at(start) as usize,
+                    file.line_at(end) as usize,
+                    name,
+                ))
+            })
+            .collect();
+        spans.sort_by_key(|&(start, ..)| start);
+        Items {
+            lines: file.text.lines().collect(),
+            spans,
+        }
+    }
+
+    // The first and last lines of the item around `offset`, and its name if it's a definition
+    fn around(&self, file: &SourceFile, offset: usize) -> (usize, usize, Option<&'a str>) {
+        let i = self.spans.partition_point(|&(start, ..)| start <= offset);
+        match i.checked_sub(1).map(|i| self.spans[i]) {
+            Some((_, end, first, last, name)) if offset < end => (first, last, name),
+            _ => {
+                let line = file.line_at(offset) as usize;
+                (line, line, None)
+            }
+        }
+    }
+
+    fn suppressed(&self, file: &SourceFile, diagnostic: &Diagnostic, encoding: Encoding) -> bool {
+        let Some(code) = code(diagnostic) else {
+            return false;
+        };
+        let offset = file.offset_at(diagnostic.range.start, encoding);
+        let (first, last, _) = self.around(file, offset);
+        let before = first
+            .checked_sub(1)
+            .and_then(|i| self.lines.get(i))
+            .filter(|line| is_comment_line(line));
+        before
+            .into_iter()
+            .chain(self.lines.get(first))
+            .chain(self.lines.get(last))
+            .any(|line| allowed_on(line).contains(&code))
+    }
+}
+
+fn code(diagnostic: &Diagnostic) -> Option<&str> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) if diagnostic.source.as_deref() == Some("xdr-ls") => {
+            Some(code)
+        }
+        _ => None,
+    }
+}
+
+fn in_skipped(file: &SourceFile, diagnostic: &Diagnostic, encoding: Encoding) -> bool {
+    let offset = file.offset_at(diagnostic.range.start, encoding);
+    file.skipped
+        .iter()
+        .any(|&(start, end)| start <= offset && offset < end)
+}
+
+// "Suppress" quick fixes for lint diagnostics: each adds the diagnostic's code to the
+// suppression comment before its item, adding the comment if there isn't one
+pub fn add_suppression(
+    file: &SourceFile,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    encoding: Encoding,
+) -> Vec<CodeAction> {
+    let items = Items::new(file);
+    let lines = &items.lines;
+    let mut result = vec![];
+    for diagnostic in diagnostics {
+        let Some(code) = code(diagnostic) else {
+            continue;
+        };
+        let offset = file.offset_at(diagnostic.range.start, encoding);
+        let (first, _, name) = items.around(file, offset);
+        let existing = first
+            .checked_sub(1)
+            .filter(|&i| lines.get(i).is_some_and(|line| is_comment_line(line)))
+            .and_then(|i| {
+                let line = lines[i];
+                let list = line.find(DIRECTIVE)? + DIRECTIVE.len();
+                let close = column(&line[..list + line[list..].find(')')?], encoding);
+                line[list..]
+                    .trim_start()
+                    .starts_with("allow(")
+                    .then_some((i, close))
+            });
+        let edit = match existing {
+            Some((line, close)) => TextEdit {
+                range: Range::new(
+                    Position::new(line as u32, close),
+                    Position::new(line as u32, close),
+                ),
+                new_text: format!(", {code}"),
+            },
+            None => {
+                let line = lines.get(first).copied().unwrap_or_default();
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let at = Position::new(first as u32, 0);
+                TextEdit {
+                    range: Range::new(at, at),
+                    new_text: format!("{indent}/* {DIRECTIVE} allow({code}) */\n"),
+                }
+            }
+        };
+        result.push(CodeAction {
+            title: match name {
+                Some(name) => format!("Suppress `{code}` for `{name}`"),
+                None => format!("Suppress `{code}` on this line"),
+            },
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    result
+}
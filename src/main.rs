@@ -1,447 +1,58 @@
-use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use tokio::sync::Mutex;
-use tower_lsp::jsonrpc::{Error, Result};
-use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tower_lsp::Server;
 
-use lalrpop_util::lalrpop_mod;
+use xdr_ls::{cli, codegen, server};
 
-lalrpop_mod!(xdr);
-pub mod ast;
-use ast::*;
-
-#[derive(Debug)]
-struct Token {
-    start: u32,
-    end: u32,
-    val: String,
-}
-
-#[derive(Debug)]
-struct Backend {
-    client: Client,
-    // Used to find the identifier at a given location, note the vector must be sorted
-    // file -> line -> list of identifiers
-    identifiers: Mutex<HashMap<PathBuf, HashMap<u32, Vec<Token>>>>,
-    // TODO: these probably should also take a file parameter so they can be updated
-    // Used to find references to a given identifier, the vector is unsorted
-    referenced_locs: Mutex<HashMap<String, Vec<Location>>>,
-    // Used to find where identifiers are defined
-    defn_locs: Mutex<HashMap<String, Location>>,
-
-    // Set of potential generated header files
-    header_files: Mutex<HashSet<PathBuf>>,
-}
-
-fn make_error(code: i64, message: &'static str) -> Error {
-    Error {
-        code: tower_lsp::jsonrpc::ErrorCode::ServerError(code),
-        message: std::borrow::Cow::Borrowed(message),
-        data: None,
-    }
-}
-
-fn get_xdr_files(dir: &PathBuf, cb: &mut dyn FnMut(&PathBuf)) {
-    if dir.is_dir()
-        && let Ok(entries) = fs::read_dir(dir)
-    {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_dir() {
-                    get_xdr_files(&path, cb);
-                } else if path.extension().is_some_and(|ext| ext == "x") {
-                    cb(&path);
-                }
-            }
-        }
-    }
-}
-
-fn visit_identifiers(spec: &Specification, cb: &mut dyn FnMut(&Identifier, bool)) {
-    for defn in &spec.defns {
-        visit_identifiers_defn(defn, cb);
-    }
-}
-
-fn visit_identifiers_defn(defn: &Definition, cb: &mut dyn FnMut(&Identifier, bool)) {
-    match defn {
-        Definition::Constant { id, .. } => {
-            cb(id, true);
-        }
-        Definition::TypeDef(decl) => {
-            visit_identifiers_decl(decl, true, cb);
-        }
-        Definition::Enum { id, body } => {
-            cb(id, true);
-            visit_identifiers_enum(body, cb);
-        }
-        Definition::Struct { id, body } => {
-            cb(id, true);
-            visit_identifiers_struct(body, cb);
-        }
-        Definition::Union { id, body } => {
-            cb(id, true);
-            visit_identifiers_union(body, cb);
-        }
-    }
-}
-
-fn visit_identifiers_decl(
-    decl: &Declaration,
-    in_defn: bool,
-    cb: &mut dyn FnMut(&Identifier, bool),
-) {
-    match decl {
-        Declaration::Normal { spec, id } | Declaration::Optional { spec, id } => {
-            visit_identifiers_type(spec, cb);
-            cb(id, in_defn);
-        }
-        Declaration::FixedArr { spec, id, size } => {
-            visit_identifiers_type(spec, cb);
-            cb(id, in_defn);
-            visit_identifiers_val(size, cb);
-        }
-        Declaration::VarArr { spec, id, size } => {
-            visit_identifiers_type(spec, cb);
-            cb(id, in_defn);
-            if let Some(size) = size {
-                visit_identifiers_val(size, cb)
-            }
-        }
-        Declaration::FixedOpaque { id, size } => {
-            cb(id, in_defn);
-            visit_identifiers_val(size, cb)
-        }
-        Declaration::VarOpaque { id, size } | Declaration::String { id, size } => {
-            cb(id, in_defn);
-            if let Some(size) = size {
-                visit_identifiers_val(size, cb)
-            }
-        }
-        Declaration::VOID => {}
-    }
-}
-
-fn visit_identifiers_enum(body: &EnumBody, cb: &mut dyn FnMut(&Identifier, bool)) {
-    for EnumAssign { id, val } in &body.body {
-        cb(id, true);
-        visit_identifiers_val(val, cb);
-    }
-}
-
-fn visit_identifiers_struct(body: &StructBody, cb: &mut dyn FnMut(&Identifier, bool)) {
-    for decl in &body.body {
-        visit_identifiers_decl(decl, false, cb);
-    }
-}
-
-fn visit_identifiers_union(body: &UnionBody, cb: &mut dyn FnMut(&Identifier, bool)) {
-    visit_identifiers_decl(&body.discriminant, false, cb);
-    for CaseSpec { values, decl } in &body.cases {
-        for val in values {
-            visit_identifiers_val(val, cb);
-        }
-        visit_identifiers_decl(decl, false, cb);
-    }
-    if let Some(decl) = &body.default {
-        visit_identifiers_decl(decl, false, cb);
-    }
-}
-
-fn visit_identifiers_val(val: &Value, cb: &mut dyn FnMut(&Identifier, bool)) {
-    if let Value::Id(id) = val {
-        cb(id, false);
-    }
-}
-
-fn visit_identifiers_type(body: &TypeSpecifier, cb: &mut dyn FnMut(&Identifier, bool)) {
-    match body {
-        TypeSpecifier::BuiltIn(_) => {}
-        TypeSpecifier::Enum(body) => visit_identifiers_enum(body, cb),
-        TypeSpecifier::Struct(body) => visit_identifiers_struct(body, cb),
-        TypeSpecifier::Union(body) => visit_identifiers_union(body, cb),
-        TypeSpecifier::Ident(id) => cb(id, false),
-    }
-}
-
-// TODO: probably want to actually pass back the errors
-// TODO: the return value is meaningless: it's just there so we can use the ? for early returns
-fn parse_file(
-    path: &PathBuf,
-    identifiers: &mut HashMap<u32, Vec<Token>>,
-    ref_locs: &mut HashMap<String, Vec<Location>>,
-    defn_locs: &mut HashMap<String, Location>,
-) -> Option<()> {
-    let uri: Url = Url::from_file_path(path).ok()?;
-    let file = fs::read_to_string(path).ok()?;
-    let spec = xdr::SpecificationParser::new().parse(&file).ok()?;
-
-    // Collect line numbers
-    let line_locs: Vec<usize> = file
-        .char_indices()
-        .filter(|(i, c)| *i == 0 || *c == '\n')
-        .map(|(i, _)| if i == 0 { 0 } else { i + 1 })
-        .collect();
-
-    visit_identifiers(&spec, &mut |id, is_defn| {
-        let start = id.start;
-        let line = line_locs.partition_point(|x| x <= &start) - 1;
-        let scol = id.start - line_locs[line];
-        let ecol = id.end - line_locs[line];
-        let loc = Location {
-            uri: uri.clone(),
-            range: Range {
-                start: Position {
-                    line: line as u32,
-                    character: scol as u32,
-                },
-                end: Position {
-                    line: line as u32,
-                    character: ecol as u32,
-                },
-            },
-        };
-
-        identifiers.entry(line as u32).or_default().push(Token {
-            start: scol as u32,
-            end: ecol as u32,
-            val: id.id.clone(),
-        });
-        if is_defn {
-            defn_locs.insert(id.id.clone(), loc);
-        } else {
-            // Note: this way we can handle when the client requests references not including
-            // definition location
-            ref_locs.entry(id.id.clone()).or_default().push(loc);
-        }
-    });
-    let keys: Vec<_> = identifiers.keys().map(|k| *k).collect();
-    for key in keys {
-        if let Some(vec) = identifiers.get_mut(&key) {
-            vec.sort_by_key(|t| t.start);
-        }
-    }
-    None
-}
-
-impl Backend {
-    fn new(client: Client) -> Self {
-        Backend {
-            client: client,
-            identifiers: Mutex::new(HashMap::new()),
-            referenced_locs: Mutex::new(HashMap::new()),
-            defn_locs: Mutex::new(HashMap::new()),
-            header_files: Mutex::new(HashSet::new()),
-        }
-    }
-
-    async fn get_ident_at(&self, path: &PathBuf, pos: Position) -> Option<String> {
-        let Position {
-            line,
-            character: ch,
-        } = pos;
-        self.identifiers
-            .lock()
-            .await
-            .get(path)
-            .and_then(|m| m.get(&line))
-            .and_then(|idents| {
-                let index = idents.partition_point(|i| i.start <= ch);
-                if index == 0 {
-                    None
-                } else {
-                    idents.get(index - 1)
-                }
-            })
-            .and_then(|token| {
-                if token.start <= ch && ch <= token.end {
-                    Some(token.val.clone())
-                } else {
-                    None
-                }
-            })
-    }
-}
-
-impl Backend {
-    async fn lookup_ident(&self, ident: &String) -> Option<GotoDefinitionResponse> {
-        match self.defn_locs.lock().await.get(ident) {
-            Some(loc) => Some(GotoDefinitionResponse::Scalar(loc.clone())),
-            None => None,
-        }
-    }
-}
-
-#[tower_lsp::async_trait]
-impl LanguageServer for Backend {
-    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        let uri = match params.root_uri {
-            Some(uri) => uri,
-            None => {
-                return Err(make_error(
-                    0,
-                    "This language server requires root_uri to be set",
-                ));
-            }
-        };
-        let uri = match uri.to_file_path() {
-            Ok(uri) => uri,
-            Err(_) => {
-                return Err(make_error(
-                    0,
-                    "root_uri doesn't seem to be a valid filepath",
-                ));
-            }
-        };
-        if !uri.is_dir() {
-            return Err(make_error(0, "root_uri doesn't name a directory"));
-        }
-        let mut paths: Vec<PathBuf> = vec![];
-        get_xdr_files(&uri, &mut |path| paths.push(path.to_path_buf()));
-        {
-            let mut header_files = self.header_files.lock().await;
-            for path in &paths {
-                let mut new_path = path.clone();
-                new_path.set_extension("h");
-                header_files.insert(new_path);
-            }
-        }
-        {
-            let mut identifiers = self.identifiers.lock().await;
-            let mut refs = self.referenced_locs.lock().await;
-            let mut defns = self.defn_locs.lock().await;
-            for path in &paths {
-                parse_file(
-                    path,
-                    identifiers.entry(path.to_path_buf()).or_default(),
-                    &mut refs,
-                    &mut defns,
-                );
-            }
-        }
-        Ok(InitializeResult {
-            capabilities: ServerCapabilities {
-                definition_provider: Some(OneOf::Left(true)),
-                references_provider: Some(OneOf::Left(true)),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-    }
-
-    async fn initialized(&self, _: InitializedParams) {
-        self.client
-            .log_message(MessageType::INFO, "server initialized!")
-            .await;
-    }
-
-    async fn goto_definition(
-        &self,
-        params: GotoDefinitionParams,
-    ) -> Result<Option<GotoDefinitionResponse>> {
-        if let Ok(path) = params
-            .text_document_position_params
-            .text_document
-            .uri
-            .to_file_path()
-        {
-            if let Some(ext) = path.extension()
-                && ext == "h"
-            {
-                if !self.header_files.lock().await.contains(&path) {
-                    return Ok(None);
-                }
-                if let Ok(file) = fs::read_to_string(path) {
-                    let pos = params.text_document_position_params.position;
-                    if let Some(line) = file.lines().nth(pos.line as usize) {
-                        let character = pos.character as usize;
-                        let mut start = 0;
-                        let mut end = 0;
-                        let mut in_ident = false;
-                        let mut ident: Option<String> = None;
-                        for (i, c) in line.char_indices() {
-                            if c.is_ascii_alphabetic() {
-                                if !in_ident {
-                                    in_ident = true;
-                                    start = i;
-                                }
-                                end = i;
-                            } else if in_ident {
-                                if c.is_ascii_digit() {
-                                    end = i
-                                } else {
-                                    in_ident = false;
-                                    if start <= character && character <= end {
-                                        ident = Some(line[start..=end].to_string());
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        if let Some(ident) = ident {
-                            return Ok(self.lookup_ident(&ident).await);
-                        }
-                    }
-                }
-                return Ok(None);
-            }
-
-            match self
-                .get_ident_at(&path, params.text_document_position_params.position)
-                .await
-            {
-                None => Ok(None),
-                Some(ident) => Ok(self.lookup_ident(&ident).await),
-            }
-        } else {
-            Err(make_error(0, "Could not open file"))
-        }
-    }
-
-    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        if let Ok(path) = params
-            .text_document_position
-            .text_document
-            .uri
-            .to_file_path()
-        {
-            match self
-                .get_ident_at(&path, params.text_document_position.position)
-                .await
-            {
-                None => Ok(None),
-                Some(ident) => match self.referenced_locs.lock().await.get(&ident) {
-                    Some(locs) => Ok(Some({
-                        let mut locs = locs.clone();
-                        if params.context.include_declaration {
-                            if let Some(decl) = self.defn_locs.lock().await.get(&ident) {
-                                locs.push(decl.clone());
-                            }
-                        }
-                        locs
-                    })),
-                    None => Ok(None),
-                },
-            }
-        } else {
-            Err(make_error(0, "Could not open file"))
-        }
-    }
-
-    async fn shutdown(&self) -> Result<()> {
-        Ok(())
-    }
-}
+mod panics;
+#[cfg(feature = "sync-server")]
+mod sync_server;
+mod transport;
 
 #[tokio::main]
-async fn main() {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
-
-    let (service, socket) = LspService::new(|client| Backend::new(client));
-    Server::new(stdin, stdout, socket).serve(service).await;
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("gen") => return codegen::run(&args[1..]),
+        Some("decode") => return cli::decode(&args[1..]),
+        Some("encode") => return cli::encode(&args[1..]),
+        Some("breaking") => return cli::breaking(&args[1..]),
+        Some("stats") => return cli::stats(&args[1..]),
+        Some("rename") => return cli::rename(&args[1..]),
+        Some("resolve") => return cli::resolve(&args[1..]),
+        Some("check") => return cli::check(&args[1..], vec![]),
+        #[cfg(feature = "sync-server")]
+        Some("--sync") => return sync_server::run(),
+        _ => {}
+    }
+
+    let transport = match transport::Transport::from_args(&args) {
+        Ok(transport) => transport,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let (input, output) = match transport.open().await {
+        Ok(streams) => streams,
+        Err(err) => {
+            eprintln!("Could not open the connection: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let shut_down = Arc::new(AtomicBool::new(false));
+    let (service, socket) = server::service(shut_down.clone(), vec![]);
+    let client = service.inner().client().clone();
+    Server::new(input, output, socket)
+        .serve(panics::CatchPanics::new(service, client))
+        .await;
+    // The protocol asks for status 1 when the client exits (or goes away) without shutting down
+    if shut_down.load(Ordering::Relaxed) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
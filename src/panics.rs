@@ -0,0 +1,72 @@
+// Panic isolation
+//
+// Each message is handled on its own task, so a panic in one feature fails that request with an
+// internal error instead of taking down the server and its index. The panic is still printed to
+// stderr by the default hook, and the user is told which request failed.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tower_lsp::jsonrpc::{Error, ErrorCode, Request, Response};
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::{Client, ExitedError, LanguageServer, LspService};
+use tower_service::Service;
+
+pub struct CatchPanics<S: LanguageServer> {
+    inner: LspService<S>,
+    client: Client,
+}
+
+impl<S: LanguageServer> CatchPanics<S> {
+    pub fn new(inner: LspService<S>, client: Client) -> Self {
+        CatchPanics { inner, client }
+    }
+}
+
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
+impl<S: LanguageServer> Service<Request> for CatchPanics<S> {
+    type Response = Option<Response>;
+    type Error = ExitedError;
+    type Future = Pin<Box<dyn Future<Output = Result<Option<Response>, ExitedError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ExitedError>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let method = request.method().to_string();
+        let id = request.id().cloned();
+        let client = self.client.clone();
+        let handler = tokio::spawn(self.inner.call(request));
+        Box::pin(async move {
+            let err = match handler.await {
+                Ok(result) => return result,
+                Err(err) => err,
+            };
+            let cause = match err.try_into_panic() {
+                Ok(payload) => panic_message(payload.as_ref()).to_string(),
+                Err(err) => err.to_string(),
+            };
+            let message = format!("xdr-ls: handling `{method}` failed: {cause}");
+            client.log_message(MessageType::ERROR, &message).await;
+            client.show_message(MessageType::ERROR, &message).await;
+            Ok(id.map(|id| {
+                Response::from_error(
+                    id,
+                    Error {
+                        code: ErrorCode::InternalError,
+                        message: message.into(),
+                        data: None,
+                    },
+                )
+            }))
+        })
+    }
+}
@@ -0,0 +1,159 @@
+// Conversions between byte offsets and LSP positions
+//
+// Positions are (line, column) pairs, with columns counted in the encoding negotiated with the
+// client: UTF-16 code units, the protocol's default, unless the client offers UTF-8 (bytes). The
+// CLI counts bytes. None of these functions panic, whatever the text and however far out of range
+// the offset or position: offsets are clamped to the text and moved back to the nearest character
+// boundary.
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{ClientCapabilities, Position, PositionEncodingKind};
+
+// What columns count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    // Bytes
+    #[default]
+    Utf8,
+    // UTF-16 code units
+    Utf16,
+}
+
+impl Encoding {
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Encoding::Utf8 => PositionEncodingKind::UTF8,
+            Encoding::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+}
+
+// Picks the position encoding for a client, preferring UTF-8 if offered since it needs no
+// conversion
+pub fn negotiate(capabilities: &ClientCapabilities) -> Encoding {
+    let utf8 = capabilities
+        .general
+        .as_ref()
+        .and_then(|general| general.position_encodings.as_ref())
+        .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF8));
+    if utf8 {
+        Encoding::Utf8
+    } else {
+        Encoding::Utf16
+    }
+}
+
+// How many columns `text`, which doesn't span lines, takes up
+pub fn column(text: &str, encoding: Encoding) -> u32 {
+    match encoding {
+        Encoding::Utf8 => text.len() as u32,
+        Encoding::Utf16 => text.encode_utf16().count() as u32,
+    }
+}
+
+// The byte offset of `character` columns into `line`, at most its length
+fn column_offset(line: &str, character: u32, encoding: Encoding) -> usize {
+    if encoding == Encoding::Utf8 {
+        return (character as usize).min(line.len());
+    }
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character as usize {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+// Byte offset at which each line starts
+pub fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+// Where a text's lines start, for converting many offsets and positions in it without scanning
+// it from the start for each. Built for each parsed file (see `SourceFile::lines`); the functions
+// below are for one-off conversions. Gives the same results as they do, for the text it was built
+// from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lines {
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    pub fn new(text: &str) -> Self {
+        Lines {
+            starts: line_starts(text),
+        }
+    }
+
+    // The line `offset` is on, counting from 0
+    pub fn line_at(&self, text: &str, offset: usize) -> u32 {
+        let offset = floor_boundary(text, offset);
+        (self.starts.partition_point(|&start| start <= offset) - 1) as u32
+    }
+
+    pub fn offset_at(&self, text: &str, pos: Position, encoding: Encoding) -> usize {
+        let line_start = self
+            .starts
+            .get(pos.line as usize)
+            .map_or(text.len(), |&start| start.min(text.len()));
+        let line_end = self
+            .starts
+            .get(pos.line as usize + 1)
+            .map_or(text.len(), |&next| (next - 1).min(text.len()));
+        floor_boundary(
+            text,
+            line_start + column_offset(&text[line_start..line_end], pos.character, encoding),
+        )
+    }
+
+    pub fn position_at(&self, text: &str, offset: usize, encoding: Encoding) -> Position {
+        let offset = floor_boundary(text, offset);
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        Position {
+            line: line as u32,
+            character: column(&text[self.starts[line]..offset], encoding),
+        }
+    }
+}
+
+// The nearest character boundary at or before `offset`, within the text
+fn floor_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+pub fn offset_at(text: &str, pos: Position, encoding: Encoding) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(pos.line as usize)
+        .map(str::len)
+        .sum();
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |i| line_start + i);
+    floor_boundary(
+        text,
+        line_start + column_offset(&text[line_start..line_end], pos.character, encoding),
+    )
+}
+
+// The line `offset` is on, counting from 0
+pub fn line_at(text: &str, offset: usize) -> u32 {
+    text[..floor_boundary(text, offset)].matches('\n').count() as u32
+}
+
+pub fn position_at(text: &str, offset: usize, encoding: Encoding) -> Position {
+    let before = &text[..floor_boundary(text, offset)];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: line as u32,
+        character: column(&before[line_start..], encoding),
+    }
+}
@@ -0,0 +1,118 @@
+// Reachability from schema roots
+//
+// A schema usually has a few top-level messages, its roots, with everything else existing to be
+// part of one. The roots are the types named by the `rootTypes` setting or annotated `@root`,
+// along with any RPC programs. Types that no root leads to through the type graph are unreachable:
+// unlike unreferenced types, this catches groups of dead types that only refer to each other.
+//
+// Unreferenced types and constants are found too, for workspaces without declared roots.
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Definition;
+use crate::codegen::{Schema, SourceFile, type_deps};
+use crate::index::visit_identifiers_defn;
+use crate::stdlib;
+
+const ROOT_ANNOTATION: &str = "root";
+
+// The definitions of `files`, each with whether it's a root by its annotations
+fn definitions(files: &[SourceFile]) -> Vec<(&SourceFile, &Definition, bool)> {
+    files
+        .iter()
+        .flat_map(|file| {
+            let annotations = file.annotations();
+            file.spec
+                .defns
+                .iter()
+                .zip(annotations)
+                .map(move |(defn, annotations)| {
+                    let root = annotations.iter().any(|(name, _)| *name == ROOT_ANNOTATION);
+                    (file, defn, root)
+                })
+        })
+        .collect()
+}
+
+// The types of `files` unreachable from the roots, or None if no roots are declared
+pub fn unreachable<'a>(
+    files: &'a [SourceFile],
+    root_types: &[String],
+) -> Option<Vec<(&'a SourceFile, &'a Definition)>> {
+    let defns = definitions(files);
+    let mut by_name: HashMap<&str, Vec<&Definition>> = HashMap::new();
+    let mut stack: Vec<String> = root_types.to_vec();
+    let mut declared = !stack.is_empty();
+    for &(_, defn, root) in &defns {
+        let Some(id) = defn.id() else {
+            continue;
+        };
+        by_name.entry(&id.id).or_default().push(defn);
+        if root {
+            declared = true;
+            stack.push(id.id.clone());
+        } else if matches!(defn, Definition::Program { .. }) {
+            stack.push(id.id.clone());
+        }
+    }
+    if !declared {
+        return None;
+    }
+    let mut reached: HashSet<String> = HashSet::new();
+    while let Some(name) = stack.pop() {
+        if !reached.insert(name.clone()) {
+            continue;
+        }
+        for defn in by_name.get(name.as_str()).into_iter().flatten() {
+            stack.extend(type_deps(defn));
+        }
+    }
+    Some(
+        defns
+            .into_iter()
+            .filter(|(file, defn, _)| {
+                !matches!(
+                    defn,
+                    Definition::Constant { .. } | Definition::Program { .. }
+                ) && !stdlib::is_library(&file.path)
+                    && defn.id().is_some_and(|id| !reached.contains(&id.id))
+            })
+            .map(|(file, defn, _)| (file, defn))
+            .collect(),
+    )
+}
+
+// The types and constants of `files` nothing else refers to, roots aside. A definition referring
+// to itself doesn't count, and naming an enum member counts as using the enum.
+pub fn unreferenced<'a>(
+    files: &'a [SourceFile],
+    root_types: &[String],
+) -> Vec<(&'a SourceFile, &'a Definition)> {
+    let schema = Schema::new(files);
+    let mut used: HashSet<String> = HashSet::new();
+    for defn in files.iter().flat_map(|file| &file.spec.defns) {
+        let own = defn.id().map(|id| id.id.as_str());
+        visit_identifiers_defn(defn, &mut |id, is_defn| {
+            let name = id.id.as_str();
+            for name in [Some(name), schema.enum_of.get(name).copied()]
+                .into_iter()
+                .flatten()
+            {
+                if !is_defn && Some(name) != own {
+                    used.insert(name.to_string());
+                }
+            }
+        });
+    }
+    definitions(files)
+        .into_iter()
+        .filter(|(file, defn, root)| {
+            !stdlib::is_library(&file.path)
+                && !matches!(defn, Definition::Program { .. })
+                && !root
+                && defn
+                    .id()
+                    .is_some_and(|id| !used.contains(&id.id) && !root_types.contains(&id.id))
+        })
+        .map(|(file, defn, _)| (file, defn))
+        .collect()
+}
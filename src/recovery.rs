@@ -0,0 +1,140 @@
+// Error recovery for the parser
+//
+// lalrpop parses a file all or nothing, so one typo would drop every definition in it from the
+// index. When a file doesn't parse, the top-level definition holding the error is blanked out and
+// the rest parsed again, until what's left parses. Blanking keeps every byte offset, so positions
+// in the result are positions in the file.
+use lalrpop_util::ParseError;
+use lalrpop_util::lexer::Token;
+
+use crate::ast::Specification;
+use crate::rename::non_code_spans;
+use crate::xdr::SpecificationParser;
+
+// Gives up after blanking this many definitions
+const MAX_ATTEMPTS: usize = 64;
+
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "const",
+    "enum",
+    "namespace",
+    "program",
+    "struct",
+    "typedef",
+    "union",
+];
+
+// Where top-level definitions can start and end: after each `;` outside braces and around the
+// braces of `namespace` blocks, where a definition is known to be closed, and also before each line
+// starting with a definition keyword in its first column, which bounds the damage of a missing
+// `}`. Returns the closed bounds and the starts of those lines.
+fn boundaries(text: &str) -> (Vec<usize>, Vec<usize>) {
+    let mut skipped = non_code_spans(text).into_iter().peekable();
+    let mut closed = vec![0];
+    let mut lines = vec![];
+    // Whether each open brace opened a `namespace` block
+    let mut braces: Vec<bool> = vec![];
+    let mut after_namespace = false;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if let Some((_, end)) = skipped.next_if(|&(start, _)| start == i) {
+            i = end;
+            continue;
+        }
+        let line_start = i == 0 || text[..i].ends_with('\n');
+        let word_len = text[i..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len() - i);
+        let word = &text[i..i + word_len];
+        if line_start && DEFINITION_KEYWORDS.contains(&word) {
+            lines.push(i);
+        }
+        match c {
+            '{' => {
+                if after_namespace {
+                    closed.push(i + 1);
+                }
+                braces.push(std::mem::take(&mut after_namespace));
+            }
+            '}' => closed.extend((braces.pop() == Some(true)).then_some(i + 1)),
+            ';' if !braces.contains(&false) => closed.push(i + 1),
+            _ => {}
+        }
+        if word == "namespace" {
+            after_namespace = true;
+        }
+        i += word_len.max(c.len_utf8());
+    }
+    closed.push(text.len());
+    (closed, lines)
+}
+
+fn location(err: &ParseError<usize, Token<'_>, &str>) -> usize {
+    match err {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => {
+            *location
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, ..), ..
+        }
+        | ParseError::ExtraToken { token: (start, ..) } => *start,
+        ParseError::User { .. } => 0,
+    }
+}
+
+// Replaces everything but line breaks in `start..end` with spaces
+fn blank(text: &mut String, start: usize, end: usize) {
+    let blanked: String = text[start..end]
+        .chars()
+        .map(|c| {
+            if c == '\n' {
+                "\n".to_string()
+            } else {
+                " ".repeat(c.len_utf8())
+            }
+        })
+        .collect();
+    text.replace_range(start..end, &blanked);
+}
+
+// The definitions of `masked` (preprocessed text) that parse, with those holding errors left out,
+// or None if nothing can be salvaged
+pub fn parse(masked: &str) -> Option<Specification> {
+    parse_with_gaps(masked).map(|(spec, _)| spec)
+}
+
+// `parse`, along with the spans blanked to get there
+pub fn parse_with_gaps(masked: &str) -> Option<(Specification, Vec<(usize, usize)>)> {
+    let parser = SpecificationParser::new();
+    let mut text = masked.to_string();
+    let (closed, lines) = boundaries(masked);
+    let mut all: Vec<usize> = closed.iter().chain(&lines).copied().collect();
+    all.sort();
+    let mut gaps = vec![];
+    for _ in 0..MAX_ATTEMPTS {
+        let location = match parser.parse(&text) {
+            Ok(spec) => return Some((spec, gaps)),
+            Err(err) => location(&err),
+        };
+        // From the end of the last definition known to be closed to the next place a definition
+        // could start. An error on a line starting a definition, after another was started, is
+        // that one not being closed (a missing `;` or `}`), so only that goes.
+        let start = closed
+            .iter()
+            .copied()
+            .rfind(|&bound| bound <= location)
+            .unwrap_or_default();
+        let end = lines
+            .iter()
+            .copied()
+            .rfind(|&line| start < line && line <= location && !text[start..line].trim().is_empty())
+            .or_else(|| all.iter().copied().find(|&bound| bound > location))
+            .unwrap_or(text.len());
+        if text[start..end].trim().is_empty() {
+            return None;
+        }
+        blank(&mut text, start, end);
+        gaps.push((start, end));
+    }
+    None
+}
@@ -0,0 +1,86 @@
+// Text occurrences of names outside the code: in comments and `%` passthrough lines, which the
+// parser skips
+use tower_lsp::lsp_types::Range;
+
+use crate::position::{Encoding, Lines};
+
+// Byte ranges of the comments and `%` lines in a file, skipped the same way as by the lexer
+pub fn non_code_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let len = if rest.starts_with("//") || rest.starts_with('%') {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            body.find("*/").map_or(rest.len(), |end| end + 4)
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        spans.push((i, i + len));
+        i += len;
+    }
+    spans
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The grammar's keywords and built-in type names, which can't name a definition
+const KEYWORDS: &[&str] = &[
+    "bool",
+    "case",
+    "const",
+    "default",
+    "double",
+    "enum",
+    "float",
+    "hyper",
+    "int",
+    "namespace",
+    "opaque",
+    "program",
+    "quadruple",
+    "string",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "version",
+    "void",
+];
+
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+// Whether `name` can be renamed to
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(is_ident_char)
+        && !is_keyword(name)
+}
+
+// Whole-word occurrences of `name` in comments and passthrough lines
+pub fn comment_occurrences(text: &str, name: &str, encoding: Encoding) -> Vec<Range> {
+    let lines = Lines::new(text);
+    let mut result = vec![];
+    for (start, end) in non_code_spans(text) {
+        let span = &text[start..end];
+        for (i, _) in span.match_indices(name) {
+            let before = span[..i].chars().next_back();
+            let after = span[i + name.len()..].chars().next();
+            if before.is_some_and(is_ident_char) || after.is_some_and(is_ident_char) {
+                continue;
+            }
+            result.push(Range {
+                start: lines.position_at(text, start + i, encoding),
+                end: lines.position_at(text, start + i + name.len(), encoding),
+            });
+        }
+    }
+    result
+}
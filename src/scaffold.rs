@@ -0,0 +1,284 @@
+// Union scaffolding
+//
+// Completion inside the empty body of a union switching on an enum offers one item writing a
+// `case` arm with a `void` declaration for every member, in the enum's order. The file doesn't
+// parse until the body has an arm, so the union is found in the text before the cursor. After
+// `case` in such a union, completion offers just the members the other arms don't handle yet,
+// found in the text the same way.
+use std::collections::HashSet;
+use std::path::Path;
+
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::codegen::{Schema, SourceFile};
+use crate::format::Style;
+use crate::hover::value_display;
+use crate::rename::non_code_spans;
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The discriminant type of the union whose body is open and empty at `offset`, with whether the
+// cursor is still on the line of the `{`
+fn open_union(text: &str, offset: usize) -> Option<(&str, bool)> {
+    // Allow a partly typed word, such as `ca`
+    let before = text[..offset].trim_end_matches(is_ident_char);
+    let body = before.trim_end();
+    let brace = body.strip_suffix('{')?;
+    let same_line = !before[body.len()..].contains('\n');
+    // Nothing but the closing brace may follow
+    let after = text[offset..]
+        .trim_start_matches(is_ident_char)
+        .trim_start();
+    if !(after.is_empty() || after.starts_with('}')) {
+        return None;
+    }
+    Some((switch_type(brace)?, same_line))
+}
+
+// The discriminant type of a union from the text before its `{`, which ends with
+// `switch (type name)`, if it's a single name (as an enum's is)
+fn switch_type(header: &str) -> Option<&str> {
+    let header = header.trim_end().strip_suffix(')')?;
+    let open = header.rfind('(')?;
+    if !header[..open].trim_end().ends_with("switch") {
+        return None;
+    }
+    let mut words = header[open + 1..].split_whitespace();
+    let (ty, _name, None) = (words.next()?, words.next()?, words.next()) else {
+        return None;
+    };
+    ty.chars().all(is_ident_char).then_some(ty)
+}
+
+// The byte offset of the `{` opening the body `offset` is in, and of the `}` closing it (or the
+// end of the text), in `code` (the text with comments blanked out)
+pub fn enclosing_body(code: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let open = (0..offset).rev().find(|&i| match code[i] {
+        b'{' if depth == 0 => true,
+        b'{' => {
+            depth -= 1;
+            false
+        }
+        b'}' => {
+            depth += 1;
+            false
+        }
+        _ => false,
+    })?;
+    let mut depth = 0;
+    let close = (open + 1..code.len())
+        .find(|&i| match code[i] {
+            b'}' if depth == 0 => true,
+            b'}' => {
+                depth -= 1;
+                false
+            }
+            b'{' => {
+                depth += 1;
+                false
+            }
+            _ => false,
+        })
+        .unwrap_or(code.len());
+    Some((open, close))
+}
+
+// The names following `case` directly in `code[start..end]` (not in nested bodies), except the
+// one at `skip`, the label being completed
+fn case_labels(code: &[u8], start: usize, end: usize, skip: usize) -> HashSet<String> {
+    let mut labels = HashSet::new();
+    let mut depth = 0;
+    let mut after_case = false;
+    let mut i = start;
+    while i < end {
+        let c = code[i] as char;
+        if is_ident_char(c) {
+            let word_start = i;
+            while i < end && is_ident_char(code[i] as char) {
+                i += 1;
+            }
+            let word = String::from_utf8_lossy(&code[word_start..i]);
+            if depth == 0 {
+                if after_case && word_start != skip {
+                    labels.insert(word.to_string());
+                }
+                after_case = word == "case";
+            }
+            continue;
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if c.is_whitespace() => {}
+            _ => after_case = false,
+        }
+        i += 1;
+    }
+    labels
+}
+
+// Completion of a `case` label being typed at `offset`, when the union it's in switches on an
+// enum: the enum's members (in its order) that no other arm handles. None elsewhere, leaving the
+// usual completion.
+pub fn case_members(schema: &Schema, text: &str, offset: usize) -> Option<Vec<CompletionItem>> {
+    // Allow a partly typed word, such as `RE`
+    let word = text[..offset].trim_end_matches(is_ident_char);
+    let keyword = word.trim_end();
+    if keyword.len() == word.len() {
+        return None;
+    }
+    let before = keyword.strip_suffix("case")?;
+    if before.ends_with(is_ident_char) {
+        return None;
+    }
+    let mut code = text.as_bytes().to_vec();
+    for (start, end) in non_code_spans(text) {
+        code[start..end].fill(b' ');
+    }
+    let (open, close) = enclosing_body(&code, before.len())?;
+    let ty = switch_type(&text[..open])?;
+    let used = case_labels(&code, open + 1, close, word.len());
+    Some(
+        members(schema, ty)?
+            .into_iter()
+            .filter(|member| !used.contains(*member))
+            .enumerate()
+            .map(|(i, member)| CompletionItem {
+                label: member.to_string(),
+                kind: Some(CompletionItemKind::ENUM_MEMBER),
+                detail: schema
+                    .consts
+                    .get(member)
+                    .map(|&value| format!("= {}", value_display(value))),
+                // The enum's order rather than the alphabet's
+                sort_text: Some(format!("{i:04}")),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+// The names of the members of the enum `ty` (through typedefs)
+fn members<'a>(schema: &'a Schema, ty: &str) -> Option<Vec<&'a str>> {
+    let spec = TypeSpecifier::Ident(Identifier {
+        id: ty.to_string(),
+        start: 0,
+        end: 0,
+    });
+    let name = match schema.resolve(&spec) {
+        TypeSpecifier::Ident(id) => id.id.clone(),
+        _ => return None,
+    };
+    match schema.types.get(name.as_str()) {
+        Some(Definition::Enum { body, .. }) => Some(
+            body.body
+                .iter()
+                .map(|assign| assign.id.id.as_str())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+// Parses `text` with the union open at `offset` closed
+fn close_union(path: &Path, text: &str, offset: usize) -> Option<SourceFile> {
+    let (before, after) = text.split_at(offset);
+    ["case 0: void;", "case 0: void; };"]
+        .iter()
+        .find_map(|arm| SourceFile::parse(path.to_path_buf(), format!("{before}{arm}{after}")).ok())
+}
+
+// A `case` arm with a `void` declaration for each of `members`, as lines starting with `indent`.
+// Each declaration goes on the line of its label, or if `decl_indent` is given on a line of its
+// own starting with it. The first declaration is a placeholder if `snippets`.
+pub fn arm_lines(
+    members: &[&str],
+    indent: &str,
+    decl_indent: Option<&str>,
+    snippets: bool,
+) -> Vec<String> {
+    let mut lines = vec![];
+    for (i, member) in members.iter().enumerate() {
+        let decl = if i == 0 && snippets {
+            "${1:void};"
+        } else {
+            "void;"
+        };
+        match decl_indent {
+            None => lines.push(format!("{indent}case {member}: {decl}")),
+            Some(decl_indent) => {
+                lines.push(format!("{indent}case {member}:"));
+                lines.push(format!("{decl_indent}{decl}"));
+            }
+        }
+    }
+    lines
+}
+
+// The completion scaffolding the arms of the union open at `offset` of the document at `path`,
+// if it switches on an enum and has none yet. The document replaces its copy in `files`, the
+// workspace's parsed files, if it parses with the union closed.
+pub fn union_arms(
+    files: &[SourceFile],
+    path: &Path,
+    text: &str,
+    offset: usize,
+    style: &Style,
+    snippets: bool,
+) -> Option<CompletionItem> {
+    let (ty, same_line) = open_union(text, offset)?;
+    let closed: Vec<SourceFile>;
+    let files = match close_union(path, text, offset) {
+        Some(file) => {
+            closed = files
+                .iter()
+                .filter(|other| other.path != path)
+                .cloned()
+                .chain([file])
+                .collect();
+            &closed
+        }
+        None => files,
+    };
+    let schema = Schema::new(files);
+    let members = members(&schema, ty)?;
+    // The editor indents the lines after the first as far as the cursor's line
+    let indent = if same_line {
+        "\t".repeat(style.case_indent)
+    } else {
+        String::new()
+    };
+    let decl_indent = (!style.case_same_line).then(|| format!("{indent}\t"));
+    let mut insert = arm_lines(&members, &indent, decl_indent.as_deref(), snippets).join("\n");
+    if same_line {
+        insert.insert(0, '\n');
+        // Move a closing brace right after the cursor onto its own line
+        if text[offset..]
+            .trim_start_matches(is_ident_char)
+            .starts_with('}')
+        {
+            insert.push('\n');
+        }
+    }
+    Some(CompletionItem {
+        label: format!("case … ({} arms)", members.len()),
+        kind: Some(if snippets {
+            CompletionItemKind::SNIPPET
+        } else {
+            CompletionItemKind::TEXT
+        }),
+        detail: Some(format!("An arm for every member of `{ty}`")),
+        filter_text: Some("case".to_string()),
+        insert_text: Some(insert),
+        insert_text_format: Some(if snippets {
+            InsertTextFormat::SNIPPET
+        } else {
+            InsertTextFormat::PLAIN_TEXT
+        }),
+        ..Default::default()
+    })
+}
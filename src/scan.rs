@@ -0,0 +1,166 @@
+// Finding the `.x` files in a workspace
+use std::path::{Path, PathBuf};
+
+// Options for the workspace scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanOptions {
+    // Skip files excluded by .gitignore (and .git/info/exclude and the global excludes file)
+    pub respect_gitignore: bool,
+    // How many directories deep to descend below the root
+    pub max_depth: usize,
+    // Stop scanning after finding this many files
+    pub max_files: usize,
+    // Skip files larger than this many bytes (0 for no limit)
+    pub max_file_size: u64,
+    // Extensions (without the dot) of the files holding schemas, `x` and any configured
+    pub extensions: Vec<String>,
+    // Globs in .gitignore syntax, relative to the folder scanned, for paths never to scan
+    pub exclude: Vec<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            respect_gitignore: true,
+            max_depth: 32,
+            max_files: 10_000,
+            max_file_size: 1 << 20,
+            extensions: vec!["x".to_string()],
+            // Build output and installed packages hold copies of schemas, often stale
+            exclude: vec!["target/".to_string(), "node_modules/".to_string()],
+        }
+    }
+}
+
+impl ScanOptions {
+    pub fn from_init_options(options: Option<&serde_json::Value>) -> Self {
+        let mut scan = ScanOptions::default();
+        let Some(options) = options else {
+            return scan;
+        };
+        if let Some(respect) = options
+            .get("respectGitignore")
+            .and_then(serde_json::Value::as_bool)
+        {
+            scan.respect_gitignore = respect;
+        }
+        if let Some(depth) = options
+            .get("maxScanDepth")
+            .and_then(serde_json::Value::as_u64)
+        {
+            scan.max_depth = depth as usize;
+        }
+        if let Some(count) = options.get("maxFiles").and_then(serde_json::Value::as_u64) {
+            scan.max_files = count as usize;
+        }
+        if let Some(size) = options
+            .get("maxFileSize")
+            .and_then(serde_json::Value::as_u64)
+        {
+            scan.max_file_size = size;
+        }
+        if let Some(extensions) = options
+            .get("extraExtensions")
+            .and_then(serde_json::Value::as_array)
+        {
+            scan.extensions.extend(
+                extensions
+                    .iter()
+                    .filter_map(serde_json::Value::as_str)
+                    .map(|ext| ext.trim_start_matches('.').to_string())
+                    .filter(|ext| !ext.is_empty() && ext != "x"),
+            );
+        }
+        if let Some(globs) = options.get("exclude").and_then(serde_json::Value::as_array) {
+            scan.exclude = globs
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .map(str::to_string)
+                .collect();
+        }
+        scan
+    }
+
+    // Whether `path` has the extension of a schema file
+    pub fn is_schema(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions.iter().any(|known| ext == known.as_str()))
+    }
+
+    // A glob matching schema files anywhere, for file watchers and document selectors
+    pub fn glob(&self) -> String {
+        match self.extensions.as_slice() {
+            [ext] => format!("**/*.{ext}"),
+            exts => format!("**/*.{{{}}}", exts.join(",")),
+        }
+    }
+}
+
+// Calls `cb` on each schema file under `dir`, returning warnings about anything skipped (invalid
+// exclude globs, symlink loops, unreadable directories, oversized files, hitting the file limit)
+pub fn get_xdr_files(
+    dir: &PathBuf,
+    opts: &ScanOptions,
+    cb: &mut dyn FnMut(&PathBuf),
+) -> Vec<String> {
+    let mut warnings = vec![];
+    // Negated overrides are ignore rules, taking precedence over .gitignore
+    let mut overrides = ignore::overrides::OverrideBuilder::new(dir);
+    for glob in &opts.exclude {
+        if let Err(err) = overrides.add(&format!("!{glob}")) {
+            warnings.push(format!("Invalid exclude glob `{glob}`: {err}"));
+        }
+    }
+    let overrides = overrides.build().unwrap_or_else(|err| {
+        warnings.push(err.to_string());
+        ignore::overrides::Override::empty()
+    });
+    // Symlinks are followed; the walker detects loops and reports them as errors
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .follow_links(true)
+        .max_depth(Some(opts.max_depth))
+        .git_ignore(opts.respect_gitignore)
+        .git_exclude(opts.respect_gitignore)
+        .git_global(opts.respect_gitignore)
+        .parents(opts.respect_gitignore)
+        .overrides(overrides)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    let mut count = 0;
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warnings.push(err.to_string());
+                continue;
+            }
+        };
+        let path = entry.into_path();
+        if path.is_file() && opts.is_schema(&path) {
+            if count == opts.max_files {
+                warnings.push(format!(
+                    "Stopped scanning {} after {} files; raise maxFiles to index more",
+                    dir.display(),
+                    count
+                ));
+                break;
+            }
+            // Most likely generated or a data dump rather than a schema
+            if let Ok(meta) = path.metadata()
+                && opts.max_file_size != 0
+                && meta.len() > opts.max_file_size
+            {
+                warnings.push(format!(
+                    "Skipped {} ({} bytes); raise maxFileSize to index it",
+                    path.display(),
+                    meta.len()
+                ));
+                continue;
+            }
+            count += 1;
+            cb(&path);
+        }
+    }
+    warnings
+}
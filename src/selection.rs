@@ -0,0 +1,113 @@
+// Selection ranges ("expand selection")
+//
+// From a position the selection grows through the syntax around it, innermost first: the name,
+// the member declaration, union case, or enum member it's part of, the body of the struct, union,
+// or enum holding that (through each level of nesting), and the whole definition. In programs,
+// the procedure and the version come in between.
+use tower_lsp::lsp_types::{Range, SelectionRange};
+
+use crate::ast::*;
+use crate::codegen::SourceFile;
+use crate::index::visit_identifiers_defn;
+use crate::position::Encoding;
+
+// The selection ranges around byte offset `offset`, or just the empty range there if it's outside
+// every definition
+pub fn selection_range(file: &SourceFile, offset: usize, encoding: Encoding) -> SelectionRange {
+    let mut spans = vec![];
+    for defn in &file.spec.defns {
+        let (Some(start), Some(end)) = (file.definition_start(defn), file.definition_end(defn))
+        else {
+            continue;
+        };
+        if start <= offset && offset <= end {
+            spans.push((start, end));
+            definition_spans(defn, &mut spans);
+        }
+    }
+    spans.retain(|&(start, end)| start <= offset && offset <= end);
+    // Outermost first, so each range is the parent of the next
+    spans.sort_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+    spans.dedup();
+    let here = file.position_at(offset, encoding);
+    let mut selection: Option<SelectionRange> = None;
+    for (start, end) in spans {
+        selection = Some(SelectionRange {
+            range: Range::new(
+                file.position_at(start, encoding),
+                file.position_at(end, encoding),
+            ),
+            parent: selection.map(Box::new),
+        });
+    }
+    selection.unwrap_or(SelectionRange {
+        range: Range::new(here, here),
+        parent: None,
+    })
+}
+
+fn definition_spans(defn: &Definition, spans: &mut Vec<(usize, usize)>) {
+    visit_identifiers_defn(defn, &mut |id, _| spans.push((id.start, id.end)));
+    match defn {
+        Definition::Constant { .. } => {}
+        Definition::TypeDef(decl) => declaration_spans(decl, spans),
+        Definition::Enum { body, .. } => enum_spans(body, spans),
+        Definition::Struct { body, .. } => struct_spans(body, spans),
+        Definition::Union { body, .. } => union_spans(body, spans),
+        Definition::Program { versions, .. } => {
+            for version in versions {
+                spans.push((version.start, version.end));
+                for proc in &version.procedures {
+                    spans.push((proc.start, proc.end));
+                    for spec in proc.args.iter().chain(&proc.result) {
+                        type_spans(spec, spans);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn declaration_spans(decl: &Declaration, spans: &mut Vec<(usize, usize)>) {
+    if let Declaration::Normal { spec, .. }
+    | Declaration::FixedArr { spec, .. }
+    | Declaration::VarArr { spec, .. }
+    | Declaration::Optional { spec, .. } = decl
+    {
+        type_spans(spec, spans);
+    }
+}
+
+fn type_spans(spec: &TypeSpecifier, spans: &mut Vec<(usize, usize)>) {
+    match spec {
+        TypeSpecifier::Enum(body) => enum_spans(body, spans),
+        TypeSpecifier::Struct(body) => struct_spans(body, spans),
+        TypeSpecifier::Union(body) => union_spans(body, spans),
+        TypeSpecifier::BuiltIn(_) | TypeSpecifier::Ident(_) => {}
+    }
+}
+
+fn enum_spans(body: &EnumBody, spans: &mut Vec<(usize, usize)>) {
+    spans.push((body.start, body.end));
+    spans.extend(body.body.iter().map(|assign| (assign.start, assign.end)));
+}
+
+fn struct_spans(body: &StructBody, spans: &mut Vec<(usize, usize)>) {
+    spans.push((body.start, body.end));
+    spans.extend(&body.spans);
+    for decl in &body.body {
+        declaration_spans(decl, spans);
+    }
+}
+
+fn union_spans(body: &UnionBody, spans: &mut Vec<(usize, usize)>) {
+    spans.push((body.start, body.end));
+    declaration_spans(&body.discriminant, spans);
+    for case in &body.cases {
+        spans.push((case.start, case.end));
+        declaration_spans(&case.decl, spans);
+    }
+    if let Some(decl) = &body.default {
+        declaration_spans(decl, spans);
+    }
+}
@@ -0,0 +1,252 @@
+// Semantic tokens
+//
+// Names a file uses are highlighted by the kind of definition they resolve to, and names it
+// defines the same way with the `declaration` modifier (struct fields and union arms as
+// properties). Names that don't resolve to any definition in the workspace get the `unresolved`
+// modifier, so editors can dim or underline them independently of diagnostics. Keywords, built-in
+// types, numbers, and comments are highlighted too.
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::*;
+
+use crate::codegen::SourceFile;
+use crate::includes::{self, RefKind};
+use crate::position::{Encoding, column};
+use crate::rename::non_code_spans;
+use crate::symbols;
+
+const TOKEN_TYPES: [SemanticTokenType; 13] = [
+    SemanticTokenType::TYPE,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::ENUM,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::MACRO,
+];
+
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 4] = [
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::new("unresolved"),
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+
+const READONLY: u32 = 1 << 0;
+const UNRESOLVED: u32 = 1 << 1;
+const DECLARATION: u32 = 1 << 2;
+const DEFAULT_LIBRARY: u32 = 1 << 3;
+
+const KEYWORDS: &[&str] = &[
+    "case",
+    "const",
+    "default",
+    "enum",
+    "namespace",
+    "opaque",
+    "program",
+    "string",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "version",
+    "void",
+];
+
+// Highlighted as types from the language itself
+const BUILT_IN_TYPES: &[&str] = &[
+    "int",
+    "unsigned",
+    "hyper",
+    "float",
+    "double",
+    "quadruple",
+    "bool",
+];
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+fn token_type(kind: &SemanticTokenType) -> u32 {
+    TOKEN_TYPES.iter().position(|t| t == kind).unwrap_or(0) as u32
+}
+
+// The token type and modifiers for a name of the given kind
+fn classify(kind: Option<SymbolKind>, used_as: RefKind) -> (SemanticTokenType, u32) {
+    match kind {
+        Some(SymbolKind::STRUCT) => (SemanticTokenType::STRUCT, 0),
+        Some(SymbolKind::CLASS) => (SemanticTokenType::CLASS, 0),
+        Some(SymbolKind::ENUM) => (SemanticTokenType::ENUM, 0),
+        Some(SymbolKind::ENUM_MEMBER) => (SemanticTokenType::ENUM_MEMBER, 0),
+        Some(SymbolKind::CONSTANT) => (SemanticTokenType::VARIABLE, READONLY),
+        // RPC programs and versions, and procedures
+        Some(SymbolKind::MODULE | SymbolKind::NAMESPACE) => (SemanticTokenType::NAMESPACE, 0),
+        Some(SymbolKind::FUNCTION) => (SemanticTokenType::FUNCTION, 0),
+        Some(_) => (SemanticTokenType::TYPE, 0),
+        None => match used_as {
+            RefKind::Type => (SemanticTokenType::TYPE, UNRESOLVED),
+            RefKind::Value => (SemanticTokenType::VARIABLE, UNRESOLVED),
+        },
+    }
+}
+
+// A token by byte range, before encoding
+struct Span {
+    start: usize,
+    end: usize,
+    token: SemanticTokenType,
+    modifiers: u32,
+}
+
+// Keywords, built-in types, numbers, comments, and `%` passthrough lines, found by scanning the
+// text; names are left to the AST
+fn lexical(text: &str, out: &mut Vec<Span>) {
+    let mut code_start = 0;
+    let mut spans = non_code_spans(text);
+    spans.push((text.len(), text.len()));
+    for (start, end) in spans {
+        let code = &text[code_start..start];
+        let mut i = 0;
+        while let Some(c) = code[i..].chars().next() {
+            let word_len = code[i..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(code.len() - i);
+            if word_len == 0 {
+                i += c.len_utf8();
+                continue;
+            }
+            let word = &code[i..i + word_len];
+            let kind = if c.is_ascii_digit() {
+                Some((SemanticTokenType::NUMBER, 0))
+            } else if KEYWORDS.contains(&word) {
+                Some((SemanticTokenType::KEYWORD, 0))
+            } else if BUILT_IN_TYPES.contains(&word) {
+                Some((SemanticTokenType::TYPE, DEFAULT_LIBRARY))
+            } else {
+                None
+            };
+            if let Some((token, modifiers)) = kind {
+                // A negative number's sign belongs to it
+                let negative = c.is_ascii_digit() && code[..i].ends_with('-');
+                out.push(Span {
+                    start: code_start + i - usize::from(negative),
+                    end: code_start + i + word_len,
+                    token,
+                    modifiers,
+                });
+            }
+            i += word_len;
+        }
+        // Comments can span lines, but tokens can't
+        let token = if text[start..end].starts_with('%') {
+            SemanticTokenType::MACRO
+        } else {
+            SemanticTokenType::COMMENT
+        };
+        let mut line_start = start;
+        for line in text[start..end].split_inclusive('\n') {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            if len > 0 {
+                out.push(Span {
+                    start: line_start,
+                    end: line_start + len,
+                    token: token.clone(),
+                    modifiers: 0,
+                });
+            }
+            line_start += line.len();
+        }
+        code_start = end;
+    }
+}
+
+// The names `file` defines, from its outline
+fn definitions(
+    file: &SourceFile,
+    outline: &[DocumentSymbol],
+    encoding: Encoding,
+    out: &mut Vec<Span>,
+) {
+    for symbol in outline {
+        let (token, modifiers) = match symbol.kind {
+            SymbolKind::FIELD => (SemanticTokenType::PROPERTY, 0),
+            kind => classify(Some(kind), RefKind::Type),
+        };
+        out.push(Span {
+            start: file.offset_at(symbol.selection_range.start, encoding),
+            end: file.offset_at(symbol.selection_range.end, encoding),
+            token,
+            modifiers: modifiers | DECLARATION,
+        });
+        definitions(
+            file,
+            symbol.children.as_deref().unwrap_or_default(),
+            encoding,
+            out,
+        );
+    }
+}
+
+// Tokens for `file`, or only those on the lines of `range`. `kinds` maps each name defined in the
+// workspace to its kind.
+pub fn tokens(
+    file: &SourceFile,
+    kinds: &HashMap<String, SymbolKind>,
+    range: Option<Range>,
+    encoding: Encoding,
+) -> Vec<SemanticToken> {
+    let mut spans = vec![];
+    let mut refs = vec![];
+    includes::visit_references(&file.spec, &mut refs);
+    for (id, used_as) in refs {
+        let kind = kinds.get(id.id.as_str()).copied().or_else(|| {
+            // Case labels of a bool union
+            matches!(id.id.as_str(), "TRUE" | "FALSE").then_some(SymbolKind::ENUM_MEMBER)
+        });
+        let (token, modifiers) = classify(kind, used_as);
+        spans.push(Span {
+            start: id.start,
+            end: id.end,
+            token,
+            modifiers,
+        });
+    }
+    let outline = symbols::document_symbols(file, encoding);
+    definitions(file, &outline, encoding, &mut spans);
+    lexical(&file.text, &mut spans);
+    spans.sort_by_key(|span| span.start);
+    spans.dedup_by_key(|span| span.start);
+
+    let mut result = vec![];
+    let mut last = Position::new(0, 0);
+    for span in spans {
+        let start = file.position_at(span.start, encoding);
+        if range.is_some_and(|range| start.line < range.start.line || start.line > range.end.line) {
+            continue;
+        }
+        let delta_start = if start.line == last.line {
+            start.character - last.character
+        } else {
+            start.character
+        };
+        result.push(SemanticToken {
+            delta_line: start.line - last.line,
+            delta_start,
+            length: column(&file.text[span.start..span.end], encoding),
+            token_type: token_type(&span.token),
+            token_modifiers_bitset: span.modifiers,
+        });
+        last = start;
+    }
+    result
+}
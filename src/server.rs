@@ -0,0 +1,3419 @@
+// The language server
+//
+// `Backend` answers the protocol's requests and notifications over the index of the workspace
+// and the analyses in the rest of the crate. `service` wraps it, with the custom `xdr/` requests,
+// for tower-lsp to serve; the `xdr-ls` binary serves it over stdio or a socket, and `harness`
+// drives it in memory.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use regex::Regex;
+use tokio::sync::{Mutex, Notify, RwLock};
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::request::{GotoTypeDefinitionParams, GotoTypeDefinitionResponse};
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
+use yoke::{Yoke, Yokeable};
+
+use crate::ast::*;
+use crate::cache::IndexCache;
+use crate::index::{DocumentIndex, index_in_parallel, token_at, unscoped};
+use crate::position::Encoding;
+use crate::scan::{ScanOptions, get_xdr_files};
+use crate::{
+    actions, breaking, codec, codegen, dialect, expand, fields, folding, format, hierarchy, hints,
+    hover, includes, lint, position, reachability, rename, scaffold, selection, semantic, since,
+    stdlib, symbols,
+};
+
+// A document open in the editor
+#[derive(Debug)]
+struct Document {
+    version: i32,
+    text: String,
+}
+
+// The syntax trees of the indexed documents that parse, with their URIs, in the order a `Schema`
+// wants them (the standard library first)
+struct Parsed {
+    docs: Vec<Url>,
+    // The trees, and the schema over them, built with them
+    schema: Yoke<WorkspaceSchema<'static>, Arc<Vec<codegen::SourceFile>>>,
+    // The kind of each name defined, for semantic tokens
+    kinds: HashMap<String, SymbolKind>,
+}
+
+#[derive(Yokeable)]
+struct WorkspaceSchema<'a>(codegen::Schema<'a>);
+
+impl Parsed {
+    fn new(
+        docs: Vec<Url>,
+        files: Vec<codegen::SourceFile>,
+        kinds: HashMap<String, SymbolKind>,
+    ) -> Self {
+        Parsed {
+            docs,
+            schema: Yoke::attach_to_cart(Arc::new(files), |files| {
+                WorkspaceSchema(codegen::Schema::new(files))
+            }),
+            kinds,
+        }
+    }
+
+    fn files(&self) -> &[codegen::SourceFile] {
+        self.schema.backing_cart()
+    }
+
+    fn schema(&self) -> &codegen::Schema<'_> {
+        &self.schema.get().0
+    }
+
+    // The syntax tree of `uri`, if it's indexed and parses
+    fn file(&self, uri: &Url) -> Option<&codegen::SourceFile> {
+        let i = self.docs.iter().position(|doc| doc == uri)?;
+        Some(&self.files()[i])
+    }
+
+    // The URIs and trees, without copying the trees unless a request is still using them
+    fn into_files(self: Arc<Self>) -> (Vec<Url>, Vec<codegen::SourceFile>) {
+        match Arc::try_unwrap(self) {
+            Ok(parsed) => {
+                let files = Arc::try_unwrap(parsed.schema.into_backing_cart())
+                    .unwrap_or_else(|files| files.to_vec());
+                (parsed.docs, files)
+            }
+            Err(parsed) => (parsed.docs.clone(), parsed.files().to_vec()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Parsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Parsed")
+            .field("docs", &self.docs)
+            .finish_non_exhaustive()
+    }
+}
+
+// The syntax trees kept between requests. While the closed files that parse number no more than
+// the budget (`maxParsedFiles`), the whole view of the workspace is kept. Past it, the view lives
+// only as long as requests use it, and only the trees of open documents and of the most recently
+// parsed closed files are kept to build the next one from; the others are parsed again when
+// needed. Their `DocumentIndex` is kept either way, so navigation doesn't need the trees.
+#[derive(Debug, Default)]
+struct Trees {
+    kept: Option<Arc<Parsed>>,
+    // The last view, while anything holds it
+    last: std::sync::Weak<Parsed>,
+    // Past the budget, the trees kept to build the next view from
+    retained: HashMap<Url, codegen::SourceFile>,
+    // The closed files parsed, least recently first
+    recent: Vec<Url>,
+}
+
+impl Trees {
+    // The trees of the last view, and those retained, by URI, leaving none behind
+    fn take(&mut self) -> HashMap<Url, codegen::SourceFile> {
+        let mut trees = std::mem::take(&mut self.retained);
+        if let Some(last) = self.kept.take().or_else(|| self.last.upgrade()) {
+            let (docs, files) = last.into_files();
+            trees.extend(docs.into_iter().zip(files));
+        }
+        trees
+    }
+
+    // Keeps `parsed`, or past `budget` the trees of the documents in `open` and of the most
+    // recently parsed closed files
+    fn keep(&mut self, parsed: &Arc<Parsed>, open: &HashSet<Url>, budget: usize) {
+        self.last = Arc::downgrade(parsed);
+        let docs: HashSet<&Url> = parsed.docs.iter().collect();
+        self.recent
+            .retain(|uri| !open.contains(uri) && docs.contains(uri));
+        let closed = docs.iter().filter(|uri| !open.contains(**uri)).count();
+        if closed <= budget {
+            self.kept = Some(parsed.clone());
+            return;
+        }
+        let recent: HashSet<&Url> = self.recent[self.recent.len().saturating_sub(budget)..]
+            .iter()
+            .collect();
+        self.retained = parsed
+            .docs
+            .iter()
+            .zip(parsed.files())
+            .filter(|(uri, _)| open.contains(*uri) || recent.contains(uri))
+            .map(|(uri, file)| (uri.clone(), file.clone()))
+            .collect();
+    }
+
+    // Notes that a closed file was just parsed
+    fn parsed(&mut self, uri: &Url) {
+        self.recent.retain(|other| other != uri);
+        self.recent.push(uri.clone());
+    }
+}
+
+// The server's state behind a handle, so work can carry on in a task after a notification's
+// handler returns
+#[derive(Debug, Clone)]
+pub struct Backend(Arc<State>);
+
+// Open-document work in flight, from `Backend::open_work`
+struct OpenWork<'a>(&'a State);
+
+impl Drop for OpenWork<'_> {
+    fn drop(&mut self) {
+        if self.0.open_work.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.open_work_done.notify_waiters();
+        }
+    }
+}
+
+impl std::ops::Deref for Backend {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct State {
+    client: Client,
+    // What's indexed for each document. Requests only read it, so they run side by side, and
+    // a (re)index replaces documents' entries all at once under the write lock.
+    index: RwLock<HashMap<Url, DocumentIndex>>,
+    // Syntax trees for the requests that need them, built by the first after the index changes
+    // and kept (within `max_parsed_files`) for those after
+    trees: Mutex<Trees>,
+    // How many closed files' syntax trees are kept between requests, from `maxParsedFiles`
+    max_parsed_files: Mutex<usize>,
+    // Documents (re)indexed, edited, or dropped since `parsed` was built
+    stale: Mutex<HashSet<Url>>,
+    // Contents of the documents open in the editor, which take precedence over the files on
+    // disk. Documents with other schemes (such as `untitled:`) only exist here.
+    documents: Mutex<HashMap<Url, Document>>,
+
+    // Set of potential generated header files
+    header_files: Mutex<HashSet<PathBuf>>,
+
+    // Workspace root, from `initialize`, or None when editing lone files
+    root: Mutex<Option<PathBuf>>,
+    scan: Mutex<ScanOptions>,
+    // Extra directories include paths are completed and resolved relative to, from the
+    // `includePaths` setting
+    include_paths: Mutex<Vec<PathBuf>>,
+    // Value given to constants created by the add-bound quick fix
+    default_bound: Mutex<u64>,
+    // Whether fixing everything also removes unused definitions, from `fixAllRemovesUnused`
+    fix_all_removes_unused: Mutex<bool>,
+    // The lint rules diagnostics come from; the include rules are added by `includeAware`
+    lint_rules: Mutex<lint::Registry>,
+    // Whether files only see what they include, from `includeAware`
+    include_aware: Mutex<bool>,
+    // Rules from outside the crate, given to `service`, run alongside the built-in ones
+    extra_rules: lint::Registry,
+    // The XDR variant files are written in, if configured; otherwise each file's is detected
+    dialect: Mutex<Option<dialect::Dialect>>,
+    // Whether the client accepts snippets in completions
+    snippets: Mutex<bool>,
+    // Whether the client accepts server-initiated progress (`window/workDoneProgress/create`)
+    work_done_progress: Mutex<bool>,
+    // Whether renames also cover mentions in comments and passthrough lines by default
+    rename_in_comments: Mutex<bool>,
+    // Layout choices for formatting
+    format_style: Mutex<format::Style>,
+    // Whether references also cover mentions in comments and passthrough lines by default
+    references_in_comments: Mutex<bool>,
+    // Whether references to an enum also cover references to its members by default
+    references_include_members: Mutex<bool>,
+    // Types the schema is rooted at, from the `rootTypes` setting
+    root_types: Mutex<Vec<String>>,
+    // How much hovers on names show
+    hover_verbosity: Mutex<hover::Verbosity>,
+    // Lines a definition must span to get an end-of-block hint; 0 for none
+    end_of_block_hint_lines: Mutex<usize>,
+    // Whether type definitions get a hint with their wire size
+    wire_size_hints: Mutex<bool>,
+    // Whether the client supports change annotations in workspace edits
+    change_annotations: Mutex<bool>,
+    // Whether the client handles `workspace/codeLens/refresh`
+    code_lens_refresh: Mutex<bool>,
+    // Whether the client handles `workspace/inlayHint/refresh`
+    inlay_hint_refresh: Mutex<bool>,
+    // Whether the client can watch files for us (dynamic `workspace/didChangeWatchedFiles`)
+    watch_files: Mutex<bool>,
+    // Whether the client takes the type hierarchy by dynamic registration, the only way
+    // lsp-types lets us offer it
+    type_hierarchy: Mutex<bool>,
+    // Metrics from indexing the workspace in `initialize`, sent once the client is ready
+    initial_metrics: Mutex<Vec<serde_json::Value>>,
+    // The `entryFiles` setting and the files they include, indexed in `initialize`, with the rest
+    // of the workspace left for `initialized`
+    entry_files: Mutex<HashSet<PathBuf>>,
+    deferred_paths: Mutex<Vec<PathBuf>>,
+    // Comment styles used for documentation, to match when generating comments
+    comment_styles: Mutex<actions::CommentStyles>,
+    // How long a document's edits must pause before it's reindexed, from `reindexDelay`
+    reindex_delay: Mutex<Duration>,
+    // Open documents edited since they were last indexed
+    pending: Mutex<HashSet<Url>>,
+    // Counts document changes, so work a later change redoes can be dropped
+    edits: AtomicU64,
+    // Open-document work in flight (reindexing after a pause, semantic tokens); workspace
+    // indexing waits for it to finish before starting its next batch
+    open_work: AtomicUsize,
+    open_work_done: Notify,
+    // Indexes of unchanged files from the last run, with `indexCache`; saved at shutdown
+    index_cache: Mutex<Option<Arc<IndexCache>>>,
+    // How positions count columns, agreed with the client in `initialize`
+    encoding: Mutex<Encoding>,
+    // Set by `shutdown`: indexing stops, and `main` exits successfully once the connection closes
+    shut_down: Arc<AtomicBool>,
+}
+
+// Built-in types and keywords offered alongside the names defined in the workspace
+const BUILT_IN_TYPES: &[&str] = &[
+    "int",
+    "unsigned int",
+    "hyper",
+    "unsigned hyper",
+    "float",
+    "double",
+    "quadruple",
+    "bool",
+    "opaque",
+    "string",
+    "void",
+];
+const KEYWORDS: &[&str] = &[
+    "struct", "union", "enum", "typedef", "const", "case", "default", "switch",
+];
+
+fn completion_kind(kind: SymbolKind) -> CompletionItemKind {
+    match kind {
+        SymbolKind::CONSTANT => CompletionItemKind::CONSTANT,
+        SymbolKind::ENUM => CompletionItemKind::ENUM,
+        SymbolKind::ENUM_MEMBER => CompletionItemKind::ENUM_MEMBER,
+        SymbolKind::STRUCT => CompletionItemKind::STRUCT,
+        SymbolKind::CLASS => CompletionItemKind::CLASS,
+        SymbolKind::MODULE | SymbolKind::NAMESPACE => CompletionItemKind::MODULE,
+        SymbolKind::FUNCTION => CompletionItemKind::FUNCTION,
+        _ => CompletionItemKind::TYPE_PARAMETER,
+    }
+}
+
+enum IndexMetrics {}
+
+impl notification::Notification for IndexMetrics {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "xdr/indexMetrics";
+}
+
+// `$/progress` carrying a page of results, which lsp-types only models for work done progress
+enum PartialResult {}
+
+impl notification::Notification for PartialResult {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "$/progress";
+}
+
+// Workspace symbols sent in each partial result
+const SYMBOL_PAGE_SIZE: usize = 64;
+
+// Annotation on rename edits in comments and passthrough lines
+const IN_COMMENTS: &str = "inComments";
+
+// Renames touching at least this many files report progress
+const RENAME_PROGRESS_FILES: usize = 10;
+
+// Files are indexed in batches of this many, reporting progress after each when there's more
+// than one
+const INDEX_BATCH_FILES: usize = 64;
+
+// Default for `reindexDelay`: edits closer together than this are indexed once, after the last
+const REINDEX_DELAY_MS: u64 = 150;
+// Default for `maxParsedFiles`
+const MAX_PARSED_FILES: usize = 1000;
+
+const CHECK_BREAKING: &str = "xdr.checkBreaking";
+const GENERATE_CODE: &str = "xdr.generateCode";
+// Shorthands for `xdr.generateCode` with the `rust` and `c-header` targets
+const GENERATE_RUST: &str = "xdr.generateRust";
+const GENERATE_C_HEADER: &str = "xdr.generateCHeader";
+const FIX_ALL: &str = "xdr.fixAll";
+const LIST_UNREACHABLE: &str = "xdr.listUnreachable";
+// Run by the client, not the server: shows locations in a peek view
+const SHOW_REFERENCES: &str = "editor.action.showReferences";
+
+fn make_error(code: i64, message: impl Into<std::borrow::Cow<'static, str>>) -> Error {
+    Error {
+        code: tower_lsp::jsonrpc::ErrorCode::ServerError(code),
+        message: message.into(),
+        data: None,
+    }
+}
+
+// The formatter's options for an editor's formatting options and the configured style
+fn format_options(options: &FormattingOptions, style: format::Style) -> format::Options {
+    let indent = if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    };
+    format::Options {
+        indent,
+        tab_width: options.tab_size as usize,
+        style,
+    }
+}
+
+// The lint rules' diagnostics for each of `files`, after the syntax errors found parsing them
+fn lint_files(
+    rules: &lint::Registry,
+    root: &Path,
+    files: &[codegen::SourceFile],
+    syntax_errors: Vec<(PathBuf, Diagnostic)>,
+    encoding: Encoding,
+) -> HashMap<PathBuf, Vec<Diagnostic>> {
+    let workspace = lint::ResolvedWorkspace::new(root, files, encoding);
+    let mut diagnostics = rules.run(&workspace);
+    for (path, diagnostic) in syntax_errors {
+        diagnostics.entry(path).or_default().insert(0, diagnostic);
+    }
+    diagnostics
+}
+
+// The edit formatting `text` makes: one replacing the whole document, none if it's already
+// formatted, or None if it doesn't parse
+pub fn format_edits(
+    text: &str,
+    options: &FormattingOptions,
+    style: format::Style,
+    encoding: Encoding,
+) -> Option<Vec<TextEdit>> {
+    let formatted = format::format(text, &format_options(options, style))?;
+    if formatted == text {
+        return Some(vec![]);
+    }
+    Some(vec![TextEdit {
+        range: Range::new(
+            Position::new(0, 0),
+            position::position_at(text, text.len(), encoding),
+        ),
+        new_text: formatted,
+    }])
+}
+
+// The edit formatting the lines of the top-level definitions `range` touches, none if they're
+// already formatted or it touches none, or None if the file or those lines don't parse
+pub fn range_format_edits(
+    text: &str,
+    range: Range,
+    options: &FormattingOptions,
+    style: format::Style,
+    encoding: Encoding,
+) -> Option<Vec<TextEdit>> {
+    let file = codegen::SourceFile::parse(PathBuf::new(), text.to_string()).ok()?;
+    let (from, to) = (
+        position::offset_at(text, range.start, encoding),
+        position::offset_at(text, range.end, encoding),
+    );
+    let spans: Vec<(usize, usize)> = file
+        .spec
+        .defns
+        .iter()
+        .filter_map(|defn| Some((file.definition_start(defn)?, file.definition_end(defn)?)))
+        .filter(|&(start, end)| start <= to && from <= end)
+        .collect();
+    let (Some(start), Some(end)) = (
+        spans.iter().map(|&(start, _)| start).min(),
+        spans.iter().map(|&(_, end)| end).max(),
+    ) else {
+        return Some(vec![]);
+    };
+    // Whole lines
+    let start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    let lines = &text[start..end];
+    let mut formatted = format::format(lines, &format_options(options, style))?;
+    if !lines.ends_with('\n') {
+        formatted.truncate(formatted.trim_end_matches('\n').len());
+    }
+    if formatted == lines {
+        return Some(vec![]);
+    }
+    Some(vec![TextEdit {
+        range: Range::new(
+            position::position_at(text, start, encoding),
+            position::position_at(text, end, encoding),
+        ),
+        new_text: formatted,
+    }])
+}
+
+// Hover text at a position in `uri`, one of the parsed documents `docs`, whose trees `schema` is
+// over
+pub fn hover_at(
+    docs: &[Url],
+    schema: &codegen::Schema,
+    uri: &Url,
+    pos: Position,
+    verbosity: hover::Verbosity,
+    encoding: Encoding,
+) -> Option<Hover> {
+    let files = schema.files;
+    let file = &files[docs.iter().position(|doc| doc == uri)?];
+    let offset = file.offset_at(pos, encoding);
+    let mut found: Vec<(usize, usize, String)> = hover::rpc_number(schema, file, offset)
+        .or_else(|| hover::case_value(schema, file, offset))
+        .or_else(|| hover::definition(schema, file, offset, verbosity))
+        .into_iter()
+        .collect();
+    if verbosity == hover::Verbosity::Full {
+        found.extend(hover::used_by(schema, docs, file, offset));
+        found.extend(hover::since(files, file, offset));
+    }
+    let (start, end, _) = *found.first()?;
+    let text: Vec<String> = found.into_iter().map(|(_, _, text)| text).collect();
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: text.join("\n\n"),
+        }),
+        range: Some(Range::new(
+            file.position_at(start, encoding),
+            file.position_at(end, encoding),
+        )),
+    })
+}
+
+impl Backend {
+    fn new(client: Client, shut_down: Arc<AtomicBool>, extra_rules: lint::Registry) -> Self {
+        Backend(Arc::new(State {
+            client,
+            index: RwLock::new(HashMap::new()),
+            trees: Mutex::new(Trees::default()),
+            max_parsed_files: Mutex::new(MAX_PARSED_FILES),
+            stale: Mutex::new(HashSet::new()),
+            documents: Mutex::new(HashMap::new()),
+            header_files: Mutex::new(HashSet::new()),
+            root: Mutex::new(None),
+            scan: Mutex::new(ScanOptions::default()),
+            include_paths: Mutex::new(vec![]),
+            default_bound: Mutex::new(4096),
+            fix_all_removes_unused: Mutex::new(false),
+            lint_rules: Mutex::new(lint::Registry::default()),
+            include_aware: Mutex::new(false),
+            extra_rules,
+            dialect: Mutex::new(None),
+            snippets: Mutex::new(false),
+            work_done_progress: Mutex::new(false),
+            rename_in_comments: Mutex::new(false),
+            references_in_comments: Mutex::new(false),
+            references_include_members: Mutex::new(false),
+            end_of_block_hint_lines: Mutex::new(30),
+            wire_size_hints: Mutex::new(false),
+            format_style: Mutex::new(format::Style::default()),
+            change_annotations: Mutex::new(false),
+            code_lens_refresh: Mutex::new(false),
+            inlay_hint_refresh: Mutex::new(false),
+            watch_files: Mutex::new(false),
+            type_hierarchy: Mutex::new(false),
+            initial_metrics: Mutex::new(vec![]),
+            entry_files: Mutex::new(HashSet::new()),
+            root_types: Mutex::new(vec![]),
+            hover_verbosity: Mutex::new(hover::Verbosity::default()),
+            deferred_paths: Mutex::new(vec![]),
+            comment_styles: Mutex::new(actions::CommentStyles::default()),
+            reindex_delay: Mutex::new(Duration::from_millis(REINDEX_DELAY_MS)),
+            pending: Mutex::new(HashSet::new()),
+            edits: AtomicU64::new(0),
+            open_work: AtomicUsize::new(0),
+            open_work_done: Notify::new(),
+            index_cache: Mutex::new(None),
+            // What LSP assumes when nothing else is agreed
+            encoding: Mutex::new(Encoding::Utf16),
+            shut_down,
+        }))
+    }
+
+    // The connection to the client, for reporting what the service itself can't
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    async fn get_ident_at(&self, uri: &Url, pos: Position) -> Option<String> {
+        self.flush_pending(uri).await;
+        self.index
+            .read()
+            .await
+            .get(uri)
+            .and_then(|index| token_at(&index.identifiers, pos))
+            .map(|token| token.val.clone())
+    }
+}
+
+impl Backend {
+    // Indexes files from disk, returning their entries for the metrics notification
+    async fn index_files(&self, paths: &[PathBuf]) -> Vec<serde_json::Value> {
+        {
+            let mut header_files = self.header_files.lock().await;
+            for path in paths {
+                let mut new_path = path.clone();
+                new_path.set_extension("h");
+                header_files.insert(new_path);
+            }
+        }
+        let total = paths.len();
+        let token = if total > INDEX_BATCH_FILES {
+            self.begin_progress(None, "Indexing XDR workspace".to_string())
+                .await
+        } else {
+            None
+        };
+        let cache = self.index_cache.lock().await.clone();
+        // Another server on the workspace may have indexed these files since
+        if let Some(cache) = cache.clone() {
+            let _ = tokio::task::spawn_blocking(move || cache.reload()).await;
+        }
+        let encoding = *self.encoding.lock().await;
+        let mut metrics = vec![];
+        for (i, batch) in paths.chunks(INDEX_BATCH_FILES).enumerate() {
+            // Adding a large folder can still be indexing when the client shuts down
+            if self.shut_down.load(Ordering::Relaxed) {
+                break;
+            }
+            self.yield_to_open_documents().await;
+            let batch = batch.to_vec();
+            let cache = cache.clone();
+            let indexed = tokio::task::spawn_blocking(move || {
+                index_in_parallel(&batch, cache.as_deref(), encoding)
+            })
+            .await
+            .unwrap_or_default();
+            {
+                let mut comment_styles = self.comment_styles.lock().await;
+                for (_, text, _) in &indexed {
+                    comment_styles.count(text);
+                }
+            }
+            metrics.extend(indexed.iter().map(|(uri, _, index)| index.metrics(uri)));
+            self.stale
+                .lock()
+                .await
+                .extend(indexed.iter().map(|(uri, _, _)| uri.clone()));
+            // The whole batch becomes visible at once
+            self.index
+                .write()
+                .await
+                .extend(indexed.into_iter().map(|(uri, _, index)| (uri, index)));
+            if let Some(token) = &token {
+                let done = total.min((i + 1) * INDEX_BATCH_FILES);
+                self.progress(
+                    token,
+                    WorkDoneProgress::Report(WorkDoneProgressReport {
+                        message: Some(format!("{done}/{total}")),
+                        percentage: Some((done * 100 / total) as u32),
+                        ..Default::default()
+                    }),
+                )
+                .await;
+            }
+        }
+        if let Some(token) = &token {
+            self.progress(token, WorkDoneProgress::End(WorkDoneProgressEnd::default()))
+                .await;
+        }
+        metrics
+    }
+
+    // Writes out the index cache, logging a failure
+    async fn save_index_cache(&self, cache: Arc<IndexCache>) {
+        let saved = tokio::task::spawn_blocking(move || {
+            cache
+                .save()
+                .map_err(|err| format!("{}: {err}", cache.file().display()))
+        })
+        .await;
+        if let Ok(Err(err)) = saved {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("Could not save the index cache: {err}"),
+                )
+                .await;
+        }
+    }
+
+    // (Re)indexes one document from its contents, returning its entry for the metrics
+    // notification
+    async fn index_document(&self, uri: &Url, text: &str) -> serde_json::Value {
+        let index = DocumentIndex::new(uri, text, *self.encoding.lock().await);
+        self.store_index(uri, index).await
+    }
+
+    // A burst of changes (typing, a big paste arriving in pieces) is indexed once it pauses; each
+    // newer version takes over from the one before, and a request on the document in the meantime
+    // indexes it right away
+    async fn reindex_after_pause(&self, uri: Url, version: i32, edit: u64) {
+        let delay = *self.reindex_delay.lock().await;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let current = self
+            .documents
+            .lock()
+            .await
+            .get(&uri)
+            .is_some_and(|doc| doc.version == version);
+        if !current {
+            return;
+        }
+        let _work = self.open_work();
+        self.flush_pending(&uri).await;
+        // A change since, here or in another document, publishes diagnostics covering this one
+        if self.edits.load(Ordering::Relaxed) == edit {
+            self.after_reindex().await;
+        }
+    }
+
+    // Marks open-document work as in flight until the guard drops, holding back workspace indexing
+    fn open_work(&self) -> OpenWork<'_> {
+        self.open_work.fetch_add(1, Ordering::SeqCst);
+        OpenWork(self)
+    }
+
+    // Waits for open documents' reindexes and semantic tokens, so a cold index of the workspace
+    // doesn't slow typing. Documents edited and waiting for a pause are indexed first.
+    async fn yield_to_open_documents(&self) {
+        let pending: Vec<Url> = self.pending.lock().await.iter().cloned().collect();
+        for uri in &pending {
+            self.flush_pending(uri).await;
+        }
+        loop {
+            let done = self.open_work_done.notified();
+            tokio::pin!(done);
+            done.as_mut().enable();
+            if self.open_work.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            done.await;
+        }
+    }
+
+    // Indexes a document now if it was edited and is waiting for the edits to pause, so requests
+    // on it see where its names are
+    async fn flush_pending(&self, uri: &Url) {
+        if !self.pending.lock().await.remove(uri) {
+            return;
+        }
+        if let Some(text) = self.text_of(uri).await {
+            let metrics = self.index_document(uri, &text).await;
+            self.report_metrics(vec![metrics]).await;
+        }
+    }
+
+    // Replaces what's indexed for a document, returning its entry for the metrics notification
+    async fn store_index(&self, uri: &Url, index: DocumentIndex) -> serde_json::Value {
+        let metrics = index.metrics(uri);
+        self.index.write().await.insert(uri.clone(), index);
+        self.stale.lock().await.insert(uri.clone());
+        metrics
+    }
+
+    // Starts reporting progress under `token`, or under a new server-created token if the client
+    // supports that, returning the token in use
+    async fn begin_progress(
+        &self,
+        token: Option<ProgressToken>,
+        title: String,
+    ) -> Option<ProgressToken> {
+        let token = match token {
+            Some(token) => token,
+            None if *self.work_done_progress.lock().await => {
+                let token = ProgressToken::String(format!("xdr-ls/{title}"));
+                self.client
+                    .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                        token: token.clone(),
+                    })
+                    .await
+                    .ok()?;
+                token
+            }
+            None => return None,
+        };
+        self.progress(
+            &token,
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title,
+                percentage: Some(0),
+                ..Default::default()
+            }),
+        )
+        .await;
+        Some(token)
+    }
+
+    async fn progress(&self, token: &ProgressToken, value: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+
+    // Edits renaming every occurrence of `old` to `new`. Edits to open documents carry the
+    // version they were computed from, so the client refuses them if the document has changed
+    // since; files on disk are checked here and the rename fails, naming the file, if one has
+    // changed since it was indexed. With `in_comments`, mentions in comments and passthrough
+    // lines are renamed too, annotated (if the client supports it) so the user confirms them.
+    async fn rename_edit(
+        &self,
+        old: &str,
+        new: &str,
+        in_comments: bool,
+        token: Option<ProgressToken>,
+    ) -> Result<WorkspaceEdit> {
+        let mut locations: Vec<Location> = self.references_to(old).await;
+        for index in self.index.read().await.values() {
+            locations.extend(
+                index
+                    .symbols
+                    .iter()
+                    .filter(|symbol| symbol.name == old)
+                    .map(|symbol| symbol.location.clone()),
+            );
+        }
+        // A field or union arm is only ever written where it's declared
+        let member = old != unscoped(old);
+        if member {
+            locations.extend(self.definition_of(old).await);
+        }
+        // Code occurrences, then comment occurrences
+        let mut by_uri: BTreeMap<Url, (Vec<Range>, Vec<Range>)> = BTreeMap::new();
+        for location in locations {
+            by_uri
+                .entry(location.uri)
+                .or_default()
+                .0
+                .push(location.range);
+        }
+        // Mentions of a field in comments can't be told apart from those of others named the same
+        if in_comments && !member {
+            for (uri, ranges) in self.comment_mentions(old).await {
+                by_uri.entry(uri).or_default().1 = ranges;
+            }
+        }
+
+        let token = if by_uri.len() >= RENAME_PROGRESS_FILES {
+            self.begin_progress(token, format!("Renaming {old}")).await
+        } else {
+            None
+        };
+        let total = by_uri.len();
+        let annotate = in_comments && *self.change_annotations.lock().await;
+        let encoding = *self.encoding.lock().await;
+        let mut changes = vec![];
+        let mut failure = None;
+        for (i, (uri, (mut ranges, comments))) in by_uri.into_iter().enumerate() {
+            if let Some(token) = &token {
+                self.progress(
+                    token,
+                    WorkDoneProgress::Report(WorkDoneProgressReport {
+                        message: Some(format!("{}/{} files", i + 1, total)),
+                        percentage: Some((i * 100 / total) as u32),
+                        ..Default::default()
+                    }),
+                )
+                .await;
+            }
+            let version = self.documents.lock().await.get(&uri).map(|doc| doc.version);
+            let text = self.text_of(&uri).await.unwrap_or_default();
+            let lines = position::Lines::new(&text);
+            let stale = ranges.iter().any(|range| {
+                let start = lines.offset_at(&text, range.start, encoding);
+                let end = lines.offset_at(&text, range.end, encoding);
+                text.get(start..end) != Some(unscoped(old))
+            });
+            if stale {
+                failure = Some(uri);
+                break;
+            }
+            ranges.sort_by_key(|range| (range.start.line, range.start.character));
+            ranges.dedup();
+            let edit = |range| TextEdit {
+                range,
+                new_text: new.to_string(),
+            };
+            let mut edits: Vec<OneOf<TextEdit, AnnotatedTextEdit>> = ranges
+                .into_iter()
+                .map(|range| OneOf::Left(edit(range)))
+                .collect();
+            edits.extend(comments.into_iter().map(|range| {
+                if annotate {
+                    OneOf::Right(AnnotatedTextEdit {
+                        text_edit: edit(range),
+                        annotation_id: IN_COMMENTS.to_string(),
+                    })
+                } else {
+                    OneOf::Left(edit(range))
+                }
+            }));
+            changes.push(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version },
+                edits,
+            });
+        }
+        if let Some(token) = &token {
+            self.progress(token, WorkDoneProgress::End(WorkDoneProgressEnd::default()))
+                .await;
+        }
+        if let Some(uri) = failure {
+            let name = uri
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or(uri.as_str())
+                .to_string();
+            return Err(make_error(
+                0,
+                format!("{name} has changed since it was indexed; open it and retry the rename"),
+            ));
+        }
+        let annotations = annotate.then(|| {
+            HashMap::from([(
+                IN_COMMENTS.to_string(),
+                ChangeAnnotation {
+                    label: format!("Rename `{old}` in comments and passthrough lines"),
+                    needs_confirmation: Some(true),
+                    description: Some(
+                        "These mentions aren't code, so they may not refer to the renamed symbol"
+                            .to_string(),
+                    ),
+                },
+            )])
+        });
+        Ok(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(changes)),
+            change_annotations: annotations,
+            ..Default::default()
+        })
+    }
+
+    // Whole-word mentions of `name` in comments and passthrough lines, by file
+    async fn comment_mentions(&self, name: &str) -> Vec<(Url, Vec<Range>)> {
+        let uris: Vec<Url> = self.index.read().await.keys().cloned().collect();
+        let encoding = *self.encoding.lock().await;
+        let mut mentions = vec![];
+        for uri in uris {
+            let text = self.text_of(&uri).await.unwrap_or_default();
+            let ranges = rename::comment_occurrences(&text, name, encoding);
+            if !ranges.is_empty() {
+                mentions.push((uri, ranges));
+            }
+        }
+        mentions
+    }
+
+    // References to the identifier at a position, plus its mentions in comments and passthrough
+    // lines if `in_comments` is set, and to its members if it's an enum and `with_members` is set
+    async fn reference_locations(
+        &self,
+        params: &ReferenceParams,
+        in_comments: bool,
+        with_members: bool,
+    ) -> Option<(Vec<Location>, Vec<Location>)> {
+        let ident = self
+            .get_ident_at(
+                &params.text_document_position.text_document.uri,
+                params.text_document_position.position,
+            )
+            .await?;
+        let mut names = vec![ident.clone()];
+        if with_members {
+            names.extend(
+                self.index
+                    .read()
+                    .await
+                    .values()
+                    .flat_map(|index| &index.symbols)
+                    .filter(|symbol| {
+                        symbol.kind == SymbolKind::ENUM_MEMBER
+                            && symbol.container_name.as_ref() == Some(&ident)
+                    })
+                    .map(|symbol| symbol.name.clone()),
+            );
+        }
+        let mut locs = vec![];
+        let mut mentions = vec![];
+        for name in &names {
+            locs.extend(self.references_to(name).await);
+            if params.context.include_declaration {
+                locs.extend(self.definitions_of(name).await);
+            }
+            if in_comments {
+                for (uri, ranges) in self.comment_mentions(name).await {
+                    mentions.extend(
+                        ranges
+                            .into_iter()
+                            .map(|range| Location::new(uri.clone(), range)),
+                    );
+                }
+            }
+        }
+        if locs.is_empty() && mentions.is_empty() {
+            return None;
+        }
+        Some((locs, mentions))
+    }
+
+    // Custom `xdr/references` request: `textDocument/references`, with each location flagged by
+    // whether it's a mention in a comment or passthrough line. `inComments` and `includeMembers`
+    // override the `referencesInComments` and `referencesIncludeMembers` settings.
+    async fn references_custom(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let in_comments = match params.get("inComments") {
+            Some(flag) => flag.as_bool() == Some(true),
+            None => *self.references_in_comments.lock().await,
+        };
+        let with_members = match params.get("includeMembers") {
+            Some(flag) => flag.as_bool() == Some(true),
+            None => *self.references_include_members.lock().await,
+        };
+        let params: ReferenceParams =
+            serde_json::from_value(params).map_err(|err| Error::invalid_params(err.to_string()))?;
+        let Some((locs, mentions)) = self
+            .reference_locations(&params, in_comments, with_members)
+            .await
+        else {
+            return Ok(serde_json::Value::Null);
+        };
+        let flagged = |loc: Location, in_comment: bool| serde_json::json!({"uri": loc.uri, "range": loc.range, "inComment": in_comment});
+        Ok(serde_json::Value::Array(
+            locs.into_iter()
+                .map(|loc| flagged(loc, false))
+                .chain(mentions.into_iter().map(|loc| flagged(loc, true)))
+                .collect(),
+        ))
+    }
+
+    async fn rename_with(
+        &self,
+        params: RenameParams,
+        in_comments: bool,
+    ) -> Result<Option<WorkspaceEdit>> {
+        let position = params.text_document_position;
+        let Some(old) = self
+            .get_ident_at(&position.text_document.uri, position.position)
+            .await
+        else {
+            return Ok(None);
+        };
+        let new = params.new_name;
+        if rename::is_keyword(&new) {
+            return Err(Error::invalid_params(format!("`{new}` is a keyword")));
+        }
+        if !rename::is_valid_name(&new) {
+            return Err(Error::invalid_params(format!(
+                "`{new}` is not a valid identifier"
+            )));
+        }
+        // A field can share its name with anything but the other fields of its struct or union
+        let taken = match old.rsplit_once("::") {
+            Some((scope, _)) => format!("{scope}::{new}"),
+            None => new.clone(),
+        };
+        if new != unscoped(&old) && self.definition_of(&taken).await.is_some() {
+            return Err(Error::invalid_params(format!("`{new}` is already defined")));
+        }
+        self.rename_edit(
+            &old,
+            &new,
+            in_comments,
+            params.work_done_progress_params.work_done_token,
+        )
+        .await
+        .map(Some)
+    }
+
+    // Custom `xdr/rename` request: `textDocument/rename` with an `inComments` flag overriding the
+    // `renameInComments` setting
+    async fn rename_custom(&self, params: serde_json::Value) -> Result<Option<WorkspaceEdit>> {
+        let in_comments = match params.get("inComments") {
+            Some(flag) => flag.as_bool() == Some(true),
+            None => *self.rename_in_comments.lock().await,
+        };
+        let params: RenameParams =
+            serde_json::from_value(params).map_err(|err| Error::invalid_params(err.to_string()))?;
+        self.rename_with(params, in_comments).await
+    }
+
+    // Updates what depends on the whole index once documents have been (re)indexed
+    async fn after_reindex(&self) {
+        if !self.lint_rules.lock().await.is_empty() {
+            self.publish_diagnostics().await;
+        }
+        self.refresh_code_lenses().await;
+    }
+
+    // Asks the client to re-request code lenses, whose reference counts and sizes may have
+    // changed after a reindex
+    async fn refresh_code_lenses(&self) {
+        if *self.code_lens_refresh.lock().await {
+            // Failing to refresh only leaves stale counts
+            let _ = self.client.code_lens_refresh().await;
+        }
+    }
+
+    // Sends the custom `xdr/indexMetrics` notification, `{ files: [{ uri, parseMicros, symbols,
+    // errors }] }`, for the documents just (re)indexed
+    async fn report_metrics(&self, files: Vec<serde_json::Value>) {
+        if files.is_empty() {
+            return;
+        }
+        self.client
+            .send_notification::<IndexMetrics>(serde_json::json!({ "files": files }))
+            .await;
+    }
+
+    // Drops everything indexed from one document
+    async fn forget_document(&self, uri: &Url) {
+        self.index.write().await.remove(uri);
+        self.stale.lock().await.insert(uri.clone());
+    }
+
+    // Every reference to `name`, from all documents
+    async fn references_to(&self, name: &str) -> Vec<Location> {
+        self.index
+            .read()
+            .await
+            .values()
+            .filter_map(|index| index.refs.get(name))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    // Everywhere `name` is defined, in order of URI so the answer doesn't depend on the order
+    // files were indexed in. Definitions in the workspace replace the standard library's.
+    async fn definitions_of(&self, name: &str) -> Vec<Location> {
+        let index = self.index.read().await;
+        let mut defns: Vec<(bool, &Url, &Location)> = index
+            .iter()
+            .filter_map(|(uri, index)| {
+                let library = uri
+                    .to_file_path()
+                    .is_ok_and(|path| stdlib::is_library(&path));
+                Some((library, uri, index.defns.get(name)?))
+            })
+            .collect();
+        defns.sort_by_key(|&(library, uri, _)| (library, uri.as_str()));
+        let workspace = defns.first().is_some_and(|&(library, ..)| !library);
+        defns
+            .into_iter()
+            .filter(|&(library, ..)| !(workspace && library))
+            .map(|(_, _, loc)| loc.clone())
+            .collect()
+    }
+
+    // Where `name` is defined: the first of its definitions
+    async fn definition_of(&self, name: &str) -> Option<Location> {
+        self.definitions_of(name).await.into_iter().next()
+    }
+
+    // A document's contents: the editor's copy if it's open, otherwise the file on disk
+    async fn text_of(&self, uri: &Url) -> Option<String> {
+        if let Some(doc) = self.documents.lock().await.get(uri) {
+            return Some(doc.text.clone());
+        }
+        fs::read_to_string(uri.to_file_path().ok()?).ok()
+    }
+
+    // Drops everything indexed from files under `dir`, clearing their diagnostics
+    async fn purge_folder(&self, dir: &Path) {
+        let inside = |uri: &Url| uri.to_file_path().is_ok_and(|path| path.starts_with(dir));
+        let removed: Vec<Url> = self
+            .index
+            .read()
+            .await
+            .keys()
+            .filter(|uri| inside(uri))
+            .cloned()
+            .collect();
+        for uri in removed {
+            self.forget_document(&uri).await;
+            self.client.publish_diagnostics(uri, vec![], None).await;
+        }
+        self.header_files
+            .lock()
+            .await
+            .retain(|path| !path.starts_with(dir));
+    }
+
+    // Applies the settings in `options` (`initializationOptions`, or the `xdr` section sent with
+    // `workspace/didChangeConfiguration`). Settings left out go back to their defaults. Scanning
+    // settings are only stored, taking effect at the next scan.
+    async fn apply_settings(&self, options: Option<&serde_json::Value>) {
+        let setting = |key: &str| options.and_then(|options| options.get(key));
+        let flag = |key: &str| setting(key).and_then(serde_json::Value::as_bool);
+        let names = |key: &str| -> Vec<String> {
+            setting(key)
+                .and_then(serde_json::Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(serde_json::Value::as_str)
+                .map(str::to_string)
+                .collect()
+        };
+        *self.scan.lock().await = ScanOptions::from_init_options(options);
+        *self.default_bound.lock().await = setting("defaultBound")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(4096);
+        *self.rename_in_comments.lock().await = flag("renameInComments").unwrap_or(false);
+        *self.references_in_comments.lock().await = flag("referencesInComments").unwrap_or(false);
+        *self.references_include_members.lock().await =
+            flag("referencesIncludeMembers").unwrap_or(false);
+        *self.end_of_block_hint_lines.lock().await = setting("endOfBlockHintLines")
+            .and_then(serde_json::Value::as_u64)
+            .map_or(30, |lines| lines as usize);
+        *self.wire_size_hints.lock().await = flag("wireSizeHints").unwrap_or(false);
+        *self.fix_all_removes_unused.lock().await = flag("fixAllRemovesUnused").unwrap_or(false);
+        *self.max_parsed_files.lock().await = setting("maxParsedFiles")
+            .and_then(serde_json::Value::as_u64)
+            .map_or(MAX_PARSED_FILES, |files| files as usize);
+        *self.reindex_delay.lock().await = Duration::from_millis(
+            setting("reindexDelay")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(REINDEX_DELAY_MS),
+        );
+        let mut dialect = None;
+        if let Some(name) = setting("dialect").and_then(serde_json::Value::as_str) {
+            dialect = dialect::Dialect::from_name(name);
+            if dialect.is_none() {
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("Unknown dialect `{name}`, detecting each file's"),
+                    )
+                    .await
+            }
+        }
+        *self.dialect.lock().await = dialect;
+        let mut verbosity = hover::Verbosity::default();
+        if let Some(name) = setting("hoverVerbosity").and_then(serde_json::Value::as_str) {
+            match hover::Verbosity::from_name(name) {
+                Some(named) => verbosity = named,
+                None => {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("Unknown hover verbosity `{name}`, using full"),
+                        )
+                        .await
+                }
+            }
+        }
+        *self.hover_verbosity.lock().await = verbosity;
+        let mut target_version = None;
+        if let Some(target) = setting("targetVersion").and_then(serde_json::Value::as_str) {
+            target_version = since::ProtocolVersion::parse(target);
+            if target_version.is_none() {
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("Invalid target version `{target}`, expected one like `1.2`"),
+                    )
+                    .await
+            }
+        }
+        let root_types = names("rootTypes");
+        *self.root_types.lock().await = root_types.clone();
+        let mut unused_exempt = None;
+        if let Some(pattern) = setting("unusedExempt").and_then(serde_json::Value::as_str) {
+            match Regex::new(pattern) {
+                Ok(re) => unused_exempt = Some(re),
+                Err(err) => {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("Invalid `unusedExempt` pattern: {err}"),
+                        )
+                        .await
+                }
+            }
+        }
+        let include_aware = flag("includeAware").unwrap_or(false);
+        *self.include_aware.lock().await = include_aware;
+        let mut rules = lint::Registry::builtin(
+            include_aware,
+            dialect,
+            target_version,
+            root_types,
+            flag("unhandledCases").unwrap_or(false),
+            unused_exempt,
+        );
+        rules.extend(&self.extra_rules);
+        rules.disable(&names("disabledRules"));
+        *self.lint_rules.lock().await = rules;
+        // Relative to the first folder
+        let root = self.root.lock().await.clone().unwrap_or_default();
+        *self.include_paths.lock().await = names("includePaths")
+            .iter()
+            .map(|path| root.join(path))
+            .collect();
+        *self.format_style.lock().await = format::Style::from_init_options(options);
+    }
+
+    // Scans the workspace folders again after the scanning settings change, indexing the files
+    // now found and dropping those no longer found (unless they're open)
+    async fn rescan(&self) {
+        let dirs: Vec<PathBuf> = match self.client.workspace_folders().await {
+            Ok(Some(folders)) => folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect(),
+            _ => self.root.lock().await.clone().into_iter().collect(),
+        };
+        let scan = self.scan.lock().await.clone();
+        let mut paths: HashSet<PathBuf> = HashSet::new();
+        for dir in &dirs {
+            let warnings = get_xdr_files(dir, &scan, &mut |path| {
+                paths.insert(path.to_path_buf());
+            });
+            for warning in warnings {
+                self.client
+                    .show_message(MessageType::WARNING, warning)
+                    .await;
+            }
+        }
+        let open: HashSet<Url> = self.documents.lock().await.keys().cloned().collect();
+        let indexed: Vec<Url> = self.index.read().await.keys().cloned().collect();
+        for uri in &indexed {
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            if !paths.contains(&path) && !open.contains(uri) && !stdlib::is_library(&path) {
+                self.forget_document(uri).await;
+                self.client
+                    .publish_diagnostics(uri.clone(), vec![], None)
+                    .await;
+            }
+        }
+        let mut added: Vec<PathBuf> = paths
+            .into_iter()
+            .filter(|path| Url::from_file_path(path).is_ok_and(|uri| !indexed.contains(&uri)))
+            .collect();
+        added.sort();
+        let metrics = self.index_files(&added).await;
+        self.report_metrics(metrics).await;
+    }
+
+    // Publishes the lint diagnostics for every file in the workspace, unless a document changes
+    // while they're worked out: the change publishes them again
+    async fn publish_diagnostics(&self) {
+        let edit = self.edits.load(Ordering::Relaxed);
+        let Some(diagnostics) = self.lint_diagnostics(Some(edit)).await else {
+            return;
+        };
+        if self.edits.load(Ordering::Relaxed) != edit {
+            return;
+        }
+        for (uri, diagnostics) in diagnostics {
+            self.client
+                .publish_diagnostics(uri, diagnostics, None)
+                .await;
+        }
+    }
+
+    // Publishes the diagnostics of the `entryFiles` and the files they include, from what's been
+    // indexed so far
+    async fn publish_entry_diagnostics(&self) {
+        let entries = self.entry_files.lock().await.clone();
+        for (uri, diagnostics) in self.lint_diagnostics(None).await.unwrap_or_default() {
+            if uri.to_file_path().is_ok_and(|path| entries.contains(&path)) {
+                self.client
+                    .publish_diagnostics(uri, diagnostics, None)
+                    .await;
+            }
+        }
+    }
+
+    // Runs the lint rules over the workspace, returning the diagnostics for each document. Given
+    // the count of document changes when it started, it gives up as soon as there's another.
+    async fn lint_diagnostics(&self, edit: Option<u64>) -> Option<HashMap<Url, Vec<Diagnostic>>> {
+        let root = self.root.lock().await.clone().unwrap_or_default();
+        let encoding = *self.encoding.lock().await;
+        let mut files = vec![];
+        let mut syntax_errors = vec![];
+        // Only files can be included, so other documents (untitled buffers) are checked alone
+        let mut loose = vec![];
+        let uris: Vec<Url> = self.index.read().await.keys().cloned().collect();
+        for uri in uris {
+            // Lets the changes (and cancellations) queued behind a large workspace through
+            tokio::task::yield_now().await;
+            if edit.is_some_and(|edit| self.edits.load(Ordering::Relaxed) != edit) {
+                return None;
+            }
+            let Some(text) = self.text_of(&uri).await else {
+                continue;
+            };
+            let Ok(path) = uri.to_file_path() else {
+                loose.push((uri, text));
+                continue;
+            };
+            if let Some(diagnostic) = lint::syntax_error(&text, encoding) {
+                syntax_errors.push((path.clone(), diagnostic));
+            }
+            if let Some(file) = codegen::SourceFile::parse_recovering(path, text) {
+                files.push(file);
+            }
+        }
+        let rules = self.lint_rules.lock().await;
+        let mut diagnostics = HashMap::new();
+        for (path, found) in lint_files(&rules, &root, &files, syntax_errors, encoding) {
+            if let Ok(uri) = Url::from_file_path(&path) {
+                diagnostics.insert(uri, found);
+            }
+        }
+        for (uri, text) in loose {
+            let path = PathBuf::from(uri.path());
+            let syntax_error =
+                lint::syntax_error(&text, encoding).map(|diagnostic| (path.clone(), diagnostic));
+            let files: Vec<_> = codegen::SourceFile::parse_recovering(path, text)
+                .into_iter()
+                .collect();
+            let found = lint_files(
+                &rules,
+                &root,
+                &files,
+                syntax_error.into_iter().collect(),
+                encoding,
+            );
+            diagnostics.insert(uri, found.into_values().flatten().collect());
+        }
+        Some(diagnostics)
+    }
+
+    // Every safe quick fix for a document's problems, merged into one edit, with how many fixes
+    // it makes
+    async fn fix_all_edit(&self, uri: &Url) -> Option<(WorkspaceEdit, usize)> {
+        let text = self.text_of(uri).await?;
+        let diagnostics = self
+            .lint_diagnostics(None)
+            .await?
+            .remove(uri)
+            .unwrap_or_default();
+        let mut fixes = includes::add_include(&text, uri, &diagnostics);
+        let path = uri.to_file_path().unwrap_or_default();
+        if let Some(file) = codegen::SourceFile::parse_recovering(path, text) {
+            let encoding = *self.encoding.lock().await;
+            let parsed = self.parsed_documents().await;
+            fixes.extend(actions::add_missing_arms(
+                &file,
+                uri,
+                &diagnostics,
+                parsed.schema(),
+                encoding,
+            ));
+            fixes.extend(actions::explicit_enum_values(
+                &file,
+                uri,
+                &diagnostics,
+                encoding,
+            ));
+            if *self.fix_all_removes_unused.lock().await {
+                fixes.extend(actions::remove_unused(&file, uri, &diagnostics, encoding));
+            }
+        }
+        actions::fix_all(uri, fixes)
+    }
+
+    // The `xdr.listUnreachable` command: the types no root type leads to, as `[{ name, kind, uri,
+    // range }]`
+    async fn list_unreachable(&self) -> Result<serde_json::Value> {
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let roots = self.root_types.lock().await.clone();
+        let encoding = *self.encoding.lock().await;
+        let Some(unreachable) = reachability::unreachable(files, &roots) else {
+            return Err(Error::invalid_params(
+                "no root types are declared; set `rootTypes` or annotate types with `@root`",
+            ));
+        };
+        let mut result = vec![];
+        for (file, defn) in unreachable {
+            let Some(id) = defn.id() else {
+                continue;
+            };
+            let index = files
+                .iter()
+                .position(|other| std::ptr::eq(other, file))
+                .unwrap_or_default();
+            result.push(serde_json::json!({
+                "name": id.id,
+                "kind": defn.keyword(),
+                "uri": docs[index],
+                "range": Range {
+                    start: file.position_at(id.start, encoding),
+                    end: file.position_at(id.end, encoding),
+                },
+            }));
+        }
+        Ok(serde_json::Value::Array(result))
+    }
+
+    // The `xdr.fixAll` command: applies `fix_all_edit` to the document `{ uri }` through
+    // `workspace/applyEdit`, returning `{ fixes }`, the number of fixes made
+    async fn fix_all(&self, args: Option<&serde_json::Value>) -> Result<serde_json::Value> {
+        let Some(uri) = args
+            .and_then(|args| args.get("uri"))
+            .and_then(serde_json::Value::as_str)
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return Err(Error::invalid_params("expected `uri`"));
+        };
+        let Some((edit, fixes)) = self.fix_all_edit(&uri).await else {
+            return Ok(serde_json::json!({ "fixes": 0 }));
+        };
+        match self.client.apply_edit(edit).await {
+            Ok(response) if response.applied => Ok(serde_json::json!({ "fixes": fixes })),
+            Ok(response) => Err(make_error(
+                0,
+                response
+                    .failure_reason
+                    .unwrap_or("The client didn't apply the fixes".to_string()),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    // The definition of `ident`, or all of them if it's defined in several files (like vendored
+    // copies of a spec), for the editor to choose from
+    async fn lookup_ident(&self, ident: &str) -> Option<GotoDefinitionResponse> {
+        let mut defns = self.definitions_of(ident).await;
+        match defns.len() {
+            0 => None,
+            1 => defns.pop().map(GotoDefinitionResponse::Scalar),
+            _ => Some(GotoDefinitionResponse::Array(defns)),
+        }
+    }
+
+    // Compares the workspace's schemas against those at a git revision, returning the
+    // wire-incompatible changes as a list of `{ uri, range, message }`
+    async fn check_breaking(&self, rev: &str) -> Result<serde_json::Value> {
+        let Some(root) = self.root.lock().await.clone() else {
+            return Err(make_error(0, "This needs a workspace folder"));
+        };
+        let scan = self.scan.lock().await.clone();
+        let old_files = match breaking::load_git_ref(&root, rev, &scan) {
+            Ok(files) => files,
+            Err(err) => return Err(make_error(0, err)),
+        };
+        let new_files = codegen::load_files(&[root], &scan);
+        let issues = breaking::check(
+            &codegen::Schema::new(&old_files),
+            &codegen::Schema::new(&new_files),
+        );
+        let summary = match issues.len() {
+            0 => format!("No breaking changes since {rev}"),
+            1 => format!("1 breaking change since {rev}"),
+            n => format!("{n} breaking changes since {rev}"),
+        };
+        self.client.show_message(MessageType::INFO, summary).await;
+        let issues: Vec<serde_json::Value> = issues
+            .iter()
+            .map(|issue| {
+                let pos = Position {
+                    line: issue.line as u32,
+                    character: 0,
+                };
+                serde_json::json!({
+                    "uri": Url::from_file_path(&issue.path).ok(),
+                    "range": Range { start: pos, end: pos },
+                    "message": issue.message,
+                })
+            })
+            .collect();
+        Ok(serde_json::Value::Array(issues))
+    }
+
+    // Runs a code generator on one file, reporting progress under `token` (or one of our own).
+    // Arguments are `{ uri, target, typeName?, codec?, format?, out?, show? }`; the output is
+    // written next to the file (or to `out`, relative to the file's directory) through
+    // `workspace/applyEdit`, or with `show` written to a temporary directory and opened with
+    // `window/showDocument`
+    async fn generate_code(
+        &self,
+        args: Option<&serde_json::Value>,
+        token: Option<ProgressToken>,
+    ) -> Result<serde_json::Value> {
+        let target = args
+            .and_then(|args| args.get("target"))
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or("code");
+        let token = self
+            .begin_progress(token, format!("Generating {target}"))
+            .await;
+        let result = self.run_generator(args, token.as_ref()).await;
+        if let Some(token) = &token {
+            let message = match &result {
+                Ok(_) => None,
+                Err(err) => Some(err.message.to_string()),
+            };
+            self.progress(
+                token,
+                WorkDoneProgress::End(WorkDoneProgressEnd { message }),
+            )
+            .await;
+        }
+        result
+    }
+
+    async fn run_generator(
+        &self,
+        args: Option<&serde_json::Value>,
+        token: Option<&ProgressToken>,
+    ) -> Result<serde_json::Value> {
+        let arg = |key| args.and_then(|args| args.get(key));
+        let str_arg = |key| arg(key).and_then(serde_json::Value::as_str);
+        let (Some(uri), Some(target)) = (str_arg("uri"), str_arg("target")) else {
+            return Err(Error::invalid_params("expected `uri` and `target`"));
+        };
+        let Some((uri, path)) = Url::parse(uri)
+            .ok()
+            .and_then(|uri| Some((uri.clone(), uri.to_file_path().ok()?)))
+        else {
+            return Err(Error::invalid_params("`uri` is not a file URI"));
+        };
+        // An indexed file is generated with what it can see: the files it includes with
+        // `includeAware`, or else the whole workspace. The standard library isn't generated.
+        let parsed = self.parsed_documents().await;
+        // Syntax errors the editor works around still stop the generator
+        if let Some(file) = parsed.file(&uri)
+            && !file.skipped.is_empty()
+            && let Err(err) = codegen::SourceFile::parse(path.clone(), file.text.clone())
+        {
+            return Err(make_error(0, err));
+        }
+        let mut files = vec![];
+        if parsed.file(&uri).is_some() {
+            let graph = includes::IncludeGraph::new(parsed.files());
+            let seen = if *self.include_aware.lock().await {
+                Some(graph.reachable(&path))
+            } else {
+                None
+            };
+            files.extend(
+                parsed
+                    .files()
+                    .iter()
+                    .filter(|file| {
+                        seen.as_ref()
+                            .is_none_or(|seen| seen.contains(file.path.as_path()))
+                            && !stdlib::is_library(&file.path)
+                    })
+                    .cloned(),
+            );
+        } else {
+            for (_, file) in
+                codegen::parse_files(std::slice::from_ref(&path), &ScanOptions::default())
+            {
+                files.push(file.map_err(|err| make_error(0, err))?);
+            }
+        }
+        if files.is_empty() {
+            return Err(make_error(0, "Could not parse file"));
+        }
+        let schema = codegen::Schema::new(&files);
+        let opts = codegen::Options {
+            codec: arg("codec").and_then(serde_json::Value::as_bool) == Some(true),
+            root: str_arg("typeName").map(str::to_string),
+            format: str_arg("format").map(str::to_string),
+            ..Default::default()
+        };
+        let show = arg("show").and_then(serde_json::Value::as_bool) == Some(true);
+        let stem = path
+            .file_stem()
+            .map_or("xdr".into(), |stem| stem.to_string_lossy());
+        let dir = if show {
+            std::env::temp_dir().join("xdr-ls")
+        } else {
+            path.parent().map(PathBuf::from).unwrap_or_default()
+        };
+        // The output file, or directory for docs
+        let out = str_arg("out").filter(|_| !show).map(|out| dir.join(out));
+
+        let outputs: Vec<(PathBuf, String)> = if target == "docs" {
+            let dir = out.unwrap_or_else(|| {
+                dir.join(if show {
+                    format!("{stem}-docs")
+                } else {
+                    "xdr-docs".to_string()
+                })
+            });
+            codegen::generate_docs(&schema, &opts)
+                .into_iter()
+                .map(|(name, page)| (dir.join(name), page))
+                .collect()
+        } else {
+            let Some(ext) = codegen::extension(target, opts.format.as_deref()) else {
+                return Err(Error::invalid_params(format!("unknown target {target}")));
+            };
+            let out = out.unwrap_or_else(|| dir.join(format!("{stem}.{ext}")));
+            let opts = codegen::Options {
+                name: out
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned()),
+                ..opts
+            };
+            let output =
+                codegen::generate(target, &schema, &opts).map_err(|err| make_error(0, err))?;
+            vec![(out, output)]
+        };
+        if let Some(token) = token {
+            self.progress(
+                token,
+                WorkDoneProgress::Report(WorkDoneProgressReport {
+                    message: Some(match outputs.len() {
+                        1 => "Writing 1 file".to_string(),
+                        n => format!("Writing {n} files"),
+                    }),
+                    percentage: Some(50),
+                    ..Default::default()
+                }),
+            )
+            .await;
+        }
+        let mut uris = vec![];
+        for (path, _) in &outputs {
+            match Url::from_file_path(path) {
+                Ok(uri) => uris.push(uri),
+                Err(_) => return Err(make_error(0, "Could not build an output URI")),
+            }
+        }
+
+        if show {
+            for (path, text) in &outputs {
+                let written = path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(path, text));
+                if let Err(err) = written {
+                    return Err(make_error(0, format!("{}: {}", path.display(), err)));
+                }
+            }
+            // For docs, open the index
+            let shown = outputs
+                .iter()
+                .position(|(path, _)| path.file_stem().is_some_and(|stem| stem == "index"))
+                .unwrap_or(0);
+            self.client
+                .show_document(ShowDocumentParams {
+                    uri: uris[shown].clone(),
+                    external: None,
+                    take_focus: Some(true),
+                    selection: None,
+                })
+                .await?;
+        } else {
+            let mut changes = vec![];
+            for ((_, text), uri) in outputs.iter().zip(&uris) {
+                changes.push(DocumentChangeOperation::Op(ResourceOp::Create(
+                    CreateFile {
+                        uri: uri.clone(),
+                        options: Some(CreateFileOptions {
+                            overwrite: Some(true),
+                            ignore_if_exists: None,
+                        }),
+                        annotation_id: None,
+                    },
+                )));
+                changes.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: uri.clone(),
+                        version: None,
+                    },
+                    edits: vec![OneOf::Left(TextEdit {
+                        range: Range::default(),
+                        new_text: text.clone(),
+                    })],
+                }));
+            }
+            let edit = WorkspaceEdit {
+                document_changes: Some(DocumentChanges::Operations(changes)),
+                ..Default::default()
+            };
+            let response = self.client.apply_edit(edit).await?;
+            if !response.applied {
+                let reason = response
+                    .failure_reason
+                    .unwrap_or("the client rejected the edit".to_string());
+                return Err(make_error(0, format!("Could not write output: {reason}")));
+            }
+        }
+        Ok(serde_json::json!({ "files": uris }))
+    }
+
+    // The syntax trees of every indexed document that parses, reparsing only the documents
+    // changed since the last call. Documents with edits waiting to be indexed are indexed now.
+    async fn parsed_documents(&self) -> Arc<Parsed> {
+        let pending: Vec<Url> = self.pending.lock().await.iter().cloned().collect();
+        for uri in &pending {
+            self.flush_pending(uri).await;
+        }
+        let mut trees = self.trees.lock().await;
+        let stale = std::mem::take(&mut *self.stale.lock().await);
+        if stale.is_empty()
+            && let Some(parsed) = trees.kept.clone().or_else(|| trees.last.upgrade())
+        {
+            return parsed;
+        }
+        let (kinds, mut uris) = {
+            let index = self.index.read().await;
+            let kinds: HashMap<String, SymbolKind> = index
+                .values()
+                .flat_map(|index| &index.symbols)
+                .map(|symbol| (symbol.name.clone(), symbol.kind))
+                .collect();
+            (kinds, index.keys().cloned().collect::<Vec<_>>())
+        };
+        // The standard library comes first, so later definitions replace its own in a `Schema`
+        uris.sort_by_key(|uri| {
+            !uri.to_file_path()
+                .is_ok_and(|path| stdlib::is_library(&path))
+        });
+        let open: HashSet<Url> = self.documents.lock().await.keys().cloned().collect();
+        let mut unchanged = trees.take();
+        unchanged.retain(|uri, _| !stale.contains(uri));
+        let mut docs = vec![];
+        let mut files = vec![];
+        for uri in uris {
+            let file = match unchanged.remove(&uri) {
+                Some(file) => file,
+                None => {
+                    let Some(text) = self.text_of(&uri).await else {
+                        continue;
+                    };
+                    let path = uri.to_file_path().unwrap_or_default();
+                    let Some(file) = codegen::SourceFile::parse_recovering(path, text) else {
+                        continue;
+                    };
+                    if !open.contains(&uri) {
+                        trees.parsed(&uri);
+                    }
+                    file
+                }
+            };
+            docs.push(uri);
+            files.push(file);
+        }
+        let parsed = Arc::new(Parsed::new(docs, files, kinds));
+        trees.keep(&parsed, &open, *self.max_parsed_files.lock().await);
+        parsed
+    }
+
+    // Completions of the path component being typed in `%#include "` on `line`, whose path starts
+    // at byte `start`. Paths are relative to the file's directory, the workspace root, or one of
+    // the `includePaths`.
+    async fn include_path_completions(
+        &self,
+        uri: &Url,
+        root: &Path,
+        line: &str,
+        start: usize,
+        cursor: Position,
+    ) -> Vec<CompletionItem> {
+        let path = uri.to_file_path().ok();
+        // A file can't include itself
+        let files: Vec<PathBuf> = self
+            .index
+            .read()
+            .await
+            .keys()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .filter(|file| Some(file) != path.as_ref())
+            .collect();
+        let mut bases = vec![];
+        if let Some(dir) = path.as_ref().and_then(|path| path.parent()) {
+            bases.push(dir.to_path_buf());
+        }
+        bases.push(root.to_path_buf());
+        bases.extend(self.include_paths.lock().await.iter().cloned());
+        let typed = &line[start..];
+        let component = start + typed.rfind('/').map_or(0, |i| i + 1);
+        let encoding = *self.encoding.lock().await;
+        let range = Range::new(
+            Position::new(cursor.line, position::column(&line[..component], encoding)),
+            cursor,
+        );
+        includes::path_completions(typed, &bases, &files)
+            .into_iter()
+            .map(|completion| CompletionItem {
+                label: completion.label.clone(),
+                kind: Some(if completion.file.is_some() {
+                    CompletionItemKind::FILE
+                } else {
+                    CompletionItemKind::FOLDER
+                }),
+                detail: completion.file.map(|file| {
+                    file.strip_prefix(root)
+                        .unwrap_or(&file)
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: completion.label.clone(),
+                })),
+                // Keep completing into the directory
+                command: completion.label.ends_with('/').then(|| Command {
+                    title: String::new(),
+                    command: "editor.action.triggerSuggest".to_string(),
+                    arguments: None,
+                }),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    // Custom `xdr/dumpAst` request: the syntax tree of `{ textDocument: { uri } }` as JSON. Names
+    // carry their byte range in the document's text as `start` and `end`.
+    async fn dump_ast(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let Some(uri) = params
+            .pointer("/textDocument/uri")
+            .and_then(serde_json::Value::as_str)
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return Err(Error::invalid_params("expected a `textDocument.uri`"));
+        };
+        let Some(text) = self.text_of(&uri).await else {
+            return Err(Error::invalid_params(format!("unknown document {uri}")));
+        };
+        let path = uri.to_file_path().unwrap_or_default();
+        let file = codegen::SourceFile::parse(path, text).map_err(|err| make_error(1, err))?;
+        serde_json::to_value(&file.spec).map_err(|err| make_error(1, err.to_string()))
+    }
+
+    // Custom `xdr/fieldPath` request: resolves `{ path: "T.field.subfield" }` to the `{ uri, range,
+    // type, resolvedType }` of the last field, where `type` is as declared and `resolvedType`
+    // has typedefs followed
+    async fn field_path(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let Some(path) = params.get("path").and_then(serde_json::Value::as_str) else {
+            return Err(Error::invalid_params("expected a `path` string"));
+        };
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let schema = parsed.schema();
+        let field = fields::resolve(schema, path).map_err(Error::invalid_params)?;
+        let encoding = *self.encoding.lock().await;
+        let index = files
+            .iter()
+            .position(|file| std::ptr::eq(file, field.file))
+            .unwrap_or_default();
+        Ok(serde_json::json!({
+            "uri": docs[index],
+            "range": Range {
+                start: field.file.position_at(field.id.start, encoding),
+                end: field.file.position_at(field.id.end, encoding),
+            },
+            "type": field.ty,
+            "resolvedType": field.resolved,
+        }))
+    }
+
+    // Custom `xdr/unionsOn` request: given `{ enum: "E" }`, every union switching on `E` as
+    // `{ name, field, uri, range, unhandled, hasDefault }`. `name` is the definition the union is
+    // part of, `field` the field it's the type of when it's nested (otherwise null), `range` runs
+    // from `switch` to the closing brace, and `unhandled` lists the members no case names.
+    async fn unions_on(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let Some(name) = params.get("enum").and_then(serde_json::Value::as_str) else {
+            return Err(Error::invalid_params("expected an `enum` string"));
+        };
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let schema = parsed.schema();
+        if !matches!(schema.types.get(name), Some(Definition::Enum { .. })) {
+            return Err(Error::invalid_params(format!("`{name}` isn't an enum")));
+        }
+        let encoding = *self.encoding.lock().await;
+        let mut result = vec![];
+        for union in schema.unions_on(name) {
+            let index = files
+                .iter()
+                .position(|file| std::ptr::eq(file, union.file))
+                .unwrap_or_default();
+            result.push(serde_json::json!({
+                "name": union.defn.id,
+                "field": union.field.map(|field| &field.id),
+                "uri": docs[index],
+                "range": Range {
+                    start: union.file.position_at(union.body.start, encoding),
+                    end: union.file.position_at(union.body.end, encoding),
+                },
+                "unhandled": schema.unhandled_members(union.body),
+                "hasDefault": union.body.default.is_some(),
+            }));
+        }
+        Ok(serde_json::Value::Array(result))
+    }
+
+    // Custom `xdr/expandType` request: given `{ type }`, the type's definition fully expanded as
+    // `{ text }`
+    async fn expand_type(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let Some(name) = params.get("type").and_then(serde_json::Value::as_str) else {
+            return Err(Error::invalid_params("expected a `type` string"));
+        };
+        let parsed = self.parsed_documents().await;
+        let schema = parsed.schema();
+        match expand::expand(schema, name) {
+            Some(text) => Ok(serde_json::json!({ "text": text })),
+            None => Err(Error::invalid_params(format!("unknown type `{name}`"))),
+        }
+    }
+
+    // Custom `xdr/listTypes` request: every definition in the index as `{ name, kind, uri, range,
+    // wireSize, doc }`. `wireSize` is `{ min, max }` (`max` is null when unbounded) and `doc` is
+    // the first paragraph of the definition's comment.
+    async fn list_types(&self) -> Result<serde_json::Value> {
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let schema = parsed.schema();
+        let encoding = *self.encoding.lock().await;
+        let mut result = vec![];
+        for (uri, file) in docs.iter().zip(files) {
+            for (defn, annotations) in file.spec.defns.iter().zip(file.annotations()) {
+                let (Some(id), Some(start)) = (defn.id(), file.definition_start(defn)) else {
+                    continue;
+                };
+                let end = file.definition_end(defn).unwrap_or(id.end);
+                let size = schema
+                    .definition_size(defn)
+                    .map(|size| serde_json::json!({ "min": size.min(), "max": size.max() }));
+                let doc = file.doc_comment(defn).map(|doc| {
+                    doc.split("\n\n")
+                        .next()
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                });
+                result.push(serde_json::json!({
+                    "name": id.id,
+                    "kind": defn.keyword(),
+                    "uri": uri,
+                    "range": Range {
+                        start: file.position_at(start, encoding),
+                        end: file.position_at(end, encoding),
+                    },
+                    "wireSize": size,
+                    "doc": doc,
+                    "annotations": annotations
+                        .into_iter()
+                        .map(|(name, args)| serde_json::json!({ "name": name, "args": args }))
+                        .collect::<Vec<_>>(),
+                }));
+            }
+        }
+        Ok(serde_json::Value::Array(result))
+    }
+
+    // Custom `xdr/decode` request: decodes `data` (hex or base64) as an instance of `type` and
+    // returns the field tree with the byte range of each field
+    async fn decode(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let param = |key| params.get(key).and_then(serde_json::Value::as_str);
+        let (Some(ty), Some(data)) = (param("type"), param("data")) else {
+            return Err(Error::invalid_params("expected `type` and `data` strings"));
+        };
+        let Some(bytes) = codec::from_blob(data) else {
+            return Err(Error::invalid_params("`data` is neither hex nor base64"));
+        };
+        // Types are resolved in what's indexed, unsaved edits included
+        let parsed = self.parsed_documents().await;
+        let schema = parsed.schema();
+        if !schema.types.contains_key(ty) {
+            return Err(Error::invalid_params(format!("unknown type `{ty}`")));
+        }
+        codec::decode(schema, ty, &bytes)
+            .map(|decoded| decoded.to_json())
+            .map_err(|err| Error {
+                code: tower_lsp::jsonrpc::ErrorCode::ServerError(1),
+                message: err.to_string().into(),
+                data: Some(serde_json::json!({
+                    "path": err.path,
+                    "offset": err.offset,
+                    "message": err.message,
+                })),
+            })
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let encoding = position::negotiate(&params.capabilities);
+        *self.encoding.lock().await = encoding;
+        // Clients with workspace folders send them all; older ones only send the root. With
+        // neither (a lone file opened outside any project), documents are indexed as they open.
+        let folders: Vec<Url> = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) if !folders.is_empty() => {
+                folders.into_iter().map(|folder| folder.uri).collect()
+            }
+            (_, Some(uri)) => vec![uri],
+            _ => vec![],
+        };
+        let mut dirs: Vec<PathBuf> = vec![];
+        for folder in folders {
+            let Ok(dir) = folder.to_file_path() else {
+                return Err(make_error(
+                    0,
+                    format!("{folder} doesn't seem to be a valid filepath"),
+                ));
+            };
+            if !dir.is_dir() {
+                return Err(make_error(0, format!("{folder} doesn't name a directory")));
+            }
+            dirs.push(dir);
+        }
+        // Settings with relative paths are relative to the first folder
+        let uri = dirs.first().cloned().unwrap_or_default();
+        *self.root.lock().await = dirs.first().cloned();
+        *self.work_done_progress.lock().await = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        *self.snippets.lock().await = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|item| item.snippet_support)
+            .unwrap_or(false);
+        *self.change_annotations.lock().await = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .is_some_and(|edit| edit.change_annotation_support.is_some());
+        *self.code_lens_refresh.lock().await = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.code_lens.as_ref())
+            .and_then(|code_lens| code_lens.refresh_support)
+            .unwrap_or(false);
+        *self.inlay_hint_refresh.lock().await = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            .unwrap_or(false);
+        *self.watch_files.lock().await = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched| watched.dynamic_registration)
+            .unwrap_or(false);
+        *self.type_hierarchy.lock().await = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.type_hierarchy.as_ref())
+            .and_then(|hierarchy| hierarchy.dynamic_registration)
+            .unwrap_or(false);
+        self.apply_settings(params.initialization_options.as_ref())
+            .await;
+        if let Some(root) = dirs.first()
+            && params
+                .initialization_options
+                .as_ref()
+                .and_then(|options| options.get("indexCache"))
+                .and_then(serde_json::Value::as_bool)
+                == Some(true)
+        {
+            *self.index_cache.lock().await = Some(Arc::new(IndexCache::load(root, encoding)));
+        }
+        let mut paths: Vec<PathBuf> = vec![];
+        let scan = self.scan.lock().await.clone();
+        for dir in &dirs {
+            let warnings = get_xdr_files(dir, &scan, &mut |path| paths.push(path.to_path_buf()));
+            for warning in warnings {
+                self.client
+                    .show_message(MessageType::WARNING, warning)
+                    .await;
+            }
+        }
+        // A folder nested in another would be scanned twice
+        paths.sort();
+        paths.dedup();
+        if params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("standardLibrary"))
+            .and_then(serde_json::Value::as_bool)
+            == Some(true)
+        {
+            // Indexed first, so the workspace's definitions replace its own
+            match stdlib::write() {
+                Ok(path) => paths.insert(0, path),
+                Err(err) => {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("Could not write the standard library: {err}"),
+                        )
+                        .await
+                }
+            }
+        }
+        if let Some(entries) = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("entryFiles"))
+            .and_then(serde_json::Value::as_array)
+        {
+            // Index the entries and what they include (and the standard library) now, and the
+            // rest once the client has the response and their diagnostics
+            let mut roots = HashSet::new();
+            for entry in entries.iter().filter_map(serde_json::Value::as_str) {
+                let entry = uri.join(entry);
+                match paths.iter().find(|path| **path == entry) {
+                    Some(path) => roots.extend(includes::reachable(path, &paths)),
+                    None => {
+                        self.client
+                            .show_message(
+                                MessageType::WARNING,
+                                format!("Entry file `{}` isn't in the workspace", entry.display()),
+                            )
+                            .await
+                    }
+                }
+            }
+            let (first, rest) = paths
+                .into_iter()
+                .partition(|path| roots.contains(path) || stdlib::is_library(path));
+            paths = first;
+            *self.entry_files.lock().await = roots;
+            *self.deferred_paths.lock().await = rest;
+        }
+        // Notifications can't be sent until the client has the response
+        *self.initial_metrics.lock().await = self.index_files(&paths).await;
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                position_encoding: Some(encoding.kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::FULL),
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..Default::default()
+                    },
+                )),
+                definition_provider: Some(OneOf::Left(true)),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec!["\"".to_string(), "/".to_string()]),
+                    ..Default::default()
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: semantic::legend(),
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            range: Some(true),
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(true),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::SOURCE_FIX_ALL,
+                            actions::ADD_DOC_COMMENT,
+                        ]),
+                        ..Default::default()
+                    },
+                )),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        CHECK_BREAKING.to_string(),
+                        GENERATE_CODE.to_string(),
+                        GENERATE_RUST.to_string(),
+                        GENERATE_C_HEADER.to_string(),
+                        FIX_ALL.to_string(),
+                        LIST_UNREACHABLE.to_string(),
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client
+            .log_message(MessageType::INFO, "server initialized!")
+            .await;
+        let glob = self.scan.lock().await.glob();
+        // Files changed outside the editor (a `git pull`, a code generator) are reindexed
+        if *self.watch_files.lock().await {
+            let options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: vec![FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(glob.clone()),
+                    kind: None,
+                }],
+            };
+            let registration = Registration {
+                id: "xdr-watched-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            };
+            if let Err(err) = self.client.register_capability(vec![registration]).await {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("Could not watch .x files: {err}"),
+                    )
+                    .await;
+            }
+        }
+        if *self.type_hierarchy.lock().await {
+            let options = TypeHierarchyRegistrationOptions {
+                text_document_registration_options: TextDocumentRegistrationOptions {
+                    document_selector: Some(vec![DocumentFilter {
+                        language: None,
+                        scheme: None,
+                        pattern: Some(glob),
+                    }]),
+                },
+                ..Default::default()
+            };
+            let registration = Registration {
+                id: "xdr-type-hierarchy".to_string(),
+                method: "textDocument/prepareTypeHierarchy".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            };
+            if let Err(err) = self.client.register_capability(vec![registration]).await {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("Could not offer the type hierarchy: {err}"),
+                    )
+                    .await;
+            }
+        }
+        let metrics = std::mem::take(&mut *self.initial_metrics.lock().await);
+        self.report_metrics(metrics).await;
+        let deferred = std::mem::take(&mut *self.deferred_paths.lock().await);
+        if !deferred.is_empty() {
+            if !self.lint_rules.lock().await.is_empty() {
+                self.publish_entry_diagnostics().await;
+            }
+            let metrics = self.index_files(&deferred).await;
+            self.report_metrics(metrics).await;
+        }
+        // Shared now rather than at shutdown, for other servers starting on the workspace
+        let cache = self.index_cache.lock().await.clone();
+        if let Some(cache) = cache {
+            self.save_index_cache(cache).await;
+        }
+        self.after_reindex().await;
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let doc = params.text_document;
+        self.documents.lock().await.insert(
+            doc.uri.clone(),
+            Document {
+                version: doc.version,
+                text: doc.text.clone(),
+            },
+        );
+        let metrics = self.index_document(&doc.uri, &doc.text).await;
+        self.report_metrics(vec![metrics]).await;
+        self.after_reindex().await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // Full sync: the last change holds the whole document
+        let Some(change) = params.content_changes.into_iter().next_back() else {
+            return;
+        };
+        let uri = params.text_document.uri;
+        let version = params.text_document.version;
+        {
+            let mut documents = self.documents.lock().await;
+            // Changes can be handled out of order; an older version never replaces a newer one
+            if documents.get(&uri).is_some_and(|doc| doc.version > version) {
+                return;
+            }
+            documents.insert(
+                uri.clone(),
+                Document {
+                    version,
+                    text: change.text,
+                },
+            );
+        }
+        self.pending.lock().await.insert(uri.clone());
+        self.stale.lock().await.insert(uri.clone());
+        let edit = self.edits.fetch_add(1, Ordering::Relaxed) + 1;
+        // Returns at once, so the notifications and requests behind this one aren't held up
+        let backend = self.clone();
+        tokio::spawn(async move { backend.reindex_after_pause(uri, version, edit).await });
+    }
+
+    // Reindexes a saved document from the text the client saved, if it sends it, so the index
+    // matches the file on disk even if a change notification went missing
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let text = match params.text {
+            Some(text) => Some(text),
+            None => self.text_of(&uri).await,
+        };
+        let Some(text) = text else {
+            return;
+        };
+        if let Some(doc) = self.documents.lock().await.get_mut(&uri) {
+            doc.text = text.clone();
+        }
+        let metrics = self.index_document(&uri, &text).await;
+        self.report_metrics(vec![metrics]).await;
+        self.after_reindex().await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.lock().await.remove(&uri);
+        self.pending.lock().await.remove(&uri);
+        // Go back to the file on disk, if there is one, unless there's no workspace for it to
+        // be part of
+        let text = match *self.root.lock().await {
+            Some(_) => uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| fs::read_to_string(path).ok()),
+            None => None,
+        };
+        match text {
+            Some(text) => {
+                let metrics = self.index_document(&uri, &text).await;
+                self.report_metrics(vec![metrics]).await;
+            }
+            None => {
+                self.forget_document(&uri).await;
+                self.client.publish_diagnostics(uri, vec![], None).await;
+            }
+        }
+        self.after_reindex().await;
+    }
+
+    // Files open in the editor are left alone: the editor's copy is what's indexed
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        // Created and changed files, read through the index cache
+        let mut paths = vec![];
+        for change in params.changes {
+            if self.documents.lock().await.contains_key(&change.uri) {
+                continue;
+            }
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            if !self.scan.lock().await.is_schema(&path) {
+                continue;
+            }
+            if change.typ == FileChangeType::DELETED {
+                self.forget_document(&change.uri).await;
+                self.client
+                    .publish_diagnostics(change.uri, vec![], None)
+                    .await;
+            } else {
+                paths.push(path);
+            }
+        }
+        let metrics = self.index_files(&paths).await;
+        self.report_metrics(metrics).await;
+        self.after_reindex().await;
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for folder in &params.event.removed {
+            if let Ok(dir) = folder.uri.to_file_path() {
+                self.purge_folder(&dir).await;
+            }
+        }
+        let scan = self.scan.lock().await.clone();
+        for folder in &params.event.added {
+            let Ok(dir) = folder.uri.to_file_path() else {
+                continue;
+            };
+            // The first folder opened after starting without one becomes the root
+            self.root.lock().await.get_or_insert_with(|| dir.clone());
+            let mut paths: Vec<PathBuf> = vec![];
+            let warnings = get_xdr_files(&dir, &scan, &mut |path| paths.push(path.to_path_buf()));
+            for warning in warnings {
+                self.client
+                    .show_message(MessageType::WARNING, warning)
+                    .await;
+            }
+            // A folder nested in one that's already open would be indexed twice
+            let known = self.index.read().await;
+            paths.retain(|path| {
+                Url::from_file_path(path).is_ok_and(|uri| !known.contains_key(&uri))
+            });
+            drop(known);
+            let metrics = self.index_files(&paths).await;
+            self.report_metrics(metrics).await;
+        }
+        self.after_reindex().await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients send the settings themselves, under the `xdr` section or not, or nothing and
+        // leave the server to ask for them
+        let settings = match params.settings {
+            serde_json::Value::Null => self
+                .client
+                .configuration(vec![ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("xdr".to_string()),
+                }])
+                .await
+                .ok()
+                .and_then(|mut values| values.pop())
+                .unwrap_or_default(),
+            settings => settings,
+        };
+        let settings = match settings.get("xdr") {
+            Some(section) if section.is_object() => section.clone(),
+            _ => settings,
+        };
+        let scan = self.scan.lock().await.clone();
+        self.apply_settings(Some(&settings)).await;
+        if *self.scan.lock().await != scan {
+            self.rescan().await;
+        }
+        self.after_reindex().await;
+        if *self.inlay_hint_refresh.lock().await {
+            // Failing to refresh only leaves stale hints
+            let _ = self.client.inlay_hint_refresh().await;
+        }
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if let Ok(path) = uri.to_file_path()
+            && let Some(ext) = path.extension()
+            && ext == "h"
+        {
+            if !self.header_files.lock().await.contains(&path) {
+                return Ok(None);
+            }
+            if let Ok(file) = fs::read_to_string(path) {
+                let pos = params.text_document_position_params.position;
+                let encoding = *self.encoding.lock().await;
+                if let Some(line) = file.lines().nth(pos.line as usize) {
+                    let character =
+                        position::offset_at(line, Position::new(0, pos.character), encoding);
+                    let mut start = 0;
+                    let mut end = 0;
+                    let mut in_ident = false;
+                    let mut ident: Option<String> = None;
+                    for (i, c) in line.char_indices() {
+                        if c.is_ascii_alphabetic() {
+                            if !in_ident {
+                                in_ident = true;
+                                start = i;
+                            }
+                            end = i;
+                        } else if in_ident {
+                            if c.is_ascii_digit() {
+                                end = i
+                            } else {
+                                in_ident = false;
+                                if start <= character && character <= end {
+                                    ident = Some(line[start..=end].to_string());
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    if let Some(ident) = ident {
+                        return Ok(self.lookup_ident(&ident).await);
+                    }
+                }
+            }
+            return Ok(None);
+        }
+        match self
+            .get_ident_at(uri, params.text_document_position_params.position)
+            .await
+        {
+            None => Ok(None),
+            Some(ident) => Ok(self.lookup_ident(&ident).await),
+        }
+    }
+
+    // Goes to the type of what's at the cursor: the type a field, union arm, or typedef is
+    // declared with, a procedure's result type, or an enum member's enum. A type name goes to
+    // its own definition.
+    async fn goto_type_definition(
+        &self,
+        params: GotoTypeDefinitionParams,
+    ) -> Result<Option<GotoTypeDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        self.flush_pending(&position.text_document.uri).await;
+        let ty = {
+            let index = self.index.read().await;
+            let Some(doc) = index.get(&position.text_document.uri) else {
+                return Ok(None);
+            };
+            if let Some(token) = token_at(&doc.declared_types, position.position) {
+                token.val.clone()
+            } else {
+                let Some(token) = token_at(&doc.identifiers, position.position) else {
+                    return Ok(None);
+                };
+                let Some(symbol) = index
+                    .values()
+                    .flat_map(|index| &index.symbols)
+                    .find(|symbol| symbol.name == token.val)
+                else {
+                    return Ok(None);
+                };
+                match symbol.kind {
+                    SymbolKind::ENUM_MEMBER => match &symbol.container_name {
+                        Some(container) => container.clone(),
+                        None => return Ok(None),
+                    },
+                    SymbolKind::ENUM
+                    | SymbolKind::STRUCT
+                    | SymbolKind::CLASS
+                    | SymbolKind::TYPE_PARAMETER => token.val.clone(),
+                    _ => return Ok(None),
+                }
+            }
+        };
+        Ok(self.lookup_ident(&ty).await)
+    }
+
+    async fn prepare_type_hierarchy(
+        &self,
+        params: TypeHierarchyPrepareParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        let position = params.text_document_position_params;
+        self.flush_pending(&position.text_document.uri).await;
+        let name = {
+            let index = self.index.read().await;
+            let Some(doc) = index.get(&position.text_document.uri) else {
+                return Ok(None);
+            };
+            let Some(token) = token_at(&doc.identifiers, position.position) else {
+                return Ok(None);
+            };
+            unscoped(&token.val).to_string()
+        };
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let encoding = *self.encoding.lock().await;
+        Ok(hierarchy::prepare(files, docs, &name, encoding).map(|item| vec![item]))
+    }
+
+    async fn supertypes(
+        &self,
+        params: TypeHierarchySupertypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        let (name, builtin) = hierarchy::item_name(&params.item);
+        if builtin {
+            return Ok(Some(vec![]));
+        }
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let encoding = *self.encoding.lock().await;
+        Ok(Some(hierarchy::supertypes(files, docs, &name, encoding)))
+    }
+
+    async fn subtypes(
+        &self,
+        params: TypeHierarchySubtypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        let (name, builtin) = hierarchy::item_name(&params.item);
+        let parsed = self.parsed_documents().await;
+        let (docs, files) = (&parsed.docs, parsed.files());
+        let encoding = *self.encoding.lock().await;
+        Ok(Some(hierarchy::subtypes(
+            files, docs, &name, builtin, encoding,
+        )))
+    }
+
+    // Every occurrence in the document of the name at the cursor, its definition as a write
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let position = params.text_document_position_params;
+        self.flush_pending(&position.text_document.uri).await;
+        let index = self.index.read().await;
+        let Some(doc) = index.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let Some(name) = token_at(&doc.identifiers, position.position).map(|token| &token.val)
+        else {
+            return Ok(None);
+        };
+        let defn = doc.defns.get(name).map(|loc| loc.range);
+        let mut highlights: Vec<DocumentHighlight> = doc
+            .identifiers
+            .iter()
+            .flat_map(|(&line, tokens)| {
+                tokens
+                    .iter()
+                    .filter(|token| &token.val == name)
+                    .map(move |token| {
+                        Range::new(
+                            Position::new(line, token.start),
+                            Position::new(line, token.end),
+                        )
+                    })
+            })
+            .map(|range| DocumentHighlight {
+                range,
+                kind: Some(if Some(range) == defn {
+                    DocumentHighlightKind::WRITE
+                } else {
+                    DocumentHighlightKind::READ
+                }),
+            })
+            .collect();
+        highlights.sort_by_key(|highlight| highlight.range.start);
+        Ok(Some(highlights))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let parsed = self.parsed_documents().await;
+        Ok(hover_at(
+            &parsed.docs,
+            parsed.schema(),
+            &position.text_document.uri,
+            position.position,
+            *self.hover_verbosity.lock().await,
+            *self.encoding.lock().await,
+        ))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let in_comments = *self.references_in_comments.lock().await;
+        let with_members = *self.references_include_members.lock().await;
+        // Plain locations can't be flagged, so mentions come after the references
+        Ok(self
+            .reference_locations(&params, in_comments, with_members)
+            .await
+            .map(|(mut locs, mentions)| {
+                locs.extend(mentions);
+                locs
+            }))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let pending: Vec<Url> = self.pending.lock().await.iter().cloned().collect();
+        for uri in pending {
+            self.flush_pending(&uri).await;
+        }
+        let found = {
+            let index = self.index.read().await;
+            let shards: Vec<&Vec<SymbolInformation>> = index
+                .values()
+                .flat_map(|index| [&index.symbols, &index.fields])
+                .collect();
+            symbols::search(&shards, &params.query)
+        };
+        // With a partial result token, the results go out a page at a time as `$/progress`
+        let Some(token) = params.partial_result_params.partial_result_token else {
+            return Ok(Some(found));
+        };
+        for page in found.chunks(SYMBOL_PAGE_SIZE) {
+            self.client
+                .send_notification::<PartialResult>(serde_json::json!({
+                    "token": token,
+                    "value": page,
+                }))
+                .await;
+        }
+        Ok(Some(vec![]))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let uri = &params.text_document_position.text_document.uri;
+        self.flush_pending(uri).await;
+        let root = self.root.lock().await.clone().unwrap_or_default();
+        let text = self.text_of(uri).await.unwrap_or_default();
+        let cursor = params.text_document_position.position;
+        let encoding = *self.encoding.lock().await;
+        let line_start = position::offset_at(&text, Position::new(cursor.line, 0), encoding);
+        let line = &text[line_start..position::offset_at(&text, cursor, encoding)];
+        if let Some(start) = includes::typed_include_start(line) {
+            return Ok(Some(CompletionResponse::Array(
+                self.include_path_completions(uri, &root, line, start, cursor)
+                    .await,
+            )));
+        }
+        // The trigger characters are only for include paths
+        if params
+            .context
+            .is_some_and(|context| context.trigger_kind == CompletionTriggerKind::TRIGGER_CHARACTER)
+        {
+            return Ok(None);
+        }
+        let workspace = self.parsed_documents().await;
+        let parsed = workspace.files();
+        let offset = position::offset_at(&text, cursor, encoding);
+        if let Some(items) = scaffold::case_members(workspace.schema(), &text, offset) {
+            return Ok(Some(CompletionResponse::Array(items)));
+        }
+        let mut items = vec![];
+        if let Ok(path) = uri.to_file_path() {
+            let style = self.format_style.lock().await.clone();
+            let snippets = *self.snippets.lock().await;
+            items.extend(scaffold::union_arms(
+                parsed, &path, &text, offset, &style, snippets,
+            ));
+        }
+        let since: HashMap<&str, &str> = parsed
+            .iter()
+            .flat_map(since::annotated)
+            .filter(|item| item.global)
+            .map(|item| (item.id.id.as_str(), item.since))
+            .collect();
+        let schema = workspace.schema();
+        let docs = hover::documentation(parsed);
+        let index = self.index.read().await;
+        let files: Vec<PathBuf> = index
+            .keys()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+        // Only files can include others
+        let reachable = uri
+            .to_file_path()
+            .ok()
+            .map(|path| includes::reachable(&path, &files));
+        for (file, DocumentIndex { symbols, .. }) in index.iter() {
+            let library = file
+                .to_file_path()
+                .is_ok_and(|path| stdlib::is_library(&path));
+            // Definitions from files that aren't included yet come with the include
+            let include =
+                reachable
+                    .as_ref()
+                    .zip(file.to_file_path().ok())
+                    .and_then(|(reachable, file)| {
+                        // The standard library is never included
+                        (!reachable.contains(&file) && !library).then(|| {
+                            includes::include_edit(&text, &includes::include_path(&root, &file))
+                        })
+                    });
+            for symbol in symbols {
+                // Replaced by a definition in the workspace
+                if library
+                    && index
+                        .iter()
+                        .any(|(uri, index)| uri != file && index.defns.contains_key(&symbol.name))
+                {
+                    continue;
+                }
+                items.push(CompletionItem {
+                    label: symbol.name.clone(),
+                    kind: Some(completion_kind(symbol.kind)),
+                    detail: file
+                        .path_segments()
+                        .and_then(|mut segments| segments.next_back())
+                        .map(|name| match since.get(symbol.name.as_str()) {
+                            Some(version) => format!("{name}, since {version}"),
+                            None => name.to_string(),
+                        })
+                        .map(|detail| {
+                            // Constants and enum members with their values
+                            let value = matches!(
+                                symbol.kind,
+                                SymbolKind::CONSTANT | SymbolKind::ENUM_MEMBER
+                            )
+                            .then(|| schema.consts.get(symbol.name.as_str()))
+                            .flatten();
+                            match value {
+                                Some(&value) => {
+                                    format!("= {}, {detail}", hover::value_display(value))
+                                }
+                                None => detail,
+                            }
+                        }),
+                    documentation: docs.get(symbol.name.as_str()).map(|doc| {
+                        Documentation::MarkupContent(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: doc.clone(),
+                        })
+                    }),
+                    additional_text_edits: include.clone().map(|edit| vec![edit]),
+                    ..Default::default()
+                });
+            }
+        }
+        for (names, kind) in [
+            (BUILT_IN_TYPES, CompletionItemKind::TYPE_PARAMETER),
+            (KEYWORDS, CompletionItemKind::KEYWORD),
+        ] {
+            items.extend(names.iter().map(|name| CompletionItem {
+                label: name.to_string(),
+                kind: Some(kind),
+                ..Default::default()
+            }));
+        }
+        // Definitions start their line
+        let starts_definition = line
+            .trim_start()
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if starts_definition
+            && dialect::Dialect::of(*self.dialect.lock().await, &text) == dialect::Dialect::Rpcgen
+            && *self.snippets.lock().await
+        {
+            items.push(CompletionItem {
+                label: "program".to_string(),
+                kind: Some(CompletionItemKind::SNIPPET),
+                detail: Some("RPC program block".to_string()),
+                insert_text: Some(dialect::PROGRAM_SNIPPET.to_string()),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                ..Default::default()
+            });
+        }
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    // Only names with a definition outside the standard library can be renamed
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        self.flush_pending(&params.text_document.uri).await;
+        let index = self.index.read().await;
+        let Some(token) = index
+            .get(&params.text_document.uri)
+            .and_then(|index| token_at(&index.identifiers, params.position))
+        else {
+            return Ok(None);
+        };
+        let name = token.val.clone();
+        let line = params.position.line;
+        let range = Range::new(
+            Position::new(line, token.start),
+            Position::new(line, token.end),
+        );
+        drop(index);
+        let Some(defn) = self.definition_of(&name).await else {
+            return Err(Error::invalid_params(format!("`{name}` has no definition")));
+        };
+        if defn
+            .uri
+            .to_file_path()
+            .is_ok_and(|path| stdlib::is_library(&path))
+        {
+            return Err(Error::invalid_params(format!(
+                "`{name}` is defined by the standard library"
+            )));
+        }
+        Ok(Some(PrepareRenameResponse::Range(range)))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let in_comments = *self.rename_in_comments.lock().await;
+        self.rename_with(params, in_comments).await
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let Some(text) = self.text_of(&params.text_document.uri).await else {
+            return Ok(None);
+        };
+        let style = self.format_style.lock().await.clone();
+        let encoding = *self.encoding.lock().await;
+        Ok(format_edits(&text, &params.options, style, encoding))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let Some(text) = self.text_of(&params.text_document.uri).await else {
+            return Ok(None);
+        };
+        let style = self.format_style.lock().await.clone();
+        Ok(range_format_edits(
+            &text,
+            params.range,
+            &params.options,
+            style,
+            *self.encoding.lock().await,
+        ))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let Some(text) = self.text_of(&params.text_document.uri).await else {
+            return Ok(None);
+        };
+        Ok(Some(folding::folding_ranges(&text)))
+    }
+
+    // Links from `%#include` paths to the files they include, found next to the including file,
+    // in the workspace root, or in one of the `includePaths`
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        let (Some(text), Ok(path)) = (self.text_of(&uri).await, uri.to_file_path()) else {
+            return Ok(None);
+        };
+        let mut bases: Vec<PathBuf> = self.root.lock().await.iter().cloned().collect();
+        bases.extend(self.include_paths.lock().await.iter().cloned());
+        let files: Vec<PathBuf> = self
+            .index
+            .read()
+            .await
+            .keys()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+        let encoding = *self.encoding.lock().await;
+        Ok(Some(includes::document_links(
+            &text, &path, &bases, &files, encoding,
+        )))
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let uri = params.text_document.uri;
+        let Some(text) = self.text_of(&uri).await else {
+            return Ok(None);
+        };
+        let path = uri.to_file_path().unwrap_or_default();
+        let Some(file) = codegen::SourceFile::parse_recovering(path, text) else {
+            return Ok(None);
+        };
+        let encoding = *self.encoding.lock().await;
+        Ok(Some(
+            params
+                .positions
+                .into_iter()
+                .map(|pos| {
+                    let offset = file.offset_at(pos, encoding);
+                    selection::selection_range(&file, offset, encoding)
+                })
+                .collect(),
+        ))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let Some(text) = self.text_of(&uri).await else {
+            return Ok(None);
+        };
+        let path = uri.to_file_path().unwrap_or_default();
+        let Some(file) = codegen::SourceFile::parse_recovering(path, text) else {
+            return Ok(None);
+        };
+        let encoding = *self.encoding.lock().await;
+        Ok(Some(DocumentSymbolResponse::Nested(
+            symbols::document_symbols(&file, encoding),
+        )))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let _work = self.open_work();
+        let parsed = self.parsed_documents().await;
+        let Some(file) = parsed.file(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic::tokens(file, &parsed.kinds, None, *self.encoding.lock().await),
+        })))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let _work = self.open_work();
+        let parsed = self.parsed_documents().await;
+        let Some(file) = parsed.file(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic::tokens(
+                file,
+                &parsed.kinds,
+                Some(params.range),
+                *self.encoding.lock().await,
+            ),
+        })))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let parsed = self.parsed_documents().await;
+        let Some(file) = parsed.file(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let schema = parsed.schema();
+        let encoding = *self.encoding.lock().await;
+        let start = file.offset_at(params.range.start, encoding);
+        let end = file.offset_at(params.range.end, encoding);
+        let mut hints = hints::padding(schema, file, start, end, encoding);
+        hints.extend(hints::enum_values(schema, file, start, end, encoding));
+        hints.extend(hints::discriminants(schema, file, start, end, encoding));
+        let min_lines = *self.end_of_block_hint_lines.lock().await;
+        if min_lines > 0 {
+            hints.extend(hints::block_ends(file, min_lines, start, end, encoding));
+        }
+        if *self.wire_size_hints.lock().await {
+            hints.extend(hints::wire_sizes(schema, file, start, end, encoding));
+        }
+        Ok(Some(hints))
+    }
+
+    // Lenses start out as placeholders, one with the reference count and, for types, one with
+    // the wire size; both are filled in by `code_lens_resolve`. Programs also get lenses
+    // generating their RPC stubs.
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        self.flush_pending(&uri).await;
+        let index = self.index.read().await;
+        let Some(DocumentIndex { symbols, .. }) = index.get(&uri) else {
+            return Ok(None);
+        };
+        let mut lenses = vec![];
+        for symbol in symbols {
+            // Top-level definitions only
+            if symbol.kind == SymbolKind::ENUM_MEMBER {
+                continue;
+            }
+            let mut lens = |kind| {
+                lenses.push(CodeLens {
+                    range: symbol.location.range,
+                    command: None,
+                    data: Some(serde_json::json!({
+                        "uri": uri,
+                        "name": symbol.name,
+                        "lens": kind,
+                    })),
+                })
+            };
+            lens("references");
+            match symbol.kind {
+                SymbolKind::CONSTANT | SymbolKind::NAMESPACE | SymbolKind::FUNCTION => {}
+                // Programs run the stub generator directly
+                SymbolKind::MODULE => {
+                    for (title, format) in [("TypeScript", "typescript"), ("C", "c")] {
+                        lenses.push(CodeLens {
+                            range: symbol.location.range,
+                            command: Some(Command {
+                                title: format!("Generate {title} stubs"),
+                                command: GENERATE_CODE.to_string(),
+                                arguments: Some(vec![serde_json::json!({
+                                    "uri": uri,
+                                    "target": "rpc-stubs",
+                                    "format": format,
+                                    "show": true,
+                                })]),
+                            }),
+                            data: None,
+                        });
+                    }
+                }
+                _ => lens("size"),
+            }
+        }
+        Ok(Some(lenses))
+    }
+
+    async fn code_lens_resolve(&self, mut lens: CodeLens) -> Result<CodeLens> {
+        let data = lens.data.clone().unwrap_or_default();
+        let (Some(name), Some(kind)) = (
+            data.get("name").and_then(serde_json::Value::as_str),
+            data.get("lens").and_then(serde_json::Value::as_str),
+        ) else {
+            return Err(Error::invalid_params("not a code lens from this server"));
+        };
+        lens.command = Some(if kind == "size" {
+            let parsed = self.parsed_documents().await;
+            let schema = parsed.schema();
+            let title = match schema
+                .types
+                .get(name)
+                .and_then(|defn| schema.definition_size(defn))
+            {
+                Some(size) => size.to_string(),
+                None => "size unknown".to_string(),
+            };
+            // Display only, there's nothing to run
+            Command {
+                title,
+                command: String::new(),
+                arguments: None,
+            }
+        } else {
+            let mut refs = self.references_to(name).await;
+            refs.sort_by(|a, b| {
+                (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start))
+            });
+            let title = match refs.len() {
+                1 => "1 reference".to_string(),
+                n => format!("{n} references"),
+            };
+            // Clicking opens the references in the editor's peek view; the arguments are the
+            // conventional `(uri, position, locations)`
+            let uri = data.get("uri").cloned().unwrap_or_default();
+            Command {
+                title,
+                command: SHOW_REFERENCES.to_string(),
+                arguments: Some(vec![
+                    uri,
+                    serde_json::json!(lens.range.start),
+                    serde_json::json!(refs),
+                ]),
+            }
+        });
+        Ok(lens)
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        self.flush_pending(&uri).await;
+        let Some(text) = self.text_of(&uri).await else {
+            return Ok(None);
+        };
+        let path = uri.to_file_path().unwrap_or_default();
+        // Actions on the definitions that parse stay on offer while another has a syntax error
+        let Some(file) = codegen::SourceFile::parse_recovering(path, text) else {
+            return Ok(None);
+        };
+        // Only offer the kinds the client asked for, if it asked for specific ones
+        let wanted = |kind: &CodeActionKind| {
+            params.context.only.as_ref().is_none_or(|only| {
+                only.iter()
+                    .any(|prefix| kind.as_str().starts_with(prefix.as_str()))
+            })
+        };
+        let encoding = *self.encoding.lock().await;
+        let offset = file.offset_at(params.range.start, encoding);
+        let mut result = vec![];
+        if wanted(&CodeActionKind::QUICKFIX) {
+            result.extend(
+                includes::add_include(&file.text, &uri, &params.context.diagnostics)
+                    .into_iter()
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+            result.extend(
+                lint::add_suppression(&file, &uri, &params.context.diagnostics, encoding)
+                    .into_iter()
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+            let diagnostics = &params.context.diagnostics;
+            let parsed = self.parsed_documents().await;
+            result.extend(
+                actions::add_missing_arms(&file, &uri, diagnostics, parsed.schema(), encoding)
+                    .into_iter()
+                    .chain(actions::explicit_enum_values(
+                        &file,
+                        &uri,
+                        diagnostics,
+                        encoding,
+                    ))
+                    .chain(actions::remove_unused(&file, &uri, diagnostics, encoding))
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+            let bound = *self.default_bound.lock().await;
+            let index = self.index.read().await;
+            let is_defined =
+                |name: &str| index.values().any(|index| index.defns.contains_key(name));
+            result.extend(
+                actions::add_bound(&file, &uri, diagnostics, bound, &is_defined, encoding)
+                    .into_iter()
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+            // Members are scoped by their container and can't be named on their own
+            let mut names: Vec<&str> = index
+                .values()
+                .flat_map(|index| index.defns.keys())
+                .map(String::as_str)
+                .filter(|name| !name.contains("::"))
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            result.extend(
+                actions::define_undefined(
+                    &file,
+                    &uri,
+                    &params.context.diagnostics,
+                    bound,
+                    &names,
+                    encoding,
+                )
+                .into_iter()
+                .map(CodeActionOrCommand::CodeAction),
+            );
+        }
+        // Linting the workspace is too slow for every lightbulb, so only when asked for by kind
+        if params.context.only.is_some()
+            && wanted(&CodeActionKind::SOURCE_FIX_ALL)
+            && let Some((edit, fixes)) = self.fix_all_edit(&uri).await
+        {
+            result.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: if fixes == 1 {
+                    "Apply 1 safe fix".to_string()
+                } else {
+                    format!("Apply {fixes} safe fixes")
+                },
+                kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+                edit: Some(edit),
+                ..Default::default()
+            }));
+        }
+        if wanted(&actions::ADD_DOC_COMMENT) {
+            let style = self.comment_styles.lock().await.style();
+            result.extend(
+                actions::add_doc_comment(&file, &uri, offset, style, encoding)
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+        }
+        Ok(Some(result))
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            CHECK_BREAKING => {
+                let rev = params
+                    .arguments
+                    .first()
+                    .and_then(serde_json::Value::as_str)
+                    .unwrap_or("HEAD");
+                self.check_breaking(rev).await.map(Some)
+            }
+            GENERATE_CODE => self
+                .generate_code(
+                    params.arguments.first(),
+                    params.work_done_progress_params.work_done_token,
+                )
+                .await
+                .map(Some),
+            GENERATE_RUST | GENERATE_C_HEADER => {
+                let target = if params.command == GENERATE_RUST {
+                    "rust"
+                } else {
+                    "c-header"
+                };
+                let mut args = params
+                    .arguments
+                    .first()
+                    .cloned()
+                    .unwrap_or(serde_json::json!({}));
+                if let Some(args) = args.as_object_mut() {
+                    args.insert("target".to_string(), target.into());
+                }
+                self.generate_code(
+                    Some(&args),
+                    params.work_done_progress_params.work_done_token,
+                )
+                .await
+                .map(Some)
+            }
+            FIX_ALL => self.fix_all(params.arguments.first()).await.map(Some),
+            LIST_UNREACHABLE => self.list_unreachable().await.map(Some),
+            _ => Err(Error::invalid_params("unknown command")),
+        }
+    }
+
+    // Requests after this are refused by tower-lsp with `InvalidRequest`
+    async fn shutdown(&self) -> Result<()> {
+        self.shut_down.store(true, Ordering::Relaxed);
+        if let Some(cache) = self.index_cache.lock().await.take() {
+            self.save_index_cache(cache).await;
+        }
+        Ok(())
+    }
+}
+
+// The server as a service, with the custom requests, for a transport to serve. `shut_down` is set
+// once the client asks it to shut down. `extra_rules` are lint rules of the embedder's, run
+// alongside the built-in ones whatever the settings (`disabledRules` can still turn them off).
+pub fn service(
+    shut_down: Arc<AtomicBool>,
+    extra_rules: Vec<Box<dyn lint::LintRule>>,
+) -> (LspService<Backend>, ClientSocket) {
+    let mut extra = lint::Registry::default();
+    for rule in extra_rules {
+        extra.register(rule);
+    }
+    LspService::build(|client| Backend::new(client, shut_down, extra))
+        .custom_method("xdr/decode", Backend::decode)
+        .custom_method("xdr/listTypes", Backend::list_types)
+        .custom_method("xdr/dumpAst", Backend::dump_ast)
+        .custom_method("xdr/fieldPath", Backend::field_path)
+        .custom_method("xdr/unionsOn", Backend::unions_on)
+        .custom_method("xdr/expandType", Backend::expand_type)
+        .custom_method("xdr/rename", Backend::rename_custom)
+        .custom_method("xdr/references", Backend::references_custom)
+        .finish()
+}
@@ -0,0 +1,171 @@
+// `@since` annotations
+//
+// A protocol that grows by minor versions can note in which one each definition or member
+// appeared, with `@since 1.2` in a comment. A definition's annotation goes in the comments before
+// it, like any other. A member's goes on the lines between it and the member before it, or trails
+// the member's line. Hover and completion show the version, and with `targetVersion` set, items
+// newer than the target are flagged.
+use std::cmp::Ordering;
+
+use crate::ast::{Definition, Identifier};
+use crate::codegen::SourceFile;
+use crate::dialect::ExtensionKind;
+
+// A dotted version like `1.2` (or `v1.2`), compared component by component, with missing
+// components counting as 0
+#[derive(Debug, Clone)]
+pub struct ProtocolVersion(Vec<u64>);
+
+impl ProtocolVersion {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.strip_prefix(['v', 'V']).unwrap_or(text);
+        text.split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()
+            .map(ProtocolVersion)
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+impl Ord for ProtocolVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        let part = |v: &Self, i: usize| v.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| part(self, i).cmp(&part(other, i)))
+            .find(|order| order.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for ProtocolVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ProtocolVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for ProtocolVersion {}
+
+pub struct Annotated<'a> {
+    pub id: &'a Identifier,
+    // The annotation's arguments, which should be a version
+    pub since: &'a str,
+    // Whether the name is visible across the workspace: definitions, enum members, and RPC
+    // versions and procedures, but not fields
+    pub global: bool,
+}
+
+// The members of a definition, with whether their names are global
+fn members(defn: &Definition) -> Vec<(&Identifier, bool)> {
+    let mut members: Vec<(&Identifier, bool)> = defn
+        .declarations()
+        .into_iter()
+        .filter_map(|decl| decl.id())
+        .filter(|id| defn.id().is_none_or(|defn_id| defn_id.start != id.start))
+        .map(|id| (id, false))
+        .collect();
+    match defn {
+        Definition::Enum { body, .. } => {
+            members.extend(body.body.iter().map(|assign| (&assign.id, true)));
+        }
+        Definition::Program { versions, .. } => {
+            for version in versions {
+                members.push((&version.id, true));
+                members.extend(version.procedures.iter().map(|proc| (&proc.id, true)));
+            }
+        }
+        _ => {}
+    }
+    members.sort_by_key(|(id, _)| id.start);
+    members
+}
+
+fn line_end(text: &str, offset: usize) -> usize {
+    text[offset..].find('\n').map_or(text.len(), |i| offset + i)
+}
+
+// The arguments of the last `@since` annotation within `start..end`
+fn since_in(file: &SourceFile, start: usize, end: usize) -> Option<&str> {
+    file.extensions
+        .iter()
+        .filter(|item| start <= item.start && item.end <= end)
+        .filter_map(|item| match &item.kind {
+            ExtensionKind::Annotation { name, args } if name == "since" => Some(args.as_str()),
+            _ => None,
+        })
+        .next_back()
+}
+
+// The definitions and members of `file` with an `@since` annotation
+pub fn annotated(file: &SourceFile) -> Vec<Annotated<'_>> {
+    let mut result = vec![];
+    for (defn, annotations) in file.spec.defns.iter().zip(file.annotations()) {
+        let Some(id) = defn.id() else {
+            continue;
+        };
+        // Annotations trailing the line the definition's name is on are the definition's too
+        let mut bound = line_end(&file.text, id.end);
+        let since = since_in(file, id.end, bound).or_else(|| {
+            annotations
+                .into_iter()
+                .filter(|(name, _)| *name == "since")
+                .map(|(_, args)| args)
+                .next_back()
+        });
+        if let Some(since) = since {
+            result.push(Annotated {
+                id,
+                since,
+                global: true,
+            });
+        }
+        for (member, global) in members(defn) {
+            let trailing = line_end(&file.text, member.end);
+            let since = since_in(file, member.end, trailing)
+                .or_else(|| since_in(file, bound, member.start));
+            if let Some(since) = since {
+                result.push(Annotated {
+                    id: member,
+                    since,
+                    global,
+                });
+            }
+            bound = bound.max(trailing);
+        }
+    }
+    result
+}
+
+// The `@since` version of the name at byte range `start..end` of `file`: the annotation on it if
+// it's a definition or member, or else that of the global name it refers to
+pub fn since_of<'a>(
+    files: &'a [SourceFile],
+    file: &'a SourceFile,
+    start: usize,
+    end: usize,
+) -> Option<&'a str> {
+    let name = &file.text[start..end];
+    if let Some(item) = annotated(file)
+        .into_iter()
+        .find(|item| item.id.start == start)
+    {
+        return Some(item.since);
+    }
+    files
+        .iter()
+        .flat_map(annotated)
+        .find(|item| item.global && item.id.id == name)
+        .map(|item| item.since)
+}
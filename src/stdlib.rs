@@ -0,0 +1,30 @@
+// The standard library
+//
+// rpcgen specs are compiled against an implicit environment: the C headers define `u_int`,
+// `int32_t`, and friends, and specs written against RFC 5531 use its message and authentication
+// types without defining them. With `standardLibrary` set, a file of those definitions is written
+// to the temporary directory and indexed before the workspace, so such names resolve (and
+// navigation can open the file). Definitions in the workspace take precedence.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const LIBRARY: &str = include_str!("stdlib.x");
+
+pub fn path() -> PathBuf {
+    std::env::temp_dir().join("xdr-ls").join("rpc-std.x")
+}
+
+pub fn is_library(path: &Path) -> bool {
+    path == self::path()
+}
+
+// Writes the library out, returning where it is
+pub fn write() -> io::Result<PathBuf> {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, LIBRARY)?;
+    Ok(path)
+}
@@ -0,0 +1,144 @@
+/*
+ * xdr-ls standard library
+ *
+ * Definitions rpcgen specs may use without defining: typedefs from the C
+ * headers rpcgen's output is compiled against, and the RPC message and
+ * authentication types of RFC 5531. Definitions in the workspace take
+ * precedence over these.
+ */
+
+/* Integer typedefs from the C headers; rpcgen encodes each as 4 or 8 bytes */
+typedef unsigned int u_int;
+typedef unsigned int u_long;
+typedef unsigned int u_short;
+typedef unsigned int u_char;
+typedef int int32_t;
+typedef unsigned int uint32_t;
+typedef hyper int64_t;
+typedef unsigned hyper uint64_t;
+typedef hyper quad_t;
+typedef unsigned hyper u_quad_t;
+typedef hyper longlong_t;
+typedef unsigned hyper u_longlong_t;
+
+/* Network object from <rpc/xdr.h> */
+const MAX_NETOBJ_SZ = 1024;
+typedef opaque netobj<MAX_NETOBJ_SZ>;
+
+/* Authentication (RFC 5531, section 8) */
+enum auth_flavor {
+    AUTH_NONE = 0,
+    AUTH_SYS = 1,
+    AUTH_SHORT = 2,
+    AUTH_DH = 3,
+    RPCSEC_GSS = 6
+};
+
+struct opaque_auth {
+    auth_flavor flavor;
+    opaque body<400>;
+};
+
+/* Credentials of the AUTH_SYS flavor (RFC 5531, appendix A) */
+struct authsys_parms {
+    unsigned int stamp;
+    string machinename<255>;
+    unsigned int uid;
+    unsigned int gid;
+    unsigned int gids<16>;
+};
+
+/* RPC messages (RFC 5531, section 9) */
+enum msg_type {
+    CALL = 0,
+    REPLY = 1
+};
+
+enum reply_stat {
+    MSG_ACCEPTED = 0,
+    MSG_DENIED = 1
+};
+
+enum accept_stat {
+    SUCCESS = 0,
+    PROG_UNAVAIL = 1,
+    PROG_MISMATCH = 2,
+    PROC_UNAVAIL = 3,
+    GARBAGE_ARGS = 4,
+    SYSTEM_ERR = 5
+};
+
+enum reject_stat {
+    RPC_MISMATCH = 0,
+    AUTH_ERROR = 1
+};
+
+enum auth_stat {
+    AUTH_OK = 0,
+    AUTH_BADCRED = 1,
+    AUTH_REJECTEDCRED = 2,
+    AUTH_BADVERF = 3,
+    AUTH_REJECTEDVERF = 4,
+    AUTH_TOOWEAK = 5,
+    AUTH_INVALIDRESP = 6,
+    AUTH_FAILED = 7,
+    AUTH_KERB_GENERIC = 8,
+    AUTH_TIMEEXPIRE = 9,
+    AUTH_TKT_FILE = 10,
+    AUTH_DECODE = 11,
+    AUTH_NET_ADDR = 12,
+    RPCSEC_GSS_CREDPROBLEM = 13,
+    RPCSEC_GSS_CTXPROBLEM = 14
+};
+
+struct call_body {
+    unsigned int rpcvers;
+    unsigned int prog;
+    unsigned int vers;
+    unsigned int proc;
+    opaque_auth cred;
+    opaque_auth verf;
+};
+
+struct accepted_reply {
+    opaque_auth verf;
+    union switch (accept_stat stat) {
+    case SUCCESS:
+        /* The procedure's results follow */
+        opaque results[0];
+    case PROG_MISMATCH:
+        struct {
+            unsigned int low;
+            unsigned int high;
+        } mismatch_info;
+    default:
+        void;
+    } reply_data;
+};
+
+union rejected_reply switch (reject_stat stat) {
+case RPC_MISMATCH:
+    struct {
+        unsigned int low;
+        unsigned int high;
+    } mismatch_info;
+case AUTH_ERROR:
+    auth_stat stat;
+};
+
+union reply_body switch (reply_stat stat) {
+case MSG_ACCEPTED:
+    accepted_reply areply;
+case MSG_DENIED:
+    rejected_reply rreply;
+};
+
+struct rpc_msg {
+    unsigned int xid;
+    union switch (msg_type mtype) {
+    case CALL:
+        call_body cbody;
+    case REPLY:
+        reply_body rbody;
+    } body;
+};
@@ -0,0 +1,426 @@
+// Fuzzy workspace symbol search over per-file symbol shards
+use std::collections::HashSet;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use tower_lsp::lsp_types::*;
+
+use crate::ast::*;
+use crate::breaking::{decl_text, spec_text};
+use crate::codegen::SourceFile;
+use crate::position::{Encoding, Lines};
+
+// Maximum number of results returned for a query
+pub const MAX_RESULTS: usize = 256;
+
+// Lower is better; None if `name` doesn't contain the characters of `query` in order
+fn score(query: &str, name: &str) -> Option<usize> {
+    let name = name.to_ascii_lowercase();
+    if name == query {
+        return Some(0);
+    }
+    if name.starts_with(query) {
+        return Some(1);
+    }
+    if name.contains(query) {
+        return Some(2);
+    }
+    // Subsequence match, penalized by the number of skipped characters
+    let mut chars = name.chars();
+    let mut skipped = 0;
+    for q in query.chars() {
+        loop {
+            let c = chars.next()?;
+            if c == q {
+                break;
+            }
+            skipped += 1;
+        }
+    }
+    Some(3 + skipped)
+}
+
+// The name a symbol is matched by: qualified with its container when the query is, like
+// `nfs_fh3::data`
+fn search_name(symbol: &SymbolInformation, qualified: bool) -> String {
+    match (&symbol.container_name, qualified) {
+        (Some(container), true) => format!("{container}::{}", symbol.name),
+        _ => symbol.name.clone(),
+    }
+}
+
+// Searches the shards in parallel, stopping once `MAX_RESULTS` matches have been found. Matches
+// rank by score, with definitions ahead of members scoring the same. The same symbol reached
+// through several paths to its file is returned once.
+pub fn search(shards: &[&Vec<SymbolInformation>], query: &str) -> Vec<SymbolInformation> {
+    let query = query.to_ascii_lowercase();
+    let qualified = query.contains("::");
+    let found = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = shards.len().div_ceil(threads).max(1);
+    let mut matches: Vec<((usize, bool), SymbolInformation)> = thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .chunks(chunk)
+            .map(|shards| {
+                let query = &query;
+                let found = &found;
+                scope.spawn(move || {
+                    let mut matches = vec![];
+                    for symbol in shards.iter().flat_map(|shard| shard.iter()) {
+                        if found.load(Ordering::Relaxed) >= MAX_RESULTS {
+                            break;
+                        }
+                        if let Some(score) = score(query, &search_name(symbol, qualified)) {
+                            found.fetch_add(1, Ordering::Relaxed);
+                            let member = symbol.container_name.is_some();
+                            matches.push(((score, member), symbol.clone()));
+                        }
+                    }
+                    matches
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    matches.sort_by(|(a, x), (b, y)| {
+        a.cmp(b)
+            .then_with(|| x.name.cmp(&y.name))
+            .then_with(|| x.container_name.cmp(&y.container_name))
+    });
+    let mut seen = HashSet::new();
+    matches.retain(|(_, symbol)| {
+        let file = symbol.location.uri.to_file_path().ok();
+        let file = file
+            .as_ref()
+            .and_then(|path| fs::canonicalize(path).ok())
+            .or(file);
+        seen.insert((
+            symbol.name.clone(),
+            symbol.container_name.clone(),
+            file,
+            symbol.location.range.start.line,
+            symbol.location.range.start.character,
+        ))
+    });
+    matches.truncate(MAX_RESULTS);
+    matches.into_iter().map(|(_, symbol)| symbol).collect()
+}
+
+// Builds a workspace symbol; the deprecated field has to be given
+#[allow(deprecated)]
+fn member_symbol(
+    uri: &Url,
+    text: &str,
+    lines: &Lines,
+    encoding: Encoding,
+    id: &Identifier,
+    container: &str,
+) -> SymbolInformation {
+    SymbolInformation {
+        name: id.id.clone(),
+        kind: SymbolKind::FIELD,
+        tags: None,
+        deprecated: None,
+        location: Location::new(
+            uri.clone(),
+            Range::new(
+                lines.position_at(text, id.start, encoding),
+                lines.position_at(text, id.end, encoding),
+            ),
+        ),
+        container_name: Some(container.to_string()),
+    }
+}
+
+// Collects field symbols, with `container` the name they're qualified by
+struct Fields<'a> {
+    uri: &'a Url,
+    text: &'a str,
+    lines: Lines,
+    encoding: Encoding,
+    out: Vec<SymbolInformation>,
+}
+
+impl Fields<'_> {
+    fn decl(&mut self, decl: &Declaration, container: &str) {
+        let Some(id) = decl.id() else {
+            return;
+        };
+        self.out.push(member_symbol(
+            self.uri,
+            self.text,
+            &self.lines,
+            self.encoding,
+            id,
+            container,
+        ));
+        if let Declaration::Normal { spec, .. }
+        | Declaration::FixedArr { spec, .. }
+        | Declaration::VarArr { spec, .. }
+        | Declaration::Optional { spec, .. } = decl
+        {
+            self.spec(spec, &format!("{container}::{}", id.id));
+        }
+    }
+
+    fn spec(&mut self, spec: &TypeSpecifier, container: &str) {
+        match spec {
+            TypeSpecifier::Struct(body) => self.struct_body(body, container),
+            TypeSpecifier::Union(body) => self.union_body(body, container),
+            _ => {}
+        }
+    }
+
+    fn struct_body(&mut self, body: &StructBody, container: &str) {
+        for decl in &body.body {
+            self.decl(decl, container);
+        }
+    }
+
+    fn union_body(&mut self, body: &UnionBody, container: &str) {
+        self.decl(&body.discriminant, container);
+        for decl in body
+            .cases
+            .iter()
+            .map(|case| &case.decl)
+            .chain(body.default.as_deref())
+        {
+            self.decl(decl, container);
+        }
+    }
+}
+
+// The fields of the structs and unions of `spec`, for workspace symbols. Fields of nested
+// structs and unions are contained by the field they're the type of, like `outer::inner`.
+pub fn field_symbols(
+    uri: &Url,
+    text: &str,
+    encoding: Encoding,
+    spec: &Specification,
+) -> Vec<SymbolInformation> {
+    let mut fields = Fields {
+        uri,
+        text,
+        lines: Lines::new(text),
+        encoding,
+        out: vec![],
+    };
+    for defn in &spec.defns {
+        match defn {
+            Definition::Struct { id, body } => fields.struct_body(body, &id.id),
+            Definition::Union { id, body } => fields.union_body(body, &id.id),
+            // `typedef struct { ... } name;`
+            Definition::TypeDef(
+                Declaration::Normal { spec, id }
+                | Declaration::FixedArr { spec, id, .. }
+                | Declaration::VarArr { spec, id, .. }
+                | Declaration::Optional { spec, id },
+            ) => fields.spec(spec, &id.id),
+            _ => {}
+        }
+    }
+    fields.out
+}
+
+// The symbol kind for a definition
+pub fn kind(defn: &Definition) -> SymbolKind {
+    match defn {
+        Definition::Constant { .. } => SymbolKind::CONSTANT,
+        Definition::TypeDef(_) => SymbolKind::TYPE_PARAMETER,
+        Definition::Enum { .. } => SymbolKind::ENUM,
+        Definition::Struct { .. } => SymbolKind::STRUCT,
+        Definition::Union { .. } => SymbolKind::CLASS,
+        Definition::Program { .. } => SymbolKind::MODULE,
+    }
+}
+
+fn range(file: &SourceFile, encoding: Encoding, start: usize, end: usize) -> Range {
+    Range::new(
+        file.position_at(start, encoding),
+        file.position_at(end, encoding),
+    )
+}
+
+// Builds a document symbol; the deprecated field has to be given
+#[allow(deprecated)]
+fn symbol(
+    file: &SourceFile,
+    encoding: Encoding,
+    id: &Identifier,
+    kind: SymbolKind,
+    detail: Option<String>,
+    span: (usize, usize),
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name: id.id.clone(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: range(file, encoding, span.0, span.1),
+        selection_range: range(file, encoding, id.start, id.end),
+        children: (!children.is_empty()).then_some(children),
+    }
+}
+
+fn signature(proc: &Procedure) -> String {
+    let text = |spec: Option<&TypeSpecifier>| spec.map_or("void".to_string(), spec_text);
+    let args: Vec<String> = proc.args.iter().map(spec_text).collect();
+    let args = if args.is_empty() {
+        text(None)
+    } else {
+        args.join(", ")
+    };
+    format!("{} ({args})", text(proc.result.as_ref()))
+}
+
+fn enum_members(file: &SourceFile, encoding: Encoding, body: &EnumBody) -> Vec<DocumentSymbol> {
+    body.body
+        .iter()
+        .map(|assign| {
+            let span = (assign.id.start, assign.id.end);
+            symbol(
+                file,
+                encoding,
+                &assign.id,
+                SymbolKind::ENUM_MEMBER,
+                None,
+                span,
+                vec![],
+            )
+        })
+        .collect()
+}
+
+// A struct field or union arm, with the type as its detail and the members of an anonymous
+// struct, union, or enum type under it
+fn field(file: &SourceFile, encoding: Encoding, decl: &Declaration) -> Option<DocumentSymbol> {
+    let id = decl.id()?;
+    let children = match decl {
+        Declaration::Normal { spec, .. }
+        | Declaration::FixedArr { spec, .. }
+        | Declaration::VarArr { spec, .. }
+        | Declaration::Optional { spec, .. } => members(file, encoding, spec),
+        _ => vec![],
+    };
+    let detail = Some(decl_text(decl));
+    Some(symbol(
+        file,
+        encoding,
+        id,
+        SymbolKind::FIELD,
+        detail,
+        (id.start, id.end),
+        children,
+    ))
+}
+
+// The members of a type written inline
+fn members(file: &SourceFile, encoding: Encoding, spec: &TypeSpecifier) -> Vec<DocumentSymbol> {
+    match spec {
+        TypeSpecifier::Enum(body) => enum_members(file, encoding, body),
+        TypeSpecifier::Struct(body) => struct_fields(file, encoding, body),
+        TypeSpecifier::Union(body) => union_arms(file, encoding, body),
+        _ => vec![],
+    }
+}
+
+fn struct_fields(file: &SourceFile, encoding: Encoding, body: &StructBody) -> Vec<DocumentSymbol> {
+    body.body
+        .iter()
+        .filter_map(|decl| field(file, encoding, decl))
+        .collect()
+}
+
+// The discriminant, then the arms
+fn union_arms(file: &SourceFile, encoding: Encoding, body: &UnionBody) -> Vec<DocumentSymbol> {
+    std::iter::once(body.discriminant.as_ref())
+        .chain(body.cases.iter().map(|case| &case.decl))
+        .chain(body.default.as_deref())
+        .filter_map(|decl| field(file, encoding, decl))
+        .collect()
+}
+
+// The outline of a file: its definitions, with the members of enums, structs, and unions under
+// them, and the versions and procedures of programs
+pub fn document_symbols(file: &SourceFile, encoding: Encoding) -> Vec<DocumentSymbol> {
+    let mut result = vec![];
+    for defn in &file.spec.defns {
+        let (Some(id), Some(start)) = (defn.id(), file.definition_start(defn)) else {
+            continue;
+        };
+        let end = file.definition_end(defn).unwrap_or(id.end);
+        let children = match defn {
+            Definition::Enum { body, .. } => enum_members(file, encoding, body),
+            Definition::Struct { body, .. } => struct_fields(file, encoding, body),
+            Definition::Union { body, .. } => union_arms(file, encoding, body),
+            // `typedef struct { ... } name;`
+            Definition::TypeDef(
+                Declaration::Normal { spec, .. }
+                | Declaration::FixedArr { spec, .. }
+                | Declaration::VarArr { spec, .. }
+                | Declaration::Optional { spec, .. },
+            ) => members(file, encoding, spec),
+            Definition::Program { versions, .. } => versions
+                .iter()
+                .map(|version| {
+                    let procedures = version
+                        .procedures
+                        .iter()
+                        .map(|proc| {
+                            let detail = Some(signature(proc));
+                            let span = (proc.start, proc.end);
+                            symbol(
+                                file,
+                                encoding,
+                                &proc.id,
+                                SymbolKind::FUNCTION,
+                                detail,
+                                span,
+                                vec![],
+                            )
+                        })
+                        .collect();
+                    let span = (version.start, version.end);
+                    symbol(
+                        file,
+                        encoding,
+                        &version.id,
+                        SymbolKind::NAMESPACE,
+                        None,
+                        span,
+                        procedures,
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        result.push(symbol(
+            file,
+            encoding,
+            id,
+            kind(defn),
+            None,
+            (start, end),
+            children,
+        ));
+    }
+    for (id, kind) in file.extension_definitions() {
+        result.push(symbol(
+            file,
+            encoding,
+            id,
+            kind,
+            None,
+            (id.start, id.end),
+            vec![],
+        ));
+    }
+    result.sort_by_key(|symbol| symbol.range.start);
+    result
+}
@@ -0,0 +1,496 @@
+// A synchronous server on `lsp-server`, run with `xdr-ls --sync` when built with the
+// `sync-server` feature. Messages are handled one at a time on the main thread, which makes it
+// small and deterministic. It shares the analysis with the tower-lsp server and serves the core
+// of it: definitions, references, hover, workspace symbols, semantic tokens, formatting, and lint
+// diagnostics.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Response};
+use regex::Regex;
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::*;
+
+use xdr_ls::codegen::{Schema, SourceFile};
+use xdr_ls::dialect::Dialect;
+use xdr_ls::index::{DocumentIndex, token_at};
+use xdr_ls::position::Encoding;
+use xdr_ls::scan::{ScanOptions, get_xdr_files};
+use xdr_ls::server::{format_edits, hover_at, range_format_edits};
+use xdr_ls::since::ProtocolVersion;
+use xdr_ls::{format, hover, lint, position, semantic, stdlib, symbols};
+
+use crate::panics;
+
+// What one document contributes to the index
+#[derive(Default)]
+struct FileIndex {
+    text: String,
+    index: DocumentIndex,
+}
+
+#[derive(Default)]
+struct State {
+    root: Option<PathBuf>,
+    files: BTreeMap<Url, FileIndex>,
+    lint_rules: lint::Registry,
+    format_style: format::Style,
+    hover_verbosity: hover::Verbosity,
+    // How positions count columns, agreed with the client at initialization
+    encoding: Encoding,
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        references_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic::legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                range: Some(true),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+impl State {
+    fn index(&mut self, uri: Url, text: String) {
+        let index = DocumentIndex::new(&uri, &text, self.encoding);
+        self.files.insert(uri, FileIndex { text, index });
+    }
+
+    fn ident_at(&self, position: &TextDocumentPositionParams) -> Option<&str> {
+        let file = self.files.get(&position.text_document.uri)?;
+        token_at(&file.index.identifiers, position.position).map(|token| token.val.as_str())
+    }
+
+    // Indexed files with the standard library first, so the workspace's definitions replace its
+    // own when collected into maps
+    fn library_first(&self) -> Vec<(&Url, &FileIndex)> {
+        let mut files: Vec<_> = self.files.iter().collect();
+        files.sort_by_key(|(uri, _)| {
+            !uri.to_file_path()
+                .is_ok_and(|path| stdlib::is_library(&path))
+        });
+        files
+    }
+
+    fn parsed(&self) -> (Vec<Url>, Vec<SourceFile>) {
+        let mut docs = vec![];
+        let mut files = vec![];
+        for (uri, file) in self.library_first() {
+            let path = uri.to_file_path().unwrap_or_default();
+            if let Some(file) = SourceFile::parse_recovering(path, file.text.clone()) {
+                docs.push(uri.clone());
+                files.push(file);
+            }
+        }
+        (docs, files)
+    }
+
+    fn initialize(&mut self, params: &InitializeParams) {
+        let options = params.initialization_options.as_ref();
+        self.format_style = format::Style::from_init_options(options);
+        self.hover_verbosity = options
+            .and_then(|options| options.get("hoverVerbosity"))
+            .and_then(Value::as_str)
+            .and_then(hover::Verbosity::from_name)
+            .unwrap_or_default();
+        let include_aware = options
+            .and_then(|options| options.get("includeAware"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let dialect = options
+            .and_then(|options| options.get("dialect"))
+            .and_then(Value::as_str)
+            .and_then(Dialect::from_name);
+        let target_version = options
+            .and_then(|options| options.get("targetVersion"))
+            .and_then(Value::as_str)
+            .and_then(ProtocolVersion::parse);
+        let root_types = options
+            .and_then(|options| options.get("rootTypes"))
+            .and_then(Value::as_array)
+            .map(|roots| {
+                roots
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let unhandled_cases = options
+            .and_then(|options| options.get("unhandledCases"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let unused_exempt = options
+            .and_then(|options| options.get("unusedExempt"))
+            .and_then(Value::as_str)
+            .and_then(|pattern| Regex::new(pattern).ok());
+        self.lint_rules = lint::Registry::builtin(
+            include_aware,
+            dialect,
+            target_version,
+            root_types,
+            unhandled_cases,
+            unused_exempt,
+        );
+        let disabled: Vec<String> = options
+            .and_then(|options| options.get("disabledRules"))
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+        self.lint_rules.disable(&disabled);
+        let Some(root) = params
+            .root_uri
+            .as_ref()
+            .and_then(|uri| uri.to_file_path().ok())
+        else {
+            return;
+        };
+        let mut paths = vec![];
+        let library = options
+            .and_then(|options| options.get("standardLibrary"))
+            .and_then(Value::as_bool)
+            == Some(true);
+        if library {
+            match stdlib::write() {
+                Ok(path) => paths.push(path),
+                Err(err) => eprintln!("could not write the standard library: {err}"),
+            }
+        }
+        let scan = ScanOptions::from_init_options(options);
+        for warning in get_xdr_files(&root, &scan, &mut |path| paths.push(path.to_path_buf())) {
+            eprintln!("{warning}");
+        }
+        for path in paths {
+            if let (Ok(uri), Ok(text)) = (Url::from_file_path(&path), fs::read_to_string(&path)) {
+                self.index(uri, text);
+            }
+        }
+        self.root = Some(root);
+    }
+
+    fn request(&self, method: &str, value: Value) -> Result<Value, (ErrorCode, String)> {
+        let invalid = |err: serde_json::Error| (ErrorCode::InvalidParams, err.to_string());
+        match method {
+            "textDocument/definition" => {
+                let params: GotoDefinitionParams =
+                    serde_json::from_value(value).map_err(invalid)?;
+                let Some(ident) = self.ident_at(&params.text_document_position_params) else {
+                    return Ok(Value::Null);
+                };
+                // Every definition in the workspace, or the standard library's if there's none
+                let (library, workspace): (Vec<_>, Vec<_>) = self
+                    .library_first()
+                    .into_iter()
+                    .filter_map(|(uri, file)| Some((uri, file.index.defns.get(ident)?)))
+                    .partition(|(uri, _)| {
+                        uri.to_file_path()
+                            .is_ok_and(|path| stdlib::is_library(&path))
+                    });
+                let mut defns: Vec<&Location> = if workspace.is_empty() {
+                    library
+                } else {
+                    workspace
+                }
+                .into_iter()
+                .map(|(_, loc)| loc)
+                .collect();
+                Ok(match defns.len() {
+                    0 => Value::Null,
+                    1 => json!(defns.pop()),
+                    _ => json!(defns),
+                })
+            }
+            "textDocument/references" => {
+                let params: ReferenceParams = serde_json::from_value(value).map_err(invalid)?;
+                let Some(ident) = self.ident_at(&params.text_document_position) else {
+                    return Ok(Value::Null);
+                };
+                let mut locs: Vec<&Location> = self
+                    .files
+                    .values()
+                    .flat_map(|file| file.index.refs.get(ident).into_iter().flatten())
+                    .collect();
+                if params.context.include_declaration {
+                    locs.extend(
+                        self.files
+                            .values()
+                            .filter_map(|file| file.index.defns.get(ident)),
+                    );
+                }
+                Ok(json!(locs))
+            }
+            "textDocument/hover" => {
+                let params: HoverParams = serde_json::from_value(value).map_err(invalid)?;
+                let position = params.text_document_position_params;
+                let (docs, files) = self.parsed();
+                Ok(json!(hover_at(
+                    &docs,
+                    &Schema::new(&files),
+                    &position.text_document.uri,
+                    position.position,
+                    self.hover_verbosity,
+                    self.encoding,
+                )))
+            }
+            "workspace/symbol" => {
+                let params: WorkspaceSymbolParams =
+                    serde_json::from_value(value).map_err(invalid)?;
+                let shards: Vec<&Vec<SymbolInformation>> = self
+                    .files
+                    .values()
+                    .flat_map(|file| [&file.index.symbols, &file.index.fields])
+                    .collect();
+                Ok(json!(symbols::search(&shards, &params.query)))
+            }
+            "textDocument/semanticTokens/full" | "textDocument/semanticTokens/range" => {
+                // Only there for a range request
+                let range: Option<Range> = value
+                    .get("range")
+                    .cloned()
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(invalid)?;
+                let params: SemanticTokensParams =
+                    serde_json::from_value(value).map_err(invalid)?;
+                let (docs, files) = self.parsed();
+                let Some(file) = docs
+                    .iter()
+                    .position(|uri| *uri == params.text_document.uri)
+                    .map(|i| &files[i])
+                else {
+                    return Ok(Value::Null);
+                };
+                let kinds: HashMap<String, SymbolKind> = self
+                    .files
+                    .values()
+                    .flat_map(|file| &file.index.symbols)
+                    .map(|symbol| (symbol.name.clone(), symbol.kind))
+                    .collect();
+                Ok(json!(SemanticTokens {
+                    result_id: None,
+                    data: semantic::tokens(file, &kinds, range, self.encoding),
+                }))
+            }
+            "textDocument/formatting" => {
+                let params: DocumentFormattingParams =
+                    serde_json::from_value(value).map_err(invalid)?;
+                let edits = self.files.get(&params.text_document.uri).and_then(|file| {
+                    format_edits(
+                        &file.text,
+                        &params.options,
+                        self.format_style.clone(),
+                        self.encoding,
+                    )
+                });
+                Ok(json!(edits))
+            }
+            "textDocument/rangeFormatting" => {
+                let params: DocumentRangeFormattingParams =
+                    serde_json::from_value(value).map_err(invalid)?;
+                let edits = self.files.get(&params.text_document.uri).and_then(|file| {
+                    range_format_edits(
+                        &file.text,
+                        params.range,
+                        &params.options,
+                        self.format_style.clone(),
+                        self.encoding,
+                    )
+                });
+                Ok(json!(edits))
+            }
+            _ => Err((
+                ErrorCode::MethodNotFound,
+                format!("unhandled method {method}"),
+            )),
+        }
+    }
+
+    // Handles a notification, returning whether the index changed
+    fn notify(&mut self, method: &str, value: Value) -> bool {
+        match method {
+            "textDocument/didOpen" => {
+                let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(value) else {
+                    return false;
+                };
+                self.index(params.text_document.uri, params.text_document.text);
+                true
+            }
+            "textDocument/didChange" => {
+                let Ok(params) = serde_json::from_value::<DidChangeTextDocumentParams>(value)
+                else {
+                    return false;
+                };
+                // Full sync: the last change holds the whole document
+                let Some(change) = params.content_changes.into_iter().next_back() else {
+                    return false;
+                };
+                self.index(params.text_document.uri, change.text);
+                true
+            }
+            "textDocument/didClose" => {
+                let Ok(params) = serde_json::from_value::<DidCloseTextDocumentParams>(value) else {
+                    return false;
+                };
+                let uri = params.text_document.uri;
+                // Go back to the file on disk, if there is one
+                match uri
+                    .to_file_path()
+                    .ok()
+                    .and_then(|path| fs::read_to_string(path).ok())
+                {
+                    Some(text) => self.index(uri, text),
+                    None => {
+                        self.files.remove(&uri);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn publish_diagnostics(&self, connection: &Connection) {
+        if self.lint_rules.is_empty() {
+            return;
+        }
+        let root = self.root.clone().unwrap_or_default();
+        let (_, files) = self.parsed();
+        let workspace = lint::ResolvedWorkspace::new(&root, &files, self.encoding);
+        let mut found = self.lint_rules.run(&workspace);
+        for (uri, file) in &self.files {
+            if let Ok(path) = uri.to_file_path()
+                && let Some(diagnostic) = lint::syntax_error(&file.text, self.encoding)
+            {
+                found.entry(path).or_default().insert(0, diagnostic);
+            }
+        }
+        for (path, diagnostics) in found {
+            let Ok(uri) = Url::from_file_path(&path) else {
+                continue;
+            };
+            let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+            let notification =
+                Notification::new("textDocument/publishDiagnostics".to_string(), params);
+            // Only fails once the client has gone away
+            let _ = connection.sender.send(Message::Notification(notification));
+        }
+    }
+}
+
+// Runs a handler, turning a panic into a message that's shown to the user, like
+// `panics::CatchPanics` does for the tower-lsp server
+fn isolated<T>(
+    connection: &Connection,
+    method: &str,
+    handler: impl FnOnce() -> T,
+) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(handler)).map_err(|payload| {
+        let message = format!(
+            "xdr-ls: handling `{method}` failed: {}",
+            panics::panic_message(payload.as_ref())
+        );
+        let params = ShowMessageParams {
+            typ: MessageType::ERROR,
+            message: message.clone(),
+        };
+        let notification = Notification::new("window/showMessage".to_string(), params);
+        let _ = connection.sender.send(Message::Notification(notification));
+        message
+    })
+}
+
+pub fn run() -> ExitCode {
+    let (connection, io_threads) = Connection::stdio();
+    let Ok((id, params)) = connection.initialize_start() else {
+        return ExitCode::FAILURE;
+    };
+    let params = serde_json::from_value::<InitializeParams>(params);
+    let encoding = params.as_ref().map_or(Encoding::Utf16, |params| {
+        position::negotiate(&params.capabilities)
+    });
+    let capabilities = ServerCapabilities {
+        position_encoding: Some(encoding.kind()),
+        ..capabilities()
+    };
+    if connection
+        .initialize_finish(id, json!({ "capabilities": capabilities }))
+        .is_err()
+    {
+        return ExitCode::FAILURE;
+    }
+    let mut state = State {
+        encoding,
+        ..State::default()
+    };
+    match params {
+        Ok(params) => state.initialize(&params),
+        Err(err) => eprintln!("invalid initialize params: {err}"),
+    }
+    state.publish_diagnostics(&connection);
+    let mut shut_down = false;
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                let response = if shut_down {
+                    Response::new_err(
+                        request.id,
+                        ErrorCode::InvalidRequest as i32,
+                        "the server is shutting down".to_string(),
+                    )
+                } else if request.method == "shutdown" {
+                    shut_down = true;
+                    Response::new_ok(request.id, ())
+                } else {
+                    let handler = || state.request(&request.method, request.params);
+                    match isolated(&connection, &request.method, handler) {
+                        Ok(Ok(result)) => Response::new_ok(request.id, result),
+                        Ok(Err((code, message))) => {
+                            Response::new_err(request.id, code as i32, message)
+                        }
+                        Err(message) => {
+                            Response::new_err(request.id, ErrorCode::InternalError as i32, message)
+                        }
+                    }
+                };
+                if connection.sender.send(Message::Response(response)).is_err() {
+                    break;
+                }
+            }
+            Message::Notification(notification) if notification.method == "exit" => break,
+            Message::Notification(notification) => {
+                if shut_down {
+                    continue;
+                }
+                let handler = || state.notify(&notification.method, notification.params);
+                if isolated(&connection, &notification.method, handler).unwrap_or(false) {
+                    let publish = || state.publish_diagnostics(&connection);
+                    let _ = isolated(&connection, "textDocument/publishDiagnostics", publish);
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    drop(connection);
+    // The protocol asks for status 1 when the client exits (or goes away) without shutting down
+    match io_threads.join() {
+        Ok(()) if shut_down => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
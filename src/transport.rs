@@ -0,0 +1,88 @@
+// Transports
+//
+// The server talks to its client over stdin and stdout by default (`--stdio`). `--listen
+// <addr:port>` accepts one TCP connection instead, and `--pipe <path>` uses a Unix socket: it
+// connects to one the client already listens on (as editors create it), or otherwise listens at
+// the path and accepts one connection. Both flags also take the `--flag=value` form.
+use std::io;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+pub type Input = Box<dyn AsyncRead + Send + Unpin>;
+pub type Output = Box<dyn AsyncWrite + Send + Unpin>;
+
+#[derive(Debug)]
+pub enum Transport {
+    Stdio,
+    Listen(String),
+    Pipe(PathBuf),
+}
+
+impl Transport {
+    // The transport the command line asks for. Other arguments, such as the `--clientProcessId`
+    // editors pass, are ignored.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut transport = Transport::Stdio;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = |flag: &str| {
+                value
+                    .clone()
+                    .or_else(|| iter.next().cloned())
+                    .ok_or(format!("{flag} requires an argument"))
+            };
+            transport = match flag {
+                "--stdio" => Transport::Stdio,
+                "--listen" => Transport::Listen(value(flag)?),
+                "--pipe" => Transport::Pipe(PathBuf::from(value(flag)?)),
+                _ => continue,
+            };
+        }
+        Ok(transport)
+    }
+
+    // Waits for the client to connect, if it has to
+    pub async fn open(self) -> io::Result<(Input, Output)> {
+        match self {
+            Transport::Stdio => Ok((Box::new(tokio::io::stdin()), Box::new(tokio::io::stdout()))),
+            Transport::Listen(addr) => {
+                let listener = TcpListener::bind(&addr).await?;
+                eprintln!("Listening on {}", listener.local_addr()?);
+                let (stream, _) = listener.accept().await?;
+                let (input, output) = stream.into_split();
+                Ok((Box::new(input), Box::new(output)))
+            }
+            Transport::Pipe(path) => open_pipe(path).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn open_pipe(path: PathBuf) -> io::Result<(Input, Output)> {
+    use tokio::net::{UnixListener, UnixStream};
+
+    let stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(_) => {
+            let listener = UnixListener::bind(&path)?;
+            eprintln!("Listening on {}", path.display());
+            listener.accept().await?.0
+        }
+    };
+    let (input, output) = stream.into_split();
+    Ok((Box::new(input), Box::new(output)))
+}
+
+#[cfg(not(unix))]
+async fn open_pipe(_: PathBuf) -> io::Result<(Input, Output)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--pipe is only supported on Unix",
+    ))
+}